@@ -1,63 +1,242 @@
 #![no_std]
 
-use drivers_virtio::fs;
+extern crate alloc;
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, Ordering};
+use drivers_virtio::hal::hal;
+use vfs::VfsError;
 
 pub struct Model {
     pub data: &'static [u8],
     pub size: usize,
+    /// `true` si `data` está proyectado por el backend (p. ej. ventana DAX de virtio-fs), sin copia en RAM del guest.
+    pub zero_copy: bool,
+    /// Contenido de las plantillas de prompts del modelo (ver [`prompts_path`]), si existen.
+    pub prompts: Option<Vec<u8>>,
 }
 
 pub static mut MODEL: Option<Model> = None;
 
-/// Carga un modelo AI desde virtio-fs y lo mapea en memoria contigua.
+/// Tamaño máximo del fichero de plantillas de prompts.
+const MAX_PROMPTS_FILE: u64 = 64 * 1024;
+
+/// Los modelos copiados ocupan frames de 2MiB del kernel, que vuelven al allocator
+/// (y, desde ahí, al host vía virtio-balloon) al descargar el modelo.
+const FRAME_SIZE: usize = 2 * 1024 * 1024;
+
+/// Carga un modelo AI a través del VFS (virtio-fs o imagen sobre virtio-blk) y lo mapea en memoria contigua.
+///
+/// Si el backend permite proyectar el fichero (ventana DAX de virtio-fs), el modelo se
+/// usa directamente desde la page cache del host; si no, se copia a frames contiguos del kernel.
+/// Junto al modelo se leen sus plantillas de prompts ([`prompts_path`]), si las tiene.
 pub fn load_model(path: &str) -> Result<(), &'static str> {
-    // Asume que el buffer máximo de modelo es 8 MiB
-    const MAX_MODEL_SIZE: usize = 8 * 1024 * 1024;
-    // Reservar buffer estático
-    static mut MODEL_BUF: [u8; MAX_MODEL_SIZE] = [0; MAX_MODEL_SIZE];
-    let buf = unsafe { &mut MODEL_BUF[..] };
-    let read = fs::read_file(path, buf).ok_or("fs read error")?;
+    unload_model();
+    let prompts = read_prompts(&prompts_path(path));
+    match vfs::map_file(path) {
+        Ok(data) => {
+            unsafe {
+                MODEL = Some(Model { data, size: data.len(), zero_copy: true, prompts });
+            }
+            return Ok(());
+        }
+        Err(VfsError::Unsupported) => {}
+        Err(e) => return Err(e.as_str()),
+    }
+    let file = vfs::open(path).map_err(|e| e.as_str())?;
+    let size = file.size() as usize;
+    if size == 0 {
+        file.close();
+        unsafe { MODEL = Some(Model { data: &[], size: 0, zero_copy: false, prompts }); }
+        return Ok(());
+    }
+    let base = hal().alloc_frames(size);
+    if base.is_null() {
+        file.close();
+        return Err("model too large");
+    }
+    let buf = unsafe { core::slice::from_raw_parts_mut(base, size) };
+    let mut read = 0;
+    while read < size {
+        match file.read_at(read as u64, &mut buf[read..]) {
+            Ok(0) => break,
+            Ok(n) => read += n,
+            Err(e) => {
+                file.close();
+                free_frames(buf);
+                return Err(e.as_str());
+            }
+        }
+    }
+    file.close();
+    if read < size {
+        // Fichero truncado durante la lectura: no se acepta un modelo a medias
+        free_frames(buf);
+        return Err("model read truncated");
+    }
     unsafe {
         MODEL = Some(Model {
-            data: &buf[..read],
-            size: read,
+            data: buf,
+            size,
+            zero_copy: false,
+            prompts,
         });
     }
     Ok(())
 }
 
+/// Ruta de las plantillas de prompts de un modelo: el mismo nombre con extensión
+/// `.prompts.json` (`/models/tiny.bin` -> `/models/tiny.prompts.json`).
+pub fn prompts_path(model_path: &str) -> String {
+    let name_start = model_path.rfind('/').map_or(0, |i| i + 1);
+    let stem = match model_path[name_start..].rfind('.') {
+        Some(dot) if dot > 0 => &model_path[..name_start + dot],
+        _ => model_path,
+    };
+    let mut path = String::from(stem);
+    path.push_str(".prompts.json");
+    path
+}
+
+/// Un modelo sin plantillas (o con un fichero ilegible) usa las del servidor.
+fn read_prompts(path: &str) -> Option<Vec<u8>> {
+    let size = vfs::stat(path).ok()?.size;
+    if size > MAX_PROMPTS_FILE {
+        return None;
+    }
+    let mut buf = alloc::vec![0u8; size as usize];
+    let n = vfs::read_file(path, &mut buf).ok()?;
+    buf.truncate(n);
+    Some(buf)
+}
+
+/// Plantillas de prompts del modelo cargado.
+pub fn prompt_templates() -> Option<&'static [u8]> {
+    unsafe { (*core::ptr::addr_of!(MODEL)).as_ref()?.prompts.as_deref() }
+}
+
+fn free_frames(data: &[u8]) {
+    let start = data.as_ptr() as usize;
+    let mut frame = start - start % FRAME_SIZE;
+    while frame < start + data.len() {
+        hal().free_frame(frame);
+        frame += FRAME_SIZE;
+    }
+}
+
+/// Descarga el modelo actual, deshaciendo la proyección DAX si la había o devolviendo
+/// sus frames al allocator si se copió.
+pub fn unload_model() {
+    if let Some(model) = unsafe { (*core::ptr::addr_of_mut!(MODEL)).take() } {
+        if model.zero_copy {
+            let _ = vfs::unmap_file(model.data);
+        } else if !model.data.is_empty() {
+            free_frames(model.data);
+        }
+    }
+}
+
+/// El backend va a retirar la proyección `data` (hot-unplug de virtio-fs). Si es la
+/// del modelo cargado, el modelo se copia a frames del kernel mientras la ventana
+/// sigue mapeada; si no hay memoria para la copia, se descarta el modelo.
+pub fn revoke_mapping(data: &'static [u8]) {
+    let slot = unsafe { &mut *core::ptr::addr_of_mut!(MODEL) };
+    let Some(model) = slot.as_mut() else { return };
+    if !model.zero_copy || model.data.as_ptr() != data.as_ptr() {
+        return;
+    }
+    let base = hal().alloc_frames(model.size);
+    if base.is_null() {
+        // Sin `vfs::unmap_file`: el backend desmapea la ventana por su cuenta
+        *slot = None;
+        return;
+    }
+    let buf = unsafe { core::slice::from_raw_parts_mut(base, model.size) };
+    buf.copy_from_slice(model.data);
+    model.data = buf;
+    model.zero_copy = false;
+}
+
+/// Bytes del modelo cargado que ocupan RAM del guest (0 si no hay modelo o se usa vía DAX).
+pub fn resident_bytes() -> usize {
+    match unsafe { (*core::ptr::addr_of!(MODEL)).as_ref() } {
+        Some(model) if !model.zero_copy => model.size,
+        _ => 0,
+    }
+}
+
+/// Pares (prompt, respuesta) del modelo cargado, en el orden del fichero. El modelo
+/// es un diccionario serializado: [len][prompt][len][respuesta]... Una entrada
+/// truncada termina la lista.
+fn entries() -> impl Iterator<Item = (&'static [u8], &'static [u8])> {
+    let data: &'static [u8] = unsafe { (*core::ptr::addr_of!(MODEL)).as_ref().map_or(&[], |m| m.data) };
+    let mut i = 0;
+    core::iter::from_fn(move || {
+        let klen = *data.get(i)? as usize;
+        let k = data.get(i + 1..i + 1 + klen)?;
+        i += 1 + klen;
+        let vlen = *data.get(i)? as usize;
+        let v = data.get(i + 1..i + 1 + vlen)?;
+        i += 1 + vlen;
+        Some((k, v))
+    })
+}
+
+/// Respuesta del modelo cargado para `prompt`.
+fn lookup(prompt: &str) -> Option<&'static [u8]> {
+    entries().find(|(k, _)| *k == prompt.as_bytes()).map(|(_, v)| v)
+}
+
+/// Prompts que sabe responder el modelo cargado (los que no son UTF-8 se omiten).
+pub fn known_prompts(mut f: impl FnMut(&str)) {
+    for (k, _) in entries() {
+        if let Ok(prompt) = core::str::from_utf8(k) {
+            f(prompt);
+        }
+    }
+}
+
 /// Realiza inferencia real sobre el modelo cargado.
-/// El modelo es un diccionario serializado: [len][prompt][len][respuesta]...
+/// Sin modelo o sin respuesta para el prompt devuelve una respuesta vacía.
 pub fn infer(prompt: &str) -> &'static str {
+    let Some(v) = lookup(prompt) else { return "" };
     unsafe {
-        if let Some(model) = MODEL.as_ref() {
-            let mut i = 0;
-            let data = model.data;
-            while i < data.len() {
-                if i + 1 > data.len() { break; }
-                let klen = data[i] as usize;
-                i += 1;
-                if i + klen > data.len() { break; }
-                let k = &data[i..i + klen];
-                i += klen;
-                if i + 1 > data.len() { break; }
-                let vlen = data[i] as usize;
-                i += 1;
-                if i + vlen > data.len() { break; }
-                let v = &data[i..i + vlen];
-                i += vlen;
-                if k == prompt.as_bytes() {
-                    // Copiar la respuesta a un buffer estático para devolver &'static str
-                    static mut RESP_BUF: [u8; 256] = [0; 256];
-                    let n = v.len().min(255);
-                    RESP_BUF[..n].copy_from_slice(&v[..n]);
-                    RESP_BUF[n] = 0;
-                    return core::str::from_utf8_unchecked(&RESP_BUF[..n]);
-                }
-            }
-            return ""; // Prompt no encontrado: respuesta vacía concreta
-        } else {
-            return ""; // No hay modelo cargado: respuesta vacía concreta
+        // Copiar la respuesta a un buffer estático para devolver &'static str
+        static mut RESP_BUF: [u8; 256] = [0; 256];
+        let buf = &mut *core::ptr::addr_of_mut!(RESP_BUF);
+        let n = v.len().min(255);
+        buf[..n].copy_from_slice(&v[..n]);
+        buf[n] = 0;
+        core::str::from_utf8_unchecked(&buf[..n])
+    }
+}
+
+/// Genera la respuesta a `prompt` token a token: llama a `on_token` con cada token
+/// (una palabra con el espacio que la sigue) según se produce, hasta el final o
+/// hasta que `on_token` devuelva `false`. Devuelve los tokens entregados.
+///
+/// `cancel` se comprueba entre tokens; si se activa, la generación para y devuelve
+/// `Err("cancelled")`.
+pub fn infer_stream(prompt: &str, cancel: &AtomicBool, mut on_token: impl FnMut(&str) -> bool) -> Result<usize, &'static str> {
+    let Some(v) = lookup(prompt) else { return Ok(0) };
+    let text = match core::str::from_utf8(v) {
+        Ok(text) => text,
+        Err(e) => unsafe { core::str::from_utf8_unchecked(&v[..e.valid_up_to()]) },
+    };
+    let mut tokens = 0;
+    let mut rest = text;
+    while !rest.is_empty() {
+        if cancel.load(Ordering::Relaxed) {
+            return Err("cancelled");
+        }
+        let word_end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+        let end = rest[word_end..].find(|c: char| !c.is_whitespace()).map_or(rest.len(), |i| word_end + i);
+        tokens += 1;
+        if !on_token(&rest[..end]) {
+            break;
         }
+        rest = &rest[end..];
     }
+    Ok(tokens)
 }
@@ -0,0 +1,481 @@
+//! Driver virtio-balloon (virtio 1.x, sección 5.5) sobre el frame allocator del kernel.
+//!
+//! El host fija `num_pages` (páginas de 4KiB) en la configuración; el driver infla
+//! reservando frames de 2MiB al allocator y entregando sus PFN por `inflateq`, y
+//! desinfla avisando por `deflateq` antes de devolver los frames al allocator.
+//! Con VIRTIO_BALLOON_F_PAGE_REPORTING, los frames libres que aún no se han
+//! notificado se envían por `reporting_vq` para que el host pueda descartarlos.
+//! Las estadísticas (`statsq`) salen de un proveedor que registra el kernel.
+
+use crate::dma::{self, PageAligned};
+use crate::devmgr::{Driver, Match};
+use crate::hal::hal;
+use crate::pci::VirtioDevice;
+use crate::transport::{Recovery, TransportError, VirtioPci};
+use crate::virtqueue::{Segment, VirtQueue, DEFAULT_SPIN_LIMIT};
+use crate::watchdog;
+use core::fmt;
+use core::ptr::addr_of_mut;
+
+pub const VIRTIO_ID_BALLOON: u16 = 5;
+
+const VIRTIO_BALLOON_F_MUST_TELL_HOST: u64 = 1 << 0;
+const VIRTIO_BALLOON_F_STATS_VQ: u64 = 1 << 1;
+const VIRTIO_BALLOON_F_PAGE_REPORTING: u64 = 1 << 5;
+
+// Configuración del dispositivo
+const CONFIG_NUM_PAGES: usize = 0;
+const CONFIG_ACTUAL: usize = 4;
+
+// Etiquetas de virtio_balloon_stat
+const VIRTIO_BALLOON_S_MEMFREE: u16 = 4;
+const VIRTIO_BALLOON_S_MEMTOT: u16 = 5;
+const VIRTIO_BALLOON_S_AVAIL: u16 = 6;
+const VIRTIO_BALLOON_S_CACHES: u16 = 7;
+const STAT_LEN: usize = 10;
+const NUM_STATS: usize = 4;
+
+/// El protocolo cuenta en páginas de 4KiB; el allocator del kernel trabaja con frames de 2MiB.
+const BALLOON_PAGE_SIZE: usize = 4096;
+const FRAME_SIZE: usize = 2 * 1024 * 1024;
+const PAGES_PER_FRAME: usize = FRAME_SIZE / BALLOON_PAGE_SIZE;
+const MAX_BALLOON_FRAMES: usize = 128;
+/// Frames libres que se notifican por petición de `reporting_vq`.
+const REPORT_BATCH: usize = 16;
+const QUEUE_SIZE: u16 = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BalloonError {
+    /// No hay dispositivo virtio-balloon o se ha retirado.
+    NoDevice,
+    Transport(TransportError),
+    /// El dispositivo no confirmó la petición a tiempo.
+    Timeout,
+}
+
+impl BalloonError {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BalloonError::NoDevice => "virtio-balloon: no device",
+            BalloonError::Transport(TransportError::MissingCapability) => "virtio-balloon: missing PCI capability",
+            BalloonError::Transport(TransportError::FeaturesRejected) => "virtio-balloon: features rejected",
+            BalloonError::Transport(TransportError::QueueUnavailable) => "virtio-balloon: queue unavailable",
+            BalloonError::Timeout => "virtio-balloon: timeout",
+        }
+    }
+}
+
+impl fmt::Display for BalloonError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Uso de memoria del guest, tal como lo ve el kernel.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MemStats {
+    /// Bytes en frames libres del allocator.
+    pub free_bytes: u64,
+    /// Bytes gestionados por el allocator de frames.
+    pub total_bytes: u64,
+    pub heap_used: u64,
+    pub heap_size: u64,
+    /// Bytes del modelo AI copiados en RAM del guest (0 si se usa sin copia vía DAX).
+    pub model_bytes: u64,
+}
+
+struct BalloonDevice {
+    transport: VirtioPci,
+    inflate: VirtQueue,
+    deflate: VirtQueue,
+    stats: Option<VirtQueue>,
+    reporting: Option<VirtQueue>,
+    /// Frames entregados al host, en orden de inflado.
+    frames: [usize; MAX_BALLOON_FRAMES],
+    num_frames: usize,
+}
+
+static mut BALLOON: Option<BalloonDevice> = None;
+static mut STATS_PROVIDER: Option<fn() -> MemStats> = None;
+
+// Buffers compartidos con el dispositivo
+static mut PFN_BUF: [u32; PAGES_PER_FRAME] = [0; PAGES_PER_FRAME];
+static mut STATS_BUF: PageAligned<[u8; STAT_LEN * NUM_STATS]> = PageAligned([0; STAT_LEN * NUM_STATS]);
+
+fn device() -> Result<&'static mut BalloonDevice, BalloonError> {
+    unsafe { (*addr_of_mut!(BALLOON)).as_mut().ok_or(BalloonError::NoDevice) }
+}
+
+/// Registra la función que calcula las estadísticas de memoria del guest.
+pub fn set_stats_provider(provider: fn() -> MemStats) {
+    unsafe { STATS_PROVIDER = Some(provider); }
+}
+
+/// Estadísticas actuales del proveedor registrado (ceros si no hay ninguno).
+pub fn mem_stats() -> MemStats {
+    match unsafe { STATS_PROVIDER } {
+        Some(provider) => provider(),
+        None => MemStats::default(),
+    }
+}
+
+fn fill_stats_buf() -> &'static [u8] {
+    let stats = mem_stats();
+    let buf = unsafe { &mut (*addr_of_mut!(STATS_BUF)).0 };
+    let entries = [
+        (VIRTIO_BALLOON_S_MEMFREE, stats.free_bytes),
+        (VIRTIO_BALLOON_S_MEMTOT, stats.total_bytes),
+        (VIRTIO_BALLOON_S_AVAIL, stats.free_bytes),
+        // El modelo copiado se puede volver a leer del sistema de ficheros, como una caché
+        (VIRTIO_BALLOON_S_CACHES, stats.model_bytes),
+    ];
+    for (i, (tag, value)) in entries.iter().enumerate() {
+        let off = i * STAT_LEN;
+        buf[off..off + 2].copy_from_slice(&tag.to_le_bytes());
+        buf[off + 2..off + 10].copy_from_slice(&value.to_le_bytes());
+    }
+    buf
+}
+
+/// Entrega al dispositivo la lista de PFN de 4KiB que cubre el frame `addr` y espera la confirmación.
+fn send_pfns(vq: &mut VirtQueue, addr: usize) -> Result<(), BalloonError> {
+    let pfns = unsafe { &mut *addr_of_mut!(PFN_BUF) };
+    let first = (dma::virt_to_phys(addr) / BALLOON_PAGE_SIZE as u64) as u32;
+    for (i, pfn) in pfns.iter_mut().enumerate() {
+        *pfn = first + i as u32;
+    }
+    let bytes = unsafe { core::slice::from_raw_parts(pfns.as_ptr() as *const u8, core::mem::size_of_val(pfns)) };
+    let head = vq.add(&[Segment::readable(bytes)]).ok_or(BalloonError::Timeout)?;
+    vq.notify();
+    vq.wait_used(head, DEFAULT_SPIN_LIMIT).map(|_| ()).ok_or(BalloonError::Timeout)
+}
+
+impl BalloonDevice {
+    fn target_pages(&self) -> usize {
+        self.transport.config_read32(CONFIG_NUM_PAGES) as usize
+    }
+
+    fn actual_pages(&self) -> usize {
+        self.num_frames * PAGES_PER_FRAME
+    }
+
+    fn update_actual(&self) {
+        self.transport.config_write32(CONFIG_ACTUAL, self.actual_pages() as u32);
+    }
+
+    fn inflate_one(&mut self) -> Result<bool, BalloonError> {
+        if self.num_frames == MAX_BALLOON_FRAMES {
+            return Ok(false);
+        }
+        let Some(addr) = hal().alloc_frame() else { return Ok(false) };
+        if let Err(e) = send_pfns(&mut self.inflate, addr) {
+            hal().free_frame(addr);
+            return Err(e);
+        }
+        self.frames[self.num_frames] = addr;
+        self.num_frames += 1;
+        Ok(true)
+    }
+
+    fn deflate_one(&mut self) -> Result<bool, BalloonError> {
+        if self.num_frames == 0 {
+            return Ok(false);
+        }
+        let addr = self.frames[self.num_frames - 1];
+        // Con MUST_TELL_HOST el host debe saberlo antes de que el guest vuelva a usar el frame
+        send_pfns(&mut self.deflate, addr)?;
+        self.num_frames -= 1;
+        hal().free_frame(addr);
+        Ok(true)
+    }
+
+    /// Infla o desinfla en frames completos hasta acercarse lo más posible a `num_pages`.
+    fn resize(&mut self) -> Result<(), BalloonError> {
+        let target = self.target_pages();
+        let start = self.num_frames;
+        let result = (|| {
+            while self.actual_pages() + PAGES_PER_FRAME <= target {
+                if !self.inflate_one()? {
+                    break; // sin frames libres: el globo se queda por debajo del objetivo
+                }
+            }
+            while self.actual_pages() > target {
+                self.deflate_one()?;
+            }
+            Ok(())
+        })();
+        if self.num_frames != start {
+            self.update_actual();
+        }
+        result
+    }
+
+    fn service_stats(&mut self) {
+        let Some(vq) = self.stats.as_mut() else { return };
+        // El dispositivo devuelve el buffer cuando quiere estadísticas nuevas
+        if vq.pop_used().is_some() {
+            let buf = fill_stats_buf();
+            if vq.add(&[Segment::readable(buf)]).is_some() {
+                vq.notify();
+            }
+        }
+    }
+
+    /// Notifica al host hasta `REPORT_BATCH` frames libres no notificados todavía.
+    fn report_free_frames(&mut self) -> usize {
+        let Some(vq) = self.reporting.as_mut() else { return 0 };
+        let mut frames = [0usize; REPORT_BATCH];
+        let mut n = 0;
+        while n < REPORT_BATCH {
+            match hal().take_unreported_free_frame() {
+                Some(addr) => {
+                    frames[n] = addr;
+                    n += 1;
+                }
+                None => break,
+            }
+        }
+        if n == 0 {
+            return 0;
+        }
+        let mut segments = [Segment::EMPTY; REPORT_BATCH];
+        for (seg, &addr) in segments.iter_mut().zip(&frames[..n]) {
+            *seg = Segment::physical(dma::virt_to_phys(addr), FRAME_SIZE as u32, true);
+        }
+        let reported = match vq.add(&segments[..n]) {
+            Some(head) => {
+                vq.notify();
+                vq.wait_used(head, DEFAULT_SPIN_LIMIT).is_some()
+            }
+            None => false,
+        };
+        for &addr in &frames[..n] {
+            // Si el dispositivo no respondió, el frame vuelve como libre sin notificar
+            if reported {
+                hal().release_reported_frame(addr);
+            } else {
+                hal().free_frame(addr);
+            }
+        }
+        if reported { n } else { 0 }
+    }
+
+    /// Tras un reset completo el host ya no conoce el globo: se vuelven a entregar
+    /// los frames retenidos y el buffer de estadísticas.
+    fn restore(&mut self) -> Result<(), BalloonError> {
+        if let Some(vq) = self.stats.as_mut() {
+            if vq.add(&[Segment::readable(fill_stats_buf())]).is_some() {
+                vq.notify();
+            }
+        }
+        for i in 0..self.num_frames {
+            send_pfns(&mut self.inflate, self.frames[i])?;
+        }
+        self.update_actual();
+        Ok(())
+    }
+}
+
+pub static DRIVER: Driver = Driver {
+    name: "virtio-balloon",
+    matches: &[Match::Virtio(VIRTIO_ID_BALLOON)],
+    max_instances: 1,
+    probe,
+    remove,
+};
+
+fn probe(dev: VirtioDevice) -> Result<usize, &'static str> {
+    attach(dev).map(|()| 0).map_err(|e| e.as_str())
+}
+
+fn remove(_instance: usize) {
+    // Sin dispositivo nadie reclama ya esas páginas: vuelven al allocator
+    if let Ok(balloon) = device() {
+        for &addr in &balloon.frames[..balloon.num_frames] {
+            hal().free_frame(addr);
+        }
+    }
+    unsafe { BALLOON = None; }
+}
+
+/// Configura las colas del dispositivo virtio-balloon y aplica el objetivo inicial.
+fn attach(dev: VirtioDevice) -> Result<(), BalloonError> {
+    let mut transport = VirtioPci::new(dev).map_err(BalloonError::Transport)?;
+    let supported = VIRTIO_BALLOON_F_MUST_TELL_HOST | VIRTIO_BALLOON_F_STATS_VQ | VIRTIO_BALLOON_F_PAGE_REPORTING;
+    let features = transport.begin_init(supported).map_err(BalloonError::Transport)?;
+    // Las colas opcionales solo existen si se negoció su feature y se numeran sin huecos
+    let setup = (|| -> Result<_, TransportError> {
+        let inflate = transport.setup_queue(0, QUEUE_SIZE)?;
+        let deflate = transport.setup_queue(1, QUEUE_SIZE)?;
+        let mut next = 2;
+        let stats = if features & VIRTIO_BALLOON_F_STATS_VQ != 0 {
+            next += 1;
+            Some(transport.setup_queue(next - 1, QUEUE_SIZE)?)
+        } else {
+            None
+        };
+        let reporting = if features & VIRTIO_BALLOON_F_PAGE_REPORTING != 0 {
+            Some(transport.setup_queue(next, QUEUE_SIZE)?)
+        } else {
+            None
+        };
+        Ok((inflate, deflate, stats, reporting))
+    })();
+    let (mut inflate, mut deflate, stats, mut reporting) = match setup {
+        Ok(queues) => queues,
+        Err(e) => {
+            transport.fail();
+            return Err(BalloonError::Transport(e));
+        }
+    };
+    transport.finish_init();
+    // La cola de estadísticas no se vigila: su buffer queda en manos del dispositivo
+    // hasta que el host pide estadísticas nuevas
+    inflate.enable_watchdog();
+    deflate.enable_watchdog();
+    if let Some(vq) = reporting.as_mut() {
+        vq.enable_watchdog();
+    }
+
+    let mut balloon = BalloonDevice {
+        transport,
+        inflate,
+        deflate,
+        stats,
+        reporting,
+        frames: [0; MAX_BALLOON_FRAMES],
+        num_frames: 0,
+    };
+    // El primer buffer de estadísticas se entrega en cuanto el dispositivo está listo
+    if let Some(vq) = balloon.stats.as_mut() {
+        if vq.add(&[Segment::readable(fill_stats_buf())]).is_some() {
+            vq.notify();
+        }
+    }
+    balloon.update_actual();
+    unsafe { BALLOON = Some(balloon); }
+    watchdog::register("virtio-balloon", watchdog_stalled, watchdog_recover);
+    device()?.resize()
+}
+
+/// Atiende al dispositivo: ajusta el globo al objetivo del host, responde a las
+/// peticiones de estadísticas y notifica frames libres. Se llama desde el scheduler.
+pub fn poll() -> Result<(), BalloonError> {
+    let balloon = device()?;
+    balloon.service_stats();
+    if balloon.target_pages() != balloon.actual_pages() {
+        balloon.resize()?;
+    }
+    balloon.report_free_frames();
+    Ok(())
+}
+
+fn watchdog_stalled(deadline: u64) -> bool {
+    device().is_ok_and(|b| {
+        b.inflate.stalled(deadline) || b.deflate.stalled(deadline) || b.reporting.as_ref().is_some_and(|vq| vq.stalled(deadline))
+    })
+}
+
+fn watchdog_recover() -> Result<Recovery, &'static str> {
+    let balloon = device().map_err(|e| e.as_str())?;
+    let BalloonDevice { transport, inflate, deflate, stats, reporting, .. } = balloon;
+    let recovery = watchdog::recover_device(transport, |f| {
+        f(inflate)?;
+        f(deflate)?;
+        if let Some(vq) = stats.as_mut() {
+            f(vq)?;
+        }
+        match reporting.as_mut() {
+            Some(vq) => f(vq),
+            None => Ok(()),
+        }
+    })
+    .map_err(|e| BalloonError::Transport(e).as_str())?;
+    if recovery == Recovery::DeviceReset {
+        balloon.restore().map_err(|e| e.as_str())?;
+    }
+    Ok(recovery)
+}
+
+/// Bytes entregados al host actualmente.
+pub fn inflated_bytes() -> usize {
+    device().map(|b| b.num_frames * FRAME_SIZE).unwrap_or(0)
+}
+
+/// `true` si el dispositivo negoció la notificación de páginas libres.
+pub fn reporting_enabled() -> bool {
+    device().is_ok_and(|b| b.reporting.is_some())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{self, balloon::BalloonBackend, FrameState};
+    use FrameState::{Free, Reported, Used};
+
+    fn install(backend: BalloonBackend, frames: &[FrameState]) -> std::sync::MutexGuard<'static, ()> {
+        let guard = mock::install(backend);
+        mock::set_frames(frames);
+        unsafe { BALLOON = None; }
+        assert_eq!(probe(mock::pci_device()), Ok(0));
+        guard
+    }
+
+    /// PFN de 4KiB de los frames `indices` del pool simulado.
+    fn pfns_of(indices: &[usize]) -> Vec<u32> {
+        indices
+            .iter()
+            .flat_map(|&i| {
+                let first = (mock::frame_addr(i) / BALLOON_PAGE_SIZE) as u32;
+                first..first + PAGES_PER_FRAME as u32
+            })
+            .collect()
+    }
+
+    #[test]
+    fn inflate_never_takes_reserved_frames() {
+        // Frames 0, 1 y 4: memoria baja e imagen del kernel, reservados en el arranque
+        let frames = [Used, Used, Free, Free, Used, Free];
+        let _guard = install(BalloonBackend::new(3 * PAGES_PER_FRAME as u32), &frames);
+        assert_eq!(inflated_bytes(), 3 * FRAME_SIZE);
+        assert_eq!(mock::frames(), [Used; 6]);
+        mock::with_backend(|b: &mut BalloonBackend| {
+            assert_eq!(b.inflated, pfns_of(&[2, 3, 5]));
+            assert_eq!(b.actual, 3 * PAGES_PER_FRAME as u32);
+            // Más de lo que hay libre: el globo se queda por debajo del objetivo
+            b.num_pages = 8 * PAGES_PER_FRAME as u32;
+        });
+        poll().unwrap();
+        assert_eq!(inflated_bytes(), 3 * FRAME_SIZE);
+
+        // Desinflar devuelve al allocator solo los frames que el globo tomó
+        mock::with_backend(|b: &mut BalloonBackend| b.num_pages = PAGES_PER_FRAME as u32);
+        poll().unwrap();
+        assert_eq!(mock::frames(), [Used, Used, Used, Free, Used, Free]);
+        mock::with_backend(|b: &mut BalloonBackend| {
+            assert_eq!(b.inflated, pfns_of(&[2]));
+            assert_eq!(b.actual, PAGES_PER_FRAME as u32);
+        });
+        remove(0);
+        assert_eq!(mock::frames(), [Used, Used, Free, Free, Used, Free]);
+    }
+
+    #[test]
+    fn reporting_skips_used_and_reported_frames() {
+        let mut backend = BalloonBackend::new(0);
+        backend.reporting = true;
+        let _guard = install(backend, &[Used, Free, Used, Reported, Free]);
+        assert!(reporting_enabled());
+        poll().unwrap();
+        assert_eq!(mock::frames(), [Used, Reported, Used, Reported, Reported]);
+        let expected = [(mock::frame_addr(1) as u64, FRAME_SIZE as u32), (mock::frame_addr(4) as u64, FRAME_SIZE as u32)];
+        mock::with_backend(|b: &mut BalloonBackend| assert_eq!(b.reported, expected));
+        // Nada nuevo que notificar
+        poll().unwrap();
+        mock::with_backend(|b: &mut BalloonBackend| assert_eq!(b.reported.len(), 2));
+        // Un frame reutilizado y liberado vuelve a notificarse
+        hal().free_frame(mock::frame_addr(3));
+        poll().unwrap();
+        mock::with_backend(|b: &mut BalloonBackend| assert_eq!(b.reported[2], (mock::frame_addr(3) as u64, FRAME_SIZE as u32)));
+    }
+}
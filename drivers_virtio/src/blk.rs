@@ -0,0 +1,724 @@
+//! Driver virtio-blk: almacén alternativo de modelos y datos cuando no hay virtiofsd
+//! (por ejemplo, en Firecracker).
+//!
+//! Cada petición es una cadena `virtio_blk_req` (cabecera legible) + datos + byte de
+//! estado (escrito por el dispositivo). Las cabeceras y estados viven en una tabla de
+//! slots propia del dispositivo, lo que permite tener varias peticiones en vuelo.
+
+use crate::devmgr::{Driver, Match};
+use crate::pci;
+use crate::transport::{Recovery, TransportError, VirtioPci, DEFAULT_QUEUE_SIZE};
+use crate::virtqueue::{Segment, VirtQueue, DEFAULT_SPIN_LIMIT, WATCHDOG_DEADLINE_TSC};
+use crate::watchdog;
+use core::fmt;
+use logging::events::{self, Category, Event, EventKind};
+
+pub const VIRTIO_ID_BLOCK: u16 = 2;
+
+// Features
+const VIRTIO_BLK_F_SIZE_MAX: u64 = 1 << 1;
+const VIRTIO_BLK_F_SEG_MAX: u64 = 1 << 2;
+const VIRTIO_BLK_F_RO: u64 = 1 << 5;
+const VIRTIO_BLK_F_BLK_SIZE: u64 = 1 << 6;
+const VIRTIO_BLK_F_FLUSH: u64 = 1 << 9;
+
+// Tipos de petición
+const VIRTIO_BLK_T_IN: u32 = 0;
+const VIRTIO_BLK_T_OUT: u32 = 1;
+const VIRTIO_BLK_T_FLUSH: u32 = 4;
+const VIRTIO_BLK_T_GET_ID: u32 = 8;
+
+// Estados
+const VIRTIO_BLK_S_OK: u8 = 0;
+const VIRTIO_BLK_S_IOERR: u8 = 1;
+const VIRTIO_BLK_S_UNSUPP: u8 = 2;
+/// Valor centinela mientras el dispositivo no ha escrito el estado.
+const STATUS_PENDING: u8 = 0xFF;
+
+// Offsets en virtio_blk_config
+const CONFIG_CAPACITY: usize = 0;
+const CONFIG_SIZE_MAX: usize = 8;
+const CONFIG_BLK_SIZE: usize = 20;
+
+/// Los sectores de virtio-blk son siempre de 512 bytes, con independencia de `blk_size`.
+pub const SECTOR_SIZE: usize = 512;
+pub const DEVICE_ID_LEN: usize = 20;
+/// Peticiones simultáneas por dispositivo.
+const MAX_IN_FLIGHT: usize = 32;
+/// Tamaño máximo de transferencia por petición si el dispositivo no anuncia SIZE_MAX.
+const DEFAULT_MAX_TRANSFER: usize = 128 * 1024;
+const MAX_DEVICES: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlkError {
+    /// No hay dispositivo en ese índice o se ha retirado.
+    NoDevice,
+    Transport(TransportError),
+    Timeout,
+    /// No quedan slots ni descriptores para más peticiones en vuelo.
+    QueueFull,
+    /// VIRTIO_BLK_S_IOERR
+    IoError,
+    /// VIRTIO_BLK_S_UNSUPP o feature no negociada.
+    Unsupported,
+    ReadOnly,
+    /// Acceso más allá de la capacidad del dispositivo.
+    OutOfRange,
+    /// Buffer que no es múltiplo del tamaño de bloque.
+    Misaligned,
+}
+
+impl BlkError {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BlkError::NoDevice => "virtio-blk: no device",
+            BlkError::Transport(_) => "virtio-blk: transport error",
+            BlkError::Timeout => "virtio-blk: timeout",
+            BlkError::QueueFull => "virtio-blk: queue full",
+            BlkError::IoError => "virtio-blk: I/O error",
+            BlkError::Unsupported => "virtio-blk: unsupported request",
+            BlkError::ReadOnly => "virtio-blk: read-only device",
+            BlkError::OutOfRange => "virtio-blk: out of range",
+            BlkError::Misaligned => "virtio-blk: misaligned buffer",
+        }
+    }
+}
+
+impl fmt::Display for BlkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Dispositivo de bloques genérico sobre el que se montan sistemas de ficheros de solo
+/// lectura o el cargador de particiones de modelos. `lba` se expresa en bloques de
+/// `block_size()` bytes.
+pub trait BlockDevice {
+    fn block_size(&self) -> usize;
+    fn num_blocks(&self) -> u64;
+    fn read_only(&self) -> bool;
+    fn read_blocks(&mut self, lba: u64, buf: &mut [u8]) -> Result<(), BlkError>;
+    fn write_blocks(&mut self, lba: u64, buf: &[u8]) -> Result<(), BlkError>;
+    fn flush(&mut self) -> Result<(), BlkError>;
+}
+
+/// Identificador de una petición en vuelo devuelto por `submit_*`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RequestToken(usize);
+
+#[derive(Clone, Copy)]
+struct RequestSlot {
+    header: [u8; 16],
+    status: u8,
+    head: u16,
+    len: usize,
+    in_use: bool,
+    /// El dispositivo ya devolvió la cadena; el resultado espera a que lo recojan.
+    completed: bool,
+    /// La petición se perdió en una recuperación del watchdog y se completa con error.
+    aborted: bool,
+}
+
+const EMPTY_SLOT: RequestSlot =
+    RequestSlot { header: [0; 16], status: STATUS_PENDING, head: 0, len: 0, in_use: false, completed: false, aborted: false };
+
+pub struct VirtioBlk {
+    transport: VirtioPci,
+    queue: VirtQueue,
+    capacity: u64,
+    block_size: usize,
+    max_transfer: usize,
+    features: u64,
+    slots: [RequestSlot; MAX_IN_FLIGHT],
+    /// El dispositivo se retiró del bus. La entrada no se libera ni se reutiliza porque
+    /// los sistemas de ficheros montados guardan referencias `'static` a ella.
+    removed: bool,
+}
+
+static mut BLK_DEVICES: [Option<VirtioBlk>; MAX_DEVICES] = [None, None, None, None];
+
+pub static DRIVER: Driver = Driver {
+    name: "virtio-blk",
+    matches: &[Match::Virtio(VIRTIO_ID_BLOCK)],
+    max_instances: MAX_DEVICES,
+    probe,
+    remove,
+};
+
+/// Inicializa el dispositivo en la primera entrada libre; su índice es el de [`device`].
+fn probe(dev: pci::VirtioDevice) -> Result<usize, &'static str> {
+    let devices = unsafe { &mut *core::ptr::addr_of_mut!(BLK_DEVICES) };
+    let index = devices.iter().position(|d| d.is_none()).ok_or("virtio-blk: no free slot")?;
+    devices[index] = Some(VirtioBlk::new(dev).map_err(|e| e.as_str())?);
+    watchdog::register("virtio-blk", watchdog_stalled, watchdog_recover);
+    Ok(index)
+}
+
+/// Las peticiones en vuelo se completan con [`BlkError::IoError`] y las nuevas fallan
+/// con [`BlkError::NoDevice`].
+fn remove(index: usize) {
+    let devices = unsafe { &mut *core::ptr::addr_of_mut!(BLK_DEVICES) };
+    if let Some(blk) = devices.get_mut(index).and_then(|d| d.as_mut()) {
+        blk.removed = true;
+        for slot in blk.slots.iter_mut().filter(|s| s.in_use) {
+            slot.aborted = true;
+        }
+    }
+}
+
+/// Dispositivo virtio-blk número `index` (en orden de descubrimiento). `None` si se
+/// ha retirado.
+pub fn device(index: usize) -> Option<&'static mut VirtioBlk> {
+    unsafe { (*core::ptr::addr_of_mut!(BLK_DEVICES)).get_mut(index)?.as_mut().filter(|blk| !blk.removed) }
+}
+
+impl VirtioBlk {
+    /// Solo para `probe`: las cabeceras y estados de `slots` son destino de DMA, así
+    /// que el valor se mueve a `BLK_DEVICES` antes de publicar ninguna petición y ya
+    /// no se mueve de ahí.
+    fn new(dev: pci::VirtioDevice) -> Result<Self, BlkError> {
+        let mut transport = VirtioPci::new(dev).map_err(BlkError::Transport)?;
+        let supported = VIRTIO_BLK_F_SIZE_MAX
+            | VIRTIO_BLK_F_SEG_MAX
+            | VIRTIO_BLK_F_RO
+            | VIRTIO_BLK_F_BLK_SIZE
+            | VIRTIO_BLK_F_FLUSH;
+        let features = transport.begin_init(supported).map_err(BlkError::Transport)?;
+        let mut queue = match transport.setup_queue(0, DEFAULT_QUEUE_SIZE) {
+            Ok(queue) => queue,
+            Err(e) => {
+                transport.fail();
+                return Err(BlkError::Transport(e));
+            }
+        };
+        transport.finish_init();
+        queue.enable_watchdog();
+
+        let capacity = transport.config_read64(CONFIG_CAPACITY);
+        let mut block_size = SECTOR_SIZE;
+        if features & VIRTIO_BLK_F_BLK_SIZE != 0 {
+            let size = transport.config_read32(CONFIG_BLK_SIZE) as usize;
+            if size >= SECTOR_SIZE && size.is_power_of_two() {
+                block_size = size;
+            }
+        }
+        let mut max_transfer = DEFAULT_MAX_TRANSFER;
+        if features & VIRTIO_BLK_F_SIZE_MAX != 0 {
+            let size_max = transport.config_read32(CONFIG_SIZE_MAX) as usize;
+            if size_max >= block_size {
+                max_transfer = max_transfer.min(size_max & !(block_size - 1));
+            }
+        }
+        Ok(VirtioBlk {
+            transport,
+            queue,
+            capacity,
+            block_size,
+            max_transfer,
+            features,
+            slots: [EMPTY_SLOT; MAX_IN_FLIGHT],
+            removed: false,
+        })
+    }
+
+    /// Capacidad en sectores de 512 bytes.
+    pub fn capacity_sectors(&self) -> u64 {
+        self.capacity
+    }
+
+    /// Relee la capacidad tras una interrupción de cambio de configuración (redimensionado).
+    pub fn refresh_capacity(&mut self) -> u64 {
+        self.capacity = self.transport.config_read64(CONFIG_CAPACITY);
+        self.capacity
+    }
+
+    pub fn supports_flush(&self) -> bool {
+        self.features & VIRTIO_BLK_F_FLUSH != 0
+    }
+
+    fn submit(&mut self, kind: u32, sector: u64, data: Option<Segment>) -> Result<RequestToken, BlkError> {
+        if self.removed {
+            return Err(BlkError::NoDevice);
+        }
+        let slot_idx = self.slots.iter().position(|s| !s.in_use).ok_or(BlkError::QueueFull)?;
+        let slot = &mut self.slots[slot_idx];
+        slot.header = [0; 16];
+        slot.header[0..4].copy_from_slice(&kind.to_le_bytes());
+        slot.header[8..16].copy_from_slice(&sector.to_le_bytes());
+        slot.status = STATUS_PENDING;
+        slot.len = data.map(|d| d.len as usize).unwrap_or(0);
+
+        let header = Segment::readable(&slot.header);
+        let status = Segment::writable(core::slice::from_mut(&mut slot.status));
+        let head = match data {
+            Some(data) => self.queue.add(&[header, data, status]),
+            None => self.queue.add(&[header, status]),
+        }
+        .ok_or(BlkError::QueueFull)?;
+        let slot = &mut self.slots[slot_idx];
+        slot.head = head;
+        slot.in_use = true;
+        slot.completed = false;
+        slot.aborted = false;
+        self.queue.notify();
+        Ok(RequestToken(slot_idx))
+    }
+
+    fn check_range(&self, sector: u64, len: usize) -> Result<(), BlkError> {
+        if len == 0 || !len.is_multiple_of(SECTOR_SIZE) {
+            return Err(BlkError::Misaligned);
+        }
+        let end = sector.checked_add((len / SECTOR_SIZE) as u64).ok_or(BlkError::OutOfRange)?;
+        if end > self.capacity {
+            return Err(BlkError::OutOfRange);
+        }
+        Ok(())
+    }
+
+    /// Encola una lectura a partir de `sector` sin esperar a que termine.
+    ///
+    /// # Safety
+    /// `buf` debe seguir siendo válido y no usarse hasta que la petición se complete
+    /// (vía [`VirtioBlk::poll`] o [`VirtioBlk::wait`]).
+    pub unsafe fn submit_read(&mut self, sector: u64, buf: &mut [u8]) -> Result<RequestToken, BlkError> {
+        self.check_range(sector, buf.len())?;
+        self.submit(VIRTIO_BLK_T_IN, sector, Some(Segment::writable(buf)))
+    }
+
+    /// Encola una escritura a partir de `sector` sin esperar a que termine.
+    ///
+    /// # Safety
+    /// `buf` debe seguir siendo válido hasta que la petición se complete.
+    pub unsafe fn submit_write(&mut self, sector: u64, buf: &[u8]) -> Result<RequestToken, BlkError> {
+        if self.features & VIRTIO_BLK_F_RO != 0 {
+            return Err(BlkError::ReadOnly);
+        }
+        self.check_range(sector, buf.len())?;
+        self.submit(VIRTIO_BLK_T_OUT, sector, Some(Segment::readable(buf)))
+    }
+
+    /// Pasa las cadenas devueltas por el dispositivo a sus slots, que conservan el
+    /// resultado hasta que se recoja con [`VirtioBlk::poll`] o [`VirtioBlk::wait`].
+    fn reap(&mut self) {
+        while let Some(elem) = self.queue.pop_used() {
+            let Some(slot) = self.slots.iter_mut().find(|s| s.in_use && !s.completed && s.head as u32 == elem.id) else {
+                continue;
+            };
+            slot.completed = true;
+            events::record(Event {
+                desc: slot.head,
+                op: u32::from_le_bytes([slot.header[0], slot.header[1], slot.header[2], slot.header[3]]),
+                bytes: slot.len as u32,
+                latency: self.queue.last_latency(),
+                error: slot.status as i32,
+                ..self.queue.event(Category::Blk, EventKind::Request)
+            });
+        }
+    }
+
+    /// Resultado de la petición `idx` si ya terminó (el slot queda libre).
+    fn take(&mut self, idx: usize) -> Option<Result<usize, BlkError>> {
+        let slot = self.slots.get_mut(idx).filter(|s| s.in_use && (s.completed || s.aborted))?;
+        slot.in_use = false;
+        if !slot.completed {
+            return Some(Err(BlkError::IoError));
+        }
+        Some(match slot.status {
+            VIRTIO_BLK_S_OK => Ok(slot.len),
+            VIRTIO_BLK_S_UNSUPP => Err(BlkError::Unsupported),
+            VIRTIO_BLK_S_IOERR => Err(BlkError::IoError),
+            _ => Err(BlkError::IoError),
+        })
+    }
+
+    /// Recoge una petición completada, si la hay.
+    pub fn poll(&mut self) -> Option<(RequestToken, Result<usize, BlkError>)> {
+        self.reap();
+        let idx = self.slots.iter().position(|s| s.in_use && (s.completed || s.aborted))?;
+        self.take(idx).map(|result| (RequestToken(idx), result))
+    }
+
+    /// Espera a que se complete `token`. Las demás peticiones que terminen mientras tanto
+    /// conservan su resultado para [`VirtioBlk::poll`].
+    pub fn wait(&mut self, token: RequestToken) -> Result<usize, BlkError> {
+        self.wait_all(core::slice::from_ref(&token)).and_then(|results| results[0])
+    }
+
+    /// Espera a todas las peticiones de `tokens` y devuelve sus resultados en el mismo
+    /// orden. Si el dispositivo no responde a tiempo se recupera la cola antes de
+    /// devolver [`BlkError::Timeout`], porque los buffers del llamador siguen publicados.
+    fn wait_all(&mut self, tokens: &[RequestToken]) -> Result<[Result<usize, BlkError>; MAX_IN_FLIGHT], BlkError> {
+        let mut results = [Err(BlkError::IoError); MAX_IN_FLIGHT];
+        let mut pending = tokens.len();
+        let mut taken = [false; MAX_IN_FLIGHT];
+        let mut spins = 0;
+        while pending > 0 {
+            self.reap();
+            let mut progress = false;
+            for (i, token) in tokens.iter().enumerate() {
+                if taken[i] {
+                    continue;
+                }
+                if let Some(result) = self.take(token.0) {
+                    results[i] = result;
+                    taken[i] = true;
+                    pending -= 1;
+                    progress = true;
+                }
+            }
+            if progress {
+                continue;
+            }
+            spins += 1;
+            if spins >= DEFAULT_SPIN_LIMIT {
+                self.abort_stalled();
+                for token in tokens {
+                    self.slots[token.0].in_use = false;
+                }
+                return Err(BlkError::Timeout);
+            }
+            core::hint::spin_loop();
+        }
+        Ok(results)
+    }
+
+    /// Recupera la cola tras agotar el tiempo de espera de una petición síncrona.
+    fn abort_stalled(&mut self) {
+        self.queue.mark_stalled();
+        let result = self.recover().map_err(|e| BlkError::Transport(e).as_str());
+        watchdog::report("virtio-blk", result);
+    }
+
+    /// Divide la transferencia en peticiones de hasta `max_transfer` bytes, todas en vuelo a la vez.
+    fn transfer(&mut self, sector: u64, buf: *mut u8, len: usize, write: bool) -> Result<(), BlkError> {
+        self.check_range(sector, len)?;
+        let mut tokens = [RequestToken(0); MAX_IN_FLIGHT];
+        let mut done = 0;
+        while done < len {
+            let mut n = 0;
+            while done < len && n < MAX_IN_FLIGHT {
+                let chunk = (len - done).min(self.max_transfer);
+                let chunk_sector = sector + (done / SECTOR_SIZE) as u64;
+                let submitted = unsafe {
+                    let part = core::slice::from_raw_parts_mut(buf.add(done), chunk);
+                    if write {
+                        self.submit_write(chunk_sector, part)
+                    } else {
+                        self.submit_read(chunk_sector, part)
+                    }
+                };
+                match submitted {
+                    Ok(token) => {
+                        tokens[n] = token;
+                        n += 1;
+                        done += chunk;
+                    }
+                    // Cola llena: se espera a las que ya están en vuelo y se sigue
+                    Err(BlkError::QueueFull) if n > 0 => break,
+                    Err(e) => {
+                        // El error del envío prevalece sobre el de las ya enviadas
+                        let _ = self.wait_all(&tokens[..n])?;
+                        return Err(e);
+                    }
+                }
+            }
+            let results = self.wait_all(&tokens[..n])?;
+            if let Some(&Err(e)) = results[..n].iter().find(|r| r.is_err()) {
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Lee el identificador del dispositivo (VIRTIO_BLK_T_GET_ID, hasta 20 bytes, sin NUL
+    /// final si ocupa los 20).
+    pub fn device_id(&mut self) -> Result<[u8; DEVICE_ID_LEN], BlkError> {
+        let mut id = [0u8; DEVICE_ID_LEN];
+        let token = self.submit(VIRTIO_BLK_T_GET_ID, 0, Some(Segment::writable(&mut id)))?;
+        self.wait(token)?;
+        Ok(id)
+    }
+
+    /// Recupera la cola si está bloqueada. Las peticiones en vuelo se dan por
+    /// perdidas y [`VirtioBlk::poll`] las completa con [`BlkError::IoError`].
+    fn recover(&mut self) -> Result<Recovery, TransportError> {
+        let queue = &mut self.queue;
+        let recovery = watchdog::recover_device(&mut self.transport, |f| f(queue))?;
+        for slot in self.slots.iter_mut().filter(|s| s.in_use && !s.completed) {
+            slot.aborted = true;
+        }
+        Ok(recovery)
+    }
+}
+
+fn watchdog_stalled(deadline: u64) -> bool {
+    let devices = unsafe { &*core::ptr::addr_of!(BLK_DEVICES) };
+    devices.iter().flatten().any(|blk| !blk.removed && blk.queue.stalled(deadline))
+}
+
+fn watchdog_recover() -> Result<Recovery, &'static str> {
+    let devices = unsafe { &mut *core::ptr::addr_of_mut!(BLK_DEVICES) };
+    let mut result = Recovery::QueueReset;
+    for blk in devices.iter_mut().flatten() {
+        if blk.removed || !blk.queue.stalled(WATCHDOG_DEADLINE_TSC) {
+            continue;
+        }
+        if blk.recover().map_err(|e| BlkError::Transport(e).as_str())? == Recovery::DeviceReset {
+            result = Recovery::DeviceReset;
+        }
+    }
+    Ok(result)
+}
+
+impl BlockDevice for VirtioBlk {
+    fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    fn num_blocks(&self) -> u64 {
+        self.capacity / (self.block_size / SECTOR_SIZE) as u64
+    }
+
+    fn read_only(&self) -> bool {
+        self.features & VIRTIO_BLK_F_RO != 0
+    }
+
+    fn read_blocks(&mut self, lba: u64, buf: &mut [u8]) -> Result<(), BlkError> {
+        if !buf.len().is_multiple_of(self.block_size) {
+            return Err(BlkError::Misaligned);
+        }
+        let sector = lba * (self.block_size / SECTOR_SIZE) as u64;
+        self.transfer(sector, buf.as_mut_ptr(), buf.len(), false)
+    }
+
+    fn write_blocks(&mut self, lba: u64, buf: &[u8]) -> Result<(), BlkError> {
+        if self.read_only() {
+            return Err(BlkError::ReadOnly);
+        }
+        if !buf.len().is_multiple_of(self.block_size) {
+            return Err(BlkError::Misaligned);
+        }
+        let sector = lba * (self.block_size / SECTOR_SIZE) as u64;
+        // El dispositivo solo lee el buffer: el puntero mutable no se usa para escribir
+        self.transfer(sector, buf.as_ptr() as *mut u8, buf.len(), true)
+    }
+
+    fn flush(&mut self) -> Result<(), BlkError> {
+        if !self.supports_flush() {
+            // Sin VIRTIO_BLK_F_FLUSH el dispositivo es write-through
+            return Ok(());
+        }
+        let token = self.submit(VIRTIO_BLK_T_FLUSH, 0, None)?;
+        self.wait(token).map(|_| ())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{self, blk::BlkBackend};
+    use crate::transport::VIRTIO_F_RING_RESET;
+
+    fn install(backend: BlkBackend) -> std::sync::MutexGuard<'static, ()> {
+        let guard = mock::install(backend);
+        unsafe { BLK_DEVICES = [None, None, None, None]; }
+        assert_eq!(probe(mock::pci_device()), Ok(0));
+        guard
+    }
+
+    #[test]
+    fn init_reads_capacity_and_features() {
+        let _guard = install(BlkBackend::new(64));
+        let blk = device(0).unwrap();
+        assert_eq!(blk.capacity_sectors(), 64);
+        assert_eq!(blk.num_blocks(), 64);
+        assert!(blk.supports_flush());
+        assert!(!blk.read_only());
+        assert!(device(1).is_none());
+        assert_ne!(mock::status() & crate::transport::STATUS_DRIVER_OK, 0);
+    }
+
+    #[test]
+    fn read_write_round_trip() {
+        let mut backend = BlkBackend::new(64);
+        // Fuerza a dividir las transferencias en varias peticiones
+        backend.size_max = 1024;
+        let _guard = install(backend);
+        let blk = device(0).unwrap();
+        let data: Vec<u8> = (0..4096).map(|i| (i % 253) as u8).collect();
+        blk.write_blocks(3, &data).unwrap();
+        let mut back = vec![0u8; 4096];
+        blk.read_blocks(3, &mut back).unwrap();
+        assert_eq!(back, data);
+        mock::with_backend(|b: &mut BlkBackend| {
+            assert_eq!(&b.disk[3 * SECTOR_SIZE..3 * SECTOR_SIZE + 4096], &data[..]);
+            assert_eq!(b.requests, 8);
+        });
+    }
+
+    #[test]
+    fn scattered_buffers_use_bounce() {
+        let _guard = install(BlkBackend::new(64));
+        mock::set_scattered(true);
+        let blk = device(0).unwrap();
+        let data: Vec<u8> = (0..8192).map(|i| (i % 241) as u8).collect();
+        let bounces = logging::metrics::DMA_BOUNCES.get();
+        blk.write_blocks(0, &data).unwrap();
+        let mut back = vec![0u8; 8192];
+        blk.read_blocks(0, &mut back).unwrap();
+        assert_eq!(back, data);
+        assert!(logging::metrics::DMA_BOUNCES.get() >= bounces + 2);
+        assert_eq!(crate::dma::bounce_pages_in_use(), 0);
+    }
+
+    #[test]
+    fn flush_and_device_id() {
+        let _guard = install(BlkBackend::new(8));
+        let blk = device(0).unwrap();
+        blk.flush().unwrap();
+        assert_eq!(mock::with_backend(|b: &mut BlkBackend| b.flushes), 1);
+        let id = blk.device_id().unwrap();
+        assert_eq!(&id[..mock::blk::SERIAL.len()], mock::blk::SERIAL);
+    }
+
+    #[test]
+    fn rejects_bad_ranges() {
+        let mut backend = BlkBackend::new(8);
+        backend.read_only = true;
+        let _guard = install(backend);
+        let blk = device(0).unwrap();
+        let mut buf = vec![0u8; 1024];
+        assert_eq!(blk.read_blocks(7, &mut buf), Err(BlkError::OutOfRange));
+        assert_eq!(blk.read_blocks(0, &mut buf[..100]), Err(BlkError::Misaligned));
+        assert_eq!(blk.write_blocks(0, &buf), Err(BlkError::ReadOnly));
+        // Ninguna llegó al dispositivo
+        assert_eq!(mock::with_backend(|b: &mut BlkBackend| b.requests), 0);
+    }
+
+    #[test]
+    fn device_error_status() {
+        let _guard = install(BlkBackend::new(8));
+        let blk = device(0).unwrap();
+        mock::with_backend(|b: &mut BlkBackend| b.fail_next = true);
+        let mut buf = vec![0u8; 512];
+        assert_eq!(blk.read_blocks(0, &mut buf), Err(BlkError::IoError));
+        // El fallo no deja la cola en mal estado
+        assert_eq!(blk.read_blocks(0, &mut buf), Ok(()));
+    }
+
+    #[test]
+    fn requests_are_traced_when_enabled() {
+        let _guard = install(BlkBackend::new(8));
+        let blk = device(0).unwrap();
+        let mut buf = vec![0u8; 1024];
+        let start = events::head();
+        blk.read_blocks(0, &mut buf).unwrap();
+        assert_eq!(events::head(), start);
+
+        events::enable(Category::Blk);
+        events::enable(Category::Virtqueue);
+        blk.read_blocks(1, &mut buf).unwrap();
+        mock::with_backend(|b: &mut BlkBackend| b.fail_next = true);
+        assert_eq!(blk.read_blocks(0, &mut buf[..512]), Err(BlkError::IoError));
+        events::disable(Category::Blk);
+        events::disable(Category::Virtqueue);
+
+        let mut out = [Event::EMPTY; 8];
+        let (n, _) = events::read_from(start, &mut out);
+        let traced: Vec<(Category, EventKind, u32, i32)> =
+            out[..n].iter().map(|e| (e.category, e.kind, e.bytes, e.error)).collect();
+        assert_eq!(
+            traced,
+            [
+                (Category::Virtqueue, EventKind::Complete, 1025, 0),
+                (Category::Blk, EventKind::Request, 1024, 0),
+                (Category::Virtqueue, EventKind::Complete, 1, 0),
+                (Category::Blk, EventKind::Request, 512, VIRTIO_BLK_S_IOERR as i32),
+            ]
+        );
+        assert!(out[..n].iter().all(|e| e.device_type == VIRTIO_ID_BLOCK && e.queue == 0));
+        assert_eq!(out[1].op, VIRTIO_BLK_T_IN);
+        assert_eq!(out[0].desc, out[1].desc);
+    }
+
+    fn stalled_request_is_aborted(features: u64, expected: Recovery) {
+        let mut backend = BlkBackend::new(8);
+        backend.extra_features = features;
+        let _guard = install(backend);
+        let blk = device(0).unwrap();
+        mock::with_backend(|b: &mut BlkBackend| b.hold = true);
+        let mut buf = vec![0u8; 512];
+        let token = unsafe { blk.submit_read(0, &mut buf) }.unwrap();
+        assert!(blk.poll().is_none());
+        assert!(!blk.queue.stalled(WATCHDOG_DEADLINE_TSC));
+        blk.queue.age(WATCHDOG_DEADLINE_TSC + 1);
+        assert!(blk.queue.stalled(WATCHDOG_DEADLINE_TSC));
+
+        let resets = mock::device_resets();
+        assert_eq!(blk.recover(), Ok(expected));
+        let device_reset = mock::device_resets() > resets;
+        assert_eq!(device_reset, expected == Recovery::DeviceReset);
+        assert_eq!(blk.poll(), Some((token, Err(BlkError::IoError))));
+        assert!(!blk.queue.stalled(0));
+        assert_eq!(blk.poll(), None);
+
+        // La cola recuperada vuelve a funcionar
+        mock::with_backend(|b: &mut BlkBackend| b.hold = false);
+        assert_eq!(blk.read_blocks(0, &mut buf), Ok(()));
+    }
+
+    #[test]
+    fn recovery_with_queue_reset() {
+        stalled_request_is_aborted(VIRTIO_F_RING_RESET, Recovery::QueueReset);
+    }
+
+    #[test]
+    fn recovery_with_device_reset() {
+        stalled_request_is_aborted(0, Recovery::DeviceReset);
+    }
+
+    #[test]
+    fn wait_keeps_other_completions() {
+        let _guard = install(BlkBackend::new(8));
+        let blk = device(0).unwrap();
+        let (mut first, mut second) = (vec![0u8; 512], vec![0u8; 1024]);
+        let a = unsafe { blk.submit_read(0, &mut first) }.unwrap();
+        let b = unsafe { blk.submit_read(1, &mut second) }.unwrap();
+        // Las dos terminan a la vez: esperar a `b` no pierde el resultado de `a`
+        assert_eq!(blk.wait(b), Ok(1024));
+        assert_eq!(blk.poll(), Some((a, Ok(512))));
+        assert_eq!(blk.poll(), None);
+        assert!(blk.slots.iter().all(|s| !s.in_use));
+    }
+
+    fn timeout_resets_queue(features: u64, expected_device_reset: bool) {
+        let mut backend = BlkBackend::new(8);
+        backend.extra_features = features;
+        let _guard = install(backend);
+        let blk = device(0).unwrap();
+        mock::with_backend(|b: &mut BlkBackend| b.hold = true);
+        let resets = mock::device_resets();
+        let mut buf = vec![0u8; 1024];
+        assert_eq!(blk.read_blocks(0, &mut buf), Err(BlkError::Timeout));
+        assert_eq!(blk.device_id(), Err(BlkError::Timeout));
+        assert_eq!(mock::device_resets() > resets, expected_device_reset);
+        // Ningún buffer del llamador queda publicado ni ocupa un slot
+        assert_eq!(blk.queue.in_flight(), 0);
+        assert_eq!(blk.queue.num_free(), DEFAULT_QUEUE_SIZE);
+        assert!(blk.slots.iter().all(|s| !s.in_use));
+        assert_eq!(blk.poll(), None);
+
+        mock::with_backend(|b: &mut BlkBackend| b.hold = false);
+        assert_eq!(blk.read_blocks(0, &mut buf), Ok(()));
+    }
+
+    #[test]
+    fn timeout_with_queue_reset() {
+        timeout_resets_queue(VIRTIO_F_RING_RESET, false);
+    }
+
+    #[test]
+    fn timeout_with_device_reset() {
+        timeout_resets_queue(0, true);
+    }
+}
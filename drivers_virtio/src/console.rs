@@ -0,0 +1,520 @@
+//! Driver virtio-console (virtio 1.x, sección 5.3) con soporte multipuerto.
+//!
+//! Se usan dos puertos: uno para volcar el ring buffer de `logging` (el puerto
+//! llamado [`LOG_PORT_NAME`] o, si no existe, el primer puerto que no sea consola)
+//! y la consola (`virtconsole`, puerto 0) como consola interactiva de depuración.
+//! Sin VIRTIO_CONSOLE_F_MULTIPORT solo existe el puerto 0 y cumple ambos papeles.
+//!
+//! Colas: puerto 0 → rx 0 / tx 1; control → rx 2 / tx 3; puerto n ≥ 1 → rx 2n+2 / tx 2n+3.
+//! El puerto serie sigue siendo la salida durante el arranque temprano y en pánico.
+
+use crate::dma::PageAligned;
+use crate::devmgr::{Driver, Match};
+use crate::pci::VirtioDevice;
+use crate::transport::{Recovery, TransportError, VirtioPci};
+use crate::virtqueue::{Segment, VirtQueue, DEFAULT_SPIN_LIMIT};
+use crate::watchdog;
+use core::fmt;
+use core::ptr::addr_of_mut;
+
+pub const VIRTIO_ID_CONSOLE: u16 = 3;
+
+const VIRTIO_CONSOLE_F_MULTIPORT: u64 = 1 << 1;
+
+// Configuración del dispositivo
+const CONFIG_MAX_NR_PORTS: usize = 4;
+
+// Eventos de la cola de control
+const VIRTIO_CONSOLE_DEVICE_READY: u16 = 0;
+const VIRTIO_CONSOLE_DEVICE_ADD: u16 = 1;
+const VIRTIO_CONSOLE_DEVICE_REMOVE: u16 = 2;
+const VIRTIO_CONSOLE_PORT_READY: u16 = 3;
+const VIRTIO_CONSOLE_CONSOLE_PORT: u16 = 4;
+const VIRTIO_CONSOLE_PORT_OPEN: u16 = 6;
+const VIRTIO_CONSOLE_PORT_NAME: u16 = 7;
+
+/// Nombre del puerto de logs (`-device virtserialport,name=org.microkernelia.log`).
+pub const LOG_PORT_NAME: &str = "org.microkernelia.log";
+
+const MAX_PORTS: usize = 4;
+const QUEUE_SIZE: u16 = 32;
+const RX_BUFS: usize = 8;
+const RX_BUF_SIZE: usize = 256;
+const TX_BUF_SIZE: usize = 4096;
+const CONTROL_MSG_LEN: usize = 8;
+const PORT_NAME_MAX: usize = 64;
+const INPUT_BUF_SIZE: usize = 512;
+/// Espera máxima de una transmisión; el dispositivo suele completarla de inmediato.
+const TX_SPIN_LIMIT: usize = DEFAULT_SPIN_LIMIT / 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConsoleError {
+    /// No hay dispositivo virtio-console o se ha retirado.
+    NoDevice,
+    Transport(TransportError),
+    /// El dispositivo no completó la transmisión a tiempo.
+    Timeout,
+    /// No hay puerto asignado a ese papel (log o depuración).
+    NoPort,
+    /// El host no tiene el puerto abierto.
+    PortClosed,
+}
+
+impl ConsoleError {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ConsoleError::NoDevice => "virtio-console: no device",
+            ConsoleError::Transport(TransportError::MissingCapability) => "virtio-console: missing PCI capability",
+            ConsoleError::Transport(TransportError::FeaturesRejected) => "virtio-console: features rejected",
+            ConsoleError::Transport(TransportError::QueueUnavailable) => "virtio-console: queue unavailable",
+            ConsoleError::Timeout => "virtio-console: timeout",
+            ConsoleError::NoPort => "virtio-console: no such port",
+            ConsoleError::PortClosed => "virtio-console: port closed by host",
+        }
+    }
+}
+
+impl fmt::Display for ConsoleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Cola de recepción con `RX_BUFS` buffers propios siempre publicados.
+struct RxQueue {
+    vq: VirtQueue,
+    /// Índice de buffer para cada cabeza de cadena.
+    buf_of_head: [u8; QUEUE_SIZE as usize],
+    /// Índice en `RX_BUFFERS`.
+    slot: usize,
+}
+
+struct Port {
+    rx: RxQueue,
+    tx: VirtQueue,
+    /// Cadena de transmisión que no se completó a tiempo (se espera antes de reutilizar el buffer).
+    tx_pending: Option<u16>,
+    present: bool,
+    is_console: bool,
+    host_connected: bool,
+    name: [u8; PORT_NAME_MAX],
+    name_len: usize,
+}
+
+impl Port {
+    fn name(&self) -> &str {
+        core::str::from_utf8(&self.name[..self.name_len]).unwrap_or("")
+    }
+}
+
+struct ConsoleDevice {
+    transport: VirtioPci,
+    multiport: bool,
+    ports: [Option<Port>; MAX_PORTS],
+    control_rx: Option<RxQueue>,
+    control_tx: Option<VirtQueue>,
+    log_port: Option<usize>,
+    debug_port: Option<usize>,
+    /// Posición del ring de `logging` enviada ya por el puerto de log.
+    log_cursor: usize,
+}
+
+// Buffers en memoria estática; los de recepción, alineados a página
+static mut RX_BUFFERS: PageAligned<[[[u8; RX_BUF_SIZE]; RX_BUFS]; MAX_PORTS + 1]> =
+    PageAligned([[[0; RX_BUF_SIZE]; RX_BUFS]; MAX_PORTS + 1]);
+static mut TX_BUFFERS: [[u8; TX_BUF_SIZE]; MAX_PORTS] = [[0; TX_BUF_SIZE]; MAX_PORTS];
+static mut CONTROL_TX_BUF: [u8; CONTROL_MSG_LEN] = [0; CONTROL_MSG_LEN];
+
+// Entrada recibida por el puerto de depuración, pendiente de leer
+static mut INPUT_BUF: [u8; INPUT_BUF_SIZE] = [0; INPUT_BUF_SIZE];
+static mut INPUT_HEAD: usize = 0;
+static mut INPUT_TAIL: usize = 0;
+
+static mut CONSOLE: Option<ConsoleDevice> = None;
+
+fn device() -> Result<&'static mut ConsoleDevice, ConsoleError> {
+    unsafe { (*addr_of_mut!(CONSOLE)).as_mut().ok_or(ConsoleError::NoDevice) }
+}
+
+fn rx_buffer(slot: usize, idx: usize) -> &'static mut [u8; RX_BUF_SIZE] {
+    unsafe { &mut (*addr_of_mut!(RX_BUFFERS)).0[slot][idx] }
+}
+
+/// Índices de las colas rx/tx del puerto `port`.
+fn port_queues(port: usize) -> (u16, u16) {
+    let base = if port == 0 { 0 } else { 2 * port as u16 + 2 };
+    (base, base + 1)
+}
+
+impl RxQueue {
+    fn new(vq: VirtQueue, slot: usize) -> Self {
+        RxQueue { vq, buf_of_head: [0; QUEUE_SIZE as usize], slot }
+    }
+
+    fn post(&mut self, idx: usize) {
+        let buf = rx_buffer(self.slot, idx);
+        if let Some(head) = self.vq.add(&[Segment::writable(buf)]) {
+            self.buf_of_head[head as usize] = idx as u8;
+        }
+    }
+
+    fn post_all(&mut self) {
+        for idx in 0..RX_BUFS {
+            self.post(idx);
+        }
+        self.vq.notify();
+    }
+
+    /// Saca un buffer recibido, se lo pasa a `f` y lo vuelve a publicar.
+    fn pop(&mut self, f: impl FnOnce(&[u8])) -> bool {
+        let Some(elem) = self.vq.pop_used() else { return false };
+        let idx = self.buf_of_head[(elem.id as u16 % QUEUE_SIZE) as usize] as usize;
+        let buf = rx_buffer(self.slot, idx);
+        f(&buf[..(elem.len as usize).min(RX_BUF_SIZE)]);
+        self.post(idx);
+        self.vq.notify();
+        true
+    }
+}
+
+impl ConsoleDevice {
+    /// Publica los buffers de recepción y, en modo multipuerto, anuncia DEVICE_READY
+    /// y espera a que el dispositivo presente sus puertos. Se usa en `attach` y tras un
+    /// reset del dispositivo.
+    fn start(&mut self) {
+        if let Some(control) = self.control_rx.as_mut() {
+            control.post_all();
+        }
+        for port in self.ports.iter_mut().flatten() {
+            port.rx.post_all();
+        }
+        if self.multiport {
+            self.send_control(0, VIRTIO_CONSOLE_DEVICE_READY, 1);
+            // El dispositivo anuncia sus puertos en respuesta a DEVICE_READY
+            // (los que lleguen más tarde se atienden en `poll`)
+            let mut spins = 0;
+            while self.log_port.is_none() && spins < TX_SPIN_LIMIT {
+                self.poll();
+                core::hint::spin_loop();
+                spins += 1;
+            }
+            self.poll();
+        } else {
+            self.assign_roles();
+        }
+    }
+
+    fn send_control(&mut self, id: u32, event: u16, value: u16) {
+        let Some(vq) = self.control_tx.as_mut() else { return };
+        let msg = unsafe { &mut *addr_of_mut!(CONTROL_TX_BUF) };
+        msg[0..4].copy_from_slice(&id.to_le_bytes());
+        msg[4..6].copy_from_slice(&event.to_le_bytes());
+        msg[6..8].copy_from_slice(&value.to_le_bytes());
+        if let Some(head) = vq.add(&[Segment::readable(msg)]) {
+            vq.notify();
+            let _ = vq.wait_used(head, TX_SPIN_LIMIT);
+        }
+    }
+
+    fn handle_control(&mut self, msg: &[u8]) {
+        if msg.len() < CONTROL_MSG_LEN {
+            return;
+        }
+        let id = u32::from_le_bytes([msg[0], msg[1], msg[2], msg[3]]);
+        let event = u16::from_le_bytes([msg[4], msg[5]]);
+        let value = u16::from_le_bytes([msg[6], msg[7]]);
+        let index = id as usize;
+        match event {
+            VIRTIO_CONSOLE_DEVICE_ADD => {
+                let Some(port) = self.ports.get_mut(index).and_then(|p| p.as_mut()) else {
+                    // Puerto fuera de los que hemos configurado: se rechaza
+                    self.send_control(id, VIRTIO_CONSOLE_PORT_READY, 0);
+                    return;
+                };
+                port.present = true;
+                self.send_control(id, VIRTIO_CONSOLE_PORT_READY, 1);
+            }
+            VIRTIO_CONSOLE_DEVICE_REMOVE => {
+                if let Some(port) = self.ports.get_mut(index).and_then(|p| p.as_mut()) {
+                    port.present = false;
+                    port.host_connected = false;
+                    port.name_len = 0;
+                }
+            }
+            VIRTIO_CONSOLE_CONSOLE_PORT => {
+                if let Some(port) = self.ports.get_mut(index).and_then(|p| p.as_mut()) {
+                    port.is_console = true;
+                    port.host_connected = true;
+                    self.send_control(id, VIRTIO_CONSOLE_PORT_OPEN, 1);
+                }
+            }
+            VIRTIO_CONSOLE_PORT_OPEN => {
+                if let Some(port) = self.ports.get_mut(index).and_then(|p| p.as_mut()) {
+                    port.host_connected = value != 0;
+                }
+            }
+            VIRTIO_CONSOLE_PORT_NAME => {
+                if let Some(port) = self.ports.get_mut(index).and_then(|p| p.as_mut()) {
+                    let name = &msg[CONTROL_MSG_LEN..];
+                    let name = &name[..name.iter().position(|&b| b == 0).unwrap_or(name.len())];
+                    let len = name.len().min(PORT_NAME_MAX);
+                    port.name[..len].copy_from_slice(&name[..len]);
+                    port.name_len = len;
+                    // El guest abre el puerto en cuanto sabe qué es
+                    self.send_control(id, VIRTIO_CONSOLE_PORT_OPEN, 1);
+                }
+            }
+            _ => {}
+        }
+        self.assign_roles();
+    }
+
+    /// Elige el puerto de log y el de depuración entre los puertos presentes.
+    fn assign_roles(&mut self) {
+        let present = |p: &Option<Port>| p.as_ref().is_some_and(|p| p.present);
+        let console = (0..MAX_PORTS).find(|&i| present(&self.ports[i]) && self.ports[i].as_ref().is_some_and(|p| p.is_console));
+        let named = (0..MAX_PORTS).find(|&i| present(&self.ports[i]) && self.ports[i].as_ref().is_some_and(|p| p.name() == LOG_PORT_NAME));
+        let other = (0..MAX_PORTS).find(|&i| present(&self.ports[i]) && Some(i) != console);
+        self.debug_port = console.or_else(|| if present(&self.ports[0]) { Some(0) } else { None });
+        self.log_port = named.or(other).or(self.debug_port);
+    }
+
+    fn poll(&mut self) {
+        if let Some(mut control) = self.control_rx.take() {
+            while control.pop(|msg| self.handle_control(msg)) {}
+            self.control_rx = Some(control);
+        }
+        for index in 0..MAX_PORTS {
+            let is_debug = self.debug_port == Some(index);
+            let Some(port) = self.ports[index].as_mut() else { continue };
+            while port.rx.pop(|data| if is_debug { push_input(data) }) {}
+        }
+    }
+
+    fn write(&mut self, index: usize, data: &[u8]) -> Result<usize, ConsoleError> {
+        let port = self.ports.get_mut(index).and_then(|p| p.as_mut()).ok_or(ConsoleError::NoPort)?;
+        if !port.present || !port.host_connected {
+            return Err(ConsoleError::PortClosed);
+        }
+        if let Some(head) = port.tx_pending {
+            port.tx.wait_used(head, TX_SPIN_LIMIT).ok_or(ConsoleError::Timeout)?;
+            port.tx_pending = None;
+        }
+        let buf = unsafe { &mut (*addr_of_mut!(TX_BUFFERS))[index] };
+        let mut sent = 0;
+        while sent < data.len() {
+            let n = (data.len() - sent).min(TX_BUF_SIZE);
+            buf[..n].copy_from_slice(&data[sent..sent + n]);
+            let head = port.tx.add(&[Segment::readable(&buf[..n])]).ok_or(ConsoleError::Timeout)?;
+            port.tx.notify();
+            if port.tx.wait_used(head, TX_SPIN_LIMIT).is_none() {
+                port.tx_pending = Some(head);
+                return Err(ConsoleError::Timeout);
+            }
+            sent += n;
+        }
+        Ok(sent)
+    }
+}
+
+fn push_input(data: &[u8]) {
+    unsafe {
+        for &b in data {
+            let next = (INPUT_HEAD + 1) % INPUT_BUF_SIZE;
+            if next == INPUT_TAIL {
+                break; // buffer lleno: se descarta el resto
+            }
+            INPUT_BUF[INPUT_HEAD] = b;
+            INPUT_HEAD = next;
+        }
+    }
+}
+
+pub static DRIVER: Driver = Driver {
+    name: "virtio-console",
+    matches: &[Match::Virtio(VIRTIO_ID_CONSOLE)],
+    max_instances: 1,
+    probe,
+    remove,
+};
+
+fn probe(dev: VirtioDevice) -> Result<usize, &'static str> {
+    attach(dev).map(|()| 0).map_err(|e| e.as_str())
+}
+
+fn remove(_instance: usize) {
+    unsafe { CONSOLE = None; }
+}
+
+/// Configura las colas del dispositivo virtio-console y, en modo multipuerto,
+/// completa el intercambio DEVICE_READY / DEVICE_ADD / PORT_READY.
+fn attach(dev: VirtioDevice) -> Result<(), ConsoleError> {
+    let mut transport = VirtioPci::new(dev).map_err(ConsoleError::Transport)?;
+    let features = transport.begin_init(VIRTIO_CONSOLE_F_MULTIPORT).map_err(ConsoleError::Transport)?;
+    let multiport = features & VIRTIO_CONSOLE_F_MULTIPORT != 0;
+    let nr_ports = if multiport {
+        (transport.config_read32(CONFIG_MAX_NR_PORTS) as usize).clamp(1, MAX_PORTS)
+    } else {
+        1
+    };
+
+    let mut console = ConsoleDevice {
+        transport,
+        multiport,
+        ports: [None, None, None, None],
+        control_rx: None,
+        control_tx: None,
+        log_port: None,
+        debug_port: None,
+        log_cursor: 0,
+    };
+    // Todas las colas deben configurarse antes de DRIVER_OK
+    let setup = (|| -> Result<(), TransportError> {
+        for index in 0..nr_ports {
+            let (rx, tx) = port_queues(index);
+            let mut tx = console.transport.setup_queue(tx, QUEUE_SIZE)?;
+            // Solo se vigilan las colas de transmisión: las de recepción tienen
+            // siempre buffers publicados esperando datos del host
+            tx.enable_watchdog();
+            console.ports[index] = Some(Port {
+                rx: RxQueue::new(console.transport.setup_queue(rx, QUEUE_SIZE)?, index + 1),
+                tx,
+                tx_pending: None,
+                // Sin multipuerto el puerto 0 existe y está conectado desde el principio
+                present: !multiport,
+                is_console: !multiport,
+                host_connected: !multiport,
+                name: [0; PORT_NAME_MAX],
+                name_len: 0,
+            });
+        }
+        if multiport {
+            console.control_rx = Some(RxQueue::new(console.transport.setup_queue(2, QUEUE_SIZE)?, 0));
+            let mut control_tx = console.transport.setup_queue(3, QUEUE_SIZE)?;
+            control_tx.enable_watchdog();
+            console.control_tx = Some(control_tx);
+        }
+        Ok(())
+    })();
+    if let Err(e) = setup {
+        console.transport.fail();
+        return Err(ConsoleError::Transport(e));
+    }
+    console.transport.finish_init();
+
+    // Los buffers de recepción se publican ya con el dispositivo en su ubicación final
+    unsafe { CONSOLE = Some(console); }
+    device()?.start();
+    watchdog::register("virtio-console", watchdog_stalled, watchdog_recover);
+    Ok(())
+}
+
+fn watchdog_stalled(deadline: u64) -> bool {
+    device().is_ok_and(|console| {
+        console.ports.iter().flatten().any(|port| port.tx.stalled(deadline))
+            || console.control_tx.as_ref().is_some_and(|vq| vq.stalled(deadline))
+    })
+}
+
+fn watchdog_recover() -> Result<Recovery, &'static str> {
+    let console = device().map_err(|e| e.as_str())?;
+    let ConsoleDevice { transport, ports, control_rx, control_tx, .. } = console;
+    let recovery = watchdog::recover_device(transport, |f| {
+        for port in ports.iter_mut().flatten() {
+            f(&mut port.rx.vq)?;
+            f(&mut port.tx)?;
+        }
+        if let Some(control) = control_rx.as_mut() {
+            f(&mut control.vq)?;
+        }
+        match control_tx.as_mut() {
+            Some(vq) => f(vq),
+            None => Ok(()),
+        }
+    })
+    .map_err(|e| ConsoleError::Transport(e).as_str())?;
+    // Las transmisiones pendientes se perdieron con la cola
+    for port in console.ports.iter_mut().flatten() {
+        port.tx_pending = None;
+    }
+    if recovery == Recovery::DeviceReset {
+        if console.multiport {
+            // El dispositivo volverá a anunciar los puertos tras DEVICE_READY
+            for port in console.ports.iter_mut().flatten() {
+                port.present = false;
+                port.host_connected = false;
+                port.is_console = false;
+                port.name_len = 0;
+            }
+            console.log_port = None;
+            console.debug_port = None;
+        }
+        console.start();
+    }
+    Ok(recovery)
+}
+
+/// Procesa mensajes de control y datos recibidos. Se llama periódicamente desde el scheduler.
+pub fn poll() {
+    if let Ok(console) = device() {
+        console.poll();
+    }
+}
+
+/// `true` si el puerto de log está conectado en el host; mientras no lo esté, los
+/// logs deben seguir saliendo por el puerto serie.
+pub fn log_active() -> bool {
+    let Ok(console) = device() else { return false };
+    console
+        .log_port
+        .and_then(|index| console.ports[index].as_ref())
+        .is_some_and(|port| port.present && port.host_connected)
+}
+
+/// Envía por el puerto de log lo que se haya escrito en el ring de `logging` desde
+/// el último envío. Devuelve los bytes enviados.
+pub fn pump_log() -> Result<usize, ConsoleError> {
+    let console = device()?;
+    let index = console.log_port.ok_or(ConsoleError::NoPort)?;
+    let mut chunk = [0u8; 1024];
+    let mut total = 0;
+    loop {
+        let (n, next) = logging::log_read_from(console.log_cursor, &mut chunk);
+        if n == 0 {
+            return Ok(total);
+        }
+        console.write(index, &chunk[..n])?;
+        console.log_cursor = next;
+        total += n;
+    }
+}
+
+/// Escribe en la consola de depuración.
+pub fn debug_write(data: &[u8]) -> Result<usize, ConsoleError> {
+    let console = device()?;
+    let index = console.debug_port.ok_or(ConsoleError::NoPort)?;
+    console.write(index, data)
+}
+
+/// Copia en `out` la entrada pendiente de la consola de depuración.
+pub fn debug_read(out: &mut [u8]) -> usize {
+    let mut n = 0;
+    unsafe {
+        while INPUT_TAIL != INPUT_HEAD && n < out.len() {
+            out[n] = INPUT_BUF[INPUT_TAIL];
+            INPUT_TAIL = (INPUT_TAIL + 1) % INPUT_BUF_SIZE;
+            n += 1;
+        }
+    }
+    n
+}
+
+/// Recorre los puertos presentes: índice, nombre, si es consola y si el host lo tiene abierto.
+pub fn for_each_port(mut f: impl FnMut(usize, &str, bool, bool)) {
+    let Ok(console) = device() else { return };
+    for (index, port) in console.ports.iter().enumerate() {
+        if let Some(port) = port.as_ref().filter(|p| p.present) {
+            f(index, port.name(), port.is_console, port.host_connected);
+        }
+    }
+}
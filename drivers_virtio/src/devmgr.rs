@@ -0,0 +1,491 @@
+//! Gestor de dispositivos: registro de drivers, enumeración del bus y hot-plug.
+//!
+//! Los drivers se registran con un [`Driver`] (tabla de coincidencias, `probe` y
+//! `remove`). [`enumerate`] recorre el bus PCI una sola vez (bus 0 y los buses que
+//! cuelgan de cada bridge), guarda todas las funciones en una tabla y enlaza cada una
+//! con el primer driver registrado que la acepte; cada dispositivo enlazado recibe un
+//! [`DeviceHandle`].
+//!
+//! Hot-plug PCIe nativo: los root ports y downstream ports con ranura hot-plug se
+//! vigilan desde [`poll`]. Un cambio de presencia (`device_add` en QEMU) enciende la
+//! ranura, recorre el bus secundario y enlaza lo nuevo; el botón de atención
+//! (`device_del`) desenlaza los dispositivos de la ranura y la apaga, que es la
+//! confirmación que espera el host para retirarlos. Una retirada por sorpresa
+//! (presencia a 0) solo desenlaza.
+//!
+//! Las colas de un dispositivo retirado no se liberan: el [`Hal`](crate::hal::Hal) no
+//! tiene cómo devolver memoria DMA, así que cada retirada pierde esas páginas.
+
+use crate::pci::{self, VirtioDevice, PCI_CAP_ID_EXP};
+use core::ptr::{addr_of, addr_of_mut};
+
+const MAX_DRIVERS: usize = 8;
+const MAX_DEVICES: usize = 32;
+const MAX_PORTS: usize = 8;
+
+/// Buses primario, secundario y subordinado de un bridge (un byte cada uno).
+const PCI_PRIMARY_BUS: u8 = 0x18;
+
+// Registros de la capability PCI Express (desplazamientos desde su inicio)
+const PCI_EXP_FLAGS: u8 = 0x00;
+const PCI_EXP_FLAGS_SLOT: u32 = 1 << 24;
+const PCI_EXP_SLTCAP: u8 = 0x14;
+const PCI_EXP_SLTCAP_HPC: u32 = 1 << 6;
+/// Slot Control (16 bits) y Slot Status (16 bits, RW1C) comparten dword.
+const PCI_EXP_SLTCTL: u8 = 0x18;
+const PCI_EXP_SLTCTL_PIC: u16 = 3 << 8;
+const PCI_EXP_SLTCTL_PIC_ON: u16 = 1 << 8;
+const PCI_EXP_SLTCTL_PIC_OFF: u16 = 3 << 8;
+/// Power Controller Control: 1 = ranura apagada.
+const PCI_EXP_SLTCTL_PCC: u16 = 1 << 10;
+const PCI_EXP_SLTSTA_ABP: u16 = 1 << 0;
+const PCI_EXP_SLTSTA_PDC: u16 = 1 << 3;
+const PCI_EXP_SLTSTA_PDS: u16 = 1 << 6;
+const PCI_EXP_SLTSTA_DLLSC: u16 = 1 << 8;
+
+/// Qué dispositivos acepta un driver.
+#[derive(Debug, Clone, Copy)]
+pub enum Match {
+    /// Tipo de dispositivo virtio (ver [`VirtioDevice::device_type`]).
+    Virtio(u16),
+    /// Vendor y device ID PCI.
+    Pci { vendor: u16, device: u16 },
+}
+
+impl Match {
+    fn matches(&self, dev: &VirtioDevice) -> bool {
+        match *self {
+            Match::Virtio(virtio_type) => dev.device_type() == Some(virtio_type),
+            Match::Pci { vendor, device } => dev.vendor_id == vendor && dev.device_id == device,
+        }
+    }
+}
+
+pub struct Driver {
+    pub name: &'static str,
+    pub matches: &'static [Match],
+    /// Dispositivos que puede llevar a la vez. Los que no caben quedan sin enlazar y
+    /// se ofrecen de nuevo cuando se retira uno de los suyos.
+    pub max_instances: usize,
+    /// Inicializa el dispositivo y devuelve el índice de la instancia en el driver.
+    pub probe: fn(VirtioDevice) -> Result<usize, &'static str>,
+    /// El dispositivo `instance` se ha retirado o se va a retirar: el driver olvida su
+    /// estado sin esperar nada del hardware, que puede haber desaparecido ya.
+    pub remove: fn(usize),
+}
+
+/// Identifica un dispositivo de la tabla. Deja de ser válido cuando se retira, aunque
+/// otro dispositivo ocupe después la misma entrada.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeviceHandle {
+    index: u8,
+    generation: u16,
+}
+
+#[derive(Clone, Copy)]
+pub struct DeviceInfo {
+    pub handle: DeviceHandle,
+    pub dev: VirtioDevice,
+    /// Driver enlazado y su índice de instancia.
+    pub driver: Option<(&'static Driver, usize)>,
+}
+
+/// Cambio que se comunica al observador registrado con [`set_notifier`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Change {
+    Bound,
+    Removed,
+}
+
+#[derive(Clone, Copy)]
+struct Entry {
+    dev: VirtioDevice,
+    generation: u16,
+    driver: Option<(usize, usize)>,
+}
+
+/// Ranura hot-plug de un root port o downstream port PCIe.
+#[derive(Clone, Copy)]
+struct Port {
+    bridge: VirtioDevice,
+    cap: u8,
+    secondary: u8,
+    subordinate: u8,
+}
+
+struct Manager {
+    drivers: [Option<&'static Driver>; MAX_DRIVERS],
+    devices: [Option<Entry>; MAX_DEVICES],
+    ports: [Option<Port>; MAX_PORTS],
+    generation: u16,
+    enumerated: bool,
+    notifier: Option<fn(&DeviceInfo, Change)>,
+}
+
+static mut MANAGER: Manager = Manager::EMPTY;
+
+fn manager() -> &'static mut Manager {
+    unsafe { &mut *addr_of_mut!(MANAGER) }
+}
+
+/// Olvida drivers, dispositivos y ranuras (cada prueba arranca con un bus nuevo).
+#[cfg(test)]
+pub(crate) fn reset() {
+    *manager() = Manager::EMPTY;
+}
+
+/// Registra un driver. Si el bus ya se enumeró, se le ofrecen los dispositivos libres.
+pub fn register(driver: &'static Driver) {
+    let m = manager();
+    if m.drivers.iter().flatten().any(|d| core::ptr::eq(*d, driver)) {
+        return;
+    }
+    let Some(slot) = m.drivers.iter().position(|d| d.is_none()) else { return };
+    m.drivers[slot] = Some(driver);
+    if m.enumerated {
+        m.bind_all();
+    }
+}
+
+/// Observador de los enlaces y retiradas posteriores (hot-plug).
+pub fn set_notifier(notifier: fn(&DeviceInfo, Change)) {
+    manager().notifier = Some(notifier);
+}
+
+/// Recorre el bus PCI y enlaza los dispositivos con los drivers registrados, en el
+/// orden de registro. Solo enumera la primera vez. Devuelve los dispositivos enlazados.
+pub fn enumerate() -> usize {
+    let m = manager();
+    if !m.enumerated {
+        m.enumerated = true;
+        m.scan_bus(0, 0);
+    }
+    m.bind_all();
+    m.devices.iter().flatten().filter(|e| e.driver.is_some()).count()
+}
+
+/// Atiende las ranuras hot-plug. Se llama periódicamente desde el scheduler.
+pub fn poll() {
+    let m = manager();
+    let ports = m.ports;
+    for port in ports.iter().flatten() {
+        let dword = read_slot(port);
+        let (control, status) = (dword as u16, (dword >> 16) as u16);
+        let changed = status & (PCI_EXP_SLTSTA_ABP | PCI_EXP_SLTSTA_PDC | PCI_EXP_SLTSTA_DLLSC);
+        if changed == 0 {
+            continue;
+        }
+        write_slot(port, control, changed);
+        if status & PCI_EXP_SLTSTA_ABP != 0 {
+            // Petición de retirada: se suelta el dispositivo y se apaga la ranura
+            m.remove_port(port);
+            write_slot(port, (control & !PCI_EXP_SLTCTL_PIC) | PCI_EXP_SLTCTL_PCC | PCI_EXP_SLTCTL_PIC_OFF, 0);
+        } else if status & PCI_EXP_SLTSTA_PDS != 0 {
+            power_on(port, control);
+            m.scan_bus(port.secondary, 1);
+            m.bind_all();
+        } else {
+            m.remove_port(port);
+        }
+    }
+}
+
+/// Dispositivos enlazados a `driver`.
+pub fn bound(driver: &'static Driver) -> usize {
+    manager().instances(driver)
+}
+
+pub fn info(handle: DeviceHandle) -> Option<DeviceInfo> {
+    let m = manager();
+    let entry = m.devices.get(handle.index as usize)?.filter(|e| e.generation == handle.generation)?;
+    Some(m.info(handle.index as usize, &entry))
+}
+
+/// Recorre todos los dispositivos del bus, enlazados o no.
+pub fn for_each(mut f: impl FnMut(&DeviceInfo)) {
+    let m = unsafe { &*addr_of!(MANAGER) };
+    for (index, entry) in m.devices.iter().enumerate() {
+        if let Some(entry) = entry {
+            f(&m.info(index, entry));
+        }
+    }
+}
+
+fn read_slot(port: &Port) -> u32 {
+    let b = &port.bridge;
+    pci::read_config(b.bus, b.slot, b.func, port.cap + PCI_EXP_SLTCTL)
+}
+
+/// Escribe Slot Control; los bits de `clear` de Slot Status se ponen a cero (RW1C).
+fn write_slot(port: &Port, control: u16, clear: u16) {
+    let b = &port.bridge;
+    pci::write_config(b.bus, b.slot, b.func, port.cap + PCI_EXP_SLTCTL, (clear as u32) << 16 | control as u32);
+}
+
+fn power_on(port: &Port, control: u16) {
+    if control & PCI_EXP_SLTCTL_PCC != 0 {
+        write_slot(port, (control & !(PCI_EXP_SLTCTL_PCC | PCI_EXP_SLTCTL_PIC)) | PCI_EXP_SLTCTL_PIC_ON, 0);
+    }
+}
+
+impl Manager {
+    const EMPTY: Manager = Manager {
+        drivers: [None; MAX_DRIVERS],
+        devices: [None; MAX_DEVICES],
+        ports: [None; MAX_PORTS],
+        generation: 0,
+        enumerated: false,
+        notifier: None,
+    };
+
+    fn info(&self, index: usize, entry: &Entry) -> DeviceInfo {
+        DeviceInfo {
+            handle: DeviceHandle { index: index as u8, generation: entry.generation },
+            dev: entry.dev,
+            driver: entry.driver.and_then(|(d, instance)| Some((self.drivers[d]?, instance))),
+        }
+    }
+
+    fn instances(&self, driver: &'static Driver) -> usize {
+        let Some(d) = self.drivers.iter().position(|r| r.is_some_and(|r| core::ptr::eq(r, driver))) else {
+            return 0;
+        };
+        self.devices.iter().flatten().filter(|e| e.driver.is_some_and(|(bound, _)| bound == d)).count()
+    }
+
+    /// Añade las funciones de `bus` que no estén ya en la tabla y sigue por los bridges.
+    fn scan_bus(&mut self, bus: u8, depth: usize) {
+        if depth > 8 {
+            return;
+        }
+        for slot in 0..32 {
+            if pci::read_function(bus, slot, 0).is_none() {
+                continue;
+            }
+            let funcs = if pci::is_multi_function(bus, slot) { 8 } else { 1 };
+            for func in 0..funcs {
+                let Some(dev) = pci::read_function(bus, slot, func) else { continue };
+                match pci::secondary_bus(&dev) {
+                    Some(secondary) if secondary > bus => {
+                        self.add_port(dev, secondary);
+                        self.scan_bus(secondary, depth + 1);
+                    }
+                    Some(_) => {}
+                    None => self.add_device(dev),
+                }
+            }
+        }
+    }
+
+    fn add_port(&mut self, bridge: VirtioDevice, secondary: u8) {
+        if self.ports.iter().flatten().any(|p| p.secondary == secondary) {
+            return;
+        }
+        let Some(cap) = pci::find_capability(&bridge, PCI_CAP_ID_EXP) else { return };
+        let flags = pci::read_config(bridge.bus, bridge.slot, bridge.func, cap + PCI_EXP_FLAGS);
+        let slot_cap = pci::read_config(bridge.bus, bridge.slot, bridge.func, cap + PCI_EXP_SLTCAP);
+        if flags & PCI_EXP_FLAGS_SLOT == 0 || slot_cap & PCI_EXP_SLTCAP_HPC == 0 {
+            return;
+        }
+        let Some(slot) = self.ports.iter().position(|p| p.is_none()) else { return };
+        let buses = pci::read_config(bridge.bus, bridge.slot, bridge.func, PCI_PRIMARY_BUS);
+        let subordinate = (buses >> 16) as u8;
+        let port = Port { bridge, cap, secondary, subordinate: subordinate.max(secondary) };
+        // Una ranura ocupada pero apagada se enciende antes de recorrer su bus
+        let dword = read_slot(&port);
+        if (dword >> 16) as u16 & PCI_EXP_SLTSTA_PDS != 0 {
+            power_on(&port, dword as u16);
+        }
+        self.ports[slot] = Some(port);
+    }
+
+    fn add_device(&mut self, dev: VirtioDevice) {
+        let present = self.devices.iter().flatten().any(|e| {
+            (e.dev.bus, e.dev.slot, e.dev.func) == (dev.bus, dev.slot, dev.func)
+        });
+        if present {
+            return;
+        }
+        let Some(index) = self.devices.iter().position(|e| e.is_none()) else {
+            logging::serial_println!("[devmgr] tabla llena, se ignora {:02x}:{:02x}.{}\n", dev.bus, dev.slot, dev.func);
+            return;
+        };
+        self.generation = self.generation.wrapping_add(1);
+        self.devices[index] = Some(Entry { dev, generation: self.generation, driver: None });
+    }
+
+    /// Ofrece cada dispositivo libre a los drivers, en orden de registro.
+    fn bind_all(&mut self) {
+        for d in 0..MAX_DRIVERS {
+            let Some(driver) = self.drivers[d] else { continue };
+            for index in 0..MAX_DEVICES {
+                let Some(entry) = self.devices[index] else { continue };
+                if entry.driver.is_some() || !driver.matches.iter().any(|m| m.matches(&entry.dev)) {
+                    continue;
+                }
+                if self.instances(driver) >= driver.max_instances {
+                    break;
+                }
+                let dev = entry.dev;
+                match (driver.probe)(dev) {
+                    Ok(instance) => {
+                        let entry = self.devices[index].as_mut().map(|e| {
+                            e.driver = Some((d, instance));
+                            *e
+                        });
+                        if let (Some(entry), Some(notify)) = (entry, self.notifier) {
+                            notify(&self.info(index, &entry), Change::Bound);
+                        }
+                    }
+                    Err(e) => logging::serial_println!(
+                        "[devmgr] {:02x}:{:02x}.{} {}: {}\n",
+                        dev.bus,
+                        dev.slot,
+                        dev.func,
+                        driver.name,
+                        e
+                    ),
+                }
+            }
+        }
+    }
+
+    /// Desenlaza y olvida los dispositivos que cuelgan de `port` (su bus secundario y
+    /// los que haya detrás de otros bridges dentro de la ranura).
+    fn remove_port(&mut self, port: &Port) {
+        let behind = |bus: u8| (port.secondary..=port.subordinate).contains(&bus);
+        let mut freed = false;
+        for index in 0..MAX_DEVICES {
+            let Some(entry) = self.devices[index] else { continue };
+            if !behind(entry.dev.bus) {
+                continue;
+            }
+            if let Some(driver) = entry.driver.and_then(|(d, instance)| Some((self.drivers[d]?, instance))) {
+                (driver.0.remove)(driver.1);
+                freed = true;
+            }
+            if let Some(notify) = self.notifier {
+                notify(&self.info(index, &entry), Change::Removed);
+            }
+            self.devices[index] = None;
+        }
+        for nested in self.ports.iter_mut() {
+            if nested.is_some_and(|p| behind(p.bridge.bus)) {
+                *nested = None;
+            }
+        }
+        // Un dispositivo que esperaba hueco en su driver puede enlazarse ahora
+        if freed {
+            self.bind_all();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{self, fs::FsBackend, vsock::VsockBackend};
+    use crate::{fs, vsock};
+    use std::sync::Mutex;
+    use std::vec::Vec;
+
+    static CHANGES: Mutex<Vec<(&'static str, Change)>> = Mutex::new(Vec::new());
+
+    fn record(info: &DeviceInfo, change: Change) {
+        let name = info.driver.map_or("-", |(driver, _)| driver.name);
+        CHANGES.lock().unwrap().push((name, change));
+    }
+
+    fn take_changes() -> Vec<(&'static str, Change)> {
+        core::mem::take(&mut *CHANGES.lock().unwrap())
+    }
+
+    fn devices() -> Vec<DeviceInfo> {
+        let mut out = Vec::new();
+        for_each(|info| out.push(*info));
+        out
+    }
+
+    #[test]
+    fn enumerate_binds_matching_driver() {
+        let _guard = mock::install(FsBackend::new());
+        register(&vsock::DRIVER);
+        register(&fs::DRIVER);
+        assert_eq!(enumerate(), 1);
+        assert_eq!(bound(&fs::DRIVER), 1);
+        assert_eq!(bound(&vsock::DRIVER), 0);
+        assert!(fs::tag().is_some());
+
+        let all = devices();
+        assert_eq!(all.len(), 1);
+        let (driver, instance) = all[0].driver.unwrap();
+        assert_eq!((driver.name, instance), ("virtio-fs", 0));
+        assert_eq!(all[0].dev.device_type(), Some(fs::VIRTIO_ID_FS));
+        assert!(info(all[0].handle).is_some());
+        // Una segunda llamada no vuelve a enumerar ni a enlazar
+        assert_eq!(enumerate(), 1);
+        assert_eq!(devices().len(), 1);
+    }
+
+    #[test]
+    fn driver_registered_late_binds_free_devices() {
+        let _guard = mock::install(VsockBackend::new());
+        register(&fs::DRIVER);
+        assert_eq!(enumerate(), 0);
+        assert!(devices()[0].driver.is_none());
+        register(&vsock::DRIVER);
+        assert_eq!(bound(&vsock::DRIVER), 1);
+        assert!(vsock::guest_cid().is_some());
+    }
+
+    #[test]
+    fn attention_button_unplugs_device() {
+        let _guard = mock::install_port();
+        mock::hot_add(FsBackend::new());
+        register(&fs::DRIVER);
+        assert_eq!(enumerate(), 1);
+        let handle = devices()[0].handle;
+        assert_eq!(devices()[0].dev.bus, 1);
+        set_notifier(record);
+        take_changes();
+        poll();
+        assert!(take_changes().is_empty());
+
+        mock::attention();
+        poll();
+        assert!(!mock::present());
+        assert_eq!(take_changes(), [("virtio-fs", Change::Removed)]);
+        assert_eq!(bound(&fs::DRIVER), 0);
+        assert!(info(handle).is_none());
+        assert_eq!(fs::tag(), None);
+        // El cambio de presencia posterior a la retirada no tiene nada que quitar
+        poll();
+        assert!(take_changes().is_empty());
+        assert!(devices().is_empty());
+    }
+
+    #[test]
+    fn hot_added_device_is_bound() {
+        let _guard = mock::install_port();
+        register(&vsock::DRIVER);
+        assert_eq!(enumerate(), 0);
+        set_notifier(record);
+        take_changes();
+
+        mock::hot_add(VsockBackend::new());
+        poll();
+        assert_eq!(take_changes(), [("virtio-vsock", Change::Bound)]);
+        assert_eq!(bound(&vsock::DRIVER), 1);
+        assert!(vsock::guest_cid().is_some());
+        let handle = devices()[0].handle;
+
+        // Retirarlo y volver a añadirlo da un handle nuevo
+        mock::attention();
+        poll();
+        mock::hot_add(VsockBackend::new());
+        poll();
+        assert_eq!(bound(&vsock::DRIVER), 1);
+        assert!(info(handle).is_none());
+        assert_ne!(devices()[0].handle, handle);
+    }
+}
@@ -0,0 +1,272 @@
+//! Memoria DMA para los drivers virtio.
+//!
+//! Hay dos tipos de memoria que ve el dispositivo:
+//!
+//! - Regiones coherentes ([`DmaRegion`]): reservadas con el [`Hal`](crate::hal::Hal),
+//!   físicamente contiguas y con dirección física conocida. Son los anillos de las
+//!   virtqueues y el pool de rebote.
+//! - Buffers de streaming ([`map`] / [`unmap`]): memoria del llamador que se presta
+//!   al dispositivo mientras dura una petición. Si el buffer ocupa páginas que no son
+//!   físicamente contiguas, se usa un buffer de rebote del pool: se copia al mapear y,
+//!   si el dispositivo escribe, se copia de vuelta al desmapear.
+//!
+//! Todas las direcciones que llegan al dispositivo salen de aquí, así que es el punto
+//! donde un guest confidencial (SEV/TDX) tendría que convertir las páginas a
+//! compartidas.
+
+use crate::hal::hal;
+use core::ptr::addr_of_mut;
+use logging::metrics;
+
+pub const PAGE_SIZE: usize = 4096;
+
+/// Páginas del pool de rebote (256 KiB: dos transferencias máximas de blk o FUSE_READ).
+const BOUNCE_PAGES: usize = 64;
+
+/// Alinea a página los buffers de recepción estáticos. Quedan publicados de forma
+/// indefinida, así que no deben cruzar páginas: si no, ocuparían el pool de rebote.
+#[repr(C, align(4096))]
+pub struct PageAligned<T>(pub T);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// El dispositivo lee el buffer.
+    ToDevice,
+    /// El dispositivo escribe el buffer.
+    FromDevice,
+}
+
+/// Región coherente: el driver y el dispositivo la comparten durante toda su vida.
+#[derive(Debug)]
+pub struct DmaRegion {
+    virt: *mut u8,
+    phys: u64,
+    len: usize,
+}
+
+impl DmaRegion {
+    /// Reserva `len` bytes a cero, alineados a `align` (potencia de dos, como mínimo
+    /// una página) y físicamente contiguos.
+    pub fn alloc(len: usize, align: usize) -> Option<Self> {
+        let virt = hal().dma_alloc(len, align.max(PAGE_SIZE));
+        if virt.is_null() {
+            return None;
+        }
+        Some(DmaRegion { virt, phys: hal().virt_to_phys(virt as usize), len })
+    }
+
+    pub fn virt(&self) -> *mut u8 {
+        self.virt
+    }
+
+    /// Dirección física del primer byte; el resto de la región le sigue contigua.
+    pub fn phys(&self) -> u64 {
+        self.phys
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Dirección física de `offset` dentro de la región.
+    pub fn phys_at(&self, offset: usize) -> u64 {
+        self.phys + offset as u64
+    }
+}
+
+/// Buffer de streaming mapeado para el dispositivo. Hay que pasarlo a [`unmap`]
+/// cuando el dispositivo haya terminado con él.
+#[derive(Debug, Clone, Copy)]
+pub struct DmaMapping {
+    phys: u64,
+    virt: usize,
+    len: u32,
+    direction: Direction,
+    /// Primera página del pool de rebote, si el buffer no era contiguo.
+    bounce: Option<u16>,
+}
+
+impl DmaMapping {
+    /// Dirección que se entrega al dispositivo.
+    pub fn phys(&self) -> u64 {
+        self.phys
+    }
+
+    pub fn is_bounced(&self) -> bool {
+        self.bounce.is_some()
+    }
+}
+
+/// Dirección física de memoria del kernel (anillos y demás estructuras coherentes).
+pub fn virt_to_phys(virt: usize) -> u64 {
+    hal().virt_to_phys(virt)
+}
+
+/// `true` si `len` bytes a partir de `virt` son físicamente contiguos.
+pub fn is_contiguous(virt: usize, len: usize) -> bool {
+    if len == 0 {
+        return true;
+    }
+    let base = hal().virt_to_phys(virt);
+    let mut page = (virt & !(PAGE_SIZE - 1)) + PAGE_SIZE;
+    while page < virt + len {
+        if hal().virt_to_phys(page) != base + (page - virt) as u64 {
+            return false;
+        }
+        page += PAGE_SIZE;
+    }
+    true
+}
+
+struct BouncePool {
+    region: Option<DmaRegion>,
+    /// Bit `i` a 1: página `i` en uso.
+    used: u64,
+}
+
+static mut BOUNCE_POOL: BouncePool = BouncePool { region: None, used: 0 };
+
+fn pool() -> &'static mut BouncePool {
+    unsafe { &mut *addr_of_mut!(BOUNCE_POOL) }
+}
+
+impl BouncePool {
+    /// Reserva `pages` páginas consecutivas (first-fit). El pool se crea en el primer uso.
+    fn take(&mut self, pages: usize) -> Option<u16> {
+        if pages == 0 || pages > BOUNCE_PAGES {
+            return None;
+        }
+        if self.region.is_none() {
+            self.region = DmaRegion::alloc(BOUNCE_PAGES * PAGE_SIZE, PAGE_SIZE);
+        }
+        self.region.as_ref()?;
+        let mask = if pages == 64 { u64::MAX } else { (1u64 << pages) - 1 };
+        let first = (0..=BOUNCE_PAGES - pages).find(|&i| self.used & (mask << i) == 0)?;
+        self.used |= mask << first;
+        Some(first as u16)
+    }
+
+    fn release(&mut self, first: u16, pages: usize) {
+        let mask = if pages == 64 { u64::MAX } else { (1u64 << pages) - 1 };
+        self.used &= !(mask << first);
+    }
+
+    fn buffer(&self, first: u16) -> Option<(*mut u8, u64)> {
+        let region = self.region.as_ref()?;
+        let offset = first as usize * PAGE_SIZE;
+        Some((region.virt.wrapping_add(offset), region.phys_at(offset)))
+    }
+}
+
+/// Presta `len` bytes a partir de `virt` al dispositivo. Devuelve `None` si el buffer
+/// necesita rebote y el pool no tiene sitio (el llamador puede reintentar cuando se
+/// completen otras peticiones).
+///
+/// # Safety
+/// `virt..virt + len` debe ser memoria válida que no se toque hasta el [`unmap`].
+pub unsafe fn map(virt: usize, len: usize, direction: Direction) -> Option<DmaMapping> {
+    if is_contiguous(virt, len) {
+        let phys = hal().virt_to_phys(virt);
+        return Some(DmaMapping { phys, virt, len: len as u32, direction, bounce: None });
+    }
+    let pages = len.div_ceil(PAGE_SIZE);
+    let pool = pool();
+    let first = pool.take(pages)?;
+    let (bounce, phys) = pool.buffer(first)?;
+    // También al recibir: al desmapear se copia el buffer entero, y lo que el
+    // dispositivo no escriba debe conservar el contenido original
+    core::ptr::copy_nonoverlapping(virt as *const u8, bounce, len);
+    metrics::DMA_BOUNCES.inc();
+    Some(DmaMapping { phys, virt, len: len as u32, direction, bounce: Some(first) })
+}
+
+/// Devuelve el buffer al llamador, copiando lo que haya escrito el dispositivo si se
+/// usó rebote.
+pub fn unmap(mapping: DmaMapping) {
+    let Some(first) = mapping.bounce else { return };
+    let len = mapping.len as usize;
+    let pool = pool();
+    if mapping.direction == Direction::FromDevice {
+        if let Some((bounce, _)) = pool.buffer(first) {
+            unsafe { core::ptr::copy_nonoverlapping(bounce, mapping.virt as *mut u8, len) };
+        }
+    }
+    pool.release(first, len.div_ceil(PAGE_SIZE));
+}
+
+/// Libera un mapeo cuyo contenido ya no interesa (petición abandonada tras un reset).
+pub fn discard(mapping: DmaMapping) {
+    if let Some(first) = mapping.bounce {
+        pool().release(first, (mapping.len as usize).div_ceil(PAGE_SIZE));
+    }
+}
+
+/// Páginas del pool de rebote en uso.
+pub fn bounce_pages_in_use() -> usize {
+    pool().used.count_ones() as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock;
+
+    #[test]
+    fn contiguous_buffers_are_not_bounced() {
+        let _guard = mock::install_hal();
+        let buf = vec![5u8; 3 * PAGE_SIZE];
+        let mapping = unsafe { map(buf.as_ptr() as usize, buf.len(), Direction::ToDevice) }.unwrap();
+        assert!(!mapping.is_bounced());
+        assert_eq!(mapping.phys(), buf.as_ptr() as u64);
+        unmap(mapping);
+    }
+
+    #[test]
+    fn scattered_buffers_are_bounced() {
+        let _guard = mock::install_hal();
+        mock::set_scattered(true);
+        let mut buf = vec![0u8; 2 * PAGE_SIZE];
+        for (i, b) in buf.iter_mut().enumerate() {
+            *b = i as u8;
+        }
+        // Dentro de una página no hace falta rebote
+        let inside = unsafe { map(buf.as_ptr() as usize + PAGE_SIZE - (buf.as_ptr() as usize % PAGE_SIZE), 16, Direction::ToDevice) }.unwrap();
+        assert!(!inside.is_bounced());
+        assert!(!is_contiguous(buf.as_ptr() as usize, buf.len()));
+
+        let bounces = metrics::DMA_BOUNCES.get();
+        let mapping = unsafe { map(buf.as_mut_ptr() as usize, buf.len(), Direction::FromDevice) }.unwrap();
+        assert!(mapping.is_bounced());
+        assert_eq!(metrics::DMA_BOUNCES.get(), bounces + 1);
+        assert_eq!(bounce_pages_in_use(), 2);
+        // El rebote parte del contenido original; el dispositivo escribe en él
+        let bounce = mock::phys_to_virt(mapping.phys()) as *mut u8;
+        unsafe {
+            assert_eq!(*bounce.add(5), 5);
+            *bounce.add(5) = 0xAA;
+        }
+        unmap(mapping);
+        assert_eq!(buf[5], 0xAA);
+        assert_eq!(buf[6], 6);
+        assert_eq!(bounce_pages_in_use(), 0);
+        mock::set_scattered(false);
+    }
+
+    #[test]
+    fn bounce_pool_exhaustion() {
+        let _guard = mock::install_hal();
+        mock::set_scattered(true);
+        let buf = vec![0u8; (BOUNCE_PAGES / 2 + 1) * PAGE_SIZE];
+        let first = unsafe { map(buf.as_ptr() as usize, buf.len(), Direction::ToDevice) }.unwrap();
+        assert!(unsafe { map(buf.as_ptr() as usize, buf.len(), Direction::ToDevice) }.is_none());
+        discard(first);
+        let again = unsafe { map(buf.as_ptr() as usize, buf.len(), Direction::ToDevice) }.unwrap();
+        discard(again);
+        assert_eq!(bounce_pages_in_use(), 0);
+        mock::set_scattered(false);
+    }
+}
@@ -0,0 +1,1142 @@
+//! Cliente virtio-fs: protocolo FUSE sobre virtqueues.
+//!
+//! La cola 0 (hiprio) se usa para FORGET y la cola 1 para el resto de peticiones.
+//! Cada petición es una cadena `fuse_in_header + args` (legible por el dispositivo)
+//! seguida de `fuse_out_header + respuesta` (escrita por el dispositivo). Las lecturas
+//! se hacen directamente sobre el buffer del llamador, sin copias intermedias.
+//!
+//! Si el dispositivo expone la ventana de caché DAX (región de memoria compartida 0),
+//! [`map_file`] proyecta un fichero en ella con FUSE_SETUPMAPPING y lo mapea en el
+//! espacio del kernel como memoria de solo lectura y NX, respaldada por la page cache
+//! del host.
+
+use crate::hal::hal;
+use crate::devmgr::{Driver, Match};
+use crate::pci::VirtioDevice;
+use crate::transport::{Recovery, ShmRegion, TransportError, VirtioPci, DEFAULT_QUEUE_SIZE};
+use crate::watchdog;
+use crate::virtqueue::{Segment, VirtQueue, DEFAULT_SPIN_LIMIT};
+use core::fmt;
+use logging::events::{self, Category, Event, EventKind};
+
+pub const VIRTIO_ID_FS: u16 = 26;
+
+const FUSE_KERNEL_VERSION: u32 = 7;
+const FUSE_KERNEL_MINOR_VERSION: u32 = 31;
+pub const FUSE_ROOT_ID: u64 = 1;
+
+// Opcodes FUSE
+const FUSE_LOOKUP: u32 = 1;
+const FUSE_FORGET: u32 = 2;
+const FUSE_GETATTR: u32 = 3;
+const FUSE_OPEN: u32 = 14;
+const FUSE_READ: u32 = 15;
+const FUSE_RELEASE: u32 = 18;
+const FUSE_INIT: u32 = 26;
+const FUSE_OPENDIR: u32 = 27;
+const FUSE_READDIR: u32 = 28;
+const FUSE_RELEASEDIR: u32 = 29;
+const FUSE_SETUPMAPPING: u32 = 48;
+const FUSE_REMOVEMAPPING: u32 = 49;
+
+// Flags de FUSE_INIT
+const FUSE_MAP_ALIGNMENT: u32 = 1 << 26;
+const FUSE_SETUPMAPPING_FLAG_READ: u64 = 1 << 1;
+
+/// Identificador de la región de memoria compartida que actúa como ventana DAX.
+const VIRTIO_FS_SHMCAP_ID_CACHE: u8 = 0;
+/// Granularidad de las proyecciones DAX: el kernel mapea la ventana con páginas de 2MiB.
+const DAX_PAGE_SIZE: u64 = 2 * 1024 * 1024;
+const MAX_DAX_MAPPINGS: usize = 8;
+/// Longitud máxima de la ruta que se guarda de cada proyección para rehacerla tras un reset.
+const DAX_PATH_MAX: usize = 256;
+
+// Tamaños de las estructuras del protocolo
+const IN_HEADER_LEN: usize = 40;
+const OUT_HEADER_LEN: usize = 16;
+const ATTR_LEN: usize = 88;
+const ENTRY_OUT_LEN: usize = 40 + ATTR_LEN;
+const ATTR_OUT_LEN: usize = 16 + ATTR_LEN;
+const INIT_IN_LEN: usize = 16;
+const INIT_OUT_LEN: usize = 64;
+const OPEN_OUT_LEN: usize = 16;
+const READ_IN_LEN: usize = 40;
+const RELEASE_IN_LEN: usize = 24;
+const SETUPMAPPING_IN_LEN: usize = 40;
+const DIRENT_HEADER_LEN: usize = 24;
+
+pub const NAME_MAX: usize = 255;
+/// Tamaño máximo de cada FUSE_READ.
+const READ_CHUNK: usize = 128 * 1024;
+const READDIR_BUF_SIZE: usize = 4096;
+
+const O_RDONLY: u32 = 0;
+const S_IFMT: u32 = 0o170000;
+const S_IFDIR: u32 = 0o040000;
+const S_IFREG: u32 = 0o100000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FsError {
+    /// No hay dispositivo virtio-fs o se ha retirado.
+    NoDevice,
+    Transport(TransportError),
+    /// El dispositivo no completó la petición a tiempo.
+    Timeout,
+    /// No quedan descriptores libres en la cola de peticiones.
+    QueueFull,
+    /// Respuesta mal formada o versión de FUSE no soportada.
+    Protocol,
+    InvalidPath,
+    /// El fichero no cabe en el buffer del llamador.
+    BufferTooSmall,
+    /// El dispositivo no expone ventana DAX o el servidor no soporta SETUPMAPPING.
+    DaxUnavailable,
+    /// No queda hueco libre en la ventana DAX para la proyección pedida.
+    DaxWindowFull,
+    /// Error devuelto por el servidor FUSE (errno positivo, p. ej. ENOENT = 2).
+    Fuse(i32),
+}
+
+impl FsError {
+    /// errno FUSE asociado al error, si lo devolvió el servidor.
+    pub fn errno(&self) -> Option<i32> {
+        match self {
+            FsError::Fuse(errno) => Some(*errno),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FsError::NoDevice => "virtio-fs: no device",
+            FsError::Transport(_) => "virtio-fs: transport error",
+            FsError::Timeout => "virtio-fs: timeout",
+            FsError::QueueFull => "virtio-fs: queue full",
+            FsError::Protocol => "virtio-fs: protocol error",
+            FsError::InvalidPath => "virtio-fs: invalid path",
+            FsError::BufferTooSmall => "virtio-fs: buffer too small",
+            FsError::DaxUnavailable => "virtio-fs: DAX unavailable",
+            FsError::DaxWindowFull => "virtio-fs: DAX window full",
+            FsError::Fuse(errno) => errno_str(*errno),
+        }
+    }
+}
+
+impl fmt::Display for FsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FsError::Fuse(errno) => write!(f, "{} (errno {})", errno_str(*errno), errno),
+            FsError::Transport(e) => write!(f, "virtio-fs: transport error {:?}", e),
+            other => f.write_str(other.as_str()),
+        }
+    }
+}
+
+fn errno_str(errno: i32) -> &'static str {
+    match errno {
+        1 => "fuse: EPERM",
+        2 => "fuse: ENOENT",
+        5 => "fuse: EIO",
+        9 => "fuse: EBADF",
+        12 => "fuse: ENOMEM",
+        13 => "fuse: EACCES",
+        20 => "fuse: ENOTDIR",
+        21 => "fuse: EISDIR",
+        22 => "fuse: EINVAL",
+        27 => "fuse: EFBIG",
+        36 => "fuse: ENAMETOOLONG",
+        38 => "fuse: ENOSYS",
+        95 => "fuse: EOPNOTSUPP",
+        _ => "fuse: error",
+    }
+}
+
+/// Atributos de un inodo (`fuse_attr`).
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FileAttr {
+    pub ino: u64,
+    pub size: u64,
+    pub blocks: u64,
+    pub atime: u64,
+    pub mtime: u64,
+    pub ctime: u64,
+    pub mode: u32,
+    pub nlink: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub blksize: u32,
+}
+
+impl FileAttr {
+    pub fn is_dir(&self) -> bool {
+        self.mode & S_IFMT == S_IFDIR
+    }
+
+    pub fn is_file(&self) -> bool {
+        self.mode & S_IFMT == S_IFREG
+    }
+
+    fn parse(raw: &[u8]) -> Self {
+        FileAttr {
+            ino: get_u64(raw, 0),
+            size: get_u64(raw, 8),
+            blocks: get_u64(raw, 16),
+            atime: get_u64(raw, 24),
+            mtime: get_u64(raw, 32),
+            ctime: get_u64(raw, 40),
+            mode: get_u32(raw, 60),
+            nlink: get_u32(raw, 64),
+            uid: get_u32(raw, 68),
+            gid: get_u32(raw, 72),
+            blksize: get_u32(raw, 80),
+        }
+    }
+}
+
+/// Resultado de LOOKUP: el llamador posee una referencia sobre `nodeid` que debe
+/// liberar con [`forget`] (salvo la raíz).
+#[derive(Debug, Clone, Copy)]
+pub struct Entry {
+    pub nodeid: u64,
+    pub attr: FileAttr,
+}
+
+/// Entrada de directorio devuelta por READDIR.
+#[derive(Debug, Clone, Copy)]
+pub struct DirEntry<'a> {
+    pub ino: u64,
+    pub kind: u32,
+    pub name: &'a str,
+}
+
+/// Fichero abierto (nodeid + file handle FUSE).
+#[derive(Debug)]
+pub struct File {
+    pub nodeid: u64,
+    pub fh: u64,
+    pub attr: FileAttr,
+}
+
+impl File {
+    /// Lee a partir de `offset` hasta llenar `buf` o llegar al final del fichero.
+    pub fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<usize, FsError> {
+        read(self.nodeid, self.fh, offset, buf)
+    }
+
+    /// RELEASE del handle y FORGET del nodo.
+    pub fn close(self) {
+        release(self.nodeid, self.fh, FUSE_RELEASE);
+        put_node(self.nodeid);
+    }
+}
+
+/// Fichero proyectado en la ventana DAX (mantiene abierto su handle FUSE).
+#[derive(Debug, Clone, Copy)]
+struct DaxMapping {
+    nodeid: u64,
+    fh: u64,
+    moffset: u64,
+    len: u64,
+    virt: usize,
+    path: [u8; DAX_PATH_MAX],
+    path_len: usize,
+}
+
+struct DaxWindow {
+    region: ShmRegion,
+    mappings: [Option<DaxMapping>; MAX_DAX_MAPPINGS],
+}
+
+impl DaxWindow {
+    /// Primer desplazamiento libre (first-fit) con hueco para `len` bytes.
+    fn find_free(&self, len: u64) -> Option<u64> {
+        let overlaps = |start: u64| {
+            self.mappings.iter().flatten().any(|m| start < m.moffset + m.len && m.moffset < start + len)
+        };
+        core::iter::once(0)
+            .chain(self.mappings.iter().flatten().map(|m| m.moffset + m.len))
+            .filter(|&start| start + len <= self.region.len && !overlaps(start))
+            .min()
+    }
+}
+
+struct FsDevice {
+    transport: VirtioPci,
+    hiprio: VirtQueue,
+    request: VirtQueue,
+    unique: u64,
+    minor: u32,
+    tag: [u8; 36],
+    dax: Option<DaxWindow>,
+    /// Hay una recuperación en curso (evita rehacer la sesión FUSE de forma anidada).
+    recovering: bool,
+}
+
+static mut FS_DEVICE: Option<FsDevice> = None;
+static mut DAX_REVOKE_HANDLER: Option<fn(&'static [u8])> = None;
+
+fn device() -> Result<&'static mut FsDevice, FsError> {
+    unsafe { (*core::ptr::addr_of_mut!(FS_DEVICE)).as_mut().ok_or(FsError::NoDevice) }
+}
+
+fn get_u32(buf: &[u8], off: usize) -> u32 {
+    let mut b = [0u8; 4];
+    b.copy_from_slice(&buf[off..off + 4]);
+    u32::from_le_bytes(b)
+}
+
+fn get_u64(buf: &[u8], off: usize) -> u64 {
+    let mut b = [0u8; 8];
+    b.copy_from_slice(&buf[off..off + 8]);
+    u64::from_le_bytes(b)
+}
+
+fn put_u32(buf: &mut [u8], off: usize, val: u32) {
+    buf[off..off + 4].copy_from_slice(&val.to_le_bytes());
+}
+
+fn put_u64(buf: &mut [u8], off: usize, val: u64) {
+    buf[off..off + 8].copy_from_slice(&val.to_le_bytes());
+}
+
+impl FsDevice {
+    fn next_unique(&mut self) -> u64 {
+        self.unique = self.unique.wrapping_add(1);
+        self.unique
+    }
+
+    fn in_header(&mut self, opcode: u32, nodeid: u64, args_len: usize) -> ([u8; IN_HEADER_LEN], u64) {
+        let unique = self.next_unique();
+        let mut hdr = [0u8; IN_HEADER_LEN];
+        put_u32(&mut hdr, 0, (IN_HEADER_LEN + args_len) as u32);
+        put_u32(&mut hdr, 4, opcode);
+        put_u64(&mut hdr, 8, unique);
+        put_u64(&mut hdr, 16, nodeid);
+        // uid, gid y pid a 0: el guest actúa como root frente a virtiofsd
+        (hdr, unique)
+    }
+
+    /// Envía una petición por la cola de peticiones y espera la respuesta.
+    ///
+    /// Devuelve los bytes de respuesta que siguen al `fuse_out_header`: primero se
+    /// llena `reply` y después `data`.
+    fn call(
+        &mut self,
+        opcode: u32,
+        nodeid: u64,
+        args: &[&[u8]],
+        reply: &mut [u8],
+        data: Option<&mut [u8]>,
+    ) -> Result<usize, FsError> {
+        let args_len: usize = args.iter().map(|a| a.len()).sum();
+        let (in_header, unique) = self.in_header(opcode, nodeid, args_len);
+        let mut out_header = [0u8; OUT_HEADER_LEN];
+
+        let mut segments = [Segment::EMPTY; 6];
+        let mut n = 0;
+        segments[n] = Segment::readable(&in_header);
+        n += 1;
+        for arg in args.iter().filter(|a| !a.is_empty()) {
+            segments[n] = Segment::readable(arg);
+            n += 1;
+        }
+        segments[n] = Segment::writable(&mut out_header);
+        n += 1;
+        let mut capacity = reply.len();
+        if !reply.is_empty() {
+            segments[n] = Segment::writable(reply);
+            n += 1;
+        }
+        if let Some(data) = data.filter(|d| !d.is_empty()) {
+            capacity += data.len();
+            segments[n] = Segment::writable(data);
+            n += 1;
+        }
+
+        let head = self.request.add(&segments[..n]).ok_or(FsError::QueueFull)?;
+        self.request.notify();
+        if self.request.wait_used(head, DEFAULT_SPIN_LIMIT).is_none() {
+            // El dispositivo aún puede escribir en las cabeceras y respuestas, que están
+            // en la pila: la cola se reinicia antes de devolver el error
+            self.request.mark_stalled();
+            let result = self.recover();
+            watchdog::report("virtio-fs", result);
+            return Err(FsError::Timeout);
+        }
+
+        let out_len = get_u32(&out_header, 0) as usize;
+        let error = get_u32(&out_header, 4) as i32;
+        events::record(Event {
+            desc: head,
+            op: opcode,
+            bytes: out_len.saturating_sub(OUT_HEADER_LEN) as u32,
+            latency: self.request.last_latency(),
+            error: -error,
+            ..self.request.event(Category::Fs, EventKind::Request)
+        });
+        if get_u64(&out_header, 8) != unique || out_len < OUT_HEADER_LEN {
+            return Err(FsError::Protocol);
+        }
+        if error < 0 {
+            return Err(FsError::Fuse(-error));
+        }
+        let payload = out_len - OUT_HEADER_LEN;
+        if payload > capacity {
+            return Err(FsError::Protocol);
+        }
+        Ok(payload)
+    }
+
+    fn fuse_init(&mut self) -> Result<(), FsError> {
+        let mut init_in = [0u8; INIT_IN_LEN];
+        put_u32(&mut init_in, 0, FUSE_KERNEL_VERSION);
+        put_u32(&mut init_in, 4, FUSE_KERNEL_MINOR_VERSION);
+        put_u32(&mut init_in, 8, READ_CHUNK as u32); // max_readahead
+        if self.dax.is_some() {
+            put_u32(&mut init_in, 12, FUSE_MAP_ALIGNMENT);
+        }
+        let mut init_out = [0u8; INIT_OUT_LEN];
+        let n = self.call(FUSE_INIT, 0, &[&init_in], &mut init_out, None)?;
+        // fuse_init_out mínimo (FUSE 7.x) incluye al menos major, minor, readahead y flags
+        if n < 16 || get_u32(&init_out, 0) != FUSE_KERNEL_VERSION {
+            return Err(FsError::Protocol);
+        }
+        self.minor = get_u32(&init_out, 4).min(FUSE_KERNEL_MINOR_VERSION);
+        // map_alignment es log2 de la alineación exigida por el host para moffset/foffset
+        let flags = get_u32(&init_out, 12);
+        if flags & FUSE_MAP_ALIGNMENT != 0 && n >= 32 {
+            let map_alignment = get_u32(&init_out, 28) >> 16; // u16 tras max_pages
+            if 1u64 << map_alignment.min(63) > DAX_PAGE_SIZE {
+                self.dax = None;
+            }
+        }
+        Ok(())
+    }
+}
+
+pub static DRIVER: Driver = Driver {
+    name: "virtio-fs",
+    matches: &[Match::Virtio(VIRTIO_ID_FS)],
+    max_instances: 1,
+    probe,
+    remove,
+};
+
+fn probe(dev: VirtioDevice) -> Result<usize, &'static str> {
+    attach(dev).map(|()| 0).map_err(|e| e.as_str())
+}
+
+fn remove(_instance: usize) {
+    // Las proyecciones DAX vivas se desmapean antes de soltar el dispositivo: su dueño
+    // recibe cada una mientras aún es legible, para copiarla o dejar de usarla
+    if let Ok(fs) = device() {
+        if let Some(window) = fs.dax.as_mut() {
+            for mapping in window.mappings.iter_mut().filter_map(Option::take) {
+                if let Some(handler) = unsafe { DAX_REVOKE_HANDLER } {
+                    handler(unsafe { core::slice::from_raw_parts(mapping.virt as *const u8, mapping.len as usize) });
+                }
+                hal().unmap_shared(mapping.virt, mapping.len as usize);
+            }
+        }
+    }
+    unsafe { FS_DEVICE = None; }
+}
+
+/// Registra quién suelta las proyecciones de [`map_file`] cuando se retira el
+/// dispositivo. `handler` recibe la proyección entera (desde la dirección que devolvió
+/// `map_file`) justo antes de desmapearla y no debe llamar a [`unmap_file`].
+pub fn set_dax_revoke_handler(handler: fn(&'static [u8])) {
+    unsafe { DAX_REVOKE_HANDLER = Some(handler); }
+}
+
+/// Configura las colas del dispositivo virtio-fs y negocia FUSE_INIT.
+fn attach(dev: VirtioDevice) -> Result<(), FsError> {
+    let mut transport = VirtioPci::new(dev).map_err(FsError::Transport)?;
+    transport.begin_init(0).map_err(FsError::Transport)?;
+    let queues = transport
+        .setup_queue(0, DEFAULT_QUEUE_SIZE)
+        .and_then(|hiprio| Ok((hiprio, transport.setup_queue(1, DEFAULT_QUEUE_SIZE)?)));
+    let (mut hiprio, mut request) = match queues {
+        Ok(queues) => queues,
+        Err(e) => {
+            transport.fail();
+            return Err(FsError::Transport(e));
+        }
+    };
+    transport.finish_init();
+    hiprio.enable_watchdog();
+    request.enable_watchdog();
+
+    let mut tag = [0u8; 36];
+    transport.config_read_bytes(0, &mut tag);
+    let dax = transport
+        .shm_region(VIRTIO_FS_SHMCAP_ID_CACHE)
+        .filter(|region| region.phys % DAX_PAGE_SIZE == 0 && region.len >= DAX_PAGE_SIZE)
+        .map(|region| DaxWindow { region, mappings: [None; MAX_DAX_MAPPINGS] });
+    let mut fs = FsDevice {
+        transport,
+        hiprio,
+        request,
+        unique: 0,
+        minor: 0,
+        tag,
+        dax,
+        recovering: false,
+    };
+    if let Err(e) = fs.fuse_init() {
+        fs.transport.fail();
+        return Err(e);
+    }
+    unsafe { FS_DEVICE = Some(fs); }
+    watchdog::register("virtio-fs", watchdog_stalled, watchdog_recover);
+    Ok(())
+}
+
+/// Etiqueta (tag) del sistema de ficheros exportado por el host.
+pub fn tag() -> Option<&'static str> {
+    let fs = device().ok()?;
+    let len = fs.tag.iter().position(|&b| b == 0).unwrap_or(fs.tag.len());
+    core::str::from_utf8(&fs.tag[..len]).ok()
+}
+
+/// Versión menor de FUSE negociada en FUSE_INIT.
+pub fn protocol_minor() -> Option<u32> {
+    device().ok().map(|fs| fs.minor)
+}
+
+/// Busca `name` dentro del directorio `parent`.
+pub fn lookup(parent: u64, name: &str) -> Result<Entry, FsError> {
+    device()?.lookup(parent, name)
+}
+
+/// Libera `nlookup` referencias sobre `nodeid` (FORGET, sin respuesta).
+pub fn forget(nodeid: u64, nlookup: u64) {
+    if let Ok(fs) = device() {
+        fs.forget(nodeid, nlookup);
+    }
+}
+
+fn put_node(nodeid: u64) {
+    if let Ok(fs) = device() {
+        fs.put_node(nodeid);
+    }
+}
+
+pub fn getattr(nodeid: u64) -> Result<FileAttr, FsError> {
+    device()?.getattr(nodeid)
+}
+
+/// Resuelve una ruta absoluta mediante LOOKUP sucesivos desde la raíz (nodeid 1).
+///
+/// El nodo devuelto conserva una referencia que hay que liberar con [`forget`].
+pub fn resolve(path: &str) -> Result<Entry, FsError> {
+    device()?.resolve(path)
+}
+
+/// Atributos del fichero o directorio en `path`.
+pub fn stat(path: &str) -> Result<FileAttr, FsError> {
+    let entry = resolve(path)?;
+    put_node(entry.nodeid);
+    Ok(entry.attr)
+}
+
+fn open_node(nodeid: u64, opcode: u32) -> Result<u64, FsError> {
+    device()?.open_node(nodeid, opcode)
+}
+
+/// Abre un fichero regular en solo lectura.
+pub fn open(path: &str) -> Result<File, FsError> {
+    device()?.open(path)
+}
+
+fn release(nodeid: u64, fh: u64, opcode: u32) {
+    if let Ok(fs) = device() {
+        fs.release(nodeid, fh, opcode);
+    }
+}
+
+impl FsDevice {
+    fn lookup(&mut self, parent: u64, name: &str) -> Result<Entry, FsError> {
+        if name.is_empty() || name.len() > NAME_MAX || name.contains('/') {
+            return Err(FsError::InvalidPath);
+        }
+        // El nombre viaja terminado en NUL
+        let mut name_buf = [0u8; NAME_MAX + 1];
+        name_buf[..name.len()].copy_from_slice(name.as_bytes());
+        let mut entry_out = [0u8; ENTRY_OUT_LEN];
+        let n = self.call(FUSE_LOOKUP, parent, &[&name_buf[..name.len() + 1]], &mut entry_out, None)?;
+        if n < ENTRY_OUT_LEN {
+            return Err(FsError::Protocol);
+        }
+        let nodeid = get_u64(&entry_out, 0);
+        if nodeid == 0 {
+            // nodeid 0 equivale a una entrada negativa cacheable
+            return Err(FsError::Fuse(2));
+        }
+        Ok(Entry { nodeid, attr: FileAttr::parse(&entry_out[40..]) })
+    }
+
+    fn forget(&mut self, nodeid: u64, nlookup: u64) {
+        let mut forget_in = [0u8; 8];
+        put_u64(&mut forget_in, 0, nlookup);
+        let (in_header, _) = self.in_header(FUSE_FORGET, nodeid, forget_in.len());
+        let segments = [Segment::readable(&in_header), Segment::readable(&forget_in)];
+        if let Some(head) = self.hiprio.add(&segments) {
+            self.hiprio.notify();
+            // Solo hace falta recuperar los descriptores: FORGET no tiene respuesta
+            if self.hiprio.wait_used(head, DEFAULT_SPIN_LIMIT).is_none() {
+                // Las cabeceras están en la pila: la cola no puede quedar con ellas publicadas
+                self.hiprio.mark_stalled();
+                let result = self.recover();
+                watchdog::report("virtio-fs", result);
+            }
+        }
+    }
+
+    fn put_node(&mut self, nodeid: u64) {
+        if nodeid != FUSE_ROOT_ID {
+            self.forget(nodeid, 1);
+        }
+    }
+
+    fn getattr(&mut self, nodeid: u64) -> Result<FileAttr, FsError> {
+        let getattr_in = [0u8; 16];
+        let mut attr_out = [0u8; ATTR_OUT_LEN];
+        let n = self.call(FUSE_GETATTR, nodeid, &[&getattr_in], &mut attr_out, None)?;
+        if n < ATTR_OUT_LEN {
+            return Err(FsError::Protocol);
+        }
+        Ok(FileAttr::parse(&attr_out[16..]))
+    }
+
+    fn resolve(&mut self, path: &str) -> Result<Entry, FsError> {
+        let mut current = Entry { nodeid: FUSE_ROOT_ID, attr: FileAttr::default() };
+        let mut is_root = true;
+        for component in path.split('/').filter(|c| !c.is_empty() && *c != ".") {
+            let next = self.lookup(current.nodeid, component);
+            self.put_node(current.nodeid);
+            current = next?;
+            is_root = false;
+        }
+        if is_root {
+            current.attr = self.getattr(FUSE_ROOT_ID)?;
+        }
+        Ok(current)
+    }
+
+    fn open_node(&mut self, nodeid: u64, opcode: u32) -> Result<u64, FsError> {
+        let mut open_in = [0u8; 8];
+        put_u32(&mut open_in, 0, O_RDONLY);
+        let mut open_out = [0u8; OPEN_OUT_LEN];
+        let n = self.call(opcode, nodeid, &[&open_in], &mut open_out, None)?;
+        if n < OPEN_OUT_LEN {
+            return Err(FsError::Protocol);
+        }
+        Ok(get_u64(&open_out, 0))
+    }
+
+    fn open(&mut self, path: &str) -> Result<File, FsError> {
+        let entry = self.resolve(path)?;
+        if entry.attr.is_dir() {
+            self.put_node(entry.nodeid);
+            return Err(FsError::Fuse(21));
+        }
+        match self.open_node(entry.nodeid, FUSE_OPEN) {
+            Ok(fh) => Ok(File { nodeid: entry.nodeid, fh, attr: entry.attr }),
+            Err(e) => {
+                self.put_node(entry.nodeid);
+                Err(e)
+            }
+        }
+    }
+
+    fn release(&mut self, nodeid: u64, fh: u64, opcode: u32) {
+        let mut release_in = [0u8; RELEASE_IN_LEN];
+        put_u64(&mut release_in, 0, fh);
+        let _ = self.call(opcode, nodeid, &[&release_in], &mut [], None);
+    }
+}
+
+/// FUSE_READ en bloques de hasta `READ_CHUNK` bytes, directamente sobre `buf`.
+pub fn read(nodeid: u64, fh: u64, offset: u64, buf: &mut [u8]) -> Result<usize, FsError> {
+    let fs = device()?;
+    let mut done = 0;
+    while done < buf.len() {
+        let chunk = (buf.len() - done).min(READ_CHUNK);
+        let mut read_in = [0u8; READ_IN_LEN];
+        put_u64(&mut read_in, 0, fh);
+        put_u64(&mut read_in, 8, offset + done as u64);
+        put_u32(&mut read_in, 16, chunk as u32);
+        let n = fs.call(FUSE_READ, nodeid, &[&read_in], &mut [], Some(&mut buf[done..done + chunk]))?;
+        done += n;
+        if n < chunk {
+            break; // fin de fichero
+        }
+    }
+    Ok(done)
+}
+
+/// Lee el fichero completo en `buf`. Falla con `BufferTooSmall` si no cabe.
+pub fn read_file(path: &str, buf: &mut [u8]) -> Result<usize, FsError> {
+    let file = open(path)?;
+    if file.attr.size > buf.len() as u64 {
+        file.close();
+        return Err(FsError::BufferTooSmall);
+    }
+    let result = file.read_at(0, &mut buf[..file.attr.size as usize]);
+    file.close();
+    result
+}
+
+/// Recorre el directorio `path` (OPENDIR/READDIR/RELEASEDIR) llamando a `f` por cada
+/// entrada, sin incluir `.` ni `..`. Si `f` devuelve `false` se detiene el recorrido.
+/// Devuelve el número de entradas visitadas.
+pub fn read_dir<F>(path: &str, mut f: F) -> Result<usize, FsError>
+where
+    F: FnMut(&DirEntry) -> bool,
+{
+    let entry = resolve(path)?;
+    if !entry.attr.is_dir() {
+        put_node(entry.nodeid);
+        return Err(FsError::Fuse(20));
+    }
+    let fh = match open_node(entry.nodeid, FUSE_OPENDIR) {
+        Ok(fh) => fh,
+        Err(e) => {
+            put_node(entry.nodeid);
+            return Err(e);
+        }
+    };
+    let result = read_dir_entries(entry.nodeid, fh, &mut f);
+    release(entry.nodeid, fh, FUSE_RELEASEDIR);
+    put_node(entry.nodeid);
+    result
+}
+
+fn read_dir_entries(nodeid: u64, fh: u64, f: &mut dyn FnMut(&DirEntry) -> bool) -> Result<usize, FsError> {
+    let mut buf = [0u8; READDIR_BUF_SIZE];
+    let mut offset = 0u64;
+    let mut count = 0;
+    loop {
+        let fs = device()?;
+        let mut read_in = [0u8; READ_IN_LEN];
+        put_u64(&mut read_in, 0, fh);
+        put_u64(&mut read_in, 8, offset);
+        put_u32(&mut read_in, 16, READDIR_BUF_SIZE as u32);
+        let n = fs.call(FUSE_READDIR, nodeid, &[&read_in], &mut [], Some(&mut buf))?;
+        if n == 0 {
+            return Ok(count);
+        }
+        let mut pos = 0;
+        while pos + DIRENT_HEADER_LEN <= n {
+            let ino = get_u64(&buf, pos);
+            let off = get_u64(&buf, pos + 8);
+            let namelen = get_u32(&buf, pos + 16) as usize;
+            let kind = get_u32(&buf, pos + 20);
+            let name_end = pos + DIRENT_HEADER_LEN + namelen;
+            if namelen == 0 || name_end > n {
+                return Err(FsError::Protocol);
+            }
+            offset = off;
+            if let Ok(name) = core::str::from_utf8(&buf[pos + DIRENT_HEADER_LEN..name_end]) {
+                if name != "." && name != ".." {
+                    count += 1;
+                    if !f(&DirEntry { ino, kind, name }) {
+                        return Ok(count);
+                    }
+                }
+            }
+            // Cada fuse_dirent está alineado a 8 bytes
+            pos = (name_end + 7) & !7;
+        }
+    }
+}
+
+/// `true` si el dispositivo expone una ventana DAX utilizable.
+pub fn dax_available() -> bool {
+    device().map(|fs| fs.dax.is_some()).unwrap_or(false)
+}
+
+/// Proyecta el fichero `path` completo en la ventana DAX (FUSE_SETUPMAPPING) y lo
+/// mapea como memoria de solo lectura y NX. El contenido no se copia a la RAM del guest.
+///
+/// La proyección dura hasta [`unmap_file`]. Un fichero vacío no ocupa la ventana: se
+/// devuelve un slice vacío.
+pub fn map_file(path: &str) -> Result<&'static [u8], FsError> {
+    if !dax_available() {
+        return Err(FsError::DaxUnavailable);
+    }
+    if path.len() > DAX_PATH_MAX {
+        return Err(FsError::InvalidPath);
+    }
+    let file = open(path)?;
+    if file.attr.size == 0 {
+        file.close();
+        return Ok(&[]);
+    }
+    match setup_mapping(&file, path) {
+        Ok(data) => Ok(data),
+        Err(e) => {
+            file.close();
+            Err(e)
+        }
+    }
+}
+
+fn setup_mapping(file: &File, path: &str) -> Result<&'static [u8], FsError> {
+    let size = file.attr.size;
+    let fs = device()?;
+    let window = fs.dax.as_ref().ok_or(FsError::DaxUnavailable)?;
+    let slot = window.mappings.iter().position(|m| m.is_none()).ok_or(FsError::DaxWindowFull)?;
+    let len = (size + DAX_PAGE_SIZE - 1) & !(DAX_PAGE_SIZE - 1);
+    let moffset = window.find_free(len).ok_or(FsError::DaxWindowFull)?;
+    let phys = window.region.phys + moffset;
+    send_setupmapping(fs, file.nodeid, file.fh, moffset, len)?;
+
+    let virt = hal().map_shared_readonly(phys, len as usize);
+    if virt.is_null() {
+        remove_mapping(fs, file.nodeid, moffset, len);
+        return Err(FsError::DaxUnavailable);
+    }
+    if let Some(window) = fs.dax.as_mut() {
+        let mut mapping = DaxMapping {
+            nodeid: file.nodeid,
+            fh: file.fh,
+            moffset,
+            len,
+            virt: virt as usize,
+            path: [0; DAX_PATH_MAX],
+            path_len: path.len(),
+        };
+        mapping.path[..path.len()].copy_from_slice(path.as_bytes());
+        window.mappings[slot] = Some(mapping);
+    }
+    Ok(unsafe { core::slice::from_raw_parts(virt, size as usize) })
+}
+
+fn send_setupmapping(fs: &mut FsDevice, nodeid: u64, fh: u64, moffset: u64, len: u64) -> Result<(), FsError> {
+    let mut setup_in = [0u8; SETUPMAPPING_IN_LEN];
+    put_u64(&mut setup_in, 0, fh);
+    put_u64(&mut setup_in, 8, 0); // foffset
+    put_u64(&mut setup_in, 16, len);
+    put_u64(&mut setup_in, 24, FUSE_SETUPMAPPING_FLAG_READ);
+    put_u64(&mut setup_in, 32, moffset);
+    match fs.call(FUSE_SETUPMAPPING, nodeid, &[&setup_in], &mut [], None) {
+        Err(FsError::Fuse(38)) => Err(FsError::DaxUnavailable), // ENOSYS
+        other => other.map(|_| ()),
+    }
+}
+
+fn remove_mapping(fs: &mut FsDevice, nodeid: u64, moffset: u64, len: u64) {
+    // fuse_removemapping_in { count } + fuse_removemapping_one { moffset, len }
+    let mut remove_in = [0u8; 4];
+    put_u32(&mut remove_in, 0, 1);
+    let mut one = [0u8; 16];
+    put_u64(&mut one, 0, moffset);
+    put_u64(&mut one, 8, len);
+    let _ = fs.call(FUSE_REMOVEMAPPING, nodeid, &[&remove_in, &one], &mut [], None);
+}
+
+/// Deshace una proyección creada con [`map_file`] (FUSE_REMOVEMAPPING, desmapeo y
+/// cierre del fichero). `data` deja de ser accesible.
+pub fn unmap_file(data: &'static [u8]) -> Result<(), FsError> {
+    if data.is_empty() {
+        return Ok(());
+    }
+    let fs = device()?;
+    let window = fs.dax.as_mut().ok_or(FsError::DaxUnavailable)?;
+    let addr = data.as_ptr() as usize;
+    let slot = window
+        .mappings
+        .iter()
+        .position(|m| matches!(m, Some(m) if m.virt == addr))
+        .ok_or(FsError::InvalidPath)?;
+    let mapping = window.mappings[slot].take().ok_or(FsError::InvalidPath)?;
+    hal().unmap_shared(mapping.virt, mapping.len as usize);
+    remove_mapping(fs, mapping.nodeid, mapping.moffset, mapping.len);
+    File { nodeid: mapping.nodeid, fh: mapping.fh, attr: FileAttr::default() }.close();
+    Ok(())
+}
+
+fn watchdog_stalled(deadline: u64) -> bool {
+    device().is_ok_and(|fs| fs.hiprio.stalled(deadline) || fs.request.stalled(deadline))
+}
+
+fn watchdog_recover() -> Result<Recovery, &'static str> {
+    device().map_err(|e| e.as_str())?.recover()
+}
+
+impl FsDevice {
+    /// Recupera las colas atascadas (o el dispositivo entero) y, tras un reset del
+    /// dispositivo, abre una sesión FUSE nueva y rehace las proyecciones DAX.
+    fn recover(&mut self) -> Result<Recovery, &'static str> {
+        let FsDevice { transport, hiprio, request, .. } = self;
+        let recovery = watchdog::recover_device(transport, |f| {
+            f(hiprio)?;
+            f(request)
+        })
+        .map_err(|e| FsError::Transport(e).as_str())?;
+        // Si la sesión nueva vuelve a agotar el tiempo, la recuperación anidada solo
+        // reinicia el transporte y el error llega a quien abrió la sesión
+        if recovery == Recovery::DeviceReset && !self.recovering {
+            self.recovering = true;
+            let session = self.new_session();
+            self.recovering = false;
+            session?;
+        }
+        Ok(recovery)
+    }
+
+    fn new_session(&mut self) -> Result<(), &'static str> {
+        // Sesión FUSE nueva: los nodeid y handles anteriores dejan de ser válidos
+        self.unique = 0;
+        self.fuse_init().map_err(|e| e.as_str())?;
+        self.restore_dax_mappings()
+    }
+
+    /// Rehace las proyecciones DAX tras un reset del dispositivo, en los mismos
+    /// desplazamientos de la ventana, para que los punteros entregados sigan siendo válidos.
+    fn restore_dax_mappings(&mut self) -> Result<(), &'static str> {
+        let Some(window) = self.dax.as_ref() else { return Ok(()) };
+        let mappings = window.mappings;
+        for (slot, mapping) in mappings.iter().enumerate() {
+            let Some(mapping) = mapping else { continue };
+            let path = core::str::from_utf8(&mapping.path[..mapping.path_len]).map_err(|_| "virtio-fs: invalid DAX path")?;
+            let file = self.open(path).map_err(|_| "virtio-fs: DAX mapping lost after reset")?;
+            if send_setupmapping(self, file.nodeid, file.fh, mapping.moffset, mapping.len).is_err() {
+                self.release(file.nodeid, file.fh, FUSE_RELEASE);
+                self.put_node(file.nodeid);
+                return Err("virtio-fs: DAX mapping lost after reset");
+            }
+            if let Some(m) = self.dax.as_mut().and_then(|w| w.mappings[slot].as_mut()) {
+                m.nodeid = file.nodeid;
+                m.fh = file.fh;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{self, fs::FsBackend};
+    use crate::transport::VIRTIO_F_RING_RESET;
+
+    fn install() -> std::sync::MutexGuard<'static, ()> {
+        let guard = mock::install(FsBackend::new());
+        attach(mock::pci_device()).unwrap();
+        guard
+    }
+
+    fn outstanding_lookups() -> u64 {
+        mock::with_backend(|b: &mut FsBackend| b.outstanding_lookups())
+    }
+
+    #[test]
+    fn init_negotiates_fuse() {
+        let _guard = install();
+        assert_eq!(tag(), Some(mock::fs::TAG));
+        assert_eq!(protocol_minor(), Some(FUSE_KERNEL_MINOR_VERSION));
+        assert!(!dax_available());
+        assert_eq!(mock::with_backend(|b: &mut FsBackend| b.opcodes.clone()), [FUSE_INIT]);
+    }
+
+    #[test]
+    fn stat_parses_attributes() {
+        let _guard = install();
+        let attr = stat("/hello.txt").unwrap();
+        assert!(attr.is_file());
+        assert_eq!(attr.ino, 4);
+        assert_eq!(attr.size, mock::fs::HELLO.len() as u64);
+        assert_eq!(attr.nlink, 1);
+        assert_eq!(attr.blksize, 4096);
+        assert!(stat("/models").unwrap().is_dir());
+        assert!(stat("/").unwrap().is_dir());
+        assert_eq!(outstanding_lookups(), 0);
+    }
+
+    #[test]
+    fn missing_paths_return_errno() {
+        let _guard = install();
+        assert_eq!(stat("/nope").map(|_| ()), Err(FsError::Fuse(2)));
+        assert_eq!(stat("/models/nope/deeper").map(|_| ()), Err(FsError::Fuse(2)));
+        assert_eq!(open("/models").map(|_| ()), Err(FsError::Fuse(21)));
+        assert_eq!(read_dir("/hello.txt", |_| true), Err(FsError::Fuse(20)));
+        assert_eq!(lookup(FUSE_ROOT_ID, "a/b").map(|_| ()), Err(FsError::InvalidPath));
+        assert_eq!(outstanding_lookups(), 0);
+    }
+
+    #[test]
+    fn read_small_file() {
+        let _guard = install();
+        let mut buf = [0u8; 64];
+        let n = read_file("/hello.txt", &mut buf).unwrap();
+        assert_eq!(&buf[..n], mock::fs::HELLO);
+        let mut small = [0u8; 4];
+        assert_eq!(read_file("/hello.txt", &mut small), Err(FsError::BufferTooSmall));
+        mock::with_backend(|b: &mut FsBackend| {
+            assert_eq!(b.outstanding_lookups(), 0);
+            assert!(b.open_handles.is_empty());
+        });
+    }
+
+    #[test]
+    fn large_read_is_chunked() {
+        let _guard = install();
+        let mut buf = vec![0u8; mock::fs::TINY_LEN + 10];
+        let n = read_file("/models/tiny.bin", &mut buf).unwrap();
+        assert_eq!(n, mock::fs::TINY_LEN);
+        assert!(buf[..n].iter().enumerate().all(|(i, &b)| b == mock::fs::pattern_byte(i)));
+        let reads = mock::with_backend(|b: &mut FsBackend| b.opcodes.iter().filter(|&&op| op == FUSE_READ).count());
+        assert_eq!(reads, mock::fs::TINY_LEN.div_ceil(READ_CHUNK));
+
+        // Lectura parcial desde un desplazamiento
+        let file = open("/models/tiny.bin").unwrap();
+        let mut part = [0u8; 16];
+        assert_eq!(file.read_at(1000, &mut part), Ok(16));
+        assert_eq!(part[0], mock::fs::pattern_byte(1000));
+        assert_eq!(file.read_at(mock::fs::TINY_LEN as u64 - 4, &mut part), Ok(4));
+        file.close();
+        assert_eq!(outstanding_lookups(), 0);
+    }
+
+    #[test]
+    fn read_dir_parses_dirents() {
+        let _guard = install();
+        let mut names = Vec::new();
+        let count = read_dir("/", |entry| {
+            names.push((entry.name.to_string(), entry.ino));
+            true
+        })
+        .unwrap();
+        assert_eq!(count, 2);
+        assert_eq!(names, [("models".to_string(), 2), ("hello.txt".to_string(), 4)]);
+
+        // El recorrido se detiene cuando la función devuelve false
+        assert_eq!(read_dir("/", |_| false), Ok(1));
+        mock::with_backend(|b: &mut FsBackend| {
+            assert_eq!(b.outstanding_lookups(), 0);
+            assert!(b.open_handles.is_empty());
+        });
+    }
+
+    #[test]
+    fn map_file_without_dax_window() {
+        let _guard = install();
+        assert_eq!(map_file("/models/tiny.bin").map(|_| ()), Err(FsError::DaxUnavailable));
+    }
+
+    fn timed_out_request_resets_queue(features: u64, expected_device_reset: bool) {
+        let mut backend = FsBackend::new();
+        backend.extra_features = features;
+        let _guard = mock::install(backend);
+        attach(mock::pci_device()).unwrap();
+        mock::with_backend(|b: &mut FsBackend| b.hold_opcode = Some(FUSE_GETATTR));
+        let resets = mock::device_resets();
+        assert_eq!(getattr(FUSE_ROOT_ID).map(|_| ()), Err(FsError::Timeout));
+        assert_eq!(mock::device_resets() > resets, expected_device_reset);
+        // Tras el timeout no queda ningún buffer de la pila publicado en la cola
+        let fs = device().unwrap();
+        assert_eq!(fs.request.in_flight(), 0);
+        assert_eq!(fs.request.num_free(), DEFAULT_QUEUE_SIZE);
+
+        // La cola recuperada vuelve a funcionar
+        mock::with_backend(|b: &mut FsBackend| b.hold_opcode = None);
+        assert!(stat("/hello.txt").unwrap().is_file());
+        assert!(stat("/").unwrap().is_dir());
+    }
+
+    #[test]
+    fn timeout_with_queue_reset() {
+        timed_out_request_resets_queue(VIRTIO_F_RING_RESET, false);
+    }
+
+    #[test]
+    fn timeout_with_device_reset() {
+        timed_out_request_resets_queue(0, true);
+        let inits = mock::with_backend(|b: &mut FsBackend| b.opcodes.iter().filter(|&&op| op == FUSE_INIT).count());
+        assert_eq!(inits, 2);
+    }
+
+    fn install_dax() -> std::sync::MutexGuard<'static, ()> {
+        let guard = mock::install(FsBackend::with_dax(2 * DAX_PAGE_SIZE as usize));
+        attach(mock::pci_device()).unwrap();
+        guard
+    }
+
+    fn is_tiny(data: &[u8]) -> bool {
+        data.len() == mock::fs::TINY_LEN && data.iter().enumerate().all(|(i, &b)| b == mock::fs::pattern_byte(i))
+    }
+
+    #[test]
+    fn map_file_through_dax_window() {
+        let _guard = install_dax();
+        assert!(dax_available());
+        let hello = map_file("/hello.txt").unwrap();
+        assert_eq!(hello, mock::fs::HELLO);
+        let tiny = map_file("/models/tiny.bin").unwrap();
+        assert!(is_tiny(tiny));
+        mock::with_backend(|b: &mut FsBackend| {
+            assert_eq!(b.mappings.iter().map(|(&m, &(node, _))| (m, node)).collect::<Vec<_>>(), [(0, 4), (DAX_PAGE_SIZE, 3)]);
+            assert_eq!(b.open_handles.len(), 2);
+        });
+
+        // Sin hueco libre el fichero se cierra y la ventana no cambia
+        assert_eq!(map_file("/hello.txt").map(|_| ()), Err(FsError::DaxWindowFull));
+        assert_eq!(mock::with_backend(|b: &mut FsBackend| b.open_handles.len()), 2);
+
+        // REMOVEMAPPING libera el hueco, que reutiliza la siguiente proyección
+        let hello_addr = hello.as_ptr();
+        assert_eq!(unmap_file(hello), Ok(()));
+        mock::with_backend(|b: &mut FsBackend| {
+            assert_eq!(b.mappings.keys().copied().collect::<Vec<_>>(), [DAX_PAGE_SIZE]);
+            assert_eq!(b.open_handles.len(), 1);
+        });
+        let again = map_file("/hello.txt").unwrap();
+        assert_eq!(again.as_ptr(), hello_addr);
+        assert_eq!(again, mock::fs::HELLO);
+
+        assert_eq!(unmap_file(again), Ok(()));
+        assert_eq!(unmap_file(tiny), Ok(()));
+        assert_eq!(unmap_file(tiny), Err(FsError::InvalidPath));
+        mock::with_backend(|b: &mut FsBackend| {
+            assert!(b.mappings.is_empty());
+            assert!(b.open_handles.is_empty());
+            assert_eq!(b.outstanding_lookups(), 0);
+        });
+    }
+
+    #[test]
+    fn empty_file_maps_to_empty_slice() {
+        let _guard = install_dax();
+        let data = map_file("/models/empty.bin").unwrap();
+        assert!(data.is_empty());
+        assert_eq!(unmap_file(data), Ok(()));
+        mock::with_backend(|b: &mut FsBackend| {
+            assert!(!b.opcodes.contains(&FUSE_SETUPMAPPING));
+            assert!(b.open_handles.is_empty());
+            assert_eq!(b.outstanding_lookups(), 0);
+        });
+    }
+
+    #[test]
+    fn dax_mappings_survive_device_reset() {
+        let _guard = install_dax();
+        let tiny = map_file("/models/tiny.bin").unwrap();
+        // Sin VIRTIO_F_RING_RESET la recuperación reinicia el dispositivo, que pierde
+        // la sesión FUSE y el contenido de la ventana
+        assert_eq!(watchdog_recover(), Ok(Recovery::DeviceReset));
+        assert!(is_tiny(tiny));
+        let fh = device().unwrap().dax.as_ref().unwrap().mappings[0].unwrap().fh;
+        mock::with_backend(|b: &mut FsBackend| {
+            assert_eq!(b.mappings.get(&0), Some(&(3, DAX_PAGE_SIZE)));
+            assert_eq!(b.open_handles.get(&fh), Some(&3));
+        });
+        assert_eq!(unmap_file(tiny), Ok(()));
+        mock::with_backend(|b: &mut FsBackend| {
+            assert!(b.mappings.is_empty());
+            assert!(b.open_handles.is_empty());
+        });
+    }
+}
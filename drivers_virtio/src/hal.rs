@@ -0,0 +1,138 @@
+//! Capa de abstracción del hardware para los drivers virtio.
+//!
+//! Los drivers no reservan memoria DMA ni frames, no traducen direcciones ni tocan
+//! MMIO o el espacio de configuración PCI por su cuenta: lo hacen a través del [`Hal`]
+//! registrado con [`set_hal`]. `ai_runtime` también toma de aquí los frames de los
+//! modelos que copia a RAM. El kernel registra su implementación antes de
+//! inicializar ningún driver; las pruebas en el host registran un dispositivo
+//! simulado (módulo `mock`).
+
+use core::ptr::{addr_of, addr_of_mut, read_volatile, write_volatile};
+
+const PCI_CONFIG_ADDRESS: u16 = 0xCF8;
+const PCI_CONFIG_DATA: u16 = 0xCFC;
+
+pub trait Hal: Sync {
+    /// Reserva `size` bytes contiguos, a cero, alineados a `align` y accesibles por
+    /// DMA. Devuelve null si no hay memoria.
+    fn dma_alloc(&self, size: usize, align: usize) -> *mut u8;
+
+    /// Reserva frames de 2MiB contiguos para `size` bytes, sin poner a cero. Devuelve
+    /// null si no hay memoria. Se liberan frame a frame con [`Hal::free_frame`].
+    fn alloc_frames(&self, size: usize) -> *mut u8;
+
+    /// Reserva un frame libre de 2MiB. `None` si no queda ninguno.
+    fn alloc_frame(&self) -> Option<usize>;
+
+    /// Devuelve un frame al allocator; vuelve a contar como no notificado al host.
+    fn free_frame(&self, addr: usize);
+
+    /// Free page reporting: reserva un frame libre que aún no se ha notificado al host.
+    /// Mientras el dispositivo lo procesa, el frame figura como usado.
+    fn take_unreported_free_frame(&self) -> Option<usize>;
+
+    /// Devuelve al allocator un frame ya notificado: sigue libre, pero no se vuelve a
+    /// notificar hasta que alguien lo use y lo libere.
+    fn release_reported_frame(&self, addr: usize);
+
+    /// Dirección física (la que ve el dispositivo) de una dirección virtual del kernel.
+    fn virt_to_phys(&self, virt: usize) -> u64;
+
+    /// Mapea una región MMIO (RW, NX). Devuelve null si no se pudo mapear.
+    fn map_mmio(&self, phys: u64, size: usize) -> *mut u8;
+
+    /// Mapea memoria física del dispositivo como solo lectura y NX (ventana DAX de
+    /// virtio-fs). Devuelve null si no se pudo mapear.
+    fn map_shared_readonly(&self, phys: u64, size: usize) -> *const u8;
+
+    /// Deshace un mapeo de [`Hal::map_shared_readonly`].
+    fn unmap_shared(&self, virt: usize, size: usize);
+
+    /// Accesos MMIO volátiles. Las pruebas en el host los interceptan para emular
+    /// los registros del dispositivo.
+    ///
+    /// # Safety
+    /// `addr` debe pertenecer a una región devuelta por [`Hal::map_mmio`].
+    unsafe fn mmio_read8(&self, addr: *const u8) -> u8 {
+        read_volatile(addr)
+    }
+
+    /// # Safety
+    /// Igual que [`Hal::mmio_read8`].
+    unsafe fn mmio_read16(&self, addr: *const u16) -> u16 {
+        read_volatile(addr)
+    }
+
+    /// # Safety
+    /// Igual que [`Hal::mmio_read8`].
+    unsafe fn mmio_read32(&self, addr: *const u32) -> u32 {
+        read_volatile(addr)
+    }
+
+    /// # Safety
+    /// Igual que [`Hal::mmio_read8`].
+    unsafe fn mmio_write8(&self, addr: *mut u8, val: u8) {
+        write_volatile(addr, val)
+    }
+
+    /// # Safety
+    /// Igual que [`Hal::mmio_read8`].
+    unsafe fn mmio_write16(&self, addr: *mut u16, val: u16) {
+        write_volatile(addr, val)
+    }
+
+    /// # Safety
+    /// Igual que [`Hal::mmio_read8`].
+    unsafe fn mmio_write32(&self, addr: *mut u32, val: u32) {
+        write_volatile(addr, val)
+    }
+
+    /// Lee un dword del espacio de configuración PCI (por defecto, mecanismo #1 por
+    /// los puertos 0xCF8/0xCFC).
+    fn pci_read_config(&self, bus: u8, slot: u8, func: u8, offset: u8) -> u32 {
+        unsafe {
+            outl(PCI_CONFIG_ADDRESS, config_address(bus, slot, func, offset));
+            inl(PCI_CONFIG_DATA)
+        }
+    }
+
+    fn pci_write_config(&self, bus: u8, slot: u8, func: u8, offset: u8, value: u32) {
+        unsafe {
+            outl(PCI_CONFIG_ADDRESS, config_address(bus, slot, func, offset));
+            outl(PCI_CONFIG_DATA, value);
+        }
+    }
+}
+
+fn config_address(bus: u8, slot: u8, func: u8, offset: u8) -> u32 {
+    (1 << 31)
+        | ((bus as u32) << 16)
+        | ((slot as u32) << 11)
+        | ((func as u32) << 8)
+        | ((offset as u32) & 0xFC)
+}
+
+unsafe fn outl(port: u16, value: u32) {
+    core::arch::asm!("out dx, eax", in("dx") port, in("eax") value, options(nostack, preserves_flags));
+}
+
+unsafe fn inl(port: u16) -> u32 {
+    let value: u32;
+    core::arch::asm!("in eax, dx", in("dx") port, out("eax") value, options(nostack, preserves_flags));
+    value
+}
+
+static mut HAL: Option<&'static dyn Hal> = None;
+
+/// Registra la implementación del HAL. Debe llamarse antes de inicializar cualquier driver.
+pub fn set_hal(hal: &'static dyn Hal) {
+    unsafe { *addr_of_mut!(HAL) = Some(hal); }
+}
+
+/// HAL registrado. Usar un driver sin haber llamado a [`set_hal`] es un error de programación.
+pub fn hal() -> &'static dyn Hal {
+    match unsafe { *addr_of!(HAL) } {
+        Some(hal) => hal,
+        None => panic!("drivers_virtio: HAL no registrado (falta set_hal)"),
+    }
+}
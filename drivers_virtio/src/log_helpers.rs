@@ -1,4 +0,0 @@
-#![allow(dead_code)]
-
-// Eliminar helpers de logging: este archivo debe quedar vacío o solo con comentarios de deprecated.
-// El logging debe hacerse directamente en lib.rs, después de cerrar todos los scopes de préstamos mutables.
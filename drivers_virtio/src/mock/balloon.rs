@@ -0,0 +1,94 @@
+//! Lado del dispositivo de virtio-balloon: registra los PFN inflados y los frames
+//! notificados por free page reporting.
+
+use super::{Backend, Chain, Completion};
+use std::vec;
+use std::vec::Vec;
+
+const VIRTIO_BALLOON_F_STATS_VQ: u64 = 1 << 1;
+const VIRTIO_BALLOON_F_PAGE_REPORTING: u64 = 1 << 5;
+
+const INFLATEQ: u16 = 0;
+const DEFLATEQ: u16 = 1;
+
+pub struct BalloonBackend {
+    /// Objetivo del host, en páginas de 4KiB.
+    pub num_pages: u32,
+    /// Último valor de `actual` escrito por el driver.
+    pub actual: u32,
+    pub stats: bool,
+    pub reporting: bool,
+    /// PFN en manos del host, en orden de inflado.
+    pub inflated: Vec<u32>,
+    /// Segmentos (dirección, longitud) recibidos por `reporting_vq`.
+    pub reported: Vec<(u64, u32)>,
+}
+
+impl BalloonBackend {
+    pub fn new(num_pages: u32) -> Self {
+        BalloonBackend { num_pages, actual: 0, stats: false, reporting: false, inflated: Vec::new(), reported: Vec::new() }
+    }
+
+    fn pfns(chain: &Chain) -> Vec<u32> {
+        chain.readable().chunks_exact(4).map(|b| u32::from_le_bytes(b.try_into().unwrap())).collect()
+    }
+
+    fn reporting_queue(&self) -> Option<u16> {
+        self.reporting.then_some(2 + self.stats as u16)
+    }
+}
+
+impl Backend for BalloonBackend {
+    fn device_type(&self) -> u16 {
+        5
+    }
+
+    fn features(&self) -> u64 {
+        let mut features = 0;
+        if self.stats {
+            features |= VIRTIO_BALLOON_F_STATS_VQ;
+        }
+        if self.reporting {
+            features |= VIRTIO_BALLOON_F_PAGE_REPORTING;
+        }
+        features
+    }
+
+    fn num_queues(&self) -> u16 {
+        2 + self.stats as u16 + self.reporting as u16
+    }
+
+    fn config(&self) -> Vec<u8> {
+        let mut config = vec![0u8; 8];
+        config[0..4].copy_from_slice(&self.num_pages.to_le_bytes());
+        config[4..8].copy_from_slice(&self.actual.to_le_bytes());
+        config
+    }
+
+    fn config_write(&mut self, offset: usize, data: &[u8]) {
+        if offset == 4 && data.len() == 4 {
+            self.actual = u32::from_le_bytes(data.try_into().unwrap());
+        }
+    }
+
+    fn process(&mut self, queue: u16, chain: &Chain) -> Completion {
+        match queue {
+            INFLATEQ => self.inflated.extend(Self::pfns(chain)),
+            DEFLATEQ => {
+                let pfns = Self::pfns(chain);
+                self.inflated.retain(|pfn| !pfns.contains(pfn));
+            }
+            q if Some(q) == self.reporting_queue() => {
+                self.reported.extend(chain.segments.iter().map(|seg| (seg.addr, seg.len)));
+            }
+            // statsq: el buffer se queda en el dispositivo hasta pedir estadísticas nuevas
+            _ => return Completion::Hold,
+        }
+        Completion::Done(0)
+    }
+
+    fn reset(&mut self) {
+        self.inflated.clear();
+        self.actual = 0;
+    }
+}
@@ -0,0 +1,124 @@
+//! Lado del dispositivo de virtio-blk: un disco en memoria.
+
+use super::{Backend, Chain, Completion};
+use std::vec;
+use std::vec::Vec;
+
+const VIRTIO_BLK_F_SIZE_MAX: u64 = 1 << 1;
+const VIRTIO_BLK_F_RO: u64 = 1 << 5;
+const VIRTIO_BLK_F_FLUSH: u64 = 1 << 9;
+
+const VIRTIO_BLK_T_IN: u32 = 0;
+const VIRTIO_BLK_T_OUT: u32 = 1;
+const VIRTIO_BLK_T_FLUSH: u32 = 4;
+const VIRTIO_BLK_T_GET_ID: u32 = 8;
+
+const VIRTIO_BLK_S_OK: u8 = 0;
+const VIRTIO_BLK_S_IOERR: u8 = 1;
+const VIRTIO_BLK_S_UNSUPP: u8 = 2;
+
+const SECTOR_SIZE: usize = 512;
+
+pub const SERIAL: &[u8] = b"mock-blk-serial";
+
+pub struct BlkBackend {
+    pub disk: Vec<u8>,
+    pub read_only: bool,
+    /// `size_max` anunciado (0 = sin VIRTIO_BLK_F_SIZE_MAX).
+    pub size_max: u32,
+    /// Features extra (p. ej. VIRTIO_F_RING_RESET).
+    pub extra_features: u64,
+    /// Retiene las peticiones sin completarlas (simula un dispositivo colgado).
+    pub hold: bool,
+    /// Completa la siguiente petición con VIRTIO_BLK_S_IOERR.
+    pub fail_next: bool,
+    pub flushes: usize,
+    pub requests: usize,
+}
+
+impl BlkBackend {
+    pub fn new(sectors: usize) -> Self {
+        BlkBackend {
+            disk: vec![0; sectors * SECTOR_SIZE],
+            read_only: false,
+            size_max: 0,
+            extra_features: 0,
+            hold: false,
+            fail_next: false,
+            flushes: 0,
+            requests: 0,
+        }
+    }
+
+    fn execute(&mut self, chain: &Chain, kind: u32, sector: u64, payload: &[u8], data_len: usize) -> (u8, usize) {
+        let offset = sector as usize * SECTOR_SIZE;
+        match kind {
+            VIRTIO_BLK_T_IN => match self.disk.get(offset..offset + data_len) {
+                Some(data) => (VIRTIO_BLK_S_OK, chain.write_at(0, data)),
+                None => (VIRTIO_BLK_S_IOERR, 0),
+            },
+            VIRTIO_BLK_T_OUT if self.read_only => (VIRTIO_BLK_S_IOERR, 0),
+            VIRTIO_BLK_T_OUT => match self.disk.get_mut(offset..offset + payload.len()) {
+                Some(data) => {
+                    data.copy_from_slice(payload);
+                    (VIRTIO_BLK_S_OK, 0)
+                }
+                None => (VIRTIO_BLK_S_IOERR, 0),
+            },
+            VIRTIO_BLK_T_FLUSH => {
+                self.flushes += 1;
+                (VIRTIO_BLK_S_OK, 0)
+            }
+            VIRTIO_BLK_T_GET_ID => (VIRTIO_BLK_S_OK, chain.write_at(0, SERIAL)),
+            _ => (VIRTIO_BLK_S_UNSUPP, 0),
+        }
+    }
+}
+
+impl Backend for BlkBackend {
+    fn device_type(&self) -> u16 {
+        2
+    }
+
+    fn features(&self) -> u64 {
+        let mut features = VIRTIO_BLK_F_FLUSH | self.extra_features;
+        if self.read_only {
+            features |= VIRTIO_BLK_F_RO;
+        }
+        if self.size_max != 0 {
+            features |= VIRTIO_BLK_F_SIZE_MAX;
+        }
+        features
+    }
+
+    fn num_queues(&self) -> u16 {
+        1
+    }
+
+    fn config(&self) -> Vec<u8> {
+        let mut config = vec![0u8; 24];
+        config[0..8].copy_from_slice(&((self.disk.len() / SECTOR_SIZE) as u64).to_le_bytes());
+        config[8..12].copy_from_slice(&self.size_max.to_le_bytes());
+        config
+    }
+
+    fn process(&mut self, _queue: u16, chain: &Chain) -> Completion {
+        if self.hold {
+            return Completion::Hold;
+        }
+        self.requests += 1;
+        let readable = chain.readable();
+        let kind = u32::from_le_bytes(readable[0..4].try_into().unwrap());
+        let sector = u64::from_le_bytes(readable[8..16].try_into().unwrap());
+        // El último byte escribible es el estado; lo anterior, los datos
+        let data_len = chain.writable_len() - 1;
+        let (status, written) = if self.fail_next {
+            self.fail_next = false;
+            (VIRTIO_BLK_S_IOERR, 0)
+        } else {
+            self.execute(chain, kind, sector, &readable[16..], data_len)
+        };
+        chain.write_at(data_len, &[status]);
+        Completion::Done(written as u32 + 1)
+    }
+}
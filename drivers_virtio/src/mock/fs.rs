@@ -0,0 +1,309 @@
+//! Lado del dispositivo de virtio-fs: un servidor FUSE mínimo sobre un árbol en memoria.
+//!
+//! ```text
+//! /               nodeid 1
+//! /models         nodeid 2
+//! /models/tiny.bin nodeid 3 (TINY_LEN bytes, patrón `pattern_byte`)
+//! /hello.txt      nodeid 4
+//! /models/empty.bin nodeid 5 (vacío)
+//! ```
+//!
+//! Con [`FsBackend::with_dax`] expone además una ventana DAX: FUSE_SETUPMAPPING copia
+//! el fichero en la memoria de la ventana, como haría la page cache del host.
+
+use super::{Backend, Chain, Completion};
+use std::collections::BTreeMap;
+use std::string::String;
+use std::vec;
+use std::vec::Vec;
+
+const FUSE_LOOKUP: u32 = 1;
+const FUSE_FORGET: u32 = 2;
+const FUSE_GETATTR: u32 = 3;
+const FUSE_OPEN: u32 = 14;
+const FUSE_READ: u32 = 15;
+const FUSE_RELEASE: u32 = 18;
+const FUSE_INIT: u32 = 26;
+const FUSE_OPENDIR: u32 = 27;
+const FUSE_READDIR: u32 = 28;
+const FUSE_RELEASEDIR: u32 = 29;
+const FUSE_SETUPMAPPING: u32 = 48;
+const FUSE_REMOVEMAPPING: u32 = 49;
+
+const ENOENT: i32 = 2;
+const EBADF: i32 = 9;
+const ENOTDIR: i32 = 20;
+const EINVAL: i32 = 22;
+const ENOSYS: i32 = 38;
+
+const IN_HEADER_LEN: usize = 40;
+const ATTR_LEN: usize = 88;
+const S_IFDIR: u32 = 0o040000;
+const S_IFREG: u32 = 0o100000;
+const DT_DIR: u32 = 4;
+const DT_REG: u32 = 8;
+
+pub const TAG: &str = "mockfs";
+pub const HELLO: &[u8] = b"hola desde virtio-fs\n";
+/// Mayor que el bloque de lectura del driver, para forzar varias FUSE_READ.
+pub const TINY_LEN: usize = 300_000;
+
+pub fn pattern_byte(offset: usize) -> u8 {
+    (offset % 251) as u8
+}
+
+struct Node {
+    parent: u64,
+    name: &'static str,
+    dir: bool,
+    data: Vec<u8>,
+}
+
+pub struct FsBackend {
+    nodes: BTreeMap<u64, Node>,
+    /// Referencias LOOKUP pendientes de FORGET por nodo.
+    pub lookups: BTreeMap<u64, u64>,
+    /// Handles abiertos (fh → nodeid).
+    pub open_handles: BTreeMap<u64, u64>,
+    next_fh: u64,
+    /// Opcodes recibidos, en orden.
+    pub opcodes: Vec<u32>,
+    /// Features adicionales que ofrece el dispositivo.
+    pub extra_features: u64,
+    /// Las peticiones con este opcode se retienen sin completarse.
+    pub hold_opcode: Option<u32>,
+    /// Memoria de la ventana DAX (vacía si el dispositivo no la expone).
+    pub dax_window: Vec<u8>,
+    /// Proyecciones activas en la ventana (moffset → (nodeid, len)).
+    pub mappings: BTreeMap<u64, (u64, u64)>,
+}
+
+impl FsBackend {
+    pub fn new() -> Self {
+        let mut nodes = BTreeMap::new();
+        nodes.insert(1, Node { parent: 1, name: "", dir: true, data: Vec::new() });
+        nodes.insert(2, Node { parent: 1, name: "models", dir: true, data: Vec::new() });
+        let tiny = (0..TINY_LEN).map(pattern_byte).collect();
+        nodes.insert(3, Node { parent: 2, name: "tiny.bin", dir: false, data: tiny });
+        nodes.insert(4, Node { parent: 1, name: "hello.txt", dir: false, data: HELLO.to_vec() });
+        nodes.insert(5, Node { parent: 2, name: "empty.bin", dir: false, data: Vec::new() });
+        FsBackend {
+            nodes,
+            lookups: BTreeMap::new(),
+            open_handles: BTreeMap::new(),
+            next_fh: 100,
+            opcodes: Vec::new(),
+            extra_features: 0,
+            hold_opcode: None,
+            dax_window: Vec::new(),
+            mappings: BTreeMap::new(),
+        }
+    }
+
+    /// Servidor con una ventana DAX de `len` bytes.
+    pub fn with_dax(len: usize) -> Self {
+        FsBackend { dax_window: vec![0; len], ..Self::new() }
+    }
+
+    /// Referencias LOOKUP que el driver no ha liberado.
+    pub fn outstanding_lookups(&self) -> u64 {
+        self.lookups.values().sum()
+    }
+
+    fn attr(&self, ino: u64) -> [u8; ATTR_LEN] {
+        let node = &self.nodes[&ino];
+        let mut attr = [0u8; ATTR_LEN];
+        attr[0..8].copy_from_slice(&ino.to_le_bytes());
+        attr[8..16].copy_from_slice(&(node.data.len() as u64).to_le_bytes());
+        attr[16..24].copy_from_slice(&(node.data.len().div_ceil(512) as u64).to_le_bytes());
+        let mode = if node.dir { S_IFDIR | 0o755 } else { S_IFREG | 0o644 };
+        attr[60..64].copy_from_slice(&mode.to_le_bytes());
+        attr[64..68].copy_from_slice(&(if node.dir { 2u32 } else { 1 }).to_le_bytes());
+        attr[80..84].copy_from_slice(&4096u32.to_le_bytes());
+        attr
+    }
+
+    fn open(&mut self, nodeid: u64, want_dir: bool) -> Result<Vec<u8>, i32> {
+        let node = self.nodes.get(&nodeid).ok_or(ENOENT)?;
+        if node.dir != want_dir {
+            return Err(if want_dir { ENOTDIR } else { 21 });
+        }
+        let fh = self.next_fh;
+        self.next_fh += 1;
+        self.open_handles.insert(fh, nodeid);
+        let mut out = vec![0u8; 16];
+        out[0..8].copy_from_slice(&fh.to_le_bytes());
+        Ok(out)
+    }
+
+    fn readdir(&self, nodeid: u64, offset: usize, size: usize) -> Vec<u8> {
+        let mut names: Vec<(u64, String, u32)> = vec![(nodeid, ".".into(), DT_DIR), (self.nodes[&nodeid].parent, "..".into(), DT_DIR)];
+        for (&ino, node) in self.nodes.iter().filter(|(&ino, n)| ino != 1 && n.parent == nodeid) {
+            names.push((ino, node.name.into(), if node.dir { DT_DIR } else { DT_REG }));
+        }
+        let mut out = Vec::new();
+        for (index, (ino, name, kind)) in names.iter().enumerate().skip(offset) {
+            let entry_len = (24 + name.len() + 7) & !7;
+            if out.len() + entry_len > size {
+                break;
+            }
+            out.extend_from_slice(&ino.to_le_bytes());
+            out.extend_from_slice(&(index as u64 + 1).to_le_bytes());
+            out.extend_from_slice(&(name.len() as u32).to_le_bytes());
+            out.extend_from_slice(&kind.to_le_bytes());
+            out.extend_from_slice(name.as_bytes());
+            out.resize(out.len().next_multiple_of(8), 0);
+        }
+        out
+    }
+
+    /// Ejecuta una petición FUSE y devuelve la respuesta (sin `fuse_out_header`).
+    fn handle(&mut self, opcode: u32, nodeid: u64, args: &[u8]) -> Result<Vec<u8>, i32> {
+        let u32_at = |off: usize| u32::from_le_bytes(args[off..off + 4].try_into().unwrap());
+        let u64_at = |off: usize| u64::from_le_bytes(args[off..off + 8].try_into().unwrap());
+        match opcode {
+            FUSE_INIT => {
+                let mut out = vec![0u8; 64];
+                out[0..4].copy_from_slice(&7u32.to_le_bytes());
+                out[4..8].copy_from_slice(&31u32.to_le_bytes());
+                out[8..12].copy_from_slice(&u32_at(8).to_le_bytes());
+                Ok(out)
+            }
+            FUSE_LOOKUP => {
+                let name_len = args.iter().position(|&b| b == 0).unwrap_or(args.len());
+                let name = core::str::from_utf8(&args[..name_len]).map_err(|_| ENOENT)?;
+                let ino = self
+                    .nodes
+                    .iter()
+                    .find(|(&ino, n)| ino != 1 && n.parent == nodeid && n.name == name)
+                    .map(|(&ino, _)| ino)
+                    .ok_or(ENOENT)?;
+                *self.lookups.entry(ino).or_insert(0) += 1;
+                let mut out = vec![0u8; 40];
+                out[0..8].copy_from_slice(&ino.to_le_bytes());
+                out.extend_from_slice(&self.attr(ino));
+                Ok(out)
+            }
+            FUSE_GETATTR => {
+                if !self.nodes.contains_key(&nodeid) {
+                    return Err(ENOENT);
+                }
+                let mut out = vec![0u8; 16];
+                out.extend_from_slice(&self.attr(nodeid));
+                Ok(out)
+            }
+            FUSE_OPEN => self.open(nodeid, false),
+            FUSE_OPENDIR => self.open(nodeid, true),
+            FUSE_READ | FUSE_READDIR => {
+                let (fh, offset, size) = (u64_at(0), u64_at(8) as usize, u32_at(16) as usize);
+                if self.open_handles.get(&fh) != Some(&nodeid) {
+                    return Err(EBADF);
+                }
+                if opcode == FUSE_READDIR {
+                    return Ok(self.readdir(nodeid, offset, size));
+                }
+                let data = &self.nodes[&nodeid].data;
+                let start = offset.min(data.len());
+                Ok(data[start..(start + size).min(data.len())].to_vec())
+            }
+            FUSE_RELEASE | FUSE_RELEASEDIR => {
+                self.open_handles.remove(&u64_at(0)).map(|_| Vec::new()).ok_or(EBADF)
+            }
+            FUSE_SETUPMAPPING if !self.dax_window.is_empty() => {
+                let (fh, foffset, len, moffset) = (u64_at(0), u64_at(8) as usize, u64_at(16), u64_at(32));
+                if self.open_handles.get(&fh) != Some(&nodeid) {
+                    return Err(EBADF);
+                }
+                let end = moffset.checked_add(len).filter(|&end| end <= self.dax_window.len() as u64).ok_or(EINVAL)?;
+                let data = &self.nodes[&nodeid].data;
+                let src = &data[foffset.min(data.len())..(foffset + len as usize).min(data.len())];
+                let dst = &mut self.dax_window[moffset as usize..end as usize];
+                dst.fill(0);
+                dst[..src.len()].copy_from_slice(src);
+                self.mappings.insert(moffset, (nodeid, len));
+                Ok(Vec::new())
+            }
+            FUSE_REMOVEMAPPING if !self.dax_window.is_empty() => {
+                for i in 0..u32_at(0) as usize {
+                    let moffset = u64_at(4 + 16 * i);
+                    let (_, len) = self.mappings.remove(&moffset).ok_or(EINVAL)?;
+                    self.dax_window[moffset as usize..(moffset + len) as usize].fill(0);
+                }
+                Ok(Vec::new())
+            }
+            _ => Err(ENOSYS),
+        }
+    }
+}
+
+impl Default for FsBackend {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Backend for FsBackend {
+    fn device_type(&self) -> u16 {
+        26
+    }
+
+    fn features(&self) -> u64 {
+        self.extra_features
+    }
+
+    fn shared_memory(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.dax_window[..]).filter(|window| !window.is_empty())
+    }
+
+    fn num_queues(&self) -> u16 {
+        2
+    }
+
+    fn config(&self) -> Vec<u8> {
+        // tag[36] + num_request_queues
+        let mut config = vec![0u8; 40];
+        config[..TAG.len()].copy_from_slice(TAG.as_bytes());
+        config[36..40].copy_from_slice(&1u32.to_le_bytes());
+        config
+    }
+
+    fn process(&mut self, queue: u16, chain: &Chain) -> Completion {
+        let request = chain.readable();
+        let opcode = u32::from_le_bytes(request[4..8].try_into().unwrap());
+        let unique = u64::from_le_bytes(request[8..16].try_into().unwrap());
+        let nodeid = u64::from_le_bytes(request[16..24].try_into().unwrap());
+        let args = &request[IN_HEADER_LEN..];
+        self.opcodes.push(opcode);
+        if self.hold_opcode == Some(opcode) {
+            return Completion::Hold;
+        }
+        if queue == 0 {
+            // hiprio: solo FORGET, que no tiene respuesta
+            if opcode == FUSE_FORGET {
+                let nlookup = u64::from_le_bytes(args[0..8].try_into().unwrap());
+                if let Some(count) = self.lookups.get_mut(&nodeid) {
+                    *count = count.saturating_sub(nlookup);
+                }
+            }
+            return Completion::Done(0);
+        }
+        let (error, payload) = match self.handle(opcode, nodeid, args) {
+            Ok(payload) => (0, payload),
+            Err(errno) => (-errno, Vec::new()),
+        };
+        let mut reply = Vec::with_capacity(16 + payload.len());
+        reply.extend_from_slice(&((16 + payload.len()) as u32).to_le_bytes());
+        reply.extend_from_slice(&error.to_le_bytes());
+        reply.extend_from_slice(&unique.to_le_bytes());
+        reply.extend_from_slice(&payload);
+        Completion::Done(chain.write_at(0, &reply) as u32)
+    }
+
+    fn reset(&mut self) {
+        // Sesión nueva: se pierden las referencias, los handles y las proyecciones
+        self.lookups.clear();
+        self.open_handles.clear();
+        self.mappings.clear();
+        self.dax_window.fill(0);
+    }
+}
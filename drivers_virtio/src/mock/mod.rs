@@ -0,0 +1,785 @@
+//! Dispositivo virtio-pci simulado para las pruebas en el host (`cargo test -p drivers_virtio`).
+//!
+//! [`MockHal`] implementa el [`Hal`] sobre la memoria del proceso (dirección física =
+//! dirección virtual, salvo en el modo disperso de [`set_scattered`]) y presenta un
+//! único dispositivo en el bus PCI 0 con las capabilities virtio 1.x, o detrás de un
+//! root port PCIe con hot-plug si se instala con [`install_port`]. Los registros
+//! `common`, `notify`, `isr` y `device` se emulan en cada acceso MMIO, y el lado del
+//! dispositivo de las split virtqueues se procesa de forma síncrona al notificar. El
+//! comportamiento propio de cada tipo de dispositivo lo aporta un [`Backend`]
+//! ([`balloon`], [`blk`], [`fs`], [`vsock`]). Los frames que piden los drivers salen de
+//! un pool simulado ([`set_frames`]).
+//!
+//! Los drivers guardan su estado en estáticos, así que las pruebas que usan el
+//! dispositivo se serializan: [`install`] devuelve un guard que hay que mantener
+//! vivo durante toda la prueba.
+
+pub mod balloon;
+pub mod blk;
+pub mod fs;
+pub mod vsock;
+
+use crate::hal::{set_hal, Hal};
+use crate::pci::{self, VirtioDevice};
+use crate::transport::{STATUS_FEATURES_OK, VIRTIO_F_VERSION_1};
+use crate::virtqueue::{VirtQueue, VIRTQ_DESC_F_NEXT, VIRTQ_DESC_F_WRITE};
+use crate::{VirtqDesc, VirtqUsedElem};
+use core::any::Any;
+use core::ptr::{read_volatile, write_volatile};
+use core::sync::atomic::{fence, AtomicBool, Ordering};
+use std::alloc::{alloc_zeroed, Layout};
+use std::boxed::Box;
+use std::collections::VecDeque;
+use std::sync::{Mutex, MutexGuard};
+use std::vec::Vec;
+
+/// Dirección física (ficticia) del BAR 0 del dispositivo simulado.
+const BAR_BASE: u64 = 0xFE00_0000;
+const BAR_SIZE: u64 = 0x4000;
+// Desplazamientos de cada estructura dentro del BAR 0
+const COMMON_OFF: u64 = 0x0000;
+const COMMON_LEN: u32 = 0x40;
+const NOTIFY_OFF: u64 = 0x1000;
+const NOTIFY_MULTIPLIER: u32 = 4;
+const ISR_OFF: u64 = 0x2000;
+const DEVICE_OFF: u64 = 0x3000;
+const DEVICE_LEN: u32 = 0x1000;
+/// Dirección física (ficticia) del BAR 2, de 64 bits, con la región de memoria compartida 0.
+const SHM_BASE: u64 = 0x80_0000_0000;
+
+const MOCK_SLOT: u8 = 3;
+/// Root port con hot-plug (00:1c.0) y bus que hay detrás; el dispositivo es 01:00.0.
+const PORT_SLOT: u8 = 0x1C;
+const PORT_BUS: u8 = 1;
+const PORT_PCIE_CAP: usize = 0x40;
+// Slot Control y Slot Status del root port (ver `devmgr`)
+const SLTCTL_PIC_OFF: u16 = 3 << 8;
+const SLTCTL_PCC: u16 = 1 << 10;
+const SLTSTA_ABP: u16 = 1 << 0;
+const SLTSTA_PDC: u16 = 1 << 3;
+const SLTSTA_PDS: u16 = 1 << 6;
+/// Bit que marca como "físicas" las páginas impares en modo disperso.
+const PHYS_TAG: u64 = 1 << 62;
+/// Dirección (ficticia, nunca se desreferencia) del primer frame del pool de [`set_frames`].
+const FRAME_BASE: usize = 0x4000_0000;
+const FRAME_SIZE: usize = 2 * 1024 * 1024;
+const PAGE_SIZE: usize = 4096;
+const MAX_QUEUE_SIZE: u16 = 256;
+
+/// Segmento de una cadena de descriptores, visto desde el dispositivo.
+#[derive(Debug, Clone, Copy)]
+pub struct ChainSegment {
+    pub addr: u64,
+    pub len: u32,
+    pub writable: bool,
+}
+
+/// Cadena de descriptores publicada por el driver.
+pub struct Chain {
+    pub head: u16,
+    pub segments: Vec<ChainSegment>,
+}
+
+impl Chain {
+    /// Concatenación de los segmentos que lee el dispositivo.
+    pub fn readable(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        for seg in self.segments.iter().filter(|s| !s.writable) {
+            let data = unsafe { core::slice::from_raw_parts(phys_to_virt(seg.addr) as *const u8, seg.len as usize) };
+            out.extend_from_slice(data);
+        }
+        out
+    }
+
+    /// Capacidad total de los segmentos que escribe el dispositivo.
+    pub fn writable_len(&self) -> usize {
+        self.segments.iter().filter(|s| s.writable).map(|s| s.len as usize).sum()
+    }
+
+    /// Escribe `data` en los segmentos escribibles a partir de `offset` (contado sobre
+    /// su concatenación). Devuelve los bytes escritos.
+    pub fn write_at(&self, offset: usize, data: &[u8]) -> usize {
+        let mut skip = offset;
+        let mut written = 0;
+        for seg in self.segments.iter().filter(|s| s.writable) {
+            let len = seg.len as usize;
+            if skip >= len {
+                skip -= len;
+                continue;
+            }
+            let n = (len - skip).min(data.len() - written);
+            unsafe {
+                core::ptr::copy_nonoverlapping(data[written..].as_ptr(), (phys_to_virt(seg.addr) as *mut u8).add(skip), n);
+            }
+            written += n;
+            skip = 0;
+            if written == data.len() {
+                break;
+            }
+        }
+        written
+    }
+}
+
+/// Qué hace el dispositivo con una cadena recién publicada.
+pub enum Completion {
+    /// Se devuelve ya al driver con estos bytes escritos.
+    Done(u32),
+    /// Se retiene (buffers de recepción) hasta que el backend tenga datos.
+    Hold,
+}
+
+/// Lado del dispositivo de un tipo virtio concreto.
+pub trait Backend: Any + Send {
+    fn device_type(&self) -> u16;
+
+    /// Features propias del dispositivo (VERSION_1 se añade siempre).
+    fn features(&self) -> u64 {
+        0
+    }
+
+    fn num_queues(&self) -> u16;
+
+    /// Contenido actual de la configuración del dispositivo.
+    fn config(&self) -> Vec<u8> {
+        Vec::new()
+    }
+
+    fn config_write(&mut self, _offset: usize, _data: &[u8]) {}
+
+    fn process(&mut self, queue: u16, chain: &Chain) -> Completion;
+
+    /// Siguiente mensaje para un buffer retenido de `queue`, si lo hay.
+    fn next_output(&mut self, _queue: u16) -> Option<Vec<u8>> {
+        None
+    }
+
+    /// Reset del dispositivo (status = 0).
+    fn reset(&mut self) {}
+
+    /// Memoria de la región compartida 0 (p. ej. la ventana DAX), si el dispositivo la expone.
+    fn shared_memory(&mut self) -> Option<&mut [u8]> {
+        None
+    }
+}
+
+/// Vista de una split virtqueue desde el lado del dispositivo.
+#[derive(Debug, Clone, Copy, Default)]
+struct Ring {
+    size: u16,
+    desc: u64,
+    avail: u64,
+    used: u64,
+}
+
+impl Ring {
+    fn of(vq: &VirtQueue) -> Self {
+        Ring { size: vq.size, desc: vq.desc_addr(), avail: vq.avail_addr(), used: vq.used_addr() }
+    }
+
+    // Direcciones de la CPU de las tres áreas (las del Ring son las que ve el dispositivo)
+    fn desc(&self) -> u64 {
+        phys_to_virt(self.desc) as u64
+    }
+
+    fn avail(&self) -> u64 {
+        phys_to_virt(self.avail) as u64
+    }
+
+    fn used(&self) -> u64 {
+        phys_to_virt(self.used) as u64
+    }
+
+    fn pop_avail(&self, last_avail: &mut u16) -> Option<Chain> {
+        fence(Ordering::SeqCst);
+        let avail_idx = unsafe { read_volatile((self.avail() + 2) as *const u16) };
+        if avail_idx == *last_avail {
+            return None;
+        }
+        let slot = (*last_avail % self.size) as u64;
+        let head = unsafe { read_volatile((self.avail() + 4 + 2 * slot) as *const u16) };
+        *last_avail = last_avail.wrapping_add(1);
+        let mut segments = Vec::new();
+        let mut idx = head;
+        loop {
+            let desc = unsafe { read_volatile((self.desc() as *const VirtqDesc).add(idx as usize)) };
+            segments.push(ChainSegment {
+                addr: desc.addr,
+                len: desc.len,
+                writable: desc.flags & VIRTQ_DESC_F_WRITE != 0,
+            });
+            if desc.flags & VIRTQ_DESC_F_NEXT == 0 || segments.len() > self.size as usize {
+                break;
+            }
+            idx = desc.next;
+        }
+        Some(Chain { head, segments })
+    }
+
+    fn push_used(&self, head: u16, len: u32) {
+        unsafe {
+            let idx_ptr = (self.used() + 2) as *mut u16;
+            let idx = read_volatile(idx_ptr);
+            let ring = (self.used() + 4) as *mut VirtqUsedElem;
+            write_volatile(ring.add((idx % self.size) as usize), VirtqUsedElem { id: head as u32, len });
+            fence(Ordering::SeqCst);
+            write_volatile(idx_ptr, idx.wrapping_add(1));
+        }
+    }
+}
+
+/// Saca la siguiente cadena publicada en `vq`, actuando como dispositivo (para
+/// probar la cola sin transporte).
+pub fn pop_avail(vq: &VirtQueue, last_avail: &mut u16) -> Option<Chain> {
+    Ring::of(vq).pop_avail(last_avail)
+}
+
+/// Devuelve la cadena `head` al driver por el anillo `used` de `vq`.
+pub fn push_used(vq: &VirtQueue, head: u16, len: u32) {
+    Ring::of(vq).push_used(head, len)
+}
+
+#[derive(Default)]
+struct Queue {
+    ring: Ring,
+    enabled: bool,
+    last_avail: u16,
+    held: VecDeque<Chain>,
+}
+
+impl Queue {
+    fn new() -> Self {
+        Queue { ring: Ring { size: MAX_QUEUE_SIZE, ..Ring::default() }, ..Queue::default() }
+    }
+}
+
+struct Device {
+    backend: Box<dyn Backend>,
+    status: u8,
+    dfselect: u32,
+    gfselect: u32,
+    driver_features: u64,
+    queue_select: u16,
+    queues: Vec<Queue>,
+    resets: usize,
+}
+
+impl Device {
+    fn new(backend: Box<dyn Backend>) -> Self {
+        let queues = (0..backend.num_queues()).map(|_| Queue::new()).collect();
+        Device {
+            backend,
+            status: 0,
+            dfselect: 0,
+            gfselect: 0,
+            driver_features: 0,
+            queue_select: 0,
+            queues,
+            resets: 0,
+        }
+    }
+
+    fn offered_features(&self) -> u64 {
+        self.backend.features() | VIRTIO_F_VERSION_1
+    }
+
+    fn reset(&mut self) {
+        self.status = 0;
+        self.driver_features = 0;
+        self.queues = (0..self.backend.num_queues()).map(|_| Queue::new()).collect();
+        self.backend.reset();
+        self.resets += 1;
+    }
+
+    fn selected(&mut self) -> Option<&mut Queue> {
+        self.queues.get_mut(self.queue_select as usize)
+    }
+
+    fn read_common(&mut self, off: u64) -> u32 {
+        let q = self.queues.get(self.queue_select as usize);
+        match off {
+            0x00 => self.dfselect,
+            0x04 => match self.dfselect {
+                0 => self.offered_features() as u32,
+                1 => (self.offered_features() >> 32) as u32,
+                _ => 0,
+            },
+            0x08 => self.gfselect,
+            0x12 => self.queues.len() as u32,
+            0x14 => self.status as u32,
+            0x15 => 0, // config_generation
+            0x16 => self.queue_select as u32,
+            0x18 => q.map_or(0, |q| q.ring.size as u32),
+            0x1C => q.map_or(0, |q| q.enabled as u32),
+            0x1E => self.queue_select as u32,
+            // El reset de cola se completa al escribirlo: queue_reset vuelve a leerse 0
+            0x3A => 0,
+            _ => 0,
+        }
+    }
+
+    fn write_common(&mut self, off: u64, val: u32) {
+        match off {
+            0x00 => self.dfselect = val,
+            0x08 => self.gfselect = val,
+            0x0C => match self.gfselect {
+                0 => self.driver_features = (self.driver_features & !0xFFFF_FFFF) | val as u64,
+                1 => self.driver_features = (self.driver_features & 0xFFFF_FFFF) | ((val as u64) << 32),
+                _ => {}
+            },
+            0x14 => {
+                let status = val as u8;
+                if status == 0 {
+                    self.reset();
+                } else if status & STATUS_FEATURES_OK != 0 && self.driver_features & !self.offered_features() != 0 {
+                    // Features no ofrecidas: FEATURES_OK no se acepta
+                    self.status = status & !STATUS_FEATURES_OK;
+                } else {
+                    self.status = status;
+                }
+            }
+            0x16 => self.queue_select = val as u16,
+            0x18 => {
+                if let Some(q) = self.selected() {
+                    q.ring.size = val as u16;
+                }
+            }
+            0x1C => {
+                if let Some(q) = self.selected() {
+                    q.enabled = val != 0;
+                }
+            }
+            0x20 | 0x24 | 0x28 | 0x2C | 0x30 | 0x34 => {
+                let Some(q) = self.selected() else { return };
+                let field = match off & !7 {
+                    0x20 => &mut q.ring.desc,
+                    0x28 => &mut q.ring.avail,
+                    _ => &mut q.ring.used,
+                };
+                *field = if off & 4 == 0 {
+                    (*field & !0xFFFF_FFFF) | val as u64
+                } else {
+                    (*field & 0xFFFF_FFFF) | ((val as u64) << 32)
+                };
+            }
+            0x3A if val == 1 => {
+                // La cola vuelve a su estado inicial, con queue_enable a 0
+                if let Some(q) = self.selected() {
+                    *q = Queue::new();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn read_config(&self, off: usize, width: usize) -> u32 {
+        let config = self.backend.config();
+        let mut val = 0u32;
+        for i in (0..width).rev() {
+            val = (val << 8) | config.get(off + i).copied().unwrap_or(0) as u32;
+        }
+        val
+    }
+
+    /// Procesa las cadenas nuevas de `queue` y entrega las salidas pendientes.
+    fn notify(&mut self, queue: u16) {
+        let Some(q) = self.queues.get_mut(queue as usize) else { return };
+        if !q.enabled {
+            return;
+        }
+        while let Some(chain) = q.ring.pop_avail(&mut q.last_avail) {
+            match self.backend.process(queue, &chain) {
+                Completion::Done(len) => q.ring.push_used(chain.head, len),
+                Completion::Hold => q.held.push_back(chain),
+            }
+        }
+        self.deliver();
+    }
+
+    /// Llena los buffers retenidos con lo que el backend tenga pendiente.
+    fn deliver(&mut self) {
+        for (index, q) in self.queues.iter_mut().enumerate() {
+            while !q.held.is_empty() {
+                let Some(data) = self.backend.next_output(index as u16) else { break };
+                let Some(chain) = q.held.pop_front() else { break };
+                let len = chain.write_at(0, &data);
+                q.ring.push_used(chain.head, len as u32);
+            }
+        }
+    }
+}
+
+/// Ranura hot-plug del root port simulado.
+#[derive(Debug, Clone, Copy)]
+struct Slot {
+    control: u16,
+    status: u16,
+}
+
+impl Slot {
+    fn powered(&self) -> bool {
+        self.control & SLTCTL_PCC == 0
+    }
+}
+
+static DEVICE: Mutex<Option<Device>> = Mutex::new(None);
+static PORT: Mutex<Option<Slot>> = Mutex::new(None);
+static SCATTERED: AtomicBool = AtomicBool::new(false);
+static FRAMES: Mutex<Vec<FrameState>> = Mutex::new(Vec::new());
+static TEST_LOCK: Mutex<()> = Mutex::new(());
+
+fn lock<T>(m: &'static Mutex<T>) -> MutexGuard<'static, T> {
+    // Una prueba fallida no debe arrastrar a las demás
+    m.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Estado de un frame del pool simulado, como en el bitmap del kernel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameState {
+    Free,
+    Used,
+    /// Libre y ya notificado al host (free page reporting).
+    Reported,
+}
+
+fn frame_index(addr: usize) -> Option<usize> {
+    let i = addr.checked_sub(FRAME_BASE)? / FRAME_SIZE;
+    (addr.is_multiple_of(FRAME_SIZE) && i < lock(&FRAMES).len()).then_some(i)
+}
+
+/// HAL de las pruebas: memoria del proceso y dispositivo simulado.
+pub struct MockHal;
+
+static MOCK_HAL: MockHal = MockHal;
+
+impl MockHal {
+    fn mmio_read(&self, addr: usize, width: usize) -> u32 {
+        let off = addr as u64 - BAR_BASE;
+        let mut guard = lock(&DEVICE);
+        let Some(dev) = guard.as_mut() else { return 0 };
+        match off {
+            COMMON_OFF..NOTIFY_OFF => dev.read_common(off - COMMON_OFF),
+            ISR_OFF..DEVICE_OFF => 1,
+            DEVICE_OFF..BAR_SIZE => dev.read_config((off - DEVICE_OFF) as usize, width),
+            _ => 0,
+        }
+    }
+
+    fn mmio_write(&self, addr: usize, width: usize, val: u32) {
+        let off = addr as u64 - BAR_BASE;
+        let mut guard = lock(&DEVICE);
+        let Some(dev) = guard.as_mut() else { return };
+        match off {
+            COMMON_OFF..NOTIFY_OFF => dev.write_common(off - COMMON_OFF, val),
+            NOTIFY_OFF..ISR_OFF => dev.notify(((off - NOTIFY_OFF) / NOTIFY_MULTIPLIER as u64) as u16),
+            DEVICE_OFF..BAR_SIZE => {
+                let bytes = val.to_le_bytes();
+                dev.backend.config_write((off - DEVICE_OFF) as usize, &bytes[..width]);
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Hal for MockHal {
+    fn dma_alloc(&self, size: usize, align: usize) -> *mut u8 {
+        // Se pierde al terminar la prueba: las colas viven lo mismo que el proceso
+        match Layout::from_size_align(size.max(1), align) {
+            Ok(layout) => unsafe { alloc_zeroed(layout) },
+            Err(_) => core::ptr::null_mut(),
+        }
+    }
+
+    fn alloc_frames(&self, size: usize) -> *mut u8 {
+        // Los modelos sí se leen: memoria real del proceso, fuera del pool
+        match Layout::from_size_align(size.max(1), FRAME_SIZE) {
+            Ok(layout) => unsafe { std::alloc::alloc(layout) },
+            Err(_) => core::ptr::null_mut(),
+        }
+    }
+
+    fn alloc_frame(&self) -> Option<usize> {
+        let mut frames = lock(&FRAMES);
+        let i = frames.iter().position(|&state| state != FrameState::Used)?;
+        frames[i] = FrameState::Used;
+        Some(frame_addr(i))
+    }
+
+    fn free_frame(&self, addr: usize) {
+        if let Some(i) = frame_index(addr) {
+            lock(&FRAMES)[i] = FrameState::Free;
+        }
+    }
+
+    fn take_unreported_free_frame(&self) -> Option<usize> {
+        let mut frames = lock(&FRAMES);
+        let i = frames.iter().position(|&state| state == FrameState::Free)?;
+        frames[i] = FrameState::Used;
+        Some(frame_addr(i))
+    }
+
+    fn release_reported_frame(&self, addr: usize) {
+        if let Some(i) = frame_index(addr) {
+            lock(&FRAMES)[i] = FrameState::Reported;
+        }
+    }
+
+    fn virt_to_phys(&self, virt: usize) -> u64 {
+        if SCATTERED.load(Ordering::Relaxed) && (virt / PAGE_SIZE) % 2 == 1 {
+            virt as u64 | PHYS_TAG
+        } else {
+            virt as u64
+        }
+    }
+
+    fn map_mmio(&self, phys: u64, _size: usize) -> *mut u8 {
+        // Nunca se desreferencia: todos los accesos pasan por `mmio_*`
+        phys as *mut u8
+    }
+
+    fn map_shared_readonly(&self, phys: u64, size: usize) -> *const u8 {
+        let mut guard = lock(&DEVICE);
+        let window = guard.as_mut().and_then(|dev| dev.backend.shared_memory());
+        match (window, phys.checked_sub(SHM_BASE)) {
+            (Some(window), Some(offset)) if offset as usize + size <= window.len() => window[offset as usize..].as_ptr(),
+            _ => core::ptr::null(),
+        }
+    }
+
+    fn unmap_shared(&self, _virt: usize, _size: usize) {}
+
+    unsafe fn mmio_read8(&self, addr: *const u8) -> u8 {
+        self.mmio_read(addr as usize, 1) as u8
+    }
+
+    unsafe fn mmio_read16(&self, addr: *const u16) -> u16 {
+        self.mmio_read(addr as usize, 2) as u16
+    }
+
+    unsafe fn mmio_read32(&self, addr: *const u32) -> u32 {
+        self.mmio_read(addr as usize, 4)
+    }
+
+    unsafe fn mmio_write8(&self, addr: *mut u8, val: u8) {
+        self.mmio_write(addr as usize, 1, val as u32)
+    }
+
+    unsafe fn mmio_write16(&self, addr: *mut u16, val: u16) {
+        self.mmio_write(addr as usize, 2, val as u32)
+    }
+
+    unsafe fn mmio_write32(&self, addr: *mut u32, val: u32) {
+        self.mmio_write(addr as usize, 4, val)
+    }
+
+    fn pci_read_config(&self, bus: u8, slot: u8, func: u8, offset: u8) -> u32 {
+        let port = *lock(&PORT);
+        let off = (offset & 0xFC) as usize;
+        let space = match port {
+            Some(port) if (bus, slot, func) == (0, PORT_SLOT, 0) => port_config_space(&port),
+            _ if (bus, slot, func) != device_location() || port.is_some_and(|p| !p.powered()) => return 0xFFFF_FFFF,
+            _ => match lock(&DEVICE).as_mut() {
+                Some(dev) => {
+                    let shm_len = dev.backend.shared_memory().map_or(0, |window| window.len() as u64);
+                    config_space(dev.backend.device_type(), shm_len)
+                }
+                None => return 0xFFFF_FFFF,
+            },
+        };
+        u32::from_le_bytes([space[off], space[off + 1], space[off + 2], space[off + 3]])
+    }
+
+    fn pci_write_config(&self, bus: u8, slot: u8, func: u8, offset: u8, value: u32) {
+        if (bus, slot, func, offset as usize) != (0, PORT_SLOT, 0, PORT_PCIE_CAP + 0x18) {
+            return;
+        }
+        let mut guard = lock(&PORT);
+        let Some(port) = guard.as_mut() else { return };
+        let was_powered = port.powered();
+        port.control = value as u16;
+        port.status &= !((value >> 16) as u16);
+        // Como QEMU: apagar la ranura con el indicador apagado completa la retirada
+        if was_powered && !port.powered() && port.control & SLTCTL_PIC_OFF == SLTCTL_PIC_OFF && port.status & SLTSTA_PDS != 0 {
+            port.status = (port.status & !SLTSTA_PDS) | SLTSTA_PDC;
+            *lock(&DEVICE) = None;
+        }
+    }
+}
+
+fn device_location() -> (u8, u8, u8) {
+    if lock(&PORT).is_some() {
+        (PORT_BUS, 0, 0)
+    } else {
+        (0, MOCK_SLOT, 0)
+    }
+}
+
+/// Espacio de configuración del root port: cabecera tipo 1 con el bus secundario y la
+/// capability PCI Express con una ranura hot-plug (botón de atención y control de
+/// alimentación).
+fn port_config_space(port: &Slot) -> [u8; 256] {
+    let mut space = [0u8; 256];
+    let put32 = |space: &mut [u8; 256], off: usize, val: u32| space[off..off + 4].copy_from_slice(&val.to_le_bytes());
+    put32(&mut space, 0x00, 0x1B36 | (0x000C << 16));
+    put32(&mut space, 0x04, 1 << 20);
+    space[0x0E] = 0x01;
+    space[0x18..0x1B].copy_from_slice(&[0, PORT_BUS, PORT_BUS]);
+    space[0x34] = PORT_PCIE_CAP as u8;
+    let cap = PORT_PCIE_CAP;
+    // Versión 2, root port, slot implementado
+    put32(&mut space, cap, 0x10 | (0x0142 << 16));
+    // Slot Capabilities: botón de atención, control de alimentación, hot-plug
+    put32(&mut space, cap + 0x14, 1 | 1 << 1 | 1 << 6);
+    put32(&mut space, cap + 0x18, port.control as u32 | (port.status as u32) << 16);
+    space
+}
+
+/// Espacio de configuración PCI del dispositivo: cabecera tipo 0 con el BAR 0 y la
+/// lista de capabilities virtio (common, notify, isr, device). Si `shm_len` no es 0
+/// añade el BAR 2 de 64 bits y la capability de memoria compartida 0.
+fn config_space(device_type: u16, shm_len: u64) -> [u8; 256] {
+    let mut space = [0u8; 256];
+    let put32 = |space: &mut [u8; 256], off: usize, val: u32| space[off..off + 4].copy_from_slice(&val.to_le_bytes());
+    put32(&mut space, 0x00, 0x1AF4 | ((0x1040 + device_type as u32) << 16));
+    put32(&mut space, 0x04, 1 << 20); // status: lista de capabilities
+    put32(&mut space, 0x10, BAR_BASE as u32);
+    space[0x34] = 0x40;
+    let caps: [(usize, u8, u64, u32); 4] = [
+        (0x40, 1, COMMON_OFF, COMMON_LEN),
+        (0x54, 2, NOTIFY_OFF, 0x1000),
+        (0x68, 3, ISR_OFF, 4),
+        (0x78, 4, DEVICE_OFF, DEVICE_LEN),
+    ];
+    for (i, &(at, cfg_type, offset, length)) in caps.iter().enumerate() {
+        let next = caps.get(i + 1).map_or(0, |c| c.0 as u8);
+        let len = if cfg_type == 2 { 20 } else { 16 };
+        space[at..at + 4].copy_from_slice(&[0x09, next, len, cfg_type]);
+        // bar 0, id 0
+        put32(&mut space, at + 8, offset as u32);
+        put32(&mut space, at + 12, length);
+        if cfg_type == 2 {
+            put32(&mut space, at + 16, NOTIFY_MULTIPLIER);
+        }
+    }
+    if shm_len != 0 {
+        // BAR 2 de memoria de 64 bits y virtio_pci_cap64 enlazada tras la de device
+        put32(&mut space, 0x18, SHM_BASE as u32 | 0x4);
+        put32(&mut space, 0x1C, (SHM_BASE >> 32) as u32);
+        space[0x79] = 0x88;
+        space[0x88..0x8C].copy_from_slice(&[0x09, 0, 24, pci::VIRTIO_PCI_CAP_SHARED_MEMORY_CFG]);
+        space[0x8C] = 2;
+        put32(&mut space, 0x90, 0);
+        put32(&mut space, 0x94, shm_len as u32);
+        put32(&mut space, 0x98, 0);
+        put32(&mut space, 0x9C, (shm_len >> 32) as u32);
+    }
+    space
+}
+
+/// Registra el HAL simulado sin ningún dispositivo en el bus.
+pub fn install_hal() -> MutexGuard<'static, ()> {
+    let guard = lock(&TEST_LOCK);
+    set_hal(&MOCK_HAL);
+    SCATTERED.store(false, Ordering::Relaxed);
+    lock(&FRAMES).clear();
+    *lock(&DEVICE) = None;
+    *lock(&PORT) = None;
+    crate::devmgr::reset();
+    guard
+}
+
+/// Modo disperso: las páginas impares tienen otra dirección "física", así que ningún
+/// buffer que cruce un límite de página es contiguo y los drivers deben usar rebote.
+/// `install` lo desactiva.
+pub fn set_scattered(scattered: bool) {
+    SCATTERED.store(scattered, Ordering::Relaxed);
+}
+
+/// Pool de frames del allocator simulado: `states[i]` es el estado del frame
+/// [`frame_addr`]`(i)`. `install` lo deja vacío.
+pub fn set_frames(states: &[FrameState]) {
+    *lock(&FRAMES) = states.to_vec();
+}
+
+/// Estado actual del pool de frames.
+pub fn frames() -> Vec<FrameState> {
+    lock(&FRAMES).clone()
+}
+
+/// Dirección del frame `i` del pool.
+pub fn frame_addr(i: usize) -> usize {
+    FRAME_BASE + i * FRAME_SIZE
+}
+
+/// Dirección de la CPU que corresponde a una dirección que ve el dispositivo.
+pub fn phys_to_virt(phys: u64) -> usize {
+    (phys & !PHYS_TAG) as usize
+}
+
+/// Registra el HAL simulado con `backend` como único dispositivo del bus.
+pub fn install(backend: impl Backend) -> MutexGuard<'static, ()> {
+    let guard = install_hal();
+    *lock(&DEVICE) = Some(Device::new(Box::new(backend)));
+    guard
+}
+
+/// Registra el HAL simulado con un root port PCIe y la ranura vacía, encendida y
+/// con el indicador encendido, como la deja el firmware.
+pub fn install_port() -> MutexGuard<'static, ()> {
+    let guard = install_hal();
+    *lock(&PORT) = Some(Slot { control: 1 << 8, status: 0 });
+    guard
+}
+
+/// `device_add`: inserta `backend` en la ranura del root port.
+pub fn hot_add(backend: impl Backend) {
+    *lock(&DEVICE) = Some(Device::new(Box::new(backend)));
+    if let Some(port) = lock(&PORT).as_mut() {
+        port.status |= SLTSTA_PDS | SLTSTA_PDC;
+    }
+}
+
+/// `device_del`: pulsa el botón de atención. El dispositivo desaparece cuando el
+/// sistema apaga la ranura.
+pub fn attention() {
+    if let Some(port) = lock(&PORT).as_mut() {
+        port.status |= SLTSTA_ABP;
+    }
+}
+
+/// `true` mientras haya un dispositivo instalado.
+pub fn present() -> bool {
+    lock(&DEVICE).is_some()
+}
+
+/// El dispositivo simulado tal como lo ve la enumeración del bus.
+pub fn pci_device() -> VirtioDevice {
+    let (bus, slot, func) = device_location();
+    pci::read_function(bus, slot, func).expect("mock: no hay dispositivo instalado")
+}
+
+/// Accede al backend instalado (para inyectar eventos o comprobar su estado).
+pub fn with_backend<B: Backend, R>(f: impl FnOnce(&mut B) -> R) -> R {
+    let mut guard = lock(&DEVICE);
+    let dev = guard.as_mut().expect("mock: no hay dispositivo instalado");
+    let backend: &mut dyn Any = dev.backend.as_mut();
+    f(backend.downcast_mut::<B>().expect("mock: el backend instalado es de otro tipo"))
+}
+
+/// Entrega en los buffers retenidos lo que el backend tenga pendiente (p. ej. tras
+/// inyectar un paquete del host).
+pub fn service() {
+    if let Some(dev) = lock(&DEVICE).as_mut() {
+        dev.deliver();
+    }
+}
+
+/// Resets completos que ha recibido el dispositivo (incluido el de la inicialización).
+pub fn device_resets() -> usize {
+    lock(&DEVICE).as_ref().map_or(0, |dev| dev.resets)
+}
+
+/// `device_status` actual del dispositivo.
+pub fn status() -> u8 {
+    lock(&DEVICE).as_ref().map_or(0, |dev| dev.status)
+}
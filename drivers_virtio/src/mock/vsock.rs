@@ -0,0 +1,210 @@
+//! Lado del dispositivo de virtio-vsock: hace de host (CID 2) frente al guest.
+//!
+//! Los paquetes que envía el guest se guardan en [`VsockBackend::sent`]; los que
+//! inyectan las pruebas con los métodos de host se entregan en los buffers de rx
+//! que el driver tiene publicados.
+
+use super::{service, with_backend, Backend, Chain, Completion};
+use std::collections::{BTreeMap, VecDeque};
+use std::vec;
+use std::vec::Vec;
+
+pub const GUEST_CID: u64 = 3;
+pub const HOST_CID: u64 = 2;
+
+const HDR_LEN: usize = 44;
+const TYPE_STREAM: u16 = 1;
+
+pub const OP_REQUEST: u16 = 1;
+pub const OP_RESPONSE: u16 = 2;
+pub const OP_RST: u16 = 3;
+pub const OP_SHUTDOWN: u16 = 4;
+pub const OP_RW: u16 = 5;
+pub const OP_CREDIT_UPDATE: u16 = 6;
+pub const OP_CREDIT_REQUEST: u16 = 7;
+
+const QUEUE_RX: u16 = 0;
+const QUEUE_TX: u16 = 1;
+const QUEUE_EVENT: u16 = 2;
+const EVENT_TRANSPORT_RESET: u32 = 0;
+
+/// Paquete enviado por el guest.
+#[derive(Debug, Clone)]
+pub struct Packet {
+    pub src_cid: u64,
+    pub dst_cid: u64,
+    pub src_port: u32,
+    pub dst_port: u32,
+    pub op: u16,
+    pub flags: u32,
+    pub buf_alloc: u32,
+    pub fwd_cnt: u32,
+    pub payload: Vec<u8>,
+}
+
+impl Packet {
+    fn parse(b: &[u8]) -> Self {
+        let u16_at = |o: usize| u16::from_le_bytes(b[o..o + 2].try_into().unwrap());
+        let u32_at = |o: usize| u32::from_le_bytes(b[o..o + 4].try_into().unwrap());
+        let u64_at = |o: usize| u64::from_le_bytes(b[o..o + 8].try_into().unwrap());
+        let len = u32_at(24) as usize;
+        Packet {
+            src_cid: u64_at(0),
+            dst_cid: u64_at(8),
+            src_port: u32_at(16),
+            dst_port: u32_at(20),
+            op: u16_at(30),
+            flags: u32_at(32),
+            buf_alloc: u32_at(36),
+            fwd_cnt: u32_at(40),
+            payload: b[HDR_LEN..HDR_LEN + len].to_vec(),
+        }
+    }
+}
+
+pub struct VsockBackend {
+    pub guest_cid: u64,
+    /// Crédito que anuncia el host en cada paquete.
+    pub buf_alloc: u32,
+    /// El host consume al instante lo que recibe (su `fwd_cnt` avanza solo).
+    pub auto_consume: bool,
+    /// Features extra (p. ej. VIRTIO_F_RING_RESET).
+    pub extra_features: u64,
+    /// Retiene las transmisiones del guest sin completarlas (dispositivo colgado).
+    pub hold_tx: bool,
+    /// Puertos del host que aceptan las conexiones que abre el guest; al resto se
+    /// responde con RST.
+    pub listening: Vec<u32>,
+    pub sent: Vec<Packet>,
+    /// Datos RW recibidos por puerto del host.
+    pub received: BTreeMap<u32, Vec<u8>>,
+    /// `fwd_cnt` del host por puerto.
+    fwd_cnt: BTreeMap<u32, u32>,
+    to_guest: VecDeque<Vec<u8>>,
+    events: VecDeque<u32>,
+}
+
+impl VsockBackend {
+    pub fn new() -> Self {
+        VsockBackend {
+            guest_cid: GUEST_CID,
+            buf_alloc: 64 * 1024,
+            auto_consume: true,
+            extra_features: 0,
+            hold_tx: false,
+            listening: Vec::new(),
+            sent: Vec::new(),
+            received: BTreeMap::new(),
+            fwd_cnt: BTreeMap::new(),
+            to_guest: VecDeque::new(),
+            events: VecDeque::new(),
+        }
+    }
+
+    /// Encola un paquete del host hacia el guest.
+    pub fn inject(&mut self, host_port: u32, guest_port: u32, op: u16, flags: u32, payload: &[u8]) {
+        let mut b = vec![0u8; HDR_LEN];
+        b[0..8].copy_from_slice(&HOST_CID.to_le_bytes());
+        b[8..16].copy_from_slice(&self.guest_cid.to_le_bytes());
+        b[16..20].copy_from_slice(&host_port.to_le_bytes());
+        b[20..24].copy_from_slice(&guest_port.to_le_bytes());
+        b[24..28].copy_from_slice(&(payload.len() as u32).to_le_bytes());
+        b[28..30].copy_from_slice(&TYPE_STREAM.to_le_bytes());
+        b[30..32].copy_from_slice(&op.to_le_bytes());
+        b[32..36].copy_from_slice(&flags.to_le_bytes());
+        b[36..40].copy_from_slice(&self.buf_alloc.to_le_bytes());
+        let fwd_cnt = self.fwd_cnt.get(&host_port).copied().unwrap_or(0);
+        b[40..44].copy_from_slice(&fwd_cnt.to_le_bytes());
+        b.extend_from_slice(payload);
+        self.to_guest.push_back(b);
+    }
+
+    pub fn connect(&mut self, host_port: u32, guest_port: u32) {
+        self.inject(host_port, guest_port, OP_REQUEST, 0, &[]);
+    }
+
+    pub fn send(&mut self, host_port: u32, guest_port: u32, data: &[u8]) {
+        self.inject(host_port, guest_port, OP_RW, 0, data);
+    }
+
+    pub fn reset(&mut self, host_port: u32, guest_port: u32) {
+        self.inject(host_port, guest_port, OP_RST, 0, &[]);
+    }
+
+    /// Consume lo recibido en `host_port` y se lo comunica al guest (CREDIT_UPDATE).
+    pub fn consume(&mut self, host_port: u32, guest_port: u32) {
+        let total = self.received.get(&host_port).map_or(0, |d| d.len() as u32);
+        self.fwd_cnt.insert(host_port, total);
+        self.inject(host_port, guest_port, OP_CREDIT_UPDATE, 0, &[]);
+    }
+
+    pub fn transport_reset(&mut self) {
+        self.events.push_back(EVENT_TRANSPORT_RESET);
+    }
+
+    pub fn take_sent(&mut self) -> Vec<Packet> {
+        core::mem::take(&mut self.sent)
+    }
+}
+
+impl Default for VsockBackend {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Backend for VsockBackend {
+    fn device_type(&self) -> u16 {
+        19
+    }
+
+    fn features(&self) -> u64 {
+        self.extra_features
+    }
+
+    fn num_queues(&self) -> u16 {
+        3
+    }
+
+    fn config(&self) -> Vec<u8> {
+        self.guest_cid.to_le_bytes().to_vec()
+    }
+
+    fn process(&mut self, queue: u16, chain: &Chain) -> Completion {
+        if queue != QUEUE_TX {
+            return Completion::Hold;
+        }
+        if self.hold_tx {
+            return Completion::Hold;
+        }
+        let packet = Packet::parse(&chain.readable());
+        if packet.op == OP_REQUEST {
+            let op = if self.listening.contains(&packet.dst_port) { OP_RESPONSE } else { OP_RST };
+            self.inject(packet.dst_port, packet.src_port, op, 0, &[]);
+        }
+        if packet.op == OP_RW {
+            let data = self.received.entry(packet.dst_port).or_default();
+            data.extend_from_slice(&packet.payload);
+            if self.auto_consume {
+                self.fwd_cnt.insert(packet.dst_port, data.len() as u32);
+            }
+        }
+        self.sent.push(packet);
+        Completion::Done(0)
+    }
+
+    fn next_output(&mut self, queue: u16) -> Option<Vec<u8>> {
+        match queue {
+            QUEUE_RX => self.to_guest.pop_front(),
+            QUEUE_EVENT => self.events.pop_front().map(|e| e.to_le_bytes().to_vec()),
+            _ => None,
+        }
+    }
+}
+
+/// Actúa como host sobre el backend instalado y entrega lo que haya generado.
+pub fn host<R>(f: impl FnOnce(&mut VsockBackend) -> R) -> R {
+    let result = with_backend(f);
+    service();
+    result
+}
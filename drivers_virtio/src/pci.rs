@@ -0,0 +1,219 @@
+//! Acceso al espacio de configuración PCI (mecanismo #1, puertos 0xCF8/0xCFC),
+//! lectura de funciones y bridges para la enumeración del bus (`devmgr`) y
+//! descubrimiento de las capabilities virtio 1.x.
+
+use crate::hal::hal;
+
+pub const VIRTIO_VENDOR_ID: u16 = 0x1AF4;
+
+// Cabecera de configuración
+const PCI_HEADER_TYPE: u8 = 0x0E;
+const PCI_HEADER_TYPE_BRIDGE: u8 = 0x01;
+const PCI_HEADER_MULTI_FUNCTION: u8 = 0x80;
+const PCI_SECONDARY_BUS: u8 = 0x19;
+
+// Capabilities PCI
+const PCI_STATUS_CAP_LIST: u32 = 1 << 20;
+const PCI_CAP_PTR: u8 = 0x34;
+const PCI_CAP_ID_VNDR: u8 = 0x09;
+pub const PCI_CAP_ID_EXP: u8 = 0x10;
+
+// Tipos de capability virtio (virtio 1.x, sección 4.1.4)
+pub const VIRTIO_PCI_CAP_COMMON_CFG: u8 = 1;
+pub const VIRTIO_PCI_CAP_NOTIFY_CFG: u8 = 2;
+pub const VIRTIO_PCI_CAP_ISR_CFG: u8 = 3;
+pub const VIRTIO_PCI_CAP_DEVICE_CFG: u8 = 4;
+pub const VIRTIO_PCI_CAP_SHARED_MEMORY_CFG: u8 = 8;
+
+#[derive(Debug, Clone, Copy)]
+pub struct VirtioDevice {
+    pub bus: u8,
+    pub slot: u8,
+    pub func: u8,
+    pub vendor_id: u16,
+    pub device_id: u16,
+    pub bar0: u32,
+}
+
+impl VirtioDevice {
+    /// Tipo de dispositivo virtio (1 = net, 2 = blk, 19 = vsock, 26 = fs, ...).
+    pub fn device_type(&self) -> Option<u16> {
+        if self.vendor_id != VIRTIO_VENDOR_ID {
+            return None;
+        }
+        device_type(self.device_id)
+    }
+
+    /// Bus, slot y función en un `u16` (formato BDF), para identificarlo en las trazas.
+    pub fn location(&self) -> u16 {
+        (self.bus as u16) << 8 | (self.slot as u16) << 3 | self.func as u16
+    }
+}
+
+/// Traduce un device ID PCI (moderno 0x1040+ o transicional 0x1000..0x103F) al tipo virtio.
+pub fn device_type(device_id: u16) -> Option<u16> {
+    match device_id {
+        0x1040..=0x107F => Some(device_id - 0x1040),
+        0x1000 => Some(1),
+        0x1001 => Some(2),
+        0x1002 => Some(5),
+        0x1003 => Some(3),
+        0x1004 => Some(8),
+        0x1005 => Some(4),
+        0x1009 => Some(9),
+        _ => None,
+    }
+}
+
+/// Capability virtio leída del espacio de configuración.
+#[derive(Debug, Clone, Copy)]
+pub struct VirtioCap {
+    pub cfg_type: u8,
+    pub bar: u8,
+    pub id: u8,
+    pub offset: u64,
+    pub length: u64,
+    /// Solo válido para `VIRTIO_PCI_CAP_NOTIFY_CFG`.
+    pub notify_off_multiplier: u32,
+}
+
+pub fn read_config(bus: u8, slot: u8, func: u8, offset: u8) -> u32 {
+    hal().pci_read_config(bus, slot, func, offset)
+}
+
+pub fn write_config(bus: u8, slot: u8, func: u8, offset: u8, value: u32) {
+    hal().pci_write_config(bus, slot, func, offset, value)
+}
+
+fn read_config_u8(dev: &VirtioDevice, offset: u8) -> u8 {
+    let dword = read_config(dev.bus, dev.slot, dev.func, offset);
+    (dword >> ((offset & 3) * 8)) as u8
+}
+
+/// Lee la función `bus:slot.func`. `None` si no hay ninguna.
+pub fn read_function(bus: u8, slot: u8, func: u8) -> Option<VirtioDevice> {
+    let id = read_config(bus, slot, func, 0);
+    if id & 0xFFFF == 0xFFFF {
+        return None;
+    }
+    Some(VirtioDevice {
+        bus,
+        slot,
+        func,
+        vendor_id: id as u16,
+        device_id: (id >> 16) as u16,
+        bar0: read_config(bus, slot, func, 0x10),
+    })
+}
+
+/// `true` si la función 0 de `slot` anuncia más funciones.
+pub fn is_multi_function(bus: u8, slot: u8) -> bool {
+    let header = read_config(bus, slot, 0, PCI_HEADER_TYPE & 0xFC);
+    (header >> 16) as u8 & PCI_HEADER_MULTI_FUNCTION != 0
+}
+
+/// Bus secundario si la función es un bridge PCI-PCI (cabecera de tipo 1).
+pub fn secondary_bus(dev: &VirtioDevice) -> Option<u8> {
+    if read_config_u8(dev, PCI_HEADER_TYPE) & 0x7F != PCI_HEADER_TYPE_BRIDGE {
+        return None;
+    }
+    Some(read_config_u8(dev, PCI_SECONDARY_BUS))
+}
+
+/// Desplazamiento de la primera capability con identificador `id`.
+pub fn find_capability(dev: &VirtioDevice, id: u8) -> Option<u8> {
+    if read_config(dev.bus, dev.slot, dev.func, 0x04) & PCI_STATUS_CAP_LIST == 0 {
+        return None;
+    }
+    let mut ptr = read_config_u8(dev, PCI_CAP_PTR) & 0xFC;
+    for _ in 0..48 {
+        if ptr == 0 {
+            break;
+        }
+        let header = read_config(dev.bus, dev.slot, dev.func, ptr);
+        if header as u8 == id {
+            return Some(ptr);
+        }
+        ptr = (header >> 8) as u8 & 0xFC;
+    }
+    None
+}
+
+pub fn enable_bus_master(bus: u8, slot: u8, func: u8) {
+    let mut cmd = read_config(bus, slot, func, 4);
+    cmd |= 0x2; // Memory Space Enable (necesario para los BAR MMIO)
+    cmd |= 0x4; // Bus Master Enable
+    write_config(bus, slot, func, 4, cmd & 0xFFFF);
+}
+
+/// Dirección física de un BAR de memoria (soporta BAR de 64 bits). `None` si es de I/O o está vacío.
+pub fn read_bar(dev: &VirtioDevice, bar: u8) -> Option<u64> {
+    if bar > 5 {
+        return None;
+    }
+    let offset = 0x10 + bar * 4;
+    let low = read_config(dev.bus, dev.slot, dev.func, offset);
+    if low & 0x1 != 0 {
+        return None; // BAR de I/O: virtio 1.x usa siempre MMIO
+    }
+    let mut addr = (low & 0xFFFF_FFF0) as u64;
+    if (low >> 1) & 0x3 == 0x2 && bar < 5 {
+        let high = read_config(dev.bus, dev.slot, dev.func, offset + 4);
+        addr |= (high as u64) << 32;
+    }
+    if addr == 0 { None } else { Some(addr) }
+}
+
+/// Mapea `size` bytes a partir de `offset` dentro de un BAR como MMIO (RW, NX).
+pub fn map_bar(dev: &VirtioDevice, bar: u8, offset: u64, size: usize) -> Option<*mut u8> {
+    let base = read_bar(dev, bar)?;
+    let virt = hal().map_mmio(base + offset, size);
+    if virt.is_null() { None } else { Some(virt) }
+}
+
+/// Recorre la lista de capabilities y devuelve las capabilities virtio (vendor-specific).
+pub fn virtio_caps(dev: &VirtioDevice) -> [Option<VirtioCap>; 16] {
+    let mut caps: [Option<VirtioCap>; 16] = [None; 16];
+    let status = read_config(dev.bus, dev.slot, dev.func, 0x04);
+    if status & PCI_STATUS_CAP_LIST == 0 {
+        return caps;
+    }
+    let mut ptr = read_config_u8(dev, PCI_CAP_PTR) & 0xFC;
+    let mut idx = 0;
+    // Límite de iteraciones para no quedar atrapados en listas mal formadas
+    for _ in 0..48 {
+        if ptr == 0 || idx == caps.len() {
+            break;
+        }
+        let header = read_config(dev.bus, dev.slot, dev.func, ptr);
+        let cap_id = header as u8;
+        let next = (header >> 8) as u8 & 0xFC;
+        let cap_len = (header >> 16) as u8;
+        if cap_id == PCI_CAP_ID_VNDR && cap_len >= 16 {
+            let cfg_type = (header >> 24) as u8;
+            let word1 = read_config(dev.bus, dev.slot, dev.func, ptr + 4);
+            let mut offset = read_config(dev.bus, dev.slot, dev.func, ptr + 8) as u64;
+            let mut length = read_config(dev.bus, dev.slot, dev.func, ptr + 12) as u64;
+            let mut notify_off_multiplier = 0;
+            if cfg_type == VIRTIO_PCI_CAP_NOTIFY_CFG && cap_len >= 20 {
+                notify_off_multiplier = read_config(dev.bus, dev.slot, dev.func, ptr + 16);
+            }
+            // virtio_pci_cap64: offset_hi y length_hi tras la capability base
+            if cfg_type == VIRTIO_PCI_CAP_SHARED_MEMORY_CFG && cap_len >= 24 {
+                offset |= (read_config(dev.bus, dev.slot, dev.func, ptr + 16) as u64) << 32;
+                length |= (read_config(dev.bus, dev.slot, dev.func, ptr + 20) as u64) << 32;
+            }
+            caps[idx] = Some(VirtioCap {
+                cfg_type,
+                bar: word1 as u8,
+                id: (word1 >> 8) as u8,
+                offset,
+                length,
+                notify_off_multiplier,
+            });
+            idx += 1;
+        }
+        ptr = next;
+    }
+    caps
+}
@@ -0,0 +1,150 @@
+//! Driver virtio-rng (virtio 1.x, sección 5.4).
+//!
+//! Una sola cola (`requestq`): el driver publica buffers escribibles y el
+//! dispositivo los devuelve llenos de bytes aleatorios del host.
+
+use crate::devmgr::{Driver, Match};
+use crate::pci::VirtioDevice;
+use crate::transport::{Recovery, TransportError, VirtioPci};
+use crate::virtqueue::{Segment, VirtQueue, DEFAULT_SPIN_LIMIT};
+use crate::watchdog;
+use core::fmt;
+use core::ptr::addr_of_mut;
+
+pub const VIRTIO_ID_RNG: u16 = 4;
+
+const QUEUE_SIZE: u16 = 8;
+/// Máximo de bytes por petición (el dispositivo puede devolver menos).
+const REQUEST_MAX: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RngError {
+    /// No hay dispositivo virtio-rng o se ha retirado.
+    NoDevice,
+    Transport(TransportError),
+    /// El dispositivo no devolvió datos a tiempo.
+    Timeout,
+}
+
+impl RngError {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RngError::NoDevice => "virtio-rng: no device",
+            RngError::Transport(TransportError::MissingCapability) => "virtio-rng: missing PCI capability",
+            RngError::Transport(TransportError::FeaturesRejected) => "virtio-rng: features rejected",
+            RngError::Transport(TransportError::QueueUnavailable) => "virtio-rng: queue unavailable",
+            RngError::Timeout => "virtio-rng: timeout",
+        }
+    }
+}
+
+impl fmt::Display for RngError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+struct RngDevice {
+    transport: VirtioPci,
+    queue: VirtQueue,
+    /// Petición que no se completó a tiempo: su buffer sigue en manos del dispositivo.
+    pending: Option<u16>,
+}
+
+// El dispositivo escribe en un buffer propio, no en el del llamador, para que una
+// petición abandonada por timeout no pueda escribir en memoria ajena más tarde.
+static mut RNG_BUF: [u8; REQUEST_MAX] = [0; REQUEST_MAX];
+
+static mut RNG_DEVICE: Option<RngDevice> = None;
+
+fn device() -> Result<&'static mut RngDevice, RngError> {
+    unsafe { (*addr_of_mut!(RNG_DEVICE)).as_mut().ok_or(RngError::NoDevice) }
+}
+
+pub static DRIVER: Driver = Driver {
+    name: "virtio-rng",
+    matches: &[Match::Virtio(VIRTIO_ID_RNG)],
+    max_instances: 1,
+    probe,
+    remove,
+};
+
+fn probe(dev: VirtioDevice) -> Result<usize, &'static str> {
+    attach(dev).map(|()| 0).map_err(|e| e.as_str())
+}
+
+fn remove(_instance: usize) {
+    unsafe { RNG_DEVICE = None; }
+}
+
+/// Inicializa el dispositivo virtio-rng y configura su cola.
+fn attach(dev: VirtioDevice) -> Result<(), RngError> {
+    let mut transport = VirtioPci::new(dev).map_err(RngError::Transport)?;
+    transport.begin_init(0).map_err(RngError::Transport)?;
+    let mut queue = match transport.setup_queue(0, QUEUE_SIZE) {
+        Ok(queue) => queue,
+        Err(e) => {
+            transport.fail();
+            return Err(RngError::Transport(e));
+        }
+    };
+    transport.finish_init();
+    queue.enable_watchdog();
+    unsafe { RNG_DEVICE = Some(RngDevice { transport, queue, pending: None }); }
+    watchdog::register("virtio-rng", watchdog_stalled, watchdog_recover);
+    Ok(())
+}
+
+pub fn available() -> bool {
+    device().is_ok()
+}
+
+/// Rellena `buf` con bytes del dispositivo. Devuelve cuántos bytes se obtuvieron,
+/// que puede ser menos que `buf.len()` si el dispositivo deja de responder.
+pub fn read(buf: &mut [u8]) -> Result<usize, RngError> {
+    let rng = device()?;
+    if let Some(head) = rng.pending {
+        rng.queue.wait_used(head, DEFAULT_SPIN_LIMIT).ok_or(RngError::Timeout)?;
+        rng.pending = None;
+    }
+    let scratch = unsafe { &mut *addr_of_mut!(RNG_BUF) };
+    let mut filled = 0;
+    while filled < buf.len() {
+        let want = (buf.len() - filled).min(REQUEST_MAX);
+        let head = rng
+            .queue
+            .add(&[Segment::writable(&mut scratch[..want])])
+            .ok_or(RngError::Timeout)?;
+        rng.queue.notify();
+        match rng.queue.wait_used(head, DEFAULT_SPIN_LIMIT) {
+            Some(0) => break,
+            Some(len) => {
+                let len = (len as usize).min(want);
+                buf[filled..filled + len].copy_from_slice(&scratch[..len]);
+                filled += len;
+            }
+            None => {
+                rng.pending = Some(head);
+                if filled > 0 {
+                    break;
+                }
+                return Err(RngError::Timeout);
+            }
+        }
+    }
+    Ok(filled)
+}
+
+fn watchdog_stalled(deadline: u64) -> bool {
+    device().is_ok_and(|rng| rng.queue.stalled(deadline))
+}
+
+fn watchdog_recover() -> Result<Recovery, &'static str> {
+    let rng = device().map_err(|e| e.as_str())?;
+    let queue = &mut rng.queue;
+    let recovery = watchdog::recover_device(&mut rng.transport, |f| f(queue))
+        .map_err(|e| RngError::Transport(e).as_str())?;
+    // La petición abandonada ya no está en la cola
+    rng.pending = None;
+    Ok(recovery)
+}
@@ -0,0 +1,393 @@
+//! Transporte virtio-pci moderno (virtio 1.x, sección 4.1) compartido por los drivers.
+//!
+//! Localiza las estructuras `common`, `notify`, `isr` y `device` a través de las
+//! capabilities PCI y ofrece la secuencia de inicialización estándar.
+
+use crate::hal::hal;
+use crate::pci::{self, VirtioDevice};
+use crate::virtqueue::{self, VirtQueue};
+use logging::events::{Category, Event, EventKind};
+
+// Bits de device_status
+pub const STATUS_ACKNOWLEDGE: u8 = 1;
+pub const STATUS_DRIVER: u8 = 2;
+pub const STATUS_DRIVER_OK: u8 = 4;
+pub const STATUS_FEATURES_OK: u8 = 8;
+pub const STATUS_DEVICE_NEEDS_RESET: u8 = 64;
+pub const STATUS_FAILED: u8 = 128;
+
+// Features independientes del tipo de dispositivo
+pub const VIRTIO_F_VERSION_1: u64 = 1 << 32;
+pub const VIRTIO_F_RING_RESET: u64 = 1 << 40;
+
+// Offsets dentro de virtio_pci_common_cfg
+const COMMON_DFSELECT: usize = 0x00;
+const COMMON_DF: usize = 0x04;
+const COMMON_GFSELECT: usize = 0x08;
+const COMMON_GF: usize = 0x0C;
+const COMMON_NUMQ: usize = 0x12;
+const COMMON_STATUS: usize = 0x14;
+const COMMON_CFGGENERATION: usize = 0x15;
+const COMMON_Q_SELECT: usize = 0x16;
+const COMMON_Q_SIZE: usize = 0x18;
+const COMMON_Q_ENABLE: usize = 0x1C;
+const COMMON_Q_NOFF: usize = 0x1E;
+const COMMON_Q_DESCLO: usize = 0x20;
+const COMMON_Q_AVAILLO: usize = 0x28;
+const COMMON_Q_USEDLO: usize = 0x30;
+const COMMON_Q_RESET: usize = 0x3A;
+
+/// Tamaño máximo de cola que usan los drivers (se recorta al máximo del dispositivo).
+pub const DEFAULT_QUEUE_SIZE: u16 = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransportError {
+    /// Faltan capabilities obligatorias o no se pudo mapear algún BAR.
+    MissingCapability,
+    /// El dispositivo no ofrece VIRTIO_F_VERSION_1 o rechazó las features.
+    FeaturesRejected,
+    /// La cola no existe o no se pudo reservar memoria para ella.
+    QueueUnavailable,
+}
+
+/// Cómo se recuperó un dispositivo tras un bloqueo detectado por el watchdog.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Recovery {
+    /// Solo se reiniciaron las colas bloqueadas (VIRTIO_F_RING_RESET).
+    QueueReset,
+    /// Reset completo del dispositivo y reconfiguración de todas sus colas.
+    DeviceReset,
+}
+
+/// Región de memoria compartida anunciada por el dispositivo (virtio 1.2, sección 4.1.4.7).
+#[derive(Debug, Clone, Copy)]
+pub struct ShmRegion {
+    pub phys: u64,
+    pub len: u64,
+}
+
+pub struct VirtioPci {
+    pub dev: VirtioDevice,
+    common: *mut u8,
+    notify_base: *mut u8,
+    notify_off_multiplier: u32,
+    isr: *mut u8,
+    device_cfg: *mut u8,
+    features: u64,
+    /// Features que pidió el driver, para repetir la negociación tras un reset.
+    supported: u64,
+}
+
+impl VirtioPci {
+    /// Localiza y mapea las estructuras de configuración del dispositivo.
+    pub fn new(dev: VirtioDevice) -> Result<Self, TransportError> {
+        pci::enable_bus_master(dev.bus, dev.slot, dev.func);
+        let mut common: *mut u8 = core::ptr::null_mut();
+        let mut notify_base = core::ptr::null_mut();
+        let mut notify_off_multiplier = 0;
+        let mut isr = core::ptr::null_mut();
+        let mut device_cfg = core::ptr::null_mut();
+        for cap in pci::virtio_caps(&dev).iter().flatten() {
+            let slot = match cap.cfg_type {
+                pci::VIRTIO_PCI_CAP_COMMON_CFG => &mut common,
+                pci::VIRTIO_PCI_CAP_NOTIFY_CFG => {
+                    notify_off_multiplier = cap.notify_off_multiplier;
+                    &mut notify_base
+                }
+                pci::VIRTIO_PCI_CAP_ISR_CFG => &mut isr,
+                pci::VIRTIO_PCI_CAP_DEVICE_CFG => &mut device_cfg,
+                _ => continue,
+            };
+            // Se usa la primera capability de cada tipo, como recomienda la especificación
+            if slot.is_null() {
+                if let Some(ptr) = pci::map_bar(&dev, cap.bar, cap.offset, cap.length as usize) {
+                    *slot = ptr;
+                }
+            }
+        }
+        if common.is_null() || notify_base.is_null() {
+            return Err(TransportError::MissingCapability);
+        }
+        Ok(VirtioPci {
+            dev,
+            common,
+            notify_base,
+            notify_off_multiplier,
+            isr,
+            device_cfg,
+            features: 0,
+            supported: 0,
+        })
+    }
+
+    fn read8(&self, off: usize) -> u8 {
+        unsafe { hal().mmio_read8(self.common.wrapping_add(off)) }
+    }
+
+    fn write8(&self, off: usize, val: u8) {
+        unsafe { hal().mmio_write8(self.common.wrapping_add(off), val) }
+    }
+
+    fn read16(&self, off: usize) -> u16 {
+        unsafe { hal().mmio_read16(self.common.wrapping_add(off) as *const u16) }
+    }
+
+    fn write16(&self, off: usize, val: u16) {
+        unsafe { hal().mmio_write16(self.common.wrapping_add(off) as *mut u16, val) }
+    }
+
+    fn read32(&self, off: usize) -> u32 {
+        unsafe { hal().mmio_read32(self.common.wrapping_add(off) as *const u32) }
+    }
+
+    fn write32(&self, off: usize, val: u32) {
+        unsafe { hal().mmio_write32(self.common.wrapping_add(off) as *mut u32, val) }
+    }
+
+    fn write64(&self, off: usize, val: u64) {
+        self.write32(off, val as u32);
+        self.write32(off + 4, (val >> 32) as u32);
+    }
+
+    pub fn status(&self) -> u8 {
+        self.read8(COMMON_STATUS)
+    }
+
+    pub fn set_status(&self, status: u8) {
+        self.write8(COMMON_STATUS, status);
+    }
+
+    pub fn add_status(&self, bits: u8) {
+        self.set_status(self.status() | bits);
+    }
+
+    /// Reinicia el dispositivo y espera a que confirme el reset (status == 0).
+    pub fn reset(&self) {
+        self.set_status(0);
+        let mut spins = 0;
+        while self.status() != 0 && spins < 1_000_000 {
+            core::hint::spin_loop();
+            spins += 1;
+        }
+    }
+
+    pub fn device_features(&self) -> u64 {
+        self.write32(COMMON_DFSELECT, 0);
+        let low = self.read32(COMMON_DF) as u64;
+        self.write32(COMMON_DFSELECT, 1);
+        let high = self.read32(COMMON_DF) as u64;
+        (high << 32) | low
+    }
+
+    fn set_driver_features(&self, features: u64) {
+        self.write32(COMMON_GFSELECT, 0);
+        self.write32(COMMON_GF, features as u32);
+        self.write32(COMMON_GFSELECT, 1);
+        self.write32(COMMON_GF, (features >> 32) as u32);
+    }
+
+    /// Features aceptadas en la última negociación.
+    pub fn features(&self) -> u64 {
+        self.features
+    }
+
+    /// Reset + ACKNOWLEDGE + DRIVER + negociación de features (hasta FEATURES_OK).
+    ///
+    /// `supported` son las features que entiende el driver; VERSION_1 y RING_RESET (que
+    /// gestiona el propio transporte) se añaden siempre.
+    /// Devuelve las features negociadas. Tras configurar las colas hay que llamar a
+    /// [`VirtioPci::finish_init`].
+    pub fn begin_init(&mut self, supported: u64) -> Result<u64, TransportError> {
+        self.reset();
+        self.add_status(STATUS_ACKNOWLEDGE);
+        self.add_status(STATUS_DRIVER);
+        let offered = self.device_features();
+        if offered & VIRTIO_F_VERSION_1 == 0 {
+            self.add_status(STATUS_FAILED);
+            return Err(TransportError::FeaturesRejected);
+        }
+        let negotiated = offered & (supported | VIRTIO_F_VERSION_1 | VIRTIO_F_RING_RESET);
+        self.set_driver_features(negotiated);
+        self.add_status(STATUS_FEATURES_OK);
+        if self.status() & STATUS_FEATURES_OK == 0 {
+            self.add_status(STATUS_FAILED);
+            return Err(TransportError::FeaturesRejected);
+        }
+        self.features = negotiated;
+        self.supported = supported;
+        Ok(negotiated)
+    }
+
+    /// Marca el dispositivo como operativo (DRIVER_OK).
+    pub fn finish_init(&self) {
+        self.add_status(STATUS_DRIVER_OK);
+    }
+
+    /// Marca el dispositivo como fallido tras un error de inicialización.
+    pub fn fail(&self) {
+        self.add_status(STATUS_FAILED);
+    }
+
+    pub fn num_queues(&self) -> u16 {
+        self.read16(COMMON_NUMQ)
+    }
+
+    /// Reserva y activa la cola `queue_idx` con hasta `max_size` entradas.
+    pub fn setup_queue(&self, queue_idx: u16, max_size: u16) -> Result<VirtQueue, TransportError> {
+        if queue_idx >= self.num_queues() {
+            return Err(TransportError::QueueUnavailable);
+        }
+        self.write16(COMMON_Q_SELECT, queue_idx);
+        let device_max = self.read16(COMMON_Q_SIZE);
+        if device_max == 0 {
+            return Err(TransportError::QueueUnavailable);
+        }
+        // El tamaño de una split queue debe ser potencia de dos
+        let mut size = device_max.min(max_size);
+        while !size.is_power_of_two() {
+            size &= size - 1;
+        }
+        let mut vq = virtqueue::setup_virtqueue(queue_idx, size).ok_or(TransportError::QueueUnavailable)?;
+        vq.set_device(self.dev.device_type().unwrap_or(0), self.dev.location());
+        self.program_queue(&mut vq);
+        Ok(vq)
+    }
+
+    /// Entrega al dispositivo las direcciones de `vq` (ya seleccionada) y la activa.
+    fn program_queue(&self, vq: &mut VirtQueue) {
+        self.write16(COMMON_Q_SIZE, vq.size);
+        self.write64(COMMON_Q_DESCLO, vq.desc_addr());
+        self.write64(COMMON_Q_AVAILLO, vq.avail_addr());
+        self.write64(COMMON_Q_USEDLO, vq.used_addr());
+        let notify_off = self.read16(COMMON_Q_NOFF) as usize;
+        let notify_addr = self.notify_base.wrapping_add(notify_off * self.notify_off_multiplier as usize) as *mut u16;
+        vq.set_notify_addr(notify_addr);
+        self.write16(COMMON_Q_ENABLE, 1);
+    }
+
+    /// Evento de traza de este dispositivo, para completar con `..` antes de registrarlo.
+    pub fn event(&self, category: Category, kind: EventKind) -> Event {
+        Event {
+            category,
+            kind,
+            device_type: self.dev.device_type().unwrap_or(0),
+            device: self.dev.location(),
+            ..Event::EMPTY
+        }
+    }
+
+    /// Estrategia de recuperación disponible según las features negociadas.
+    pub fn recovery_mode(&self) -> Recovery {
+        if self.features & VIRTIO_F_RING_RESET != 0 {
+            Recovery::QueueReset
+        } else {
+            Recovery::DeviceReset
+        }
+    }
+
+    /// Reinicia solo la cola `vq` (virtio 1.2, sección 2.6.1) y la vuelve a activar
+    /// vacía. Requiere VIRTIO_F_RING_RESET.
+    pub fn reset_queue(&self, vq: &mut VirtQueue) -> Result<(), TransportError> {
+        if self.features & VIRTIO_F_RING_RESET == 0 {
+            return Err(TransportError::FeaturesRejected);
+        }
+        self.write16(COMMON_Q_SELECT, vq.queue_idx);
+        self.write16(COMMON_Q_RESET, 1);
+        // El dispositivo mantiene queue_reset a 1 mientras el reset está en curso
+        let mut spins = 0;
+        while self.read16(COMMON_Q_RESET) != 0 {
+            if spins == 1_000_000 {
+                return Err(TransportError::QueueUnavailable);
+            }
+            core::hint::spin_loop();
+            spins += 1;
+        }
+        vq.reinit();
+        self.program_queue(vq);
+        Ok(())
+    }
+
+    /// Reset completo y nueva negociación con las mismas features. A continuación
+    /// el driver llama a [`VirtioPci::restore_queue`] por cada cola y después a
+    /// [`VirtioPci::finish_init`].
+    pub fn restart(&mut self) -> Result<(), TransportError> {
+        let supported = self.supported;
+        self.begin_init(supported).map(|_| ())
+    }
+
+    /// Vuelve a configurar una cola existente, vacía, tras [`VirtioPci::restart`].
+    pub fn restore_queue(&self, vq: &mut VirtQueue) -> Result<(), TransportError> {
+        self.write16(COMMON_Q_SELECT, vq.queue_idx);
+        if self.read16(COMMON_Q_SIZE) < vq.size {
+            return Err(TransportError::QueueUnavailable);
+        }
+        vq.reinit();
+        self.program_queue(vq);
+        Ok(())
+    }
+
+    /// Busca la región de memoria compartida con identificador `id` (p. ej. la ventana DAX de virtio-fs).
+    pub fn shm_region(&self, id: u8) -> Option<ShmRegion> {
+        let cap = pci::virtio_caps(&self.dev)
+            .into_iter()
+            .flatten()
+            .find(|cap| cap.cfg_type == pci::VIRTIO_PCI_CAP_SHARED_MEMORY_CFG && cap.id == id)?;
+        let base = pci::read_bar(&self.dev, cap.bar)?;
+        if cap.length == 0 {
+            return None;
+        }
+        Some(ShmRegion { phys: base + cap.offset, len: cap.length })
+    }
+
+    /// Lee y limpia el registro ISR (bit 0: cola, bit 1: cambio de configuración).
+    pub fn isr_status(&self) -> u8 {
+        if self.isr.is_null() {
+            return 0;
+        }
+        unsafe { hal().mmio_read8(self.isr) }
+    }
+
+    pub fn config_generation(&self) -> u8 {
+        self.read8(COMMON_CFGGENERATION)
+    }
+
+    pub fn config_read8(&self, off: usize) -> u8 {
+        if self.device_cfg.is_null() { return 0; }
+        unsafe { hal().mmio_read8(self.device_cfg.wrapping_add(off)) }
+    }
+
+    pub fn config_read16(&self, off: usize) -> u16 {
+        if self.device_cfg.is_null() { return 0; }
+        unsafe { hal().mmio_read16(self.device_cfg.wrapping_add(off) as *const u16) }
+    }
+
+    pub fn config_read32(&self, off: usize) -> u32 {
+        if self.device_cfg.is_null() { return 0; }
+        unsafe { hal().mmio_read32(self.device_cfg.wrapping_add(off) as *const u32) }
+    }
+
+    pub fn config_write32(&self, off: usize, val: u32) {
+        if self.device_cfg.is_null() { return; }
+        unsafe { hal().mmio_write32(self.device_cfg.wrapping_add(off) as *mut u32, val) }
+    }
+
+    /// Lee un campo de 64 bits de la configuración del dispositivo de forma consistente
+    /// (reintenta si `config_generation` cambia entre las dos lecturas de 32 bits).
+    pub fn config_read64(&self, off: usize) -> u64 {
+        loop {
+            let gen = self.config_generation();
+            let low = self.config_read32(off) as u64;
+            let high = self.config_read32(off + 4) as u64;
+            if gen == self.config_generation() {
+                return (high << 32) | low;
+            }
+        }
+    }
+
+    /// Copia `out.len()` bytes de la configuración del dispositivo a partir de `off`.
+    pub fn config_read_bytes(&self, off: usize, out: &mut [u8]) {
+        for (i, b) in out.iter_mut().enumerate() {
+            *b = self.config_read8(off + i);
+        }
+    }
+}
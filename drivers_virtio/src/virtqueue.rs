@@ -0,0 +1,539 @@
+//! Split virtqueues (virtio 1.x, sección 2.7) compartidas por todos los drivers.
+//!
+//! La tabla de descriptores, el anillo `avail` y el anillo `used` se reservan en una
+//! única región contigua. Los descriptores libres forman una lista enlazada por `next`.
+//!
+//! Los buffers de cada cadena se mapean con [`crate::dma`] al publicarla y se
+//! desmapean (copiando desde el rebote si lo hubo) cuando el dispositivo la devuelve.
+//!
+//! Cada cola lleva la cuenta de las cadenas en vuelo y del último progreso (TSC),
+//! que usa el watchdog para detectar colas bloqueadas, y el TSC de publicación de
+//! cada cadena para medir su latencia (eventos `virtqueue` de `logging::events`).
+
+use super::{VirtqAvail, VirtqDesc, VirtqUsed, VirtqUsedElem};
+use crate::dma::{self, Direction, DmaMapping, DmaRegion};
+use crate::hal::hal;
+use core::ptr::{addr_of, addr_of_mut, read_volatile, write_volatile};
+use core::sync::atomic::{fence, Ordering};
+use logging::events::{self, Category, Event, EventKind};
+
+pub const VIRTQ_DESC_F_NEXT: u16 = 1;
+pub const VIRTQ_DESC_F_WRITE: u16 = 2;
+
+/// Iteraciones de espera activa antes de dar por perdida una petición síncrona.
+pub const DEFAULT_SPIN_LIMIT: usize = 10_000_000;
+
+/// Ciclos de TSC sin progreso con cadenas en vuelo tras los que una cola se da por
+/// bloqueada (unos segundos a las frecuencias habituales).
+pub const WATCHDOG_DEADLINE_TSC: u64 = 10_000_000_000;
+
+fn now() -> u64 {
+    events::now()
+}
+
+/// Segmento de una cadena de descriptores.
+#[derive(Debug, Clone, Copy)]
+pub struct Segment {
+    /// Dirección virtual del buffer, o física si `physical`.
+    pub addr: u64,
+    pub len: u32,
+    /// `true` si el dispositivo escribe en el buffer (descriptor `WRITE`).
+    pub device_writes: bool,
+    /// La dirección ya es física y se entrega tal cual, sin mapeo DMA.
+    pub physical: bool,
+}
+
+impl Segment {
+    /// Segmento vacío para rellenar arrays de segmentos.
+    pub const EMPTY: Segment = Segment { addr: 0, len: 0, device_writes: false, physical: false };
+
+    /// Buffer que el dispositivo solo lee (driver → dispositivo).
+    pub fn readable(buf: &[u8]) -> Self {
+        Segment { addr: buf.as_ptr() as u64, len: buf.len() as u32, device_writes: false, physical: false }
+    }
+
+    /// Buffer que el dispositivo escribe (dispositivo → driver).
+    pub fn writable(buf: &mut [u8]) -> Self {
+        Segment { addr: buf.as_mut_ptr() as u64, len: buf.len() as u32, device_writes: true, physical: false }
+    }
+
+    /// Memoria física que la CPU no toca mientras el dispositivo la tiene (p. ej. los
+    /// frames que notifica virtio-balloon): nunca se copia a un rebote.
+    pub fn physical(phys: u64, len: u32, device_writes: bool) -> Self {
+        Segment { addr: phys, len, device_writes, physical: true }
+    }
+}
+
+pub struct VirtQueue {
+    pub desc: *mut VirtqDesc,
+    pub avail: *mut VirtqAvail,
+    pub used: *mut VirtqUsed,
+    pub size: u16,
+    pub queue_idx: u16,
+    free_head: u16,
+    num_free: u16,
+    last_used_idx: u16,
+    notify_addr: *mut u16,
+    /// Mapeo DMA de cada descriptor mientras está publicado (tras el anillo `used`).
+    mappings: *mut Option<DmaMapping>,
+    /// TSC de publicación de cada cadena, indexado por su cabeza (tras los mapeos).
+    submitted: *mut u64,
+    /// Cabezas de las cadenas publicadas que el dispositivo aún no ha devuelto (tras
+    /// los TSC). El anillo `used` lo escribe el dispositivo: un id que no esté aquí no
+    /// se libera.
+    posted: *mut bool,
+    /// Latencia de la última cadena devuelta por `pop_used`.
+    last_latency: u64,
+    /// Tipo virtio y ubicación PCI del dispositivo, para las trazas.
+    device_type: u16,
+    device: u16,
+    /// Cadenas publicadas que el dispositivo aún no ha devuelto.
+    in_flight: u16,
+    last_progress: u64,
+    /// Solo las colas de peticiones se vigilan: las de recepción tienen buffers
+    /// publicados indefinidamente sin que eso sea un bloqueo.
+    watchdog: bool,
+}
+
+/// Desplazamientos de avail y used, bytes de los anillos (desc + avail alineado a
+/// página + used) y desplazamiento de la tabla de mapeos para `queue_size` entradas.
+/// Las tablas de TSC de publicación y de cabezas publicadas van detrás de la de mapeos.
+fn queue_layout(queue_size: u16) -> (usize, usize, usize, usize) {
+    let n = queue_size as usize;
+    let desc_size = core::mem::size_of::<VirtqDesc>() * n;
+    // flags + idx + ring[n] + used_event
+    let avail_size = 4 + 2 * n + 2;
+    let used_offset = (desc_size + avail_size + 4095) & !4095;
+    // flags + idx + ring[n] + avail_event
+    let used_size = 4 + core::mem::size_of::<VirtqUsedElem>() * n + 2;
+    let rings_size = used_offset + used_size;
+    let mappings_offset = rings_size.next_multiple_of(core::mem::align_of::<Option<DmaMapping>>());
+    (desc_size, used_offset, rings_size, mappings_offset)
+}
+
+pub fn setup_virtqueue(queue_idx: u16, queue_size: u16) -> Option<VirtQueue> {
+    if queue_size == 0 || !queue_size.is_power_of_two() {
+        return None;
+    }
+    let (desc_size, used_offset, _, mappings_offset) = queue_layout(queue_size);
+    let submitted_offset = (mappings_offset + core::mem::size_of::<Option<DmaMapping>>() * queue_size as usize)
+        .next_multiple_of(core::mem::align_of::<u64>());
+    let posted_offset = submitted_offset + core::mem::size_of::<u64>() * queue_size as usize;
+    let total = posted_offset + queue_size as usize;
+    let region = DmaRegion::alloc(total, 4096)?;
+    let base = region.virt();
+    let mappings = unsafe { base.add(mappings_offset) } as *mut Option<DmaMapping>;
+    for i in 0..queue_size as usize {
+        unsafe { mappings.add(i).write(None); }
+    }
+    let submitted = unsafe { base.add(submitted_offset) } as *mut u64;
+    let posted = unsafe { base.add(posted_offset) } as *mut bool;
+
+    let mut vq = VirtQueue {
+        desc: base as *mut VirtqDesc,
+        avail: unsafe { base.add(desc_size) } as *mut VirtqAvail,
+        used: unsafe { base.add(used_offset) } as *mut VirtqUsed,
+        size: queue_size,
+        queue_idx,
+        free_head: 0,
+        num_free: queue_size,
+        last_used_idx: 0,
+        notify_addr: core::ptr::null_mut(),
+        mappings,
+        submitted,
+        posted,
+        last_latency: 0,
+        device_type: 0,
+        device: 0,
+        in_flight: 0,
+        last_progress: 0,
+        watchdog: false,
+    };
+    vq.reinit();
+    Some(vq)
+}
+
+impl VirtQueue {
+    /// Direcciones físicas de las tres áreas, tal como se programan en el dispositivo.
+    pub fn desc_addr(&self) -> u64 {
+        dma::virt_to_phys(self.desc as usize)
+    }
+
+    pub fn avail_addr(&self) -> u64 {
+        dma::virt_to_phys(self.avail as usize)
+    }
+
+    pub fn used_addr(&self) -> u64 {
+        dma::virt_to_phys(self.used as usize)
+    }
+
+    pub fn num_free(&self) -> u16 {
+        self.num_free
+    }
+
+    /// Vuelve al estado recién creado: anillos a cero, todos los descriptores libres
+    /// y nada en vuelo. Se usa tras un reset de la cola o del dispositivo; los
+    /// buffers que estuvieran publicados se pierden.
+    pub fn reinit(&mut self) {
+        for i in 0..self.size as usize {
+            if let Some(mapping) = unsafe { (*self.mappings.add(i)).take() } {
+                dma::discard(mapping);
+            }
+            unsafe { *self.posted.add(i) = false; }
+        }
+        let (_, _, rings_size, _) = queue_layout(self.size);
+        unsafe { core::ptr::write_bytes(self.desc as *mut u8, 0, rings_size); }
+        // Lista de libres: cada descriptor apunta al siguiente
+        for i in 0..self.size {
+            unsafe { (*self.desc.add(i as usize)).next = i.wrapping_add(1); }
+        }
+        self.free_head = 0;
+        self.num_free = self.size;
+        self.last_used_idx = 0;
+        self.in_flight = 0;
+        self.last_progress = now();
+    }
+
+    /// Dispositivo al que pertenece la cola (lo fija el transporte al crearla).
+    pub fn set_device(&mut self, device_type: u16, device: u16) {
+        self.device_type = device_type;
+        self.device = device;
+    }
+
+    /// Evento de traza de esta cola, para completar con `..` antes de registrarlo.
+    pub fn event(&self, category: Category, kind: EventKind) -> Event {
+        Event {
+            category,
+            kind,
+            device_type: self.device_type,
+            device: self.device,
+            queue: self.queue_idx,
+            ..Event::EMPTY
+        }
+    }
+
+    /// Ciclos de TSC que tardó el dispositivo en devolver la última cadena consumida.
+    pub fn last_latency(&self) -> u64 {
+        self.last_latency
+    }
+
+    /// Activa la vigilancia del watchdog para esta cola.
+    pub fn enable_watchdog(&mut self) {
+        self.watchdog = true;
+    }
+
+    pub fn in_flight(&self) -> u16 {
+        self.in_flight
+    }
+
+    /// `true` si la cola está vigilada, tiene cadenas en vuelo y lleva más de
+    /// `deadline` ciclos de TSC sin que el dispositivo devuelva ninguna.
+    pub fn stalled(&self, deadline: u64) -> bool {
+        self.watchdog && self.in_flight > 0 && self.idle_cycles() > deadline
+    }
+
+    /// Ciclos de TSC desde el último progreso de la cola.
+    pub fn idle_cycles(&self) -> u64 {
+        now().wrapping_sub(self.last_progress)
+    }
+
+    /// Da la cola por bloqueada sin esperar al plazo del watchdog: quien esperaba una
+    /// cadena agotó su espera y la cola debe recuperarse antes de soltar los buffers.
+    pub fn mark_stalled(&mut self) {
+        self.last_progress = now().wrapping_sub(WATCHDOG_DEADLINE_TSC + 1);
+    }
+
+    /// Simula que han pasado `cycles` ciclos de TSC desde el último progreso.
+    #[cfg(test)]
+    pub(crate) fn age(&mut self, cycles: u64) {
+        self.last_progress = self.last_progress.wrapping_sub(cycles);
+    }
+
+    /// Dirección del registro de notificación (la calcula el transporte al activar la cola).
+    pub fn set_notify_addr(&mut self, addr: *mut u16) {
+        self.notify_addr = addr;
+    }
+
+    /// Publica una cadena de descriptores en el anillo `avail`. Devuelve el id de la cabeza.
+    ///
+    /// Los segmentos legibles por el dispositivo deben preceder a los escribibles.
+    /// Devuelve `None` si no quedan descriptores o espacio de rebote para la cadena.
+    pub fn add(&mut self, segments: &[Segment]) -> Option<u16> {
+        if segments.is_empty() || segments.len() > self.num_free as usize {
+            return None;
+        }
+        let head = self.free_head;
+        let mut last = head;
+        let mut idx = head;
+        for (i, seg) in segments.iter().enumerate() {
+            let (addr, mapping) = match Self::map_segment(seg) {
+                Some(mapped) => mapped,
+                None => {
+                    // Se deshacen los mapeos de los segmentos anteriores
+                    let mut undo = head;
+                    for _ in 0..i {
+                        if let Some(mapping) = unsafe { (*self.mappings.add(undo as usize)).take() } {
+                            dma::discard(mapping);
+                        }
+                        undo = unsafe { (*self.desc.add(undo as usize)).next };
+                    }
+                    return None;
+                }
+            };
+            unsafe {
+                *self.mappings.add(idx as usize) = mapping;
+                let desc = &mut *self.desc.add(idx as usize);
+                desc.addr = addr;
+                desc.len = seg.len;
+                desc.flags = if seg.device_writes { VIRTQ_DESC_F_WRITE } else { 0 };
+                if i + 1 < segments.len() {
+                    desc.flags |= VIRTQ_DESC_F_NEXT;
+                }
+                last = idx;
+                idx = desc.next;
+            }
+        }
+        self.free_head = unsafe { (*self.desc.add(last as usize)).next };
+        self.num_free -= segments.len() as u16;
+        let submitted = now();
+        unsafe {
+            *self.submitted.add(head as usize) = submitted;
+            *self.posted.add(head as usize) = true;
+        }
+        if self.in_flight == 0 {
+            self.last_progress = submitted;
+        }
+        self.in_flight += 1;
+
+        unsafe {
+            let avail_idx = read_volatile(addr_of!((*self.avail).idx));
+            let ring = addr_of_mut!((*self.avail).ring) as *mut u16;
+            write_volatile(ring.add((avail_idx % self.size) as usize), head);
+            // El dispositivo debe ver el descriptor antes que el nuevo índice
+            fence(Ordering::SeqCst);
+            write_volatile(addr_of_mut!((*self.avail).idx), avail_idx.wrapping_add(1));
+            fence(Ordering::SeqCst);
+        }
+        Some(head)
+    }
+
+    /// Notifica al dispositivo que hay buffers nuevos en la cola.
+    pub fn notify(&self) {
+        if !self.notify_addr.is_null() {
+            unsafe { hal().mmio_write16(self.notify_addr, self.queue_idx) };
+        }
+    }
+
+    /// `true` si el dispositivo ha devuelto buffers que aún no se han consumido.
+    pub fn has_used(&self) -> bool {
+        fence(Ordering::SeqCst);
+        unsafe { read_volatile(addr_of!((*self.used).idx)) != self.last_used_idx }
+    }
+
+    /// Consume un elemento del anillo `used` y libera su cadena de descriptores.
+    ///
+    /// Los elementos cuyo id no es la cabeza de una cadena en vuelo (fuera de la
+    /// tabla, ya devuelta o nunca publicada) se descartan sin tocar los descriptores.
+    pub fn pop_used(&mut self) -> Option<VirtqUsedElem> {
+        let (elem, head) = loop {
+            if !self.has_used() {
+                return None;
+            }
+            let elem = unsafe {
+                let ring = addr_of!((*self.used).ring) as *const VirtqUsedElem;
+                read_volatile(ring.add((self.last_used_idx % self.size) as usize))
+            };
+            self.last_used_idx = self.last_used_idx.wrapping_add(1);
+            if elem.id < self.size as u32 && unsafe { *self.posted.add(elem.id as usize) } {
+                break (elem, elem.id as u16);
+            }
+        };
+        unsafe { *self.posted.add(head as usize) = false; }
+        self.in_flight = self.in_flight.saturating_sub(1);
+        self.last_progress = now();
+        self.last_latency = self.last_progress.wrapping_sub(unsafe { *self.submitted.add(head as usize) });
+        events::record(Event {
+            desc: head,
+            bytes: elem.len,
+            latency: self.last_latency,
+            ..self.event(Category::Virtqueue, EventKind::Complete)
+        });
+        self.free_chain(head);
+        Some(elem)
+    }
+
+    /// Espera (activamente) a que se complete la cadena `head`. Devuelve los bytes escritos.
+    pub fn wait_used(&mut self, head: u16, spin_limit: usize) -> Option<u32> {
+        let mut spins = 0;
+        while spins < spin_limit {
+            match self.pop_used() {
+                Some(elem) if elem.id as u16 == head => return Some(elem.len),
+                Some(_) => {}
+                None => {
+                    core::hint::spin_loop();
+                    spins += 1;
+                }
+            }
+        }
+        None
+    }
+
+    /// Dirección para el descriptor y, si el buffer es virtual, su mapeo DMA.
+    fn map_segment(seg: &Segment) -> Option<(u64, Option<DmaMapping>)> {
+        if seg.physical {
+            return Some((seg.addr, None));
+        }
+        let direction = if seg.device_writes { Direction::FromDevice } else { Direction::ToDevice };
+        let mapping = unsafe { dma::map(seg.addr as usize, seg.len as usize, direction)? };
+        Some((mapping.phys(), Some(mapping)))
+    }
+
+    fn free_chain(&mut self, head: u16) {
+        let mut idx = head;
+        loop {
+            if let Some(mapping) = unsafe { (*self.mappings.add(idx as usize)).take() } {
+                dma::unmap(mapping);
+            }
+            let desc = unsafe { &mut *self.desc.add(idx as usize) };
+            self.num_free += 1;
+            if desc.flags & VIRTQ_DESC_F_NEXT == 0 {
+                desc.next = self.free_head;
+                break;
+            }
+            idx = desc.next;
+        }
+        self.free_head = head;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock;
+
+    #[test]
+    fn rejects_sizes_that_are_not_powers_of_two() {
+        let _guard = mock::install_hal();
+        assert!(setup_virtqueue(0, 0).is_none());
+        assert!(setup_virtqueue(0, 6).is_none());
+        assert!(setup_virtqueue(0, 8).is_some());
+    }
+
+    #[test]
+    fn chain_round_trip() {
+        let _guard = mock::install_hal();
+        let mut vq = setup_virtqueue(0, 8).unwrap();
+        let header = [1u8; 16];
+        let mut data = [0u8; 32];
+        let mut status = [0xFFu8];
+        let head = vq.add(&[Segment::readable(&header), Segment::writable(&mut data), Segment::writable(&mut status)]).unwrap();
+        assert_eq!(vq.num_free(), 5);
+        assert_eq!(vq.in_flight(), 1);
+
+        let mut last_avail = 0;
+        let chain = mock::pop_avail(&vq, &mut last_avail).unwrap();
+        assert!(mock::pop_avail(&vq, &mut last_avail).is_none());
+        assert_eq!(chain.head, head);
+        let flags: Vec<bool> = chain.segments.iter().map(|s| s.writable).collect();
+        assert_eq!(flags, [false, true, true]);
+        assert_eq!(chain.readable(), header);
+        assert_eq!(chain.writable_len(), 33);
+        // Una escritura que cruza el límite entre segmentos
+        assert_eq!(chain.write_at(30, &[7, 8, 9]), 3);
+
+        assert!(!vq.has_used());
+        mock::push_used(&vq, head, 33);
+        let elem = vq.pop_used().unwrap();
+        assert_eq!((elem.id as u16, elem.len), (head, 33));
+        assert_eq!(&data[30..], &[7, 8]);
+        assert_eq!(status[0], 9);
+        assert_eq!(vq.num_free(), 8);
+        assert_eq!(vq.in_flight(), 0);
+    }
+
+    #[test]
+    fn free_list_exhaustion_and_reuse() {
+        let _guard = mock::install_hal();
+        let mut vq = setup_virtqueue(0, 4).unwrap();
+        let buf = [0u8; 8];
+        let heads: Vec<u16> = (0..4).map(|_| vq.add(&[Segment::readable(&buf)]).unwrap()).collect();
+        assert!(vq.add(&[Segment::readable(&buf)]).is_none());
+        assert!(vq.add(&[]).is_none());
+
+        // Se completan fuera de orden y los descriptores vuelven a la lista de libres
+        let mut last_avail = 0;
+        while mock::pop_avail(&vq, &mut last_avail).is_some() {}
+        mock::push_used(&vq, heads[2], 0);
+        mock::push_used(&vq, heads[0], 0);
+        assert_eq!(vq.wait_used(heads[0], 10), Some(0));
+        assert_eq!(vq.num_free(), 2);
+        let chain = vq.add(&[Segment::readable(&buf), Segment::readable(&buf)]).unwrap();
+        assert!(chain == heads[0] || chain == heads[2]);
+        assert_eq!(vq.num_free(), 0);
+    }
+
+    #[test]
+    fn bogus_used_ids_are_skipped() {
+        let _guard = mock::install_hal();
+        let mut vq = setup_virtqueue(0, 4).unwrap();
+        let buf = [0u8; 8];
+        let head = vq.add(&[Segment::readable(&buf), Segment::readable(&buf)]).unwrap();
+        let mut last_avail = 0;
+        mock::pop_avail(&vq, &mut last_avail).unwrap();
+        // Fuera de la tabla, un descriptor libre y el segundo de la cadena
+        let second = unsafe { (*vq.desc.add(head as usize)).next };
+        mock::push_used(&vq, 9, 0);
+        mock::push_used(&vq, vq.free_head, 0);
+        mock::push_used(&vq, second, 0);
+        assert!(vq.pop_used().is_none());
+        assert_eq!((vq.num_free(), vq.in_flight()), (2, 1));
+
+        mock::push_used(&vq, head, 8);
+        assert_eq!(vq.pop_used().map(|e| e.id as u16), Some(head));
+        // La misma cabeza devuelta dos veces solo se libera una
+        mock::push_used(&vq, head, 8);
+        assert!(vq.pop_used().is_none());
+        assert_eq!((vq.num_free(), vq.in_flight()), (4, 0));
+    }
+
+    #[test]
+    fn ring_indices_wrap() {
+        let _guard = mock::install_hal();
+        let mut vq = setup_virtqueue(0, 2).unwrap();
+        let buf = [0u8; 4];
+        let mut last_avail = 0;
+        for _ in 0..5 {
+            let head = vq.add(&[Segment::readable(&buf)]).unwrap();
+            let chain = mock::pop_avail(&vq, &mut last_avail).unwrap();
+            assert_eq!(chain.head, head);
+            mock::push_used(&vq, head, 4);
+            assert_eq!(vq.pop_used().map(|e| e.len), Some(4));
+        }
+        assert_eq!(vq.num_free(), 2);
+    }
+
+    #[test]
+    fn stall_detection_and_reinit() {
+        let _guard = mock::install_hal();
+        let mut vq = setup_virtqueue(0, 4).unwrap();
+        let buf = [0u8; 4];
+        vq.add(&[Segment::readable(&buf)]).unwrap();
+        // Sin vigilancia nunca se considera bloqueada
+        assert!(!vq.stalled(0));
+        vq.enable_watchdog();
+        assert!(vq.stalled(0));
+        assert!(!vq.stalled(u64::MAX));
+
+        vq.reinit();
+        assert_eq!(vq.in_flight(), 0);
+        assert_eq!(vq.num_free(), 4);
+        assert!(!vq.stalled(0));
+        let mut last_avail = 0;
+        assert!(mock::pop_avail(&vq, &mut last_avail).is_none());
+
+        vq.add(&[Segment::readable(&buf)]).unwrap();
+        assert!(!vq.stalled(WATCHDOG_DEADLINE_TSC));
+        vq.mark_stalled();
+        assert!(vq.stalled(WATCHDOG_DEADLINE_TSC));
+    }
+}
@@ -0,0 +1,961 @@
+//! Driver virtio-vsock (virtio 1.x, sección 5.10), solo sockets de tipo stream.
+//!
+//! Colas: rx 0 / tx 1 / eventos 2. Cada paquete es una cabecera de 44 bytes seguida
+//! de los datos. El host conecta a un puerto registrado con [`listen`] y la conexión
+//! se recoge con [`accept`]; el guest también puede abrir conexiones con [`connect`]
+//! (al host o a otra VM), desde un puerto local efímero. El control de flujo es el de
+//! créditos del protocolo (`buf_alloc` / `fwd_cnt`).
+//!
+//! Tras un TRANSPORT_RESET o una recuperación del watchdog las conexiones se pierden,
+//! pero las escuchas se conservan y el host puede volver a conectar.
+
+use crate::dma::PageAligned;
+use crate::devmgr::{Driver, Match};
+use crate::pci::VirtioDevice;
+use crate::transport::{Recovery, TransportError, VirtioPci};
+use crate::virtqueue::{Segment, VirtQueue, DEFAULT_SPIN_LIMIT};
+use crate::watchdog;
+use core::fmt;
+use core::ptr::addr_of_mut;
+use logging::events::{self, Category, Event, EventKind};
+use logging::Level;
+
+pub const VIRTIO_ID_VSOCK: u16 = 19;
+/// CID reservado del host.
+pub const VMADDR_CID_HOST: u64 = 2;
+
+// Configuración del dispositivo
+const CONFIG_GUEST_CID: usize = 0;
+
+// virtio_vsock_hdr
+const HDR_LEN: usize = 44;
+const VIRTIO_VSOCK_TYPE_STREAM: u16 = 1;
+
+// Operaciones
+const VIRTIO_VSOCK_OP_REQUEST: u16 = 1;
+const VIRTIO_VSOCK_OP_RESPONSE: u16 = 2;
+const VIRTIO_VSOCK_OP_RST: u16 = 3;
+const VIRTIO_VSOCK_OP_SHUTDOWN: u16 = 4;
+const VIRTIO_VSOCK_OP_RW: u16 = 5;
+const VIRTIO_VSOCK_OP_CREDIT_UPDATE: u16 = 6;
+const VIRTIO_VSOCK_OP_CREDIT_REQUEST: u16 = 7;
+
+// Flags de SHUTDOWN
+const VIRTIO_VSOCK_SHUTDOWN_RCV: u32 = 1;
+const VIRTIO_VSOCK_SHUTDOWN_SEND: u32 = 2;
+
+// Eventos
+const VIRTIO_VSOCK_EVENT_TRANSPORT_RESET: u32 = 0;
+const EVENT_LEN: usize = 4;
+
+const QUEUE_SIZE: u16 = 64;
+const RX_BUFS: usize = 16;
+const RX_BUF_SIZE: usize = 4096;
+const EVENT_BUFS: usize = 4;
+/// Datos máximos por paquete RW enviado.
+const TX_PAYLOAD_MAX: usize = 4096 - HDR_LEN;
+const MAX_LISTENERS: usize = 4;
+const MAX_CONNECTIONS: usize = 4;
+/// Buffer de recepción por conexión; es el crédito que se anuncia al host.
+const CONN_BUF_SIZE: usize = 16 * 1024;
+const TX_SPIN_LIMIT: usize = DEFAULT_SPIN_LIMIT / 10;
+/// Sondeos de [`connect`] esperando la respuesta del otro extremo.
+const CONNECT_SPIN_LIMIT: usize = DEFAULT_SPIN_LIMIT;
+/// Puertos locales de las conexiones que abre el guest (rango efímero de IANA).
+const EPHEMERAL_PORTS: core::ops::Range<u32> = 49152..65536;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VsockError {
+    /// No hay dispositivo virtio-vsock o se ha retirado.
+    NoDevice,
+    Transport(TransportError),
+    /// El dispositivo no completó la transmisión a tiempo.
+    Timeout,
+    /// No quedan entradas libres en la tabla de escuchas.
+    TooManyListeners,
+    /// La conexión no existe o ya se ha cerrado.
+    NotConnected,
+    /// El host cerró la conexión (o la reinició).
+    ConnectionReset,
+    /// El otro extremo rechazó la conexión (RST a la petición).
+    ConnectionRefused,
+    /// No quedan entradas libres en la tabla de conexiones.
+    TooManyConnections,
+}
+
+impl VsockError {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            VsockError::NoDevice => "virtio-vsock: no device",
+            VsockError::Transport(TransportError::MissingCapability) => "virtio-vsock: missing PCI capability",
+            VsockError::Transport(TransportError::FeaturesRejected) => "virtio-vsock: features rejected",
+            VsockError::Transport(TransportError::QueueUnavailable) => "virtio-vsock: queue unavailable",
+            VsockError::Timeout => "virtio-vsock: timeout",
+            VsockError::TooManyListeners => "virtio-vsock: too many listeners",
+            VsockError::NotConnected => "virtio-vsock: not connected",
+            VsockError::ConnectionReset => "virtio-vsock: connection reset by peer",
+            VsockError::ConnectionRefused => "virtio-vsock: connection refused",
+            VsockError::TooManyConnections => "virtio-vsock: too many connections",
+        }
+    }
+}
+
+impl fmt::Display for VsockError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Identificador de una conexión devuelto por [`accept`] o [`connect`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConnectionId(usize);
+
+#[derive(Clone, Copy)]
+struct Header {
+    src_cid: u64,
+    dst_cid: u64,
+    src_port: u32,
+    dst_port: u32,
+    len: u32,
+    kind: u16,
+    op: u16,
+    flags: u32,
+    buf_alloc: u32,
+    fwd_cnt: u32,
+}
+
+impl Header {
+    fn parse(b: &[u8]) -> Option<Self> {
+        if b.len() < HDR_LEN {
+            return None;
+        }
+        let u16_at = |o: usize| u16::from_le_bytes([b[o], b[o + 1]]);
+        let u32_at = |o: usize| u32::from_le_bytes([b[o], b[o + 1], b[o + 2], b[o + 3]]);
+        let u64_at = |o: usize| u64::from_le_bytes([b[o], b[o + 1], b[o + 2], b[o + 3], b[o + 4], b[o + 5], b[o + 6], b[o + 7]]);
+        Some(Header {
+            src_cid: u64_at(0),
+            dst_cid: u64_at(8),
+            src_port: u32_at(16),
+            dst_port: u32_at(20),
+            len: u32_at(24),
+            kind: u16_at(28),
+            op: u16_at(30),
+            flags: u32_at(32),
+            buf_alloc: u32_at(36),
+            fwd_cnt: u32_at(40),
+        })
+    }
+
+    fn write(&self, b: &mut [u8]) {
+        b[0..8].copy_from_slice(&self.src_cid.to_le_bytes());
+        b[8..16].copy_from_slice(&self.dst_cid.to_le_bytes());
+        b[16..20].copy_from_slice(&self.src_port.to_le_bytes());
+        b[20..24].copy_from_slice(&self.dst_port.to_le_bytes());
+        b[24..28].copy_from_slice(&self.len.to_le_bytes());
+        b[28..30].copy_from_slice(&self.kind.to_le_bytes());
+        b[30..32].copy_from_slice(&self.op.to_le_bytes());
+        b[32..36].copy_from_slice(&self.flags.to_le_bytes());
+        b[36..40].copy_from_slice(&self.buf_alloc.to_le_bytes());
+        b[40..44].copy_from_slice(&self.fwd_cnt.to_le_bytes());
+    }
+}
+
+struct Connection {
+    local_port: u32,
+    peer_cid: u64,
+    peer_port: u32,
+    /// Ya entregada a la aplicación con [`accept`] (o abierta con [`connect`]).
+    accepted: bool,
+    /// El otro extremo aceptó la conexión; las que abre el guest esperan su RESPONSE.
+    connected: bool,
+    /// El host envió RST o SHUTDOWN completo: solo queda leer lo recibido.
+    closed: bool,
+    /// Índice en `CONN_BUFFERS`.
+    slot: usize,
+    rx_head: usize,
+    rx_len: usize,
+    /// Bytes recibidos consumidos por la aplicación (nuestro `fwd_cnt`).
+    fwd_cnt: u32,
+    /// `fwd_cnt` anunciado al host por última vez.
+    fwd_cnt_sent: u32,
+    /// Bytes enviados al host.
+    tx_cnt: u32,
+    peer_buf_alloc: u32,
+    peer_fwd_cnt: u32,
+}
+
+impl Connection {
+    /// Crédito que el host tiene libre para nosotros.
+    fn peer_credit(&self) -> usize {
+        self.peer_buf_alloc.saturating_sub(self.tx_cnt.wrapping_sub(self.peer_fwd_cnt)) as usize
+    }
+
+    fn rx_buffer(&self) -> &'static mut [u8; CONN_BUF_SIZE] {
+        unsafe { &mut (*addr_of_mut!(CONN_BUFFERS))[self.slot] }
+    }
+
+    fn push(&mut self, data: &[u8]) {
+        let n = data.len().min(CONN_BUF_SIZE - self.rx_len);
+        let rx = self.rx_buffer();
+        for (i, &b) in data[..n].iter().enumerate() {
+            rx[(self.rx_head + self.rx_len + i) % CONN_BUF_SIZE] = b;
+        }
+        self.rx_len += n;
+    }
+
+    fn pop(&mut self, out: &mut [u8]) -> usize {
+        let n = out.len().min(self.rx_len);
+        let rx = self.rx_buffer();
+        for b in out[..n].iter_mut() {
+            *b = rx[self.rx_head];
+            self.rx_head = (self.rx_head + 1) % CONN_BUF_SIZE;
+        }
+        self.rx_len -= n;
+        self.fwd_cnt = self.fwd_cnt.wrapping_add(n as u32);
+        n
+    }
+}
+
+struct VsockDevice {
+    transport: VirtioPci,
+    rx: VirtQueue,
+    tx: VirtQueue,
+    event: VirtQueue,
+    guest_cid: u64,
+    /// Buffer de `RX_BUFFERS` para cada cabeza de cadena de `rx`.
+    rx_buf_of_head: [u8; QUEUE_SIZE as usize],
+    event_buf_of_head: [u8; QUEUE_SIZE as usize],
+    /// Transmisión que no se completó a tiempo (se espera antes de reutilizar `TX_BUF`).
+    tx_pending: Option<u16>,
+    listeners: [Option<u32>; MAX_LISTENERS],
+    connections: [Option<Connection>; MAX_CONNECTIONS],
+    /// Siguiente puerto efímero que se probará en [`connect`].
+    next_port: u32,
+}
+
+// Buffers de recepción estáticos; cada uno cabe en una página
+static mut RX_BUFFERS: PageAligned<[[u8; RX_BUF_SIZE]; RX_BUFS]> = PageAligned([[0; RX_BUF_SIZE]; RX_BUFS]);
+static mut EVENT_BUFFERS: PageAligned<[[u8; EVENT_LEN]; EVENT_BUFS]> = PageAligned([[0; EVENT_LEN]; EVENT_BUFS]);
+static mut TX_BUF: [u8; HDR_LEN + TX_PAYLOAD_MAX] = [0; HDR_LEN + TX_PAYLOAD_MAX];
+// Datos recibidos por cada conexión, pendientes de leer por la aplicación
+static mut CONN_BUFFERS: [[u8; CONN_BUF_SIZE]; MAX_CONNECTIONS] = [[0; CONN_BUF_SIZE]; MAX_CONNECTIONS];
+
+static mut VSOCK: Option<VsockDevice> = None;
+
+fn device() -> Result<&'static mut VsockDevice, VsockError> {
+    unsafe { (*addr_of_mut!(VSOCK)).as_mut().ok_or(VsockError::NoDevice) }
+}
+
+impl VsockDevice {
+    fn post_rx(&mut self, idx: usize) {
+        let buf = unsafe { &mut (*addr_of_mut!(RX_BUFFERS)).0[idx] };
+        if let Some(head) = self.rx.add(&[Segment::writable(buf)]) {
+            self.rx_buf_of_head[head as usize] = idx as u8;
+        }
+    }
+
+    fn post_event(&mut self, idx: usize) {
+        let buf = unsafe { &mut (*addr_of_mut!(EVENT_BUFFERS)).0[idx] };
+        if let Some(head) = self.event.add(&[Segment::writable(buf)]) {
+            self.event_buf_of_head[head as usize] = idx as u8;
+        }
+    }
+
+    /// Publica todos los buffers de recepción y de eventos (colas recién configuradas).
+    fn post_all(&mut self) {
+        for idx in 0..RX_BUFS {
+            self.post_rx(idx);
+        }
+        self.rx.notify();
+        for idx in 0..EVENT_BUFS {
+            self.post_event(idx);
+        }
+        self.event.notify();
+    }
+
+    fn send_packet(&mut self, mut hdr: Header, payload: &[u8]) -> Result<(), VsockError> {
+        if let Some(head) = self.tx_pending {
+            self.tx.wait_used(head, TX_SPIN_LIMIT).ok_or(VsockError::Timeout)?;
+            self.tx_pending = None;
+        }
+        hdr.src_cid = self.guest_cid;
+        hdr.kind = VIRTIO_VSOCK_TYPE_STREAM;
+        hdr.len = payload.len() as u32;
+        let buf = unsafe { &mut *addr_of_mut!(TX_BUF) };
+        hdr.write(&mut buf[..HDR_LEN]);
+        buf[HDR_LEN..HDR_LEN + payload.len()].copy_from_slice(payload);
+        let head = self
+            .tx
+            .add(&[Segment::readable(&buf[..HDR_LEN + payload.len()])])
+            .ok_or(VsockError::Timeout)?;
+        self.tx.notify();
+        if self.tx.wait_used(head, TX_SPIN_LIMIT).is_none() {
+            self.tx_pending = Some(head);
+            return Err(VsockError::Timeout);
+        }
+        events::record(Event {
+            desc: head,
+            op: hdr.op as u32,
+            bytes: payload.len() as u32,
+            latency: self.tx.last_latency(),
+            ..self.tx.event(Category::Vsock, EventKind::Tx)
+        });
+        Ok(())
+    }
+
+    /// Envía un paquete de control de la conexión `index`, anunciando nuestro crédito.
+    fn send_control(&mut self, index: usize, op: u16, flags: u32) -> Result<(), VsockError> {
+        let conn = self.connections[index].as_mut().ok_or(VsockError::NotConnected)?;
+        conn.fwd_cnt_sent = conn.fwd_cnt;
+        let hdr = Header {
+            src_cid: 0,
+            dst_cid: conn.peer_cid,
+            src_port: conn.local_port,
+            dst_port: conn.peer_port,
+            len: 0,
+            kind: 0,
+            op,
+            flags,
+            buf_alloc: CONN_BUF_SIZE as u32,
+            fwd_cnt: conn.fwd_cnt,
+        };
+        self.send_packet(hdr, &[])
+    }
+
+    /// Responde con RST a un paquete que no corresponde a ninguna conexión o escucha.
+    fn send_reset_reply(&mut self, to: &Header) {
+        if to.op == VIRTIO_VSOCK_OP_RST {
+            return;
+        }
+        let hdr = Header {
+            src_cid: 0,
+            dst_cid: to.src_cid,
+            src_port: to.dst_port,
+            dst_port: to.src_port,
+            len: 0,
+            kind: 0,
+            op: VIRTIO_VSOCK_OP_RST,
+            flags: 0,
+            buf_alloc: 0,
+            fwd_cnt: 0,
+        };
+        let _ = self.send_packet(hdr, &[]);
+    }
+
+    fn find_connection(&self, hdr: &Header) -> Option<usize> {
+        self.connections.iter().position(|c| {
+            c.as_ref().is_some_and(|c| {
+                !c.closed && c.local_port == hdr.dst_port && c.peer_port == hdr.src_port && c.peer_cid == hdr.src_cid
+            })
+        })
+    }
+
+    fn handle_packet(&mut self, packet: &[u8]) {
+        let Some(hdr) = Header::parse(packet) else { return };
+        if hdr.kind != VIRTIO_VSOCK_TYPE_STREAM || hdr.dst_cid != self.guest_cid {
+            self.send_reset_reply(&hdr);
+            return;
+        }
+        let payload = &packet[HDR_LEN..packet.len().min(HDR_LEN + hdr.len as usize)];
+        events::record(Event {
+            op: hdr.op as u32,
+            bytes: payload.len() as u32,
+            ..self.rx.event(Category::Vsock, EventKind::Rx)
+        });
+        let Some(index) = self.find_connection(&hdr) else {
+            if hdr.op == VIRTIO_VSOCK_OP_REQUEST {
+                self.handle_request(&hdr);
+            } else {
+                self.send_reset_reply(&hdr);
+            }
+            return;
+        };
+        let Some(conn) = self.connections[index].as_mut() else { return };
+        // Todo paquete trae el crédito actual del host
+        conn.peer_buf_alloc = hdr.buf_alloc;
+        conn.peer_fwd_cnt = hdr.fwd_cnt;
+        if !conn.connected {
+            // Conexión abierta por el guest: solo cuenta la respuesta a la petición
+            match hdr.op {
+                VIRTIO_VSOCK_OP_RESPONSE => conn.connected = true,
+                VIRTIO_VSOCK_OP_RST => conn.closed = true,
+                _ => {}
+            }
+            return;
+        }
+        match hdr.op {
+            VIRTIO_VSOCK_OP_RW => conn.push(payload),
+            VIRTIO_VSOCK_OP_CREDIT_REQUEST => {
+                let _ = self.send_control(index, VIRTIO_VSOCK_OP_CREDIT_UPDATE, 0);
+            }
+            // Solo un SHUTDOWN completo cierra la conexión
+            VIRTIO_VSOCK_OP_SHUTDOWN
+                if hdr.flags & (VIRTIO_VSOCK_SHUTDOWN_RCV | VIRTIO_VSOCK_SHUTDOWN_SEND)
+                    == (VIRTIO_VSOCK_SHUTDOWN_RCV | VIRTIO_VSOCK_SHUTDOWN_SEND) =>
+            {
+                conn.closed = true;
+                let _ = self.send_control(index, VIRTIO_VSOCK_OP_RST, 0);
+            }
+            VIRTIO_VSOCK_OP_RST => conn.closed = true,
+            // CREDIT_UPDATE: el crédito ya se ha actualizado arriba
+            _ => {}
+        }
+    }
+
+    /// Petición de conexión del host: se acepta si hay escucha en el puerto y sitio libre.
+    fn handle_request(&mut self, hdr: &Header) {
+        let listening = self.listeners.contains(&Some(hdr.dst_port));
+        let slot = self.connections.iter().position(|c| c.is_none());
+        let (true, Some(index)) = (listening, slot) else {
+            self.send_reset_reply(hdr);
+            return;
+        };
+        self.connections[index] = Some(Connection {
+            local_port: hdr.dst_port,
+            peer_cid: hdr.src_cid,
+            peer_port: hdr.src_port,
+            accepted: false,
+            connected: true,
+            closed: false,
+            slot: index,
+            rx_head: 0,
+            rx_len: 0,
+            fwd_cnt: 0,
+            fwd_cnt_sent: 0,
+            tx_cnt: 0,
+            peer_buf_alloc: hdr.buf_alloc,
+            peer_fwd_cnt: hdr.fwd_cnt,
+        });
+        if self.send_control(index, VIRTIO_VSOCK_OP_RESPONSE, 0).is_err() {
+            self.connections[index] = None;
+        }
+    }
+
+    /// Puerto efímero libre: ni escuchado ni usado por otra conexión.
+    fn ephemeral_port(&mut self) -> u32 {
+        loop {
+            let port = self.next_port;
+            self.next_port = if port + 1 < EPHEMERAL_PORTS.end { port + 1 } else { EPHEMERAL_PORTS.start };
+            let in_use = self.listeners.contains(&Some(port))
+                || self.connections.iter().flatten().any(|c| c.local_port == port);
+            if !in_use {
+                return port;
+            }
+        }
+    }
+
+    fn poll(&mut self) {
+        while let Some(elem) = self.rx.pop_used() {
+            let idx = self.rx_buf_of_head[(elem.id as u16 % QUEUE_SIZE) as usize] as usize;
+            let buf = unsafe { &(*addr_of_mut!(RX_BUFFERS)).0[idx] };
+            self.handle_packet(&buf[..(elem.len as usize).min(RX_BUF_SIZE)]);
+            self.post_rx(idx);
+            self.rx.notify();
+        }
+        while let Some(elem) = self.event.pop_used() {
+            let idx = self.event_buf_of_head[(elem.id as u16 % QUEUE_SIZE) as usize] as usize;
+            let event = unsafe { u32::from_le_bytes((*addr_of_mut!(EVENT_BUFFERS)).0[idx]) };
+            if event == VIRTIO_VSOCK_EVENT_TRANSPORT_RESET {
+                // p. ej. tras una migración: el CID puede cambiar y las conexiones se pierden
+                self.guest_cid = self.transport.config_read64(CONFIG_GUEST_CID);
+                self.drop_connections();
+            }
+            self.post_event(idx);
+            self.event.notify();
+        }
+    }
+
+    fn drop_connections(&mut self) {
+        for conn in self.connections.iter_mut() {
+            *conn = None;
+        }
+    }
+}
+
+pub static DRIVER: Driver = Driver {
+    name: "virtio-vsock",
+    matches: &[Match::Virtio(VIRTIO_ID_VSOCK)],
+    max_instances: 1,
+    probe,
+    remove,
+};
+
+fn probe(dev: VirtioDevice) -> Result<usize, &'static str> {
+    attach(dev).map(|()| 0).map_err(|e| e.as_str())
+}
+
+fn remove(_instance: usize) {
+    unsafe { VSOCK = None; }
+}
+
+/// Configura las colas del dispositivo virtio-vsock y publica los buffers de recepción.
+fn attach(dev: VirtioDevice) -> Result<(), VsockError> {
+    let mut transport = VirtioPci::new(dev).map_err(VsockError::Transport)?;
+    transport.begin_init(0).map_err(VsockError::Transport)?;
+    let setup = (|| -> Result<_, TransportError> {
+        Ok((
+            transport.setup_queue(0, QUEUE_SIZE)?,
+            transport.setup_queue(1, QUEUE_SIZE)?,
+            transport.setup_queue(2, QUEUE_SIZE)?,
+        ))
+    })();
+    let (rx, mut tx, event) = match setup {
+        Ok(queues) => queues,
+        Err(e) => {
+            transport.fail();
+            return Err(VsockError::Transport(e));
+        }
+    };
+    transport.finish_init();
+    // Solo se vigila tx: rx y eventos tienen siempre buffers esperando al host
+    tx.enable_watchdog();
+    let guest_cid = transport.config_read64(CONFIG_GUEST_CID);
+
+    unsafe {
+        VSOCK = Some(VsockDevice {
+            transport,
+            rx,
+            tx,
+            event,
+            guest_cid,
+            rx_buf_of_head: [0; QUEUE_SIZE as usize],
+            event_buf_of_head: [0; QUEUE_SIZE as usize],
+            tx_pending: None,
+            listeners: [None; MAX_LISTENERS],
+            connections: [None, None, None, None],
+            next_port: EPHEMERAL_PORTS.start,
+        });
+    }
+    device()?.post_all();
+    watchdog::register("virtio-vsock", watchdog_stalled, watchdog_recover);
+    Ok(())
+}
+
+/// CID asignado al guest.
+pub fn guest_cid() -> Option<u64> {
+    device().ok().map(|vsock| vsock.guest_cid)
+}
+
+/// Acepta conexiones del host al puerto `port`.
+pub fn listen(port: u32) -> Result<(), VsockError> {
+    let vsock = device()?;
+    if vsock.listeners.contains(&Some(port)) {
+        return Ok(());
+    }
+    let slot = vsock.listeners.iter().position(|l| l.is_none()).ok_or(VsockError::TooManyListeners)?;
+    vsock.listeners[slot] = Some(port);
+    Ok(())
+}
+
+/// Procesa paquetes y eventos recibidos. Se llama periódicamente desde el scheduler
+/// y desde las operaciones que esperan datos.
+pub fn poll() {
+    if let Ok(vsock) = device() {
+        vsock.poll();
+    }
+}
+
+/// Devuelve una conexión nueva al puerto `port`, si el host ha conectado.
+pub fn accept(port: u32) -> Option<ConnectionId> {
+    let vsock = device().ok()?;
+    vsock.poll();
+    let index = vsock.connections.iter().position(|c| {
+        c.as_ref().is_some_and(|c| !c.accepted && c.local_port == port)
+    })?;
+    vsock.connections[index].as_mut()?.accepted = true;
+    Some(ConnectionId(index))
+}
+
+/// Abre una conexión con el puerto `port` de `cid` (el host, [`VMADDR_CID_HOST`], u
+/// otra VM) y espera a que el otro extremo la acepte.
+pub fn connect(cid: u64, port: u32) -> Result<ConnectionId, VsockError> {
+    let vsock = device()?;
+    vsock.poll();
+    let index = vsock.connections.iter().position(|c| c.is_none()).ok_or(VsockError::TooManyConnections)?;
+    let local_port = vsock.ephemeral_port();
+    vsock.connections[index] = Some(Connection {
+        local_port,
+        peer_cid: cid,
+        peer_port: port,
+        accepted: true,
+        connected: false,
+        closed: false,
+        slot: index,
+        rx_head: 0,
+        rx_len: 0,
+        fwd_cnt: 0,
+        fwd_cnt_sent: 0,
+        tx_cnt: 0,
+        peer_buf_alloc: 0,
+        peer_fwd_cnt: 0,
+    });
+    if let Err(e) = vsock.send_control(index, VIRTIO_VSOCK_OP_REQUEST, 0) {
+        vsock.connections[index] = None;
+        return Err(e);
+    }
+    for _ in 0..CONNECT_SPIN_LIMIT {
+        vsock.poll();
+        match vsock.connections[index].as_ref() {
+            Some(c) if c.connected => return Ok(ConnectionId(index)),
+            Some(c) if !c.closed => core::hint::spin_loop(),
+            _ => {
+                vsock.connections[index] = None;
+                return Err(VsockError::ConnectionRefused);
+            }
+        }
+    }
+    // Una respuesta tardía ya no encontrará la conexión y recibirá RST
+    vsock.connections[index] = None;
+    Err(VsockError::Timeout)
+}
+
+/// Envía `data` por la conexión. Devuelve los bytes enviados, que pueden ser menos
+/// que `data.len()` si el host no tiene crédito para más.
+pub fn send(id: ConnectionId, data: &[u8]) -> Result<usize, VsockError> {
+    let vsock = device()?;
+    vsock.poll();
+    let mut sent = 0;
+    while sent < data.len() {
+        let conn = vsock.connections[id.0].as_mut().ok_or(VsockError::NotConnected)?;
+        if conn.closed {
+            return Err(VsockError::ConnectionReset);
+        }
+        if !conn.connected {
+            return Err(VsockError::NotConnected);
+        }
+        let n = (data.len() - sent).min(TX_PAYLOAD_MAX).min(conn.peer_credit());
+        if n == 0 {
+            // Sin crédito: se pide una actualización y se devuelve lo enviado hasta ahora
+            let _ = vsock.send_control(id.0, VIRTIO_VSOCK_OP_CREDIT_REQUEST, 0);
+            break;
+        }
+        conn.fwd_cnt_sent = conn.fwd_cnt;
+        let hdr = Header {
+            src_cid: 0,
+            dst_cid: conn.peer_cid,
+            src_port: conn.local_port,
+            dst_port: conn.peer_port,
+            len: 0,
+            kind: 0,
+            op: VIRTIO_VSOCK_OP_RW,
+            flags: 0,
+            buf_alloc: CONN_BUF_SIZE as u32,
+            fwd_cnt: conn.fwd_cnt,
+        };
+        vsock.send_packet(hdr, &data[sent..sent + n])?;
+        if let Some(conn) = vsock.connections[id.0].as_mut() {
+            conn.tx_cnt = conn.tx_cnt.wrapping_add(n as u32);
+        }
+        sent += n;
+    }
+    Ok(sent)
+}
+
+/// Copia en `buf` los datos recibidos por la conexión. Devuelve 0 si no hay datos
+/// todavía y [`VsockError::ConnectionReset`] si el host cerró y ya no queda nada que leer.
+pub fn recv(id: ConnectionId, buf: &mut [u8]) -> Result<usize, VsockError> {
+    let vsock = device()?;
+    vsock.poll();
+    let conn = vsock.connections[id.0].as_mut().ok_or(VsockError::NotConnected)?;
+    let n = conn.pop(buf);
+    if n == 0 && conn.closed {
+        return Err(VsockError::ConnectionReset);
+    }
+    // Se anuncia el crédito liberado cuando el host podría estar esperando
+    if !conn.closed && conn.fwd_cnt.wrapping_sub(conn.fwd_cnt_sent) as usize >= CONN_BUF_SIZE / 2 {
+        let _ = vsock.send_control(id.0, VIRTIO_VSOCK_OP_CREDIT_UPDATE, 0);
+    }
+    Ok(n)
+}
+
+/// Cierra la conexión y libera su entrada.
+pub fn close(id: ConnectionId) {
+    let Ok(vsock) = device() else { return };
+    if vsock.connections[id.0].as_ref().is_some_and(|c| !c.closed) {
+        let _ = vsock.send_control(id.0, VIRTIO_VSOCK_OP_SHUTDOWN, VIRTIO_VSOCK_SHUTDOWN_RCV | VIRTIO_VSOCK_SHUTDOWN_SEND);
+        let _ = vsock.send_control(id.0, VIRTIO_VSOCK_OP_RST, 0);
+    }
+    vsock.connections[id.0] = None;
+}
+
+fn watchdog_stalled(deadline: u64) -> bool {
+    device().is_ok_and(|vsock| vsock.tx.stalled(deadline))
+}
+
+fn watchdog_recover() -> Result<Recovery, &'static str> {
+    let vsock = device().map_err(|e| e.as_str())?;
+    let VsockDevice { transport, rx, tx, event, .. } = vsock;
+    let recovery = watchdog::recover_device(transport, |f| {
+        f(rx)?;
+        f(tx)?;
+        f(event)
+    })
+    .map_err(|e| VsockError::Transport(e).as_str())?;
+    vsock.tx_pending = None;
+    // Con paquetes perdidos los flujos ya no son fiables: se cierran todas las conexiones
+    if recovery == Recovery::QueueReset {
+        for index in 0..MAX_CONNECTIONS {
+            if vsock.connections[index].as_ref().is_some_and(|c| !c.closed) {
+                let _ = vsock.send_control(index, VIRTIO_VSOCK_OP_RST, 0);
+            }
+        }
+    }
+    vsock.drop_connections();
+    if recovery == Recovery::DeviceReset {
+        vsock.guest_cid = vsock.transport.config_read64(CONFIG_GUEST_CID);
+        vsock.post_all();
+    }
+    // Las escuchas son estado del guest: siguen activas y el host puede reconectar
+    for port in vsock.listeners.iter().flatten() {
+        logging::log!(Level::Info, "virtio-vsock", "escucha restablecida en el puerto {}", port);
+    }
+    Ok(recovery)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{self, vsock::{host, VsockBackend, OP_CREDIT_REQUEST, OP_CREDIT_UPDATE, OP_REQUEST, OP_RESPONSE, OP_RST, OP_RW, OP_SHUTDOWN}};
+    use crate::transport::VIRTIO_F_RING_RESET;
+    use crate::virtqueue::WATCHDOG_DEADLINE_TSC;
+
+    const PORT: u32 = 5000;
+    const HOST_PORT: u32 = 40000;
+
+    fn install(backend: VsockBackend) -> std::sync::MutexGuard<'static, ()> {
+        let guard = mock::install(backend);
+        attach(mock::pci_device()).unwrap();
+        listen(PORT).unwrap();
+        guard
+    }
+
+    fn sent_ops() -> Vec<u16> {
+        host(|h| h.take_sent()).iter().map(|p| p.op).collect()
+    }
+
+    fn connect() -> ConnectionId {
+        host(|h| h.connect(HOST_PORT, PORT));
+        let id = accept(PORT).unwrap();
+        assert_eq!(sent_ops(), [OP_RESPONSE]);
+        id
+    }
+
+    #[test]
+    fn init_reads_guest_cid() {
+        let _guard = install(VsockBackend::new());
+        assert_eq!(guest_cid(), Some(mock::vsock::GUEST_CID));
+        assert!(accept(PORT).is_none());
+    }
+
+    #[test]
+    fn accepts_connection_on_listening_port() {
+        let _guard = install(VsockBackend::new());
+        host(|h| h.connect(HOST_PORT, PORT));
+        let id = accept(PORT).unwrap();
+        let sent = host(|h| h.take_sent());
+        assert_eq!(sent.len(), 1);
+        let response = &sent[0];
+        assert_eq!(response.op, OP_RESPONSE);
+        assert_eq!((response.src_cid, response.dst_cid), (mock::vsock::GUEST_CID, VMADDR_CID_HOST));
+        assert_eq!((response.src_port, response.dst_port), (PORT, HOST_PORT));
+        assert_eq!(response.buf_alloc, CONN_BUF_SIZE as u32);
+        // Cada conexión se entrega una sola vez
+        assert!(accept(PORT).is_none());
+        close(id);
+    }
+
+    #[test]
+    fn rejects_unknown_port() {
+        let _guard = install(VsockBackend::new());
+        host(|h| h.connect(HOST_PORT, PORT + 1));
+        assert!(accept(PORT + 1).is_none());
+        let sent = host(|h| h.take_sent());
+        assert_eq!(sent.len(), 1);
+        assert_eq!((sent[0].op, sent[0].src_port, sent[0].dst_port), (OP_RST, PORT + 1, HOST_PORT));
+        // Los datos para una conexión que no existe también se rechazan con RST
+        host(|h| h.send(HOST_PORT, PORT, b"hola"));
+        poll();
+        assert_eq!(sent_ops(), [OP_RST]);
+    }
+
+    #[test]
+    fn data_flows_both_ways() {
+        let _guard = install(VsockBackend::new());
+        let id = connect();
+        host(|h| h.send(HOST_PORT, PORT, b"ping"));
+        let mut buf = [0u8; 16];
+        assert_eq!(recv(id, &mut buf), Ok(4));
+        assert_eq!(&buf[..4], b"ping");
+        assert_eq!(recv(id, &mut buf), Ok(0));
+
+        assert_eq!(send(id, b"pong"), Ok(4));
+        let sent = host(|h| h.take_sent());
+        assert_eq!(sent.len(), 1);
+        assert_eq!((sent[0].op, &sent[0].payload[..]), (OP_RW, &b"pong"[..]));
+        // El guest anuncia lo que ya ha consumido
+        assert_eq!(sent[0].fwd_cnt, 4);
+
+        close(id);
+        let sent = host(|h| h.take_sent());
+        let ops: Vec<(u16, u32)> = sent.iter().map(|p| (p.op, p.flags)).collect();
+        assert_eq!(ops, [(OP_SHUTDOWN, VIRTIO_VSOCK_SHUTDOWN_RCV | VIRTIO_VSOCK_SHUTDOWN_SEND), (OP_RST, 0)]);
+        assert_eq!(recv(id, &mut buf), Err(VsockError::NotConnected));
+    }
+
+    #[test]
+    fn guest_connects_to_host() {
+        let mut backend = VsockBackend::new();
+        backend.listening = vec![HOST_PORT];
+        let _guard = install(backend);
+        let id = super::connect(VMADDR_CID_HOST, HOST_PORT).unwrap();
+        let sent = host(|h| h.take_sent());
+        assert_eq!(sent.len(), 1);
+        let request = &sent[0];
+        assert_eq!((request.op, request.dst_cid, request.dst_port), (OP_REQUEST, VMADDR_CID_HOST, HOST_PORT));
+        let local_port = request.src_port;
+        assert!(EPHEMERAL_PORTS.contains(&local_port));
+        // No es una conexión entrante: `accept` no la entrega
+        assert!(accept(PORT).is_none());
+
+        assert_eq!(send(id, b"hola"), Ok(4));
+        assert_eq!(host(|h| h.received[&HOST_PORT].clone()), b"hola");
+        host(|h| h.send(HOST_PORT, local_port, b"que tal"));
+        let mut buf = [0u8; 16];
+        assert_eq!(recv(id, &mut buf), Ok(7));
+        // La siguiente conexión sale de otro puerto
+        let other = super::connect(VMADDR_CID_HOST, HOST_PORT).unwrap();
+        let sent = host(|h| h.take_sent());
+        assert_ne!(sent.iter().find(|p| p.op == OP_REQUEST).unwrap().src_port, local_port);
+        close(id);
+        close(other);
+    }
+
+    #[test]
+    fn refused_connection_is_released() {
+        let _guard = install(VsockBackend::new());
+        for _ in 0..MAX_CONNECTIONS + 1 {
+            assert_eq!(super::connect(VMADDR_CID_HOST, HOST_PORT), Err(VsockError::ConnectionRefused));
+        }
+        assert!(sent_ops().iter().all(|&op| op == OP_REQUEST));
+        // Las entradas rechazadas quedan libres para el host
+        host(|h| h.connect(HOST_PORT, PORT));
+        assert!(accept(PORT).is_some());
+    }
+
+    #[test]
+    fn large_send_is_split_into_packets() {
+        let _guard = install(VsockBackend::new());
+        let id = connect();
+        let data: Vec<u8> = (0..10_000).map(|i| i as u8).collect();
+        assert_eq!(send(id, &data), Ok(data.len()));
+        let sent = host(|h| h.take_sent());
+        assert_eq!(sent.len(), data.len().div_ceil(TX_PAYLOAD_MAX));
+        assert!(sent.iter().all(|p| p.op == OP_RW && p.payload.len() <= TX_PAYLOAD_MAX));
+        assert_eq!(host(|h| h.received[&HOST_PORT].clone()), data);
+    }
+
+    #[test]
+    fn send_respects_peer_credit() {
+        let mut backend = VsockBackend::new();
+        backend.buf_alloc = 100;
+        backend.auto_consume = false;
+        let _guard = install(backend);
+        let id = connect();
+        assert_eq!(send(id, &[1; 150]), Ok(100));
+        assert_eq!(sent_ops(), [OP_RW, OP_CREDIT_REQUEST]);
+        assert_eq!(send(id, &[1; 10]), Ok(0));
+        assert_eq!(sent_ops(), [OP_CREDIT_REQUEST]);
+
+        // El host consume y avisa: vuelve a haber crédito
+        host(|h| h.consume(HOST_PORT, PORT));
+        assert_eq!(send(id, &[2; 50]), Ok(50));
+        assert_eq!(sent_ops(), [OP_RW]);
+    }
+
+    #[test]
+    fn recv_sends_credit_update() {
+        let _guard = install(VsockBackend::new());
+        let id = connect();
+        let chunk = [7u8; 4000];
+        for _ in 0..3 {
+            host(|h| h.send(HOST_PORT, PORT, &chunk));
+        }
+        let mut buf = vec![0u8; CONN_BUF_SIZE];
+        assert_eq!(recv(id, &mut buf[..4000]), Ok(4000));
+        assert!(sent_ops().is_empty());
+        // Al liberar la mitad del buffer se anuncia el crédito nuevo
+        assert_eq!(recv(id, &mut buf[..8000]), Ok(8000));
+        let sent = host(|h| h.take_sent());
+        assert_eq!(sent.len(), 1);
+        assert_eq!((sent[0].op, sent[0].fwd_cnt), (OP_CREDIT_UPDATE, 12000));
+    }
+
+    #[test]
+    fn host_reset_closes_connection() {
+        let _guard = install(VsockBackend::new());
+        let id = connect();
+        host(|h| {
+            h.send(HOST_PORT, PORT, b"adios");
+            h.reset(HOST_PORT, PORT);
+        });
+        let mut buf = [0u8; 16];
+        // Lo recibido antes del RST se puede leer todavía
+        assert_eq!(recv(id, &mut buf), Ok(5));
+        assert_eq!(recv(id, &mut buf), Err(VsockError::ConnectionReset));
+        assert_eq!(send(id, b"x"), Err(VsockError::ConnectionReset));
+        close(id);
+        // Sin SHUTDOWN ni RST: el host ya la cerró
+        assert!(sent_ops().is_empty());
+    }
+
+    #[test]
+    fn transport_reset_keeps_listeners() {
+        let _guard = install(VsockBackend::new());
+        let id = connect();
+        host(|h| {
+            h.guest_cid = 7;
+            h.transport_reset();
+        });
+        poll();
+        assert_eq!(guest_cid(), Some(7));
+        assert_eq!(send(id, b"x"), Err(VsockError::NotConnected));
+        host(|h| h.connect(HOST_PORT + 1, PORT));
+        assert!(accept(PORT).is_some());
+    }
+
+    fn recover_stalled_tx(features: u64, expected: Recovery) {
+        let mut backend = VsockBackend::new();
+        backend.extra_features = features;
+        let _guard = install(backend);
+        let id = connect();
+        mock::with_backend(|h: &mut VsockBackend| h.hold_tx = true);
+        assert_eq!(send(id, b"x"), Err(VsockError::Timeout));
+        assert!(!watchdog_stalled(WATCHDOG_DEADLINE_TSC));
+        device().unwrap().tx.age(WATCHDOG_DEADLINE_TSC + 1);
+        assert!(watchdog_stalled(WATCHDOG_DEADLINE_TSC));
+
+        // El reset desbloquea el dispositivo
+        mock::with_backend(|h: &mut VsockBackend| h.hold_tx = false);
+        assert_eq!(watchdog_recover(), Ok(expected));
+        assert!(!watchdog_stalled(0));
+        // Con reset de cola se avisa al host de las conexiones perdidas
+        let expected_ops: &[u16] = if expected == Recovery::QueueReset { &[OP_RST] } else { &[] };
+        assert_eq!(sent_ops(), expected_ops);
+        assert_eq!(send(id, b"x"), Err(VsockError::NotConnected));
+
+        // La escucha sigue activa y el host puede reconectar
+        host(|h| h.connect(HOST_PORT + 1, PORT));
+        assert!(accept(PORT).is_some());
+        assert_eq!(sent_ops(), [OP_RESPONSE]);
+    }
+
+    #[test]
+    fn watchdog_recovery_with_device_reset() {
+        recover_stalled_tx(0, Recovery::DeviceReset);
+    }
+
+    #[test]
+    fn watchdog_recovery_with_queue_reset() {
+        recover_stalled_tx(VIRTIO_F_RING_RESET, Recovery::QueueReset);
+    }
+}
@@ -0,0 +1,123 @@
+//! Watchdog de virtqueues.
+//!
+//! Cada driver registra una función que indica si alguna de sus colas vigiladas está
+//! bloqueada (cadenas en vuelo sin progreso durante [`WATCHDOG_DEADLINE_TSC`]) y otra
+//! que la recupera: reset de la cola con VIRTIO_F_RING_RESET si se negoció, o reset
+//! completo y reinicialización del dispositivo. Cada bloqueo y cada recuperación
+//! incrementan los contadores de `logging::metrics` y dejan un evento `watchdog` en
+//! `logging::events`.
+
+use crate::transport::{Recovery, TransportError, VirtioPci};
+use crate::virtqueue::{VirtQueue, WATCHDOG_DEADLINE_TSC};
+use logging::events::{self, Category, Event, EventKind};
+use logging::metrics;
+use logging::Level;
+
+const MAX_WATCHED: usize = 8;
+
+#[derive(Clone, Copy)]
+struct Watched {
+    name: &'static str,
+    stalled: fn(u64) -> bool,
+    recover: fn() -> Result<Recovery, &'static str>,
+}
+
+static mut WATCHED: [Option<Watched>; MAX_WATCHED] = [None; MAX_WATCHED];
+
+/// Registra un dispositivo. Registrar otra vez el mismo nombre reemplaza la entrada.
+pub fn register(
+    name: &'static str,
+    stalled: fn(u64) -> bool,
+    recover: fn() -> Result<Recovery, &'static str>,
+) {
+    let table = unsafe { &mut *core::ptr::addr_of_mut!(WATCHED) };
+    let slot = table
+        .iter()
+        .position(|w| w.is_some_and(|w| w.name == name))
+        .or_else(|| table.iter().position(|w| w.is_none()));
+    if let Some(slot) = slot {
+        table[slot] = Some(Watched { name, stalled, recover });
+    }
+}
+
+/// Revisa todos los dispositivos registrados y recupera los bloqueados. Devuelve
+/// cuántos bloqueos se detectaron. Se llama periódicamente desde el scheduler.
+pub fn poll() -> usize {
+    let table = unsafe { *core::ptr::addr_of!(WATCHED) };
+    let mut stalls = 0;
+    for watched in table.iter().flatten() {
+        if !(watched.stalled)(WATCHDOG_DEADLINE_TSC) {
+            continue;
+        }
+        stalls += 1;
+        report(watched.name, (watched.recover)());
+    }
+    stalls
+}
+
+/// Cuenta un bloqueo y el resultado de su recuperación. Además de [`poll`], lo usan
+/// los drivers que recuperan la cola por su cuenta cuando una petición síncrona agota
+/// su espera.
+pub fn report(name: &str, result: Result<Recovery, &str>) {
+    metrics::VIRTIO_QUEUE_STALLS.inc();
+    match result {
+        Ok(Recovery::QueueReset) => {
+            metrics::VIRTIO_QUEUE_RESETS.inc();
+            logging::log!(Level::Warning, "watchdog", "{}: cola bloqueada, recuperada con reset de cola", name);
+        }
+        Ok(Recovery::DeviceReset) => {
+            metrics::VIRTIO_DEVICE_RESETS.inc();
+            logging::log!(Level::Warning, "watchdog", "{}: cola bloqueada, dispositivo reiniciado", name);
+        }
+        Err(e) => {
+            metrics::VIRTIO_RECOVERY_FAILURES.inc();
+            logging::log!(Level::Error, "watchdog", "{}: recuperación fallida: {}", name, e);
+        }
+    }
+}
+
+/// Recuperación estándar de un dispositivo. `each_queue` debe aplicar la función
+/// que recibe a todas las colas del dispositivo.
+///
+/// Con VIRTIO_F_RING_RESET se reinician solo las colas bloqueadas; si no se negoció
+/// (o el reset de cola falla) se reinicia el dispositivo entero y se restauran todas
+/// las colas vacías. En ambos casos el driver debe olvidar las peticiones en vuelo
+/// y volver a publicar sus buffers de recepción.
+pub fn recover_device<F>(transport: &mut VirtioPci, mut each_queue: F) -> Result<Recovery, TransportError>
+where
+    F: FnMut(&mut dyn FnMut(&mut VirtQueue) -> Result<(), TransportError>) -> Result<(), TransportError>,
+{
+    if transport.recovery_mode() == Recovery::QueueReset {
+        let t = &*transport;
+        let reset = each_queue(&mut |vq| {
+            if !vq.stalled(WATCHDOG_DEADLINE_TSC) {
+                return Ok(());
+            }
+            // Latencia: ciclos que llevaba la cola sin progreso
+            let latency = vq.idle_cycles();
+            let result = t.reset_queue(vq);
+            events::record(Event {
+                latency,
+                error: result.is_err() as i32,
+                ..vq.event(Category::Watchdog, EventKind::QueueReset)
+            });
+            result
+        });
+        if reset.is_ok() {
+            return Ok(Recovery::QueueReset);
+        }
+    }
+    let restored = transport.restart().and_then(|()| {
+        let t = &*transport;
+        each_queue(&mut |vq| t.restore_queue(vq))
+    });
+    if let Err(e) = restored {
+        transport.fail();
+        // Código de error: variante de `TransportError` + 1
+        events::record(Event { error: e as i32 + 1, ..transport.event(Category::Watchdog, EventKind::RecoveryFailed) });
+        return Err(e);
+    }
+    transport.finish_init();
+    events::record(transport.event(Category::Watchdog, EventKind::DeviceReset));
+    Ok(Recovery::DeviceReset)
+}
@@ -0,0 +1,327 @@
+//! Consola interactiva de depuración sobre el puerto de consola de virtio-console.
+//
+// La entrada se acumula por líneas (con eco y borrado); cada línea completa se
+// interpreta como una orden. Se atiende desde la tarea de logging del scheduler.
+
+use core::fmt::{self, Write};
+use drivers_virtio::console;
+use logging::events::{self, Category};
+
+const LINE_MAX: usize = 128;
+const PROMPT: &str = "kernel> ";
+
+static mut EDITOR: LineEditor = LineEditor { line: [0; LINE_MAX], len: 0 };
+static mut PROMPT_SHOWN: bool = false;
+
+/// Efecto de un byte de entrada sobre la línea en edición.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Input {
+    /// Carácter añadido: se devuelve como eco.
+    Echo(u8),
+    /// Se borró el último carácter.
+    Erase,
+    /// Fin de línea: la orden está lista en [`LineEditor::line`].
+    Line,
+    Ignored,
+}
+
+/// Línea en edición, con borrado y longitud máxima.
+struct LineEditor {
+    line: [u8; LINE_MAX],
+    len: usize,
+}
+
+impl LineEditor {
+    fn feed(&mut self, b: u8) -> Input {
+        match b {
+            b'\r' | b'\n' => Input::Line,
+            // Backspace / DEL
+            0x08 | 0x7F if self.len > 0 => {
+                self.len -= 1;
+                Input::Erase
+            }
+            0x20..=0x7E if self.len < LINE_MAX => {
+                self.line[self.len] = b;
+                self.len += 1;
+                Input::Echo(b)
+            }
+            _ => Input::Ignored,
+        }
+    }
+
+    fn line(&self) -> &str {
+        core::str::from_utf8(&self.line[..self.len]).unwrap_or("").trim()
+    }
+
+    fn clear(&mut self) {
+        self.len = 0;
+    }
+}
+
+/// Orden de la consola ya interpretada.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Command<'a> {
+    Help,
+    Log,
+    Ports,
+    Devices,
+    Mounts,
+    Ls(&'a str),
+    Stat(Option<&'a str>),
+    Entropy,
+    Mem,
+    Metrics,
+    Events,
+    /// Activa o desactiva una categoría (o solo lista el estado si es `None`).
+    Trace(Option<(Category, bool)>),
+    /// `trace` con argumentos no válidos: se muestra el uso y el estado.
+    TraceUsage,
+    Unknown(&'a str),
+}
+
+fn parse(line: &str) -> Option<Command<'_>> {
+    let mut parts = line.split_whitespace();
+    let cmd = parts.next()?;
+    let arg = parts.next();
+    Some(match cmd {
+        "help" => Command::Help,
+        "log" => Command::Log,
+        "ports" => Command::Ports,
+        "devices" => Command::Devices,
+        "mounts" => Command::Mounts,
+        "ls" => Command::Ls(arg.unwrap_or("/")),
+        "stat" => Command::Stat(arg),
+        "entropy" => Command::Entropy,
+        "mem" => Command::Mem,
+        "metrics" => Command::Metrics,
+        "events" => Command::Events,
+        "trace" => match (arg.map(Category::from_name), parts.next()) {
+            (None, _) => Command::Trace(None),
+            (Some(Some(category)), Some("on")) => Command::Trace(Some((category, true))),
+            (Some(Some(category)), Some("off")) => Command::Trace(Some((category, false))),
+            _ => Command::TraceUsage,
+        },
+        _ => Command::Unknown(cmd),
+    })
+}
+
+/// Escritor que envía a la consola de depuración (los errores se descartan).
+struct DebugWriter;
+
+impl Write for DebugWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let _ = console::debug_write(s.as_bytes());
+        Ok(())
+    }
+}
+
+macro_rules! out {
+    ($($arg:tt)*) => {{
+        let _ = write!(DebugWriter, $($arg)*);
+    }};
+}
+
+/// Procesa la entrada pendiente de la consola de depuración.
+pub fn service() {
+    let mut input = [0u8; 64];
+    let editor = unsafe { &mut *core::ptr::addr_of_mut!(EDITOR) };
+    unsafe {
+        if !PROMPT_SHOWN && console::debug_write(PROMPT.as_bytes()).is_ok() {
+            PROMPT_SHOWN = true;
+        }
+    }
+    loop {
+        let n = console::debug_read(&mut input);
+        if n == 0 {
+            break;
+        }
+        for &b in &input[..n] {
+            match editor.feed(b) {
+                Input::Echo(b) => {
+                    let _ = console::debug_write(&[b]);
+                }
+                Input::Erase => out!("\x08 \x08"),
+                Input::Line => {
+                    out!("\r\n");
+                    if let Some(command) = parse(editor.line()) {
+                        execute(command);
+                    }
+                    editor.clear();
+                    out!("{}", PROMPT);
+                }
+                Input::Ignored => {}
+            }
+        }
+    }
+}
+
+fn execute(command: Command) {
+    match command {
+        Command::Help => {
+            out!("órdenes: help, log, ports, devices, mounts, ls <ruta>, stat <ruta>, entropy, mem, metrics, events, trace [<categoría> on|off]\r\n");
+        }
+        Command::Log => {
+            // Últimos bytes del ring de logs, sin consumirlos
+            let mut buf = [0u8; 512];
+            let mut cursor = logging::log_head().saturating_sub(4096);
+            loop {
+                let (n, next) = logging::log_read_from(cursor, &mut buf);
+                if n == 0 {
+                    break;
+                }
+                let _ = console::debug_write(&buf[..n]);
+                cursor = next;
+            }
+        }
+        Command::Ports => {
+            console::for_each_port(|index, name, is_console, open| {
+                out!(
+                    "puerto {}: {}{}{}\r\n",
+                    index,
+                    if name.is_empty() { "-" } else { name },
+                    if is_console { " [consola]" } else { "" },
+                    if open { "" } else { " (cerrado)" }
+                );
+            });
+        }
+        Command::Devices => {
+            drivers_virtio::devmgr::for_each(|info| {
+                let dev = info.dev;
+                out!("{:02x}:{:02x}.{} {:04x}:{:04x}", dev.bus, dev.slot, dev.func, dev.vendor_id, dev.device_id);
+                match info.driver {
+                    Some((driver, instance)) => out!(" {} #{}\r\n", driver.name, instance),
+                    None => out!(" sin driver\r\n"),
+                }
+            });
+        }
+        Command::Mounts => {
+            vfs::for_each_mount(|prefix, fs| out!("{} ({})\r\n", prefix, fs));
+        }
+        Command::Ls(path) => {
+            let result = vfs::list(path, |entry| {
+                let suffix = if entry.kind == vfs::FileKind::Dir { "/" } else { "" };
+                out!("{}{}\r\n", entry.name, suffix);
+                true
+            });
+            if let Err(e) = result {
+                out!("{}: {}\r\n", path, e);
+            }
+        }
+        Command::Stat(path) => match path {
+            Some(path) => match vfs::stat(path) {
+                Ok(meta) => out!("{}: {:?}, {} bytes, modo {:o}\r\n", path, meta.kind, meta.size, meta.mode),
+                Err(e) => out!("{}: {}\r\n", path, e),
+            },
+            None => out!("uso: stat <ruta>\r\n"),
+        },
+        Command::Entropy => {
+            out!("sembrado: {}\r\n", crate::rand::is_seeded());
+            crate::rand::for_each_source(|name, available, failed, bytes| {
+                let state = if !available { "no disponible" } else if failed { "FALLO de salud" } else { "ok" };
+                out!("{}: {} ({} bytes)\r\n", name, state, bytes);
+            });
+        }
+        Command::Mem => {
+            let stats = drivers_virtio::balloon::mem_stats();
+            out!("frames libres: {} KiB de {} KiB\r\n", stats.free_bytes / 1024, stats.total_bytes / 1024);
+            out!("heap: {} KiB de {} KiB\r\n", stats.heap_used / 1024, stats.heap_size / 1024);
+            out!("modelo en RAM: {} KiB\r\n", stats.model_bytes / 1024);
+            out!("globo: {} KiB\r\n", drivers_virtio::balloon::inflated_bytes() / 1024);
+        }
+        Command::Metrics => {
+            logging::metrics::for_each(|name, value| out!("{}: {}\r\n", name, value));
+            out!("degradado: {}\r\n", logging::metrics::degraded());
+        }
+        Command::Events => {
+            // Últimos eventos de la traza de drivers, sin consumirlos
+            let mut buf = [logging::events::Event::EMPTY; 16];
+            let mut cursor = logging::events::head().saturating_sub(logging::events::RING_SIZE as u64);
+            loop {
+                let (n, next) = logging::events::read_from(cursor, &mut buf);
+                if n == 0 {
+                    break;
+                }
+                for event in &buf[..n] {
+                    out!("{}\r\n", event);
+                }
+                cursor = next;
+            }
+        }
+        Command::Trace(_) | Command::TraceUsage => {
+            match command {
+                Command::Trace(Some((category, true))) => events::enable(category),
+                Command::Trace(Some((category, false))) => events::disable(category),
+                Command::TraceUsage => out!("uso: trace [<categoría> on|off]\r\n"),
+                _ => {}
+            }
+            for category in Category::ALL {
+                out!("{}: {}\r\n", category.as_str(), if events::enabled(category) { "on" } else { "off" });
+            }
+        }
+        Command::Unknown(cmd) => out!("orden desconocida: {} (prueba 'help')\r\n", cmd),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn feed_all(editor: &mut LineEditor, input: &[u8]) -> Vec<Input> {
+        input.iter().map(|&b| editor.feed(b)).collect()
+    }
+
+    #[test]
+    fn editor_echoes_and_erases() {
+        let mut editor = LineEditor { line: [0; LINE_MAX], len: 0 };
+        assert_eq!(feed_all(&mut editor, b"ls"), [Input::Echo(b'l'), Input::Echo(b's')]);
+        assert_eq!(editor.feed(0x7F), Input::Erase);
+        assert_eq!(editor.feed(0x08), Input::Erase);
+        // Borrar con la línea vacía no hace nada
+        assert_eq!(editor.feed(0x08), Input::Ignored);
+        // Los caracteres de control y los bytes no ASCII se descartan
+        assert_eq!(feed_all(&mut editor, b"\x1b\xc3"), [Input::Ignored, Input::Ignored]);
+        feed_all(&mut editor, b"  mem ");
+        assert_eq!(editor.feed(b'\r'), Input::Line);
+        assert_eq!(editor.line(), "mem");
+        editor.clear();
+        assert_eq!(editor.line(), "");
+        assert_eq!(editor.feed(b'\n'), Input::Line);
+    }
+
+    #[test]
+    fn editor_limits_line_length() {
+        let mut editor = LineEditor { line: [0; LINE_MAX], len: 0 };
+        let long = [b'a'; LINE_MAX + 10];
+        let echoed = feed_all(&mut editor, &long).iter().filter(|i| matches!(i, Input::Echo(_))).count();
+        assert_eq!(echoed, LINE_MAX);
+        assert_eq!(editor.line().len(), LINE_MAX);
+        assert_eq!(editor.feed(0x7F), Input::Erase);
+        assert_eq!(editor.feed(b'b'), Input::Echo(b'b'));
+    }
+
+    #[test]
+    fn parses_commands() {
+        assert_eq!(parse(""), None);
+        assert_eq!(parse("   "), None);
+        assert_eq!(parse("help"), Some(Command::Help));
+        assert_eq!(parse("metrics extra"), Some(Command::Metrics));
+        assert_eq!(parse("ls"), Some(Command::Ls("/")));
+        assert_eq!(parse("ls   /models"), Some(Command::Ls("/models")));
+        assert_eq!(parse("stat"), Some(Command::Stat(None)));
+        assert_eq!(parse("stat /hello.txt"), Some(Command::Stat(Some("/hello.txt"))));
+        assert_eq!(parse("reboot now"), Some(Command::Unknown("reboot")));
+        assert_eq!(parse("LS"), Some(Command::Unknown("LS")));
+    }
+
+    #[test]
+    fn parses_trace_arguments() {
+        let category = Category::ALL[0];
+        let name = category.as_str();
+        assert_eq!(parse("trace"), Some(Command::Trace(None)));
+        assert_eq!(parse(&format!("trace {} on", name)), Some(Command::Trace(Some((category, true)))));
+        assert_eq!(parse(&format!("trace {} off", name)), Some(Command::Trace(Some((category, false)))));
+        assert_eq!(parse(&format!("trace {}", name)), Some(Command::TraceUsage));
+        assert_eq!(parse(&format!("trace {} maybe", name)), Some(Command::TraceUsage));
+        assert_eq!(parse("trace nope on"), Some(Command::TraceUsage));
+    }
+}
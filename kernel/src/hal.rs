@@ -0,0 +1,114 @@
+//! HAL de `drivers_virtio` sobre el allocator de frames y la MMU del kernel.
+
+use crate::{PageTable, PML4};
+use drivers_virtio::hal::Hal;
+
+const PTE_PRESENT: u64 = 1 << 0;
+const PTE_HUGE: u64 = 1 << 7;
+/// Bits 12..51: dirección física de la página o de la tabla del siguiente nivel.
+const PTE_ADDR_MASK: u64 = 0x000F_FFFF_FFFF_F000;
+
+pub struct KernelHal;
+
+pub static KERNEL_HAL: KernelHal = KernelHal;
+
+impl Hal for KernelHal {
+    fn dma_alloc(&self, size: usize, align: usize) -> *mut u8 {
+        let ptr = crate::alloc_aligned(size, align);
+        if !ptr.is_null() {
+            // Los frames se reutilizan: el contrato del HAL exige memoria a cero
+            unsafe { core::ptr::write_bytes(ptr, 0, size); }
+        }
+        ptr
+    }
+
+    fn alloc_frames(&self, size: usize) -> *mut u8 {
+        crate::alloc_aligned(size, crate::FRAME_SIZE)
+    }
+
+    fn alloc_frame(&self) -> Option<usize> {
+        crate::alloc_frame()
+    }
+
+    fn free_frame(&self, addr: usize) {
+        crate::free_frame(addr)
+    }
+
+    fn take_unreported_free_frame(&self) -> Option<usize> {
+        crate::take_unreported_free_frame()
+    }
+
+    fn release_reported_frame(&self, addr: usize) {
+        crate::release_reported_frame(addr)
+    }
+
+    fn virt_to_phys(&self, virt: usize) -> u64 {
+        // Lo que no está en las tablas del kernel sigue en el mapeo identidad del arranque
+        translate(virt).unwrap_or(virt as u64)
+    }
+
+    fn map_mmio(&self, phys: u64, size: usize) -> *mut u8 {
+        crate::map_mmio_region(phys as usize, size)
+    }
+
+    fn map_shared_readonly(&self, phys: u64, size: usize) -> *const u8 {
+        crate::map_phys_readonly_nx(phys as usize, size)
+    }
+
+    fn unmap_shared(&self, virt: usize, size: usize) {
+        crate::unmap_phys_region(virt, size)
+    }
+}
+
+/// Traduce `virt` recorriendo las tablas del kernel (PML4 -> PDPT -> PD -> PT), con
+/// páginas de 1GiB, 2MiB y 4KiB. `None` si la dirección no está mapeada.
+fn translate(virt: usize) -> Option<u64> {
+    let mut table = core::ptr::addr_of!(PML4);
+    for (level, shift) in [39, 30, 21, 12].into_iter().enumerate() {
+        let entry = unsafe { (*table).0[(virt >> shift) & 0x1FF] };
+        if entry & PTE_PRESENT == 0 {
+            return None;
+        }
+        // PS solo tiene ese significado en PDPT (1GiB) y PD (2MiB)
+        if shift == 12 || (level > 0 && entry & PTE_HUGE != 0) {
+            let offset_mask = (1u64 << shift) - 1;
+            return Some((entry & PTE_ADDR_MASK & !offset_mask) | (virt as u64 & offset_mask));
+        }
+        // Las tablas son estáticos de la imagen del kernel: su dirección física es la virtual
+        table = (entry & PTE_ADDR_MASK) as *const PageTable;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MMIO_VIRT_BASE, PD, PDPT};
+    use core::ptr::addr_of_mut;
+
+    #[test]
+    fn translate_walks_huge_and_small_pages() {
+        let (pml4, pdpt, pd) = unsafe { (&mut *addr_of_mut!(PML4), &mut *addr_of_mut!(PDPT), &mut *addr_of_mut!(PD)) };
+        let table_addr = |table: &PageTable| table as *const PageTable as u64;
+        let nx = 1u64 << 63;
+        // Rango alto de MMIO: página de 2MiB RO+NX, como la deja `map_high_region`
+        let base = MMIO_VIRT_BASE;
+        pml4.0[(base >> 39) & 0x1FF] = table_addr(&pdpt[10]) | 0b11;
+        pdpt[10].0[0] = table_addr(&pd[20]) | 0b11;
+        pd[20].0[0] = 0xFE00_0000 | 0b10000001 | nx;
+        assert_eq!(translate(base + 0x1234), Some(0xFE00_1234));
+        assert_eq!(translate(base + 0x1F_FFFF), Some(0xFE1F_FFFF));
+        assert_eq!(translate(base + 0x20_0000), None);
+        // Página de 4KiB dentro de una tabla PT
+        pd[20].0[1] = table_addr(&pd[21]) | 0b11;
+        pd[21].0[3] = 0x8_0000_5000 | 0b11 | nx;
+        assert_eq!(translate(base + 0x20_3ABC), Some(0x8_0000_5ABC));
+        assert_eq!(translate(base + 0x20_4000), None);
+        // Página de 1GiB en la PDPT
+        pdpt[10].0[1] = 0x40_0000_0000 | 0b10000011;
+        assert_eq!(translate(base + (1 << 30) + 0x12_3456), Some(0x40_0012_3456));
+        assert_eq!(translate(base + (2 << 30)), None);
+        pml4.0[(base >> 39) & 0x1FF] = 0;
+        assert_eq!(translate(base + 0x1234), None);
+    }
+}
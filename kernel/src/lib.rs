@@ -1,5 +1,5 @@
-#![no_std]
-#![no_main]
+#![cfg_attr(not(test), no_std)]
+#![cfg_attr(not(test), no_main)]
 
 use core::panic::PanicInfo;
 extern crate alloc;
@@ -8,19 +8,25 @@ extern crate drivers_virtio;
 extern crate ai_runtime;
 extern crate mcp_vsock_transport;
 extern crate mcp_core;
+extern crate vfs;
 
 #[macro_use]
 extern crate logging;
 
 mod tests;
+mod debug_console;
+mod hal;
+pub mod rand;
 
 // Tamaño del heap: 1 MiB
 const HEAP_SIZE: usize = 1024 * 1024;
 static mut HEAP_SPACE: [u8; HEAP_SIZE] = [0; HEAP_SIZE];
 
-#[global_allocator]
+// En las pruebas en el host se usa el allocator del sistema
+#[cfg_attr(not(test), global_allocator)]
 static ALLOCATOR: LockedHeap = LockedHeap::empty();
 
+#[cfg(not(test))]
 #[no_mangle]
 pub extern "C" fn _start() -> ! {
     // Símbolos exportados por el linker para las secciones
@@ -34,6 +40,9 @@ pub extern "C" fn _start() -> ! {
         static __stack_start: u8;
         static __stack_end: u8;
     }
+    // Memoria baja del firmware y la imagen del kernel (con el heap, las tablas de
+    // páginas de la BSS y el stack): el allocator de frames no debe entregarlos
+    reserve_frames(0, unsafe { &__stack_end as *const _ as usize });
     // Inicializa MMU y protecciones
     mmu_init();
     unsafe {
@@ -49,8 +58,9 @@ pub extern "C" fn _start() -> ! {
         );
         // Inserta guard page al final del stack principal
         mmu_insert_guard_page(&__stack_end as *const _ as usize - PAGE_SIZE);
-        // Inicializa canario de stack principal
-        init_stack_canary(&__stack_start as *const _ as *mut u64);
+        // Siembra el pool de entropía con las fuentes de la CPU; virtio-rng se suma al
+        // enlazar los drivers
+        rand::init();
         // Inicializa el heap global
         ALLOCATOR.lock().init(HEAP_SPACE.as_mut_ptr(), HEAP_SIZE);
     }
@@ -58,16 +68,118 @@ pub extern "C" fn _start() -> ! {
     // tests::test_stack_canary();
     // tests::test_guard_page(); // Descomentar para probar page fault (detendrá el kernel)
     serial_println!("\n[unikernel-ai] Kernel booting...");
-    drivers_virtio::vsock::init();
-    drivers_virtio::fs::init();
-    mcp_vsock_transport::vsock_transport::init();
+    drivers_virtio::hal::set_hal(&hal::KERNEL_HAL);
+    // Drivers en orden de enlace: virtio-console primero para que el resto de logs
+    // salga ya por su puerto de log si el host lo tiene abierto
+    use drivers_virtio::{balloon, blk, console, devmgr, fs, rng, vsock};
+    balloon::set_stats_provider(memory_stats);
+    fs::set_dax_revoke_handler(ai_runtime::revoke_mapping);
+    for driver in [&console::DRIVER, &rng::DRIVER, &balloon::DRIVER, &vsock::DRIVER, &fs::DRIVER, &blk::DRIVER] {
+        devmgr::register(driver);
+    }
+    let bound = devmgr::enumerate();
+    serial_println!("[devmgr] {} dispositivo(s) enlazados", bound);
+    if devmgr::bound(&console::DRIVER) > 0 {
+        serial_println!("[virtio-console] listo (log activo: {})", console::log_active());
+    }
+    if devmgr::bound(&rng::DRIVER) > 0 {
+        rand::add_virtio_rng();
+    }
+    // Canario del stack principal, ya con todas las fuentes de entropía en el pool
+    unsafe { init_stack_canary(&__stack_start as *const _ as *mut u64); }
+    if devmgr::bound(&balloon::DRIVER) > 0 {
+        serial_println!(
+            "[virtio-balloon] listo ({} KiB inflados, free page reporting: {})",
+            balloon::inflated_bytes() / 1024,
+            balloon::reporting_enabled()
+        );
+    }
+    if let Some(cid) = vsock::guest_cid() {
+        serial_println!("[virtio-vsock] listo (CID {})", cid);
+    }
+    let virtiofs_ready = devmgr::bound(&fs::DRIVER) > 0;
+    let blk_devices = devmgr::bound(&blk::DRIVER);
+    if blk_devices > 0 {
+        serial_println!("[virtio-blk] {} dispositivo(s) de bloques", blk_devices);
+    }
+    mount_filesystems(virtiofs_ready, blk_devices);
+    if let Err(e) = mcp_vsock_transport::vsock_transport::init() {
+        serial_println!("[mcp-vsock] sin escucha en el puerto {}: {}", mcp_vsock_transport::vsock_transport::MCP_VSOCK_PORT, e);
+    }
+    // A partir de aquí los cambios llegan por hot-plug
+    devmgr::set_notifier(device_changed);
     mcp_core::mcp_server::init();
+    // Un paso del servidor MCP por ronda, entre los de log_task
+    spawn_periodic(mcp_core::mcp_server::poll, "mcp_server");
     run_scheduler();
 }
 
+/// Monta virtio-fs en `/` y la primera imagen ustar encontrada en virtio-blk en `/`
+/// (si no hay virtio-fs) o en `/blk`.
+fn mount_filesystems(virtiofs_ready: bool, blk_devices: usize) {
+    if virtiofs_ready {
+        mount_virtiofs();
+    }
+    for index in 0..blk_devices {
+        if mount_blk(index) {
+            break;
+        }
+    }
+}
+
+fn mount_virtiofs() {
+    use alloc::boxed::Box;
+    let _ = vfs::mount("/", Box::leak(Box::new(vfs::virtiofs::VirtioFs)));
+}
+
+/// Monta la imagen ustar de virtio-blk `index` en `/` o, si ya está ocupado, en `/blk`.
+fn mount_blk(index: usize) -> bool {
+    use alloc::boxed::Box;
+    let Some(dev) = drivers_virtio::blk::device(index) else { return false };
+    match vfs::tar::TarFs::new(dev) {
+        Ok(tar) => {
+            let prefix = if vfs::is_mounted("/") { "/blk" } else { "/" };
+            serial_println!("[vfs] imagen ustar en virtio-blk {} montada en {} ({} ficheros)", index, prefix, tar.file_count());
+            let _ = vfs::mount(prefix, Box::leak(Box::new(tar)));
+            true
+        }
+        Err(e) => {
+            serial_println!("[vfs] virtio-blk {} sin imagen montable: {}", index, e);
+            false
+        }
+    }
+}
+
+/// Dispositivos que aparecen o desaparecen tras el arranque (hot-plug PCIe).
+/// virtio-fs sigue montado en `/` al retirarse: un dispositivo virtio-fs nuevo lo
+/// atiende sin volver a montar.
+fn device_changed(info: &drivers_virtio::devmgr::DeviceInfo, change: drivers_virtio::devmgr::Change) {
+    use drivers_virtio::devmgr::Change;
+    let dev = info.dev;
+    let name = info.driver.map_or("sin driver", |(driver, _)| driver.name);
+    match change {
+        Change::Removed => serial_println!("[devmgr] retirado {:02x}:{:02x}.{} ({})", dev.bus, dev.slot, dev.func, name),
+        Change::Bound => serial_println!("[devmgr] añadido {:02x}:{:02x}.{} ({})", dev.bus, dev.slot, dev.func, name),
+    }
+    let Some((driver, instance)) = info.driver.filter(|_| change == Change::Bound) else { return };
+    if core::ptr::eq(driver, &drivers_virtio::fs::DRIVER) && !vfs::is_mounted("/") {
+        mount_virtiofs();
+    } else if core::ptr::eq(driver, &drivers_virtio::blk::DRIVER) && !vfs::is_mounted("/blk") {
+        mount_blk(instance);
+    } else if core::ptr::eq(driver, &drivers_virtio::vsock::DRIVER) {
+        if let Err(e) = mcp_vsock_transport::vsock_transport::init() {
+            serial_println!("[mcp-vsock] sin escucha en el puerto {}: {}", mcp_vsock_transport::vsock_transport::MCP_VSOCK_PORT, e);
+        }
+    } else if core::ptr::eq(driver, &drivers_virtio::rng::DRIVER) {
+        rand::add_virtio_rng();
+    }
+}
+
+#[cfg(not(test))]
 #[panic_handler]
-fn panic(_info: &PanicInfo) -> ! {
-    serial_println!("[PANIC] Kernel panic!");
+fn panic(info: &PanicInfo) -> ! {
+    // Directo al puerto serie: no depende del heap ni de virtio-console
+    serial::_print(format_args!("[PANIC] Kernel panic: {}\n", info));
     loop {}
 }
 
@@ -75,9 +187,8 @@ fn panic(_info: &PanicInfo) -> ! {
 #[macro_export]
 macro_rules! serial_println {
     ($($arg:tt)*) => {{
-        let msg = alloc::format!(concat!($($arg)*, "\n"));
-        logging::log_write(&msg);
-        $crate::serial::_print(format_args!("{}", msg));
+        let msg = alloc::format!("{}\n", format_args!($($arg)*));
+        $crate::serial::log_line(&msg);
     }};
 }
 
@@ -87,6 +198,15 @@ pub mod serial {
     pub fn _print(args: fmt::Arguments) {
         let _ = SerialPort.write_fmt(args);
     }
+
+    /// Guarda la línea en el ring de logs y la envía por el puerto de log de
+    /// virtio-console; si no está disponible, la escribe en el puerto serie.
+    pub fn log_line(msg: &str) {
+        logging::log_write(msg);
+        if !drivers_virtio::console::log_active() || drivers_virtio::console::pump_log().is_err() {
+            _print(format_args!("{}", msg));
+        }
+    }
     struct SerialPort;
     impl Write for SerialPort {
         fn write_str(&mut self, s: &str) -> fmt::Result {
@@ -101,14 +221,27 @@ pub mod serial {
 // Frame allocator simple (bitmap, 2MiB-aligned)
 const FRAME_SIZE: usize = 2 * 1024 * 1024;
 const MAX_FRAMES: usize = 128;
+// Estados de cada frame: libre, en uso, o libre y ya notificado al host por virtio-balloon
+const FRAME_FREE: u8 = 0;
+const FRAME_USED: u8 = 1;
+const FRAME_REPORTED: u8 = 2;
 static mut FRAME_BITMAP: [u8; MAX_FRAMES] = [0; MAX_FRAMES];
 
-// implementación interna que devuelve Option<usize>
-fn alloc_frame_impl() -> Option<usize> {
+/// Marca como usados los frames que solapan `start..end`.
+fn reserve_frames(start: usize, end: usize) {
+    let bitmap = unsafe { &mut *core::ptr::addr_of_mut!(FRAME_BITMAP) };
+    let last = end.div_ceil(FRAME_SIZE).min(MAX_FRAMES);
+    for state in bitmap.iter_mut().take(last).skip(start / FRAME_SIZE) {
+        *state = FRAME_USED;
+    }
+}
+
+/// Reserva el primer frame libre (notificado o no al host).
+fn alloc_frame() -> Option<usize> {
     unsafe {
         for (i, used) in FRAME_BITMAP.iter_mut().enumerate() {
-            if *used == 0 {
-                *used = 1;
+            if *used != FRAME_USED {
+                *used = FRAME_USED;
                 return Some(i * FRAME_SIZE);
             }
         }
@@ -116,30 +249,50 @@ fn alloc_frame_impl() -> Option<usize> {
     }
 }
 
-// wrapper con la firma que usan los drivers: extern "Rust" fn alloc_frame();
-#[no_mangle]
-pub extern "Rust" fn alloc_frame() {
-    // Llamamos a impl y descartamos el resultado. Drivers solo necesitan el efecto.
-    let _ = alloc_frame_impl();
+fn free_frame(addr: usize) {
+    unsafe {
+        let i = addr / FRAME_SIZE;
+        if i < MAX_FRAMES {
+            FRAME_BITMAP[i] = FRAME_FREE;
+        }
+    }
 }
 
-// Si otra parte del kernel necesita la dirección, expón la impl:
-pub fn alloc_frame_get() -> Option<usize> {
-    alloc_frame_impl()
+/// Reserva un frame libre que aún no se ha notificado al host (free page reporting).
+/// Mientras el dispositivo lo procesa, el frame figura como usado.
+fn take_unreported_free_frame() -> Option<usize> {
+    unsafe {
+        let i = FRAME_BITMAP.iter().position(|&state| state == FRAME_FREE)?;
+        FRAME_BITMAP[i] = FRAME_USED;
+        Some(i * FRAME_SIZE)
+    }
 }
 
-pub fn free_frame(addr: usize) {
+/// Devuelve al allocator un frame ya notificado: sigue libre, pero no se vuelve a
+/// notificar hasta que alguien lo use y lo libere.
+fn release_reported_frame(addr: usize) {
     unsafe {
         let i = addr / FRAME_SIZE;
         if i < MAX_FRAMES {
-            FRAME_BITMAP[i] = 0;
+            FRAME_BITMAP[i] = FRAME_REPORTED;
         }
     }
 }
 
+/// Estadísticas de memoria para virtio-balloon y la consola de depuración.
+fn memory_stats() -> drivers_virtio::balloon::MemStats {
+    let free_frames = unsafe { FRAME_BITMAP.iter().filter(|&&state| state != FRAME_USED).count() };
+    drivers_virtio::balloon::MemStats {
+        free_bytes: (free_frames * FRAME_SIZE) as u64,
+        total_bytes: (MAX_FRAMES * FRAME_SIZE) as u64,
+        heap_used: ALLOCATOR.lock().used() as u64,
+        heap_size: HEAP_SIZE as u64,
+        model_bytes: ai_runtime::resident_bytes() as u64,
+    }
+}
+
 /// Asigna memoria alineada de tamaño `size` y alineación `align` usando frames del kernel.
-#[no_mangle]
-pub extern "Rust" fn alloc_aligned(size: usize, align: usize) -> *mut u8 {
+fn alloc_aligned(size: usize, align: usize) -> *mut u8 {
     // Solo soporta alineaciones potencias de 2 y múltiplos de FRAME_SIZE.
     // Busca suficientes frames contiguos para satisfacer la petición.
     let frames_needed = (size + FRAME_SIZE - 1) / FRAME_SIZE;
@@ -147,12 +300,12 @@ pub extern "Rust" fn alloc_aligned(size: usize, align: usize) -> *mut u8 {
     unsafe {
         let mut count = 0;
         for i in 0..MAX_FRAMES {
-            if FRAME_BITMAP[i] == 0 {
+            if FRAME_BITMAP[i] != FRAME_USED {
                 if start.is_none() { start = Some(i); }
                 count += 1;
                 if count == frames_needed {
                     let base = start.unwrap();
-                    for j in base..(base + frames_needed) { FRAME_BITMAP[j] = 1; }
+                    for j in base..(base + frames_needed) { FRAME_BITMAP[j] = FRAME_USED; }
                     let addr = base * FRAME_SIZE;
                     // Ajustar alineación si es necesario
                     let aligned_addr = (addr + align - 1) & !(align - 1);
@@ -205,45 +358,8 @@ pub fn mmu_init() {
     }
 }
 
-/// Mapea una región física a virtual (soporta 4KiB y 2MiB, RW, Present)
-#[no_mangle]
-pub extern "Rust" fn map_phys_to_virt(phys: usize, size: usize) -> *mut u8 {
-    unsafe {
-        let mut offset = 0;
-        while offset < size {
-            let virt = phys + offset;
-            let pml4_idx = (virt >> 39) & 0x1FF;
-            let pdpt_idx = (virt >> 30) & 0x1FF;
-            let pd_idx = (virt >> 21) & 0x1FF;
-            let pt_idx = (virt >> 12) & 0x1FF;
-            // Asegura PDPT y PD
-            if PML4.0[pml4_idx] & 1 == 0 {
-                PML4.0[pml4_idx] = (&PDPT[pdpt_idx] as *const _ as u64) | 0b11;
-            }
-            if PDPT[pdpt_idx].0[pdpt_idx] & 1 == 0 {
-                PDPT[pdpt_idx].0[pdpt_idx] = (&PD[pdpt_idx * PAGE_ENTRIES + pd_idx] as *const _ as u64) | 0b11;
-            }
-            // Si está alineado a 2MiB y size >= 2MiB, usa hugepage
-            if (virt & (0x1FFFFF)) == 0 && (size - offset) >= 2 * 1024 * 1024 {
-                PD[pdpt_idx * PAGE_ENTRIES + pd_idx].0[pd_idx] = (phys as u64 + offset as u64) | 0b10000011;
-                offset += 2 * 1024 * 1024;
-            } else {
-                // 4KiB page
-                // Asume que existe una tabla PT para este PD
-                let pt_base = &mut PD[pdpt_idx * PAGE_ENTRIES + pd_idx] as *mut _ as *mut PageTable;
-                let pt = &mut (*pt_base).0;
-                pt[pt_idx] = (phys as u64 + offset as u64) | 0b11;
-                offset += 4096;
-            }
-        }
-        // Invalida TLB para la región
-        core::arch::asm!("invlpg [{}]", in(reg) phys, options(nostack, preserves_flags));
-        phys as *mut u8
-    }
-}
-
 /// Desmapea una región de memoria (actualiza tablas y hace invlpg)
-pub fn unmap_phys_region(virt: usize, size: usize) {
+pub(crate) fn unmap_phys_region(virt: usize, size: usize) {
     unsafe {
         let mut offset = 0;
         while offset < size {
@@ -267,16 +383,64 @@ pub fn unmap_phys_region(virt: usize, size: usize) {
             core::arch::asm!("invlpg [{}]", in(reg) vaddr, options(nostack, preserves_flags));
         }
     }
+    if (MMIO_VIRT_BASE..MMIO_VIRT_BASE + MMIO_VIRT_PAGES * HUGE_PAGE_SIZE).contains(&virt) {
+        free_high_virt(virt, size);
+    }
 }
 
 /// Mapea una región MMIO (ej. BAR0) en un rango virtual dedicado, RW y NX
-pub fn map_mmio_region(phys: usize, size: usize) -> *mut u8 {
-    // Elegimos un rango alto para MMIO, por ejemplo, 0xFFFF_C000_0000_0000+
-    const MMIO_VIRT_BASE: usize = 0xFFFF_C000_0000_0000;
-    static mut NEXT_MMIO_VIRT: usize = MMIO_VIRT_BASE;
+pub(crate) fn map_mmio_region(phys: usize, size: usize) -> *mut u8 {
+    // 2MiB page, RW, Present, NX (bit 63)
+    map_high_region(phys, size, 0b10000011 | (1u64 << 63))
+}
+
+/// Mapea memoria física de solo lectura y NX en el rango alto (p. ej. la ventana DAX de virtio-fs)
+pub(crate) fn map_phys_readonly_nx(phys: usize, size: usize) -> *const u8 {
+    // 2MiB page, Present, sin RW, NX (bit 63)
+    map_high_region(phys, size, 0b10000001 | (1u64 << 63))
+}
+
+// Rango virtual alto para MMIO y memoria compartida, repartido en páginas de 2MiB
+const MMIO_VIRT_BASE: usize = 0xFFFF_C000_0000_0000;
+const MMIO_VIRT_PAGES: usize = PAGE_ENTRIES;
+const HUGE_PAGE_SIZE: usize = 2 * 1024 * 1024;
+static mut MMIO_VIRT_BITMAP: [bool; MMIO_VIRT_PAGES] = [false; MMIO_VIRT_PAGES];
+
+/// Reserva (first-fit) `pages` páginas de 2MiB consecutivas del rango alto.
+fn alloc_high_virt(pages: usize) -> Option<usize> {
+    let bitmap = unsafe { &mut *core::ptr::addr_of_mut!(MMIO_VIRT_BITMAP) };
+    let mut run = 0;
+    for i in 0..MMIO_VIRT_PAGES {
+        run = if bitmap[i] { 0 } else { run + 1 };
+        if run == pages {
+            let first = i + 1 - pages;
+            bitmap[first..=i].fill(true);
+            return Some(MMIO_VIRT_BASE + first * HUGE_PAGE_SIZE);
+        }
+    }
+    None
+}
+
+/// Devuelve al rango alto las páginas que cubren `virt..virt+size`.
+fn free_high_virt(virt: usize, size: usize) {
+    let first = (virt - MMIO_VIRT_BASE) / HUGE_PAGE_SIZE;
+    let last = (virt - MMIO_VIRT_BASE + size.max(1) - 1) / HUGE_PAGE_SIZE;
+    let bitmap = unsafe { &mut *core::ptr::addr_of_mut!(MMIO_VIRT_BITMAP) };
+    for page in bitmap.iter_mut().take(last + 1).skip(first) {
+        *page = false;
+    }
+}
+
+/// Mapea `phys..phys+size` con páginas de 2MiB y los flags indicados en un rango virtual dedicado
+fn map_high_region(phys: usize, size: usize, flags: u64) -> *mut u8 {
+    // Las entradas de 2MiB necesitan una base física alineada; se conserva el desplazamiento
+    let page_offset = phys & 0x1FFFFF;
+    let phys = phys - page_offset;
+    let size = size + page_offset;
+    let Some(virt) = alloc_high_virt(size.div_ceil(HUGE_PAGE_SIZE)) else {
+        return core::ptr::null_mut();
+    };
     unsafe {
-        let virt = NEXT_MMIO_VIRT;
-        NEXT_MMIO_VIRT += (size + 0x1FFFFF) & !0x1FFFFF; // Alinear a 2MiB
         let mut offset = 0;
         while offset < size {
             let vaddr = virt + offset;
@@ -290,13 +454,12 @@ pub fn map_mmio_region(phys: usize, size: usize) -> *mut u8 {
             if PDPT[pdpt_idx].0[pdpt_idx] & 1 == 0 {
                 PDPT[pdpt_idx].0[pdpt_idx] = (&PD[pdpt_idx * PAGE_ENTRIES + pd_idx] as *const _ as u64) | 0b11;
             }
-            // 2MiB page, RW, Present, NX (bit 63)
-            PD[pdpt_idx * PAGE_ENTRIES + pd_idx].0[pd_idx] = (phys as u64 + offset as u64) | 0b10000011 | (1u64 << 63);
-            offset += 2 * 1024 * 1024;
+            PD[pdpt_idx * PAGE_ENTRIES + pd_idx].0[pd_idx] = (phys as u64 + offset as u64) | flags;
+            // El rango puede reutilizarse: cada página puede tener una traducción antigua en la TLB
+            core::arch::asm!("invlpg [{}]", in(reg) vaddr, options(nostack, preserves_flags));
+            offset += HUGE_PAGE_SIZE;
         }
-        // Invalida TLB para la región
-        core::arch::asm!("invlpg [{}]", in(reg) virt, options(nostack, preserves_flags));
-        virt as *mut u8
+        (virt + page_offset) as *mut u8
     }
 }
 
@@ -349,16 +512,27 @@ pub struct Task {
     pub entry: fn(),
     pub name: &'static str,
     pub finished: bool,
+    /// Se ejecuta en cada ronda del scheduler y nunca termina.
+    pub periodic: bool,
 }
 
 static mut TASKS: [Option<Task>; 8] = [None, None, None, None, None, None, None, None];
 static mut CURRENT: usize = 0;
 
 pub fn spawn(entry: fn(), name: &'static str) {
+    add_task(Task { entry, name, finished: false, periodic: false });
+}
+
+/// Tarea que se ejecuta una vez por ronda del scheduler (un paso cada vez, sin bloquear).
+pub fn spawn_periodic(entry: fn(), name: &'static str) {
+    add_task(Task { entry, name, finished: false, periodic: true });
+}
+
+fn add_task(task: Task) {
     unsafe {
         for slot in TASKS.iter_mut() {
             if slot.is_none() {
-                *slot = Some(Task { entry, name, finished: false });
+                *slot = Some(task);
                 break;
             }
         }
@@ -367,22 +541,30 @@ pub fn spawn(entry: fn(), name: &'static str) {
 
 // --- Logging as a Task ---
 fn log_task() {
-    // Llama periódicamente a log_flush() de drivers-virtio
-    // (solo si la feature virtio-log está activa)
-    #[cfg(feature = "virtio-log")]
-    {
-        drivers_virtio::log_flush();
+    // Hot-plug PCIe: dispositivos añadidos o retirados
+    drivers_virtio::devmgr::poll();
+    // Colas virtio bloqueadas: reset de cola o del dispositivo
+    drivers_virtio::watchdog::poll();
+    // Objetivo del globo, estadísticas y notificación de frames libres
+    let _ = drivers_virtio::balloon::poll();
+    // Conexiones y eventos de virtio-vsock
+    drivers_virtio::vsock::poll();
+    // Logs pendientes hacia virtio-console y entrada de la consola de depuración
+    drivers_virtio::console::poll();
+    if drivers_virtio::console::log_active() {
+        let _ = drivers_virtio::console::pump_log();
     }
+    debug_console::service();
     // Simula espera cooperativa (en el futuro: yield, sleep, timer, etc.)
 }
 
 // --- Scheduler multitarea cooperativo ---
 pub fn run_scheduler() -> ! {
-    // Spawnea el logging task como la primera tarea
+    // Spawnea el logging task (periódico, como el servidor MCP)
     static mut LOG_TASK_SPAWNED: bool = false;
     unsafe {
         if (!LOG_TASK_SPAWNED) {
-            spawn(log_task, "log_task");
+            spawn_periodic(log_task, "log_task");
             LOG_TASK_SPAWNED = true;
         }
     }
@@ -393,8 +575,7 @@ pub fn run_scheduler() -> ! {
                     if !t.finished {
                         CURRENT = i;
                         (t.entry)();
-                        // El logging task nunca termina
-                        if t.name != "log_task" {
+                        if !t.periodic {
                             t.finished = true;
                         }
                     }
@@ -406,13 +587,36 @@ pub fn run_scheduler() -> ! {
     }
 }
 
-/// Inicializa un canario de pila al crear el stack y verifica su integridad al hacer switch o terminar la tarea
-static STACK_CANARY: u64 = 0xDEADC0DECAFEBABE;
+static mut STACK_CANARY: u64 = 0;
 
-pub fn init_stack_canary(stack_start: *mut u64) {
-    unsafe { *stack_start = STACK_CANARY; }
+/// Inicializa un canario de pila al crear el stack y verifica su integridad al hacer switch o terminar la tarea
+///
+/// El valor se genera con `rand` la primera vez; el byte bajo es 0 para que un
+/// desbordamiento con cadenas terminadas en NUL no pueda reproducirlo. Sin entropía
+/// suficiente el canario sería predecible, así que el arranque se detiene.
+///
+/// # Safety
+///
+/// `stack_start` debe apuntar a la palabra más baja de un stack válido y alineado que
+/// nadie más esté usando como dato.
+pub unsafe fn init_stack_canary(stack_start: *mut u64) {
+    unsafe {
+        if STACK_CANARY == 0 {
+            let value = match rand::next_u64() {
+                Ok(value) => value,
+                Err(e) => panic!("canario de pila: {}", e),
+            };
+            STACK_CANARY = (value & !0xFF) | 0x100;
+        }
+        *stack_start = STACK_CANARY;
+    }
 }
 
-pub fn check_stack_canary(stack_start: *const u64) -> bool {
+/// `true` si el canario escrito por [`init_stack_canary`] sigue intacto.
+///
+/// # Safety
+///
+/// `stack_start` debe ser el mismo puntero válido que se pasó a [`init_stack_canary`].
+pub unsafe fn check_stack_canary(stack_start: *const u64) -> bool {
     unsafe { *stack_start == STACK_CANARY }
 }
@@ -0,0 +1,518 @@
+//! Pool de entropía del kernel y CSPRNG ChaCha20.
+//
+// Fuentes: virtio-rng, RDSEED/RDRAND (si CPUID los anuncia) y jitter del TSC. Cada
+// fuente pasa por los tests de salud continuos de NIST SP 800-90B (4.4.1 Repetition
+// Count y 4.4.2 Adaptive Proportion); una fuente que falla se descarta hasta el
+// siguiente arranque. Las muestras aceptadas se mezclan en la clave del generador,
+// que usa "fast key erasure": tras cada petición la clave se sustituye por salida
+// nueva, así que comprometer el estado no revela lo que ya se generó.
+
+use core::arch::asm;
+use core::arch::x86_64::{__cpuid, __cpuid_count, _rdtsc};
+use core::fmt;
+use core::ptr::addr_of_mut;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RandError {
+    /// El pool aún no ha acumulado `SEED_BITS` bits de entropía estimada.
+    Unseeded,
+}
+
+impl RandError {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RandError::Unseeded => "rand: entropy pool not seeded",
+        }
+    }
+}
+
+impl fmt::Display for RandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+    VirtioRng,
+    Rdseed,
+    Rdrand,
+    TscJitter,
+}
+
+const NUM_SOURCES: usize = 4;
+const ALL_SOURCES: [Source; NUM_SOURCES] = [Source::VirtioRng, Source::Rdseed, Source::Rdrand, Source::TscJitter];
+
+impl Source {
+    pub fn name(self) -> &'static str {
+        match self {
+            Source::VirtioRng => "virtio-rng",
+            Source::Rdseed => "rdseed",
+            Source::Rdrand => "rdrand",
+            Source::TscJitter => "tsc-jitter",
+        }
+    }
+
+    /// Entropía mínima que se atribuye a cada byte de la fuente (bits, SP 800-90B).
+    /// RDRAND sale de un DRBG condicionado, así que se le atribuye la mitad.
+    fn entropy_per_byte(self) -> u32 {
+        match self {
+            Source::VirtioRng | Source::Rdseed => 8,
+            Source::Rdrand => 4,
+            Source::TscJitter => 1,
+        }
+    }
+
+    /// Corte del Repetition Count Test para α = 2⁻²⁰: 1 + ⌈20 / H⌉.
+    fn rct_cutoff(self) -> u32 {
+        1 + 20u32.div_ceil(self.entropy_per_byte())
+    }
+
+    /// Corte del Adaptive Proportion Test (ventana de 512 muestras, tabla 2 de SP 800-90B).
+    fn apt_cutoff(self) -> u32 {
+        match self.entropy_per_byte() {
+            8 => 13,
+            4 => 62,
+            2 => 177,
+            _ => 311,
+        }
+    }
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+const APT_WINDOW: u32 = 512;
+
+/// Estado de los tests de salud continuos de una fuente.
+#[derive(Clone, Copy)]
+struct Health {
+    available: bool,
+    failed: bool,
+    last: u8,
+    repeat: u32,
+    apt_first: u8,
+    apt_count: u32,
+    apt_seen: u32,
+    bytes: u64,
+}
+
+impl Health {
+    const fn new() -> Self {
+        Health { available: false, failed: false, last: 0, repeat: 0, apt_first: 0, apt_count: 0, apt_seen: 0, bytes: 0 }
+    }
+
+    /// Pasa una muestra por RCT y APT. Devuelve `false` si la fuente ha fallado.
+    fn check(&mut self, source: Source, sample: u8) -> bool {
+        if self.bytes > 0 && sample == self.last {
+            self.repeat += 1;
+            if self.repeat >= source.rct_cutoff() {
+                self.failed = true;
+            }
+        } else {
+            self.repeat = 1;
+            self.last = sample;
+        }
+        if self.apt_seen == 0 {
+            self.apt_first = sample;
+            self.apt_count = 1;
+        } else if sample == self.apt_first {
+            self.apt_count += 1;
+            if self.apt_count >= source.apt_cutoff() {
+                self.failed = true;
+            }
+        }
+        self.apt_seen = (self.apt_seen + 1) % APT_WINDOW;
+        self.bytes += 1;
+        !self.failed
+    }
+}
+
+/// Bits de entropía estimada necesarios para considerar sembrado el generador.
+const SEED_BITS: u32 = 256;
+/// Bytes generados tras los que se vuelve a sembrar desde las fuentes.
+const RESEED_INTERVAL: u64 = 1024 * 1024;
+
+struct Pool {
+    key: [u32; 8],
+    /// Contador de mezclas: separa cada compresión de la anterior.
+    mix_count: u64,
+    entropy_bits: u32,
+    seeded: bool,
+    generated: u64,
+    health: [Health; NUM_SOURCES],
+}
+
+static mut POOL: Pool = Pool::new();
+
+fn pool() -> &'static mut Pool {
+    unsafe { &mut *addr_of_mut!(POOL) }
+}
+
+// --- ChaCha20 (RFC 8439) ---
+
+fn quarter_round(s: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize) {
+    s[a] = s[a].wrapping_add(s[b]); s[d] = (s[d] ^ s[a]).rotate_left(16);
+    s[c] = s[c].wrapping_add(s[d]); s[b] = (s[b] ^ s[c]).rotate_left(12);
+    s[a] = s[a].wrapping_add(s[b]); s[d] = (s[d] ^ s[a]).rotate_left(8);
+    s[c] = s[c].wrapping_add(s[d]); s[b] = (s[b] ^ s[c]).rotate_left(7);
+}
+
+fn chacha20_block(key: &[u32; 8], counter: u32, nonce: &[u32; 3]) -> [u8; 64] {
+    let mut state = [0u32; 16];
+    state[..4].copy_from_slice(&[0x61707865, 0x3320646e, 0x79622d32, 0x6b206574]);
+    state[4..12].copy_from_slice(key);
+    state[12] = counter;
+    state[13..16].copy_from_slice(nonce);
+    let mut working = state;
+    for _ in 0..10 {
+        quarter_round(&mut working, 0, 4, 8, 12);
+        quarter_round(&mut working, 1, 5, 9, 13);
+        quarter_round(&mut working, 2, 6, 10, 14);
+        quarter_round(&mut working, 3, 7, 11, 15);
+        quarter_round(&mut working, 0, 5, 10, 15);
+        quarter_round(&mut working, 1, 6, 11, 12);
+        quarter_round(&mut working, 2, 7, 8, 13);
+        quarter_round(&mut working, 3, 4, 9, 14);
+    }
+    let mut out = [0u8; 64];
+    for i in 0..16 {
+        out[i * 4..i * 4 + 4].copy_from_slice(&working[i].wrapping_add(state[i]).to_le_bytes());
+    }
+    out
+}
+
+fn key_from_bytes(bytes: &[u8]) -> [u32; 8] {
+    let mut key = [0u32; 8];
+    for (i, word) in key.iter_mut().enumerate() {
+        *word = u32::from_le_bytes([bytes[i * 4], bytes[i * 4 + 1], bytes[i * 4 + 2], bytes[i * 4 + 3]]);
+    }
+    key
+}
+
+// Nonces fijos que separan los usos de la función de bloque
+const NONCE_OUTPUT: [u32; 3] = [0, 0, 0];
+const NONCE_MIX: [u32; 3] = [0x78696d, 0, 0];
+
+impl Pool {
+    const fn new() -> Self {
+        Pool {
+            key: [0; 8],
+            mix_count: 0,
+            entropy_bits: 0,
+            seeded: false,
+            generated: 0,
+            health: [Health::new(); NUM_SOURCES],
+        }
+    }
+
+    /// Mezcla `data` en la clave: clave ⊕ bloque de datos y, después, la clave se
+    /// reemplaza por la salida de ChaCha20 con esa clave.
+    fn mix(&mut self, data: &[u8]) {
+        for chunk in data.chunks(32) {
+            for (i, &b) in chunk.iter().enumerate() {
+                self.key[i / 4] ^= (b as u32) << ((i % 4) * 8);
+            }
+            self.mix_count += 1;
+            let nonce = [NONCE_MIX[0], self.mix_count as u32, (self.mix_count >> 32) as u32];
+            let block = chacha20_block(&self.key, 0, &nonce);
+            self.key = key_from_bytes(&block[..32]);
+        }
+    }
+
+    /// Pasa `data` por los tests de salud de `source` y, si los supera, la mezcla.
+    /// Devuelve los bits de entropía acreditados.
+    fn add(&mut self, source: Source, data: &[u8]) -> u32 {
+        let health = &mut self.health[source.index()];
+        if health.failed || data.is_empty() {
+            return 0;
+        }
+        for &b in data {
+            if !health.check(source, b) {
+                return 0;
+            }
+        }
+        self.mix(data);
+        let bits = data.len() as u32 * source.entropy_per_byte();
+        self.entropy_bits = self.entropy_bits.saturating_add(bits);
+        if self.entropy_bits >= SEED_BITS {
+            self.seeded = true;
+        }
+        bits
+    }
+
+    fn fill(&mut self, buf: &mut [u8]) {
+        let mut counter = 1u32;
+        for chunk in buf.chunks_mut(64) {
+            let block = chacha20_block(&self.key, counter, &NONCE_OUTPUT);
+            chunk.copy_from_slice(&block[..chunk.len()]);
+            counter = counter.wrapping_add(1);
+        }
+        // Fast key erasure: el bloque 0 no se entrega nunca y pasa a ser la clave nueva
+        let block = chacha20_block(&self.key, 0, &NONCE_OUTPUT);
+        self.key = key_from_bytes(&block[..32]);
+        self.generated += buf.len() as u64;
+    }
+}
+
+// --- Fuentes ---
+
+fn cpu_has_rdrand() -> bool {
+    __cpuid(1).ecx & (1 << 30) != 0
+}
+
+fn cpu_has_rdseed() -> bool {
+    __cpuid(0).eax >= 7 && __cpuid_count(7, 0).ebx & (1 << 18) != 0
+}
+
+fn rdrand64() -> Option<u64> {
+    for _ in 0..10 {
+        let value: u64;
+        let ok: u8;
+        unsafe { asm!("rdrand {v}", "setc {ok}", v = out(reg) value, ok = out(reg_byte) ok, options(nomem, nostack)); }
+        if ok != 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+fn rdseed64() -> Option<u64> {
+    // RDSEED puede quedarse sin entropía temporalmente: se reintenta con pausas
+    for _ in 0..100 {
+        let value: u64;
+        let ok: u8;
+        unsafe { asm!("rdseed {v}", "setc {ok}", v = out(reg) value, ok = out(reg_byte) ok, options(nomem, nostack)); }
+        if ok != 0 {
+            return Some(value);
+        }
+        core::hint::spin_loop();
+    }
+    None
+}
+
+/// Una muestra de jitter: el byte bajo de la duración, en ciclos de TSC, de un
+/// trabajo corto dependiente de la caché.
+fn tsc_jitter_sample(scratch: &mut [u64; 64]) -> u8 {
+    let start = unsafe { _rdtsc() };
+    let mut acc = start;
+    for i in 0..scratch.len() {
+        let idx = (acc as usize).wrapping_mul(31).wrapping_add(i) % scratch.len();
+        scratch[idx] = scratch[idx].rotate_left(7) ^ acc;
+        acc = acc.wrapping_add(scratch[idx]);
+    }
+    core::hint::black_box(acc);
+    let end = unsafe { _rdtsc() };
+    end.wrapping_sub(start) as u8
+}
+
+/// Lee hasta `buf.len()` bytes de `source`. Devuelve cuántos se obtuvieron.
+fn read_source(source: Source, buf: &mut [u8]) -> usize {
+    match source {
+        Source::VirtioRng => drivers_virtio::rng::read(buf).unwrap_or(0),
+        Source::Rdseed | Source::Rdrand => {
+            let mut n = 0;
+            for chunk in buf.chunks_mut(8) {
+                let value = if source == Source::Rdseed { rdseed64() } else { rdrand64() };
+                let Some(value) = value else { break };
+                chunk.copy_from_slice(&value.to_le_bytes()[..chunk.len()]);
+                n += chunk.len();
+            }
+            n
+        }
+        Source::TscJitter => {
+            let mut scratch = [0u64; 64];
+            for b in buf.iter_mut() {
+                *b = tsc_jitter_sample(&mut scratch);
+            }
+            buf.len()
+        }
+    }
+}
+
+/// Recoge `bytes` de cada fuente disponible y los mezcla en el pool.
+fn gather(bytes: usize) {
+    let pool = pool();
+    let mut buf = [0u8; 64];
+    let n = bytes.min(buf.len());
+    for source in ALL_SOURCES {
+        let health = pool.health[source.index()];
+        if !health.available || health.failed {
+            continue;
+        }
+        // El jitter aporta poco por byte: se muestrea más para compensar
+        let want = if source == Source::TscJitter { buf.len() } else { n };
+        let got = read_source(source, &mut buf[..want]);
+        pool.add(source, &buf[..got]);
+    }
+}
+
+/// Siembra el pool con las fuentes de la CPU (RDSEED, RDRAND, jitter del TSC).
+/// Se llama muy pronto en el arranque; virtio-rng se añade después con
+/// [`add_virtio_rng`], antes de fijar el canario de pila.
+pub fn init() {
+    let pool = pool();
+    pool.health[Source::Rdseed.index()].available = cpu_has_rdseed();
+    pool.health[Source::Rdrand.index()].available = cpu_has_rdrand();
+    pool.health[Source::TscJitter.index()].available = true;
+    // Con solo jitter hacen falta varias rondas para llegar a SEED_BITS
+    for _ in 0..8 {
+        gather(32);
+        if pool.seeded {
+            break;
+        }
+    }
+}
+
+/// Añade virtio-rng como fuente (tras inicializar el driver) y resiembra con ella.
+pub fn add_virtio_rng() {
+    pool().health[Source::VirtioRng.index()].available = drivers_virtio::rng::available();
+    gather(32);
+}
+
+/// `true` si el pool ha acumulado al menos `SEED_BITS` bits de entropía estimada.
+pub fn is_seeded() -> bool {
+    pool().seeded
+}
+
+/// Rellena `buf` con bytes del CSPRNG. Mientras el pool no esté sembrado falla con
+/// [`RandError::Unseeded`] y deja `buf` a cero en lugar de entregar salida predecible.
+pub fn fill_bytes(buf: &mut [u8]) -> Result<(), RandError> {
+    let pool = pool();
+    if !pool.seeded || pool.generated >= RESEED_INTERVAL {
+        gather(32);
+        pool.generated = 0;
+    }
+    if !pool.seeded {
+        buf.fill(0);
+        return Err(RandError::Unseeded);
+    }
+    pool.fill(buf);
+    Ok(())
+}
+
+pub fn next_u64() -> Result<u64, RandError> {
+    let mut bytes = [0u8; 8];
+    fill_bytes(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+/// Recorre las fuentes: nombre, disponible, si falló los tests de salud y bytes aceptados.
+pub fn for_each_source(mut f: impl FnMut(&'static str, bool, bool, u64)) {
+    for source in ALL_SOURCES {
+        let health = pool().health[source.index()];
+        f(source.name(), health.available, health.failed, health.bytes);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(bytes: &[u8]) -> [u32; 3] {
+        let word = |i: usize| u32::from_le_bytes([bytes[i], bytes[i + 1], bytes[i + 2], bytes[i + 3]]);
+        [word(0), word(4), word(8)]
+    }
+
+    #[test]
+    fn quarter_round_rfc8439() {
+        // RFC 8439, 2.1.1
+        let mut s = [0u32; 16];
+        s[..4].copy_from_slice(&[0x11111111, 0x01020304, 0x9b8d6f43, 0x01234567]);
+        quarter_round(&mut s, 0, 1, 2, 3);
+        assert_eq!(s[..4], [0xea2a92f4, 0xcb1cf8ce, 0x4581472e, 0x5881c4bb]);
+    }
+
+    #[test]
+    fn chacha20_block_rfc8439() {
+        // RFC 8439, 2.3.2: clave 00..1f, nonce 00:00:00:09:00:00:00:4a:00:00:00:00, contador 1
+        let key_bytes: Vec<u8> = (0..32).collect();
+        let nonce = words(&[0, 0, 0, 0x09, 0, 0, 0, 0x4a, 0, 0, 0, 0]);
+        let expected = [
+            0x10, 0xf1, 0xe7, 0xe4, 0xd1, 0x3b, 0x59, 0x15, 0x50, 0x0f, 0xdd, 0x1f, 0xa3, 0x20, 0x71, 0xc4,
+            0xc7, 0xd1, 0xf4, 0xc7, 0x33, 0xc0, 0x68, 0x03, 0x04, 0x22, 0xaa, 0x9a, 0xc3, 0xd4, 0x6c, 0x4e,
+            0xd2, 0x82, 0x64, 0x46, 0x07, 0x9f, 0xaa, 0x09, 0x14, 0xc2, 0xd7, 0x05, 0xd9, 0x8b, 0x02, 0xa2,
+            0xb5, 0x12, 0x9c, 0xd1, 0xde, 0x16, 0x4e, 0xb9, 0xcb, 0xd0, 0x83, 0xe8, 0xa2, 0x50, 0x3c, 0x4e,
+        ];
+        assert_eq!(chacha20_block(&key_from_bytes(&key_bytes), 1, &nonce), expected);
+
+        // RFC 8439, A.1 vector 1: clave, nonce y contador a cero
+        let expected = [
+            0x76, 0xb8, 0xe0, 0xad, 0xa0, 0xf1, 0x3d, 0x90, 0x40, 0x5d, 0x6a, 0xe5, 0x53, 0x86, 0xbd, 0x28,
+            0xbd, 0xd2, 0x19, 0xb8, 0xa0, 0x8d, 0xed, 0x1a, 0xa8, 0x36, 0xef, 0xcc, 0x8b, 0x77, 0x0d, 0xc7,
+            0xda, 0x41, 0x59, 0x7c, 0x51, 0x57, 0x48, 0x8d, 0x77, 0x24, 0xe0, 0x3f, 0xb8, 0xd8, 0x4a, 0x37,
+            0x6a, 0x43, 0xb8, 0xf4, 0x15, 0x18, 0xa1, 0x1c, 0xc3, 0x87, 0xb6, 0x69, 0xb2, 0xee, 0x65, 0x86,
+        ];
+        assert_eq!(chacha20_block(&[0; 8], 0, &[0; 3]), expected);
+    }
+
+    #[test]
+    fn repetition_count_test() {
+        // H = 8 bits: corte 1 + ⌈20 / 8⌉ = 4 muestras iguales seguidas
+        assert_eq!(Source::Rdseed.rct_cutoff(), 4);
+        let mut health = Health::new();
+        assert!((0..3).all(|_| health.check(Source::Rdseed, 0x5A)));
+        assert!(health.check(Source::Rdseed, 0x00));
+        assert!((0..3).all(|_| health.check(Source::Rdseed, 0x5A)));
+        assert!(!health.check(Source::Rdseed, 0x5A));
+        // Una fuente que ha fallado no se recupera
+        assert!(!health.check(Source::Rdseed, 0x01));
+
+        // El jitter (H = 1) tolera hasta 20 repeticiones
+        let mut health = Health::new();
+        assert!((0..20).all(|_| health.check(Source::TscJitter, 7)));
+        assert!(!health.check(Source::TscJitter, 7));
+    }
+
+    #[test]
+    fn adaptive_proportion_test() {
+        // Ventana de 512 muestras: la primera se cuenta y falla al verla 13 veces (H = 8)
+        let window = |count: usize| -> Vec<u8> {
+            (0..APT_WINDOW as usize).map(|i| if i % 2 == 0 && i / 2 < count { 0xAA } else { (i % 100) as u8 + 1 }).collect()
+        };
+        let mut health = Health::new();
+        for _ in 0..3 {
+            assert!(window(12).iter().all(|&b| health.check(Source::Rdseed, b)));
+        }
+        let failing = window(13);
+        let fail_at = failing.iter().position(|&b| !health.check(Source::Rdseed, b));
+        assert_eq!(fail_at, Some(24));
+        assert!(health.failed);
+    }
+
+    #[test]
+    fn failed_source_is_not_credited() {
+        let mut pool = Pool::new();
+        assert_eq!(pool.add(Source::Rdrand, &[3; 8]), 0);
+        assert!(pool.health[Source::Rdrand.index()].failed);
+        assert_eq!(pool.add(Source::Rdrand, &[1, 2, 3, 4]), 0);
+        assert_eq!(pool.entropy_bits, 0);
+        assert_eq!(pool.key, [0; 8]);
+        // Las demás fuentes siguen contando
+        let sample: Vec<u8> = (1..=32).collect();
+        assert_eq!(pool.add(Source::Rdseed, &sample), 256);
+        assert!(pool.seeded);
+    }
+
+    #[test]
+    fn fill_bytes_fails_closed_until_seeded() {
+        // Sin fuentes disponibles el pool global no llega a sembrarse
+        let mut buf = [0xFFu8; 48];
+        assert_eq!(fill_bytes(&mut buf), Err(RandError::Unseeded));
+        assert_eq!(buf, [0; 48]);
+        assert_eq!(next_u64(), Err(RandError::Unseeded));
+
+        let sample: Vec<u8> = (1..=32).collect();
+        pool().add(Source::Rdseed, &sample);
+        assert!(is_seeded());
+        let key = pool().key;
+        assert_eq!(fill_bytes(&mut buf), Ok(()));
+        assert_ne!(buf, [0; 48]);
+        // Fast key erasure: la clave cambia tras cada petición
+        assert_ne!(pool().key, key);
+        let mut again = [0u8; 48];
+        fill_bytes(&mut again).unwrap();
+        assert_ne!(again, buf);
+    }
+}
@@ -0,0 +1,269 @@
+//! Traza estructurada de eventos de los drivers.
+//!
+//! Cada evento lleva el dispositivo (tipo virtio y ubicación PCI), la cola, el
+//! descriptor, los bytes transferidos, la latencia en ciclos de TSC y el código de
+//! error. Se guardan en un ring sin locks de [`RING_SIZE`] entradas: los escritores
+//! reservan un número de secuencia con un `fetch_add` y cada entrada lleva un sello
+//! (tipo seqlock) con el que los lectores descartan las que se están escribiendo o
+//! ya se han sobrescrito.
+//!
+//! Las categorías se activan en tiempo de ejecución (consola de depuración, MCP);
+//! registrar un evento de una categoría inactiva no cuesta más que leer la máscara.
+
+use core::cell::UnsafeCell;
+use core::fmt;
+use core::sync::atomic::{fence, AtomicU32, AtomicU64, Ordering};
+
+pub const RING_SIZE: usize = 256;
+
+/// Valor de `queue`/`desc` cuando el evento no se refiere a una cola o descriptor.
+pub const NONE: u16 = u16::MAX;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Category {
+    /// Cadenas completadas en cualquier virtqueue.
+    Virtqueue,
+    Blk,
+    Fs,
+    Vsock,
+    Watchdog,
+}
+
+impl Category {
+    pub const ALL: [Category; 5] =
+        [Category::Virtqueue, Category::Blk, Category::Fs, Category::Vsock, Category::Watchdog];
+
+    pub fn bit(self) -> u32 {
+        1 << self as u8
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Category::Virtqueue => "virtqueue",
+            Category::Blk => "blk",
+            Category::Fs => "fs",
+            Category::Vsock => "vsock",
+            Category::Watchdog => "watchdog",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Category::ALL.into_iter().find(|c| c.as_str() == name)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum EventKind {
+    /// El dispositivo devolvió una cadena.
+    Complete,
+    /// Petición de un driver terminada (con o sin error).
+    Request,
+    /// Paquete enviado al dispositivo.
+    Tx,
+    /// Paquete recibido del dispositivo.
+    Rx,
+    QueueReset,
+    DeviceReset,
+    RecoveryFailed,
+}
+
+impl EventKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            EventKind::Complete => "complete",
+            EventKind::Request => "request",
+            EventKind::Tx => "tx",
+            EventKind::Rx => "rx",
+            EventKind::QueueReset => "queue_reset",
+            EventKind::DeviceReset => "device_reset",
+            EventKind::RecoveryFailed => "recovery_failed",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Event {
+    /// Número de secuencia global (lo asigna [`record`]).
+    pub seq: u64,
+    /// TSC en el momento de registrarlo (lo asigna [`record`]).
+    pub tsc: u64,
+    pub category: Category,
+    pub kind: EventKind,
+    /// Tipo de dispositivo virtio (2 = blk, 19 = vsock, 26 = fs, ...).
+    pub device_type: u16,
+    /// Ubicación PCI: bus en el byte alto, slot y función en el bajo.
+    pub device: u16,
+    pub queue: u16,
+    pub desc: u16,
+    /// Operación propia del driver: opcode FUSE, tipo de petición de blk, op de vsock.
+    pub op: u32,
+    pub bytes: u32,
+    /// Ciclos de TSC entre la publicación de la cadena y su devolución.
+    pub latency: u64,
+    /// Código de error del dispositivo (estado de blk, errno de FUSE); 0 si no hubo.
+    pub error: i32,
+}
+
+impl Event {
+    /// Base para construir eventos con `..Event::EMPTY`.
+    pub const EMPTY: Event = Event {
+        seq: 0,
+        tsc: 0,
+        category: Category::Virtqueue,
+        kind: EventKind::Complete,
+        device_type: 0,
+        device: 0,
+        queue: NONE,
+        desc: NONE,
+        op: 0,
+        bytes: 0,
+        latency: 0,
+        error: 0,
+    };
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "#{} {} {} dev={}@{:02x}:{:02x}.{}",
+            self.seq,
+            self.category.as_str(),
+            self.kind.as_str(),
+            self.device_type,
+            self.device >> 8,
+            (self.device >> 3) & 0x1F,
+            self.device & 7
+        )?;
+        if self.queue != NONE {
+            write!(f, " q={}", self.queue)?;
+        }
+        if self.desc != NONE {
+            write!(f, " desc={}", self.desc)?;
+        }
+        write!(f, " op={} bytes={} lat={}", self.op, self.bytes, self.latency)?;
+        if self.error != 0 {
+            write!(f, " err={}", self.error)?;
+        }
+        Ok(())
+    }
+}
+
+struct Slot {
+    /// `2 * seq + 1` mientras se escribe el evento `seq`; `2 * seq + 2` al terminar.
+    stamp: AtomicU64,
+    event: UnsafeCell<Event>,
+}
+
+// Los accesos a `event` se validan con `stamp`
+unsafe impl Sync for Slot {}
+
+static RING: [Slot; RING_SIZE] =
+    [const { Slot { stamp: AtomicU64::new(0), event: UnsafeCell::new(Event::EMPTY) } }; RING_SIZE];
+static HEAD: AtomicU64 = AtomicU64::new(0);
+static TAIL: AtomicU64 = AtomicU64::new(0);
+/// Categorías activas; por defecto solo el watchdog, que es infrecuente.
+static MASK: AtomicU32 = AtomicU32::new(1 << Category::Watchdog as u8);
+
+pub fn now() -> u64 {
+    unsafe { core::arch::x86_64::_rdtsc() }
+}
+
+pub fn enabled(category: Category) -> bool {
+    MASK.load(Ordering::Relaxed) & category.bit() != 0
+}
+
+pub fn enable(category: Category) {
+    MASK.fetch_or(category.bit(), Ordering::Relaxed);
+}
+
+pub fn disable(category: Category) {
+    MASK.fetch_and(!category.bit(), Ordering::Relaxed);
+}
+
+/// Máscara de categorías activas (bit `1 << categoría`).
+pub fn mask() -> u32 {
+    MASK.load(Ordering::Relaxed)
+}
+
+pub fn set_mask(mask: u32) {
+    MASK.store(mask, Ordering::Relaxed);
+}
+
+/// Registra `event` si su categoría está activa, asignándole secuencia y TSC.
+pub fn record(mut event: Event) {
+    if !enabled(event.category) {
+        return;
+    }
+    let seq = HEAD.fetch_add(1, Ordering::AcqRel);
+    event.seq = seq;
+    event.tsc = now();
+    let slot = &RING[(seq % RING_SIZE as u64) as usize];
+    slot.stamp.store(2 * seq + 1, Ordering::Relaxed);
+    fence(Ordering::Release);
+    unsafe { core::ptr::write_volatile(slot.event.get(), event) };
+    slot.stamp.store(2 * seq + 2, Ordering::Release);
+}
+
+/// Número de secuencia del próximo evento (eventos registrados desde el arranque).
+pub fn head() -> u64 {
+    HEAD.load(Ordering::Acquire)
+}
+
+enum Load {
+    Ready(Event),
+    /// Sobrescrito por un escritor que dio la vuelta al ring.
+    Lost,
+    /// Reservado pero aún sin escribir.
+    Pending,
+}
+
+fn load(seq: u64) -> Load {
+    let slot = &RING[(seq % RING_SIZE as u64) as usize];
+    let done = 2 * seq + 2;
+    let stamp = slot.stamp.load(Ordering::Acquire);
+    if stamp < done {
+        return Load::Pending;
+    }
+    if stamp > done {
+        return Load::Lost;
+    }
+    let event = unsafe { core::ptr::read_volatile(slot.event.get()) };
+    fence(Ordering::Acquire);
+    if slot.stamp.load(Ordering::Relaxed) != done {
+        return Load::Lost;
+    }
+    Load::Ready(event)
+}
+
+/// Copia en `out` los eventos a partir de la secuencia `from` sin consumirlos.
+/// Devuelve cuántos copió y el cursor con el que continuar; los eventos que ya se
+/// sobrescribieron se saltan, igual que en [`crate::log_read_from`].
+pub fn read_from(from: u64, out: &mut [Event]) -> (usize, u64) {
+    let head = HEAD.load(Ordering::Acquire);
+    let oldest = head.saturating_sub(RING_SIZE as u64);
+    let mut cursor = from.clamp(oldest, head);
+    let mut n = 0;
+    while cursor != head && n < out.len() {
+        match load(cursor) {
+            Load::Ready(event) => {
+                out[n] = event;
+                n += 1;
+            }
+            Load::Lost => {}
+            Load::Pending => break,
+        }
+        cursor += 1;
+    }
+    (n, cursor)
+}
+
+/// Lee los eventos nuevos desde la última llamada (para exponer por MCP).
+pub fn read(out: &mut [Event]) -> usize {
+    let tail = TAIL.load(Ordering::Relaxed);
+    let (n, tail) = read_from(tail, out);
+    TAIL.store(tail, Ordering::Release);
+    n
+}
@@ -1,59 +1,116 @@
 #![no_std]
 
 // Ring buffer de logs para observabilidad MCP
+pub mod metrics;
+pub mod events;
+pub mod records;
+
+use core::fmt;
 use core::sync::atomic::{AtomicUsize, Ordering};
+pub use records::Level;
 
 const LOG_BUF_SIZE: usize = 4096;
 static mut LOG_BUF: [u8; LOG_BUF_SIZE] = [0; LOG_BUF_SIZE];
 static LOG_HEAD: AtomicUsize = AtomicUsize::new(0);
-static LOG_TAIL: AtomicUsize = AtomicUsize::new(0);
 
 #[macro_export]
 macro_rules! serial_println {
     ($($arg:tt)*) => {{
-        use core::fmt::Write;
-        struct BufWriter<'a> { buf: &'a mut [u8], pos: usize }
-        impl<'a> Write for BufWriter<'a> {
-            fn write_str(&mut self, s: &str) -> core::fmt::Result {
-                let bytes = s.as_bytes();
-                let end = core::cmp::min(self.pos + bytes.len(), self.buf.len());
-                let len = end - self.pos;
-                self.buf[self.pos..end].copy_from_slice(&bytes[..len]);
-                self.pos += len;
-                Ok(())
-            }
-        }
         let mut buf = [0u8; 256];
-        let mut w = BufWriter { buf: &mut buf, pos: 0 };
-        let _ = core::write!(&mut w, $($arg)*);
-        $crate::log_write(core::str::from_utf8(&buf[..w.pos]).unwrap_or("<log>"));
+        $crate::log_write($crate::format_into(&mut buf, format_args!($($arg)*)));
     }};
 }
 
-/// Agrega un mensaje al ring buffer de logs (llamado desde serial_println!)
-pub fn log_write(msg: &str) {
-    let bytes = msg.as_bytes();
+/// Registra un mensaje con nivel y origen: `log!(Level::Warning, "mcp", "...", args)`.
+#[macro_export]
+macro_rules! log {
+    ($level:expr, $logger:expr, $($arg:tt)*) => {{
+        let mut buf = [0u8; $crate::records::MESSAGE_SIZE];
+        $crate::log($level, $logger, $crate::format_into(&mut buf, format_args!($($arg)*)));
+    }};
+}
+
+/// Formatea `args` en `buf`; lo que no cabe se descarta sin partir caracteres.
+pub fn format_into<'a>(buf: &'a mut [u8], args: fmt::Arguments<'_>) -> &'a str {
+    struct BufWriter<'a> {
+        buf: &'a mut [u8],
+        pos: usize,
+    }
+    impl fmt::Write for BufWriter<'_> {
+        fn write_str(&mut self, s: &str) -> fmt::Result {
+            let bytes = s.as_bytes();
+            let end = core::cmp::min(self.pos + bytes.len(), self.buf.len());
+            let len = end - self.pos;
+            self.buf[self.pos..end].copy_from_slice(&bytes[..len]);
+            self.pos += len;
+            Ok(())
+        }
+    }
+    let mut w = BufWriter { buf, pos: 0 };
+    let _ = fmt::Write::write_fmt(&mut w, args);
+    let len = w.pos;
+    match core::str::from_utf8(&buf[..len]) {
+        Ok(s) => s,
+        Err(e) => core::str::from_utf8(&buf[..e.valid_up_to()]).unwrap_or_default(),
+    }
+}
+
+fn write_bytes(bytes: &[u8]) {
     unsafe {
         for &b in bytes {
             let head = LOG_HEAD.load(Ordering::Relaxed);
             LOG_BUF[head % LOG_BUF_SIZE] = b;
-            LOG_HEAD.store((head + 1) % LOG_BUF_SIZE, Ordering::Relaxed);
+            LOG_HEAD.store(head.wrapping_add(1), Ordering::Release);
         }
     }
 }
 
-/// Lee los logs acumulados en el ring buffer (para exponer por MCP)
-pub fn log_read(out: &mut [u8]) -> usize {
+/// Agrega un mensaje al ring buffer de logs (llamado desde serial_println!)
+///
+/// `LOG_HEAD` cuenta bytes escritos desde el arranque (no se reduce módulo el
+/// tamaño del buffer), así los lectores con cursor propio detectan si el
+/// escritor les ha adelantado. El mensaje queda también como registro de nivel
+/// `info` cuyo origen es la etiqueta inicial (`[devmgr] ...`), o `kernel` si no la
+/// tiene.
+pub fn log_write(msg: &str) {
+    write_bytes(msg.as_bytes());
+    let line = msg.trim_start();
+    let (logger, text) = line
+        .strip_prefix('[')
+        .and_then(|rest| rest.split_once(']'))
+        .map_or(("kernel", line), |(tag, text)| (tag, text.trim_start()));
+    records::push(Level::Info, logger, text);
+}
+
+/// Agrega `[logger] msg` como línea al ring buffer y como registro de nivel `level`.
+pub fn log(level: Level, logger: &str, msg: &str) {
+    write_bytes(b"[");
+    write_bytes(logger.as_bytes());
+    write_bytes(b"] ");
+    write_bytes(msg.as_bytes());
+    write_bytes(b"\n");
+    records::push(level, logger, msg);
+}
+
+/// Posición actual del escritor (bytes escritos desde el arranque).
+pub fn log_head() -> usize {
+    LOG_HEAD.load(Ordering::Acquire)
+}
+
+/// Copia en `out` los bytes a partir de `from` sin consumirlos. Devuelve los bytes
+/// copiados y el cursor con el que continuar; si el escritor ya sobrescribió parte
+/// de lo pedido, la lectura salta al dato más antiguo que sigue en el buffer.
+pub fn log_read_from(from: usize, out: &mut [u8]) -> (usize, usize) {
+    let head = LOG_HEAD.load(Ordering::Acquire);
+    let oldest = head.saturating_sub(LOG_BUF_SIZE);
+    let mut cursor = from.clamp(oldest, head);
     let mut n = 0;
     unsafe {
-        let mut tail = LOG_TAIL.load(Ordering::Relaxed);
-        let head = LOG_HEAD.load(Ordering::Acquire);
-        while tail != head && n < out.len() {
-            out[n] = LOG_BUF[tail % LOG_BUF_SIZE];
-            tail = (tail + 1) % LOG_BUF_SIZE;
+        while cursor != head && n < out.len() {
+            out[n] = LOG_BUF[cursor % LOG_BUF_SIZE];
+            cursor += 1;
             n += 1;
         }
-        LOG_TAIL.store(tail, Ordering::Release);
     }
-    n
+    (n, cursor)
 }
@@ -0,0 +1,66 @@
+//! Contadores de telemetría del kernel (monotónicos desde el arranque).
+//!
+//! Los escriben los drivers y el servidor MCP, y los leen la herramienta MCP `health`
+//! y la consola de depuración.
+
+use core::sync::atomic::{AtomicU64, Ordering};
+
+pub struct Counter {
+    name: &'static str,
+    value: AtomicU64,
+}
+
+impl Counter {
+    pub const fn new(name: &'static str) -> Self {
+        Counter { name, value: AtomicU64::new(0) }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub fn inc(&self) {
+        self.value.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.value.load(Ordering::Relaxed)
+    }
+}
+
+/// Virtqueues con buffers pendientes que no progresaron dentro del plazo del watchdog.
+pub static VIRTIO_QUEUE_STALLS: Counter = Counter::new("virtio.queue_stalls");
+/// Recuperaciones mediante VIRTIO_F_RING_RESET (solo la cola bloqueada).
+pub static VIRTIO_QUEUE_RESETS: Counter = Counter::new("virtio.queue_resets");
+/// Recuperaciones mediante reset completo y reinicialización del dispositivo.
+pub static VIRTIO_DEVICE_RESETS: Counter = Counter::new("virtio.device_resets");
+/// Bloqueos de los que no se pudo recuperar el dispositivo.
+pub static VIRTIO_RECOVERY_FAILURES: Counter = Counter::new("virtio.recovery_failures");
+
+/// Buffers de streaming que no eran físicamente contiguos y se copiaron al pool de rebote.
+pub static DMA_BOUNCES: Counter = Counter::new("dma.bounces");
+
+/// Peticiones MCP canceladas por el cliente (`notifications/cancelled`) antes de terminar.
+pub static MCP_CANCELLED_REQUESTS: Counter = Counter::new("mcp.cancelled_requests");
+
+static ALL: [&Counter; 6] = [
+    &VIRTIO_QUEUE_STALLS,
+    &VIRTIO_QUEUE_RESETS,
+    &VIRTIO_DEVICE_RESETS,
+    &VIRTIO_RECOVERY_FAILURES,
+    &DMA_BOUNCES,
+    &MCP_CANCELLED_REQUESTS,
+];
+
+/// Recorre todos los contadores (nombre y valor).
+pub fn for_each(mut f: impl FnMut(&'static str, u64)) {
+    for counter in ALL {
+        f(counter.name(), counter.get());
+    }
+}
+
+/// `true` si algún bloqueo de virtio no terminó en una recuperación con éxito.
+pub fn degraded() -> bool {
+    let recovered = VIRTIO_QUEUE_RESETS.get() + VIRTIO_DEVICE_RESETS.get();
+    VIRTIO_RECOVERY_FAILURES.get() > 0 || VIRTIO_QUEUE_STALLS.get() > recovered
+}
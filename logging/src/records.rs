@@ -0,0 +1,166 @@
+//! Registros de log estructurados: nivel, origen (`logger`) y mensaje.
+//!
+//! Cada línea que entra en el ring de bytes (`log_write`, `log!`) deja además un
+//! [`Record`] en un ring sin locks de [`RING_SIZE`] entradas, con el mismo esquema de
+//! sellos que [`crate::events`]. Los lectores llevan su propio cursor, así que varios
+//! consumidores (sesiones MCP, consola) no se quitan registros entre sí.
+
+use core::cell::UnsafeCell;
+use core::fmt;
+use core::sync::atomic::{fence, AtomicU64, Ordering};
+
+pub const RING_SIZE: usize = 128;
+/// Bytes máximos del origen y del mensaje; lo que sobra se trunca.
+pub const LOGGER_SIZE: usize = 16;
+pub const MESSAGE_SIZE: usize = 200;
+
+/// Niveles de syslog (RFC 5424), de menor a mayor gravedad.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(u8)]
+pub enum Level {
+    Debug,
+    Info,
+    Notice,
+    Warning,
+    Error,
+    Critical,
+    Alert,
+    Emergency,
+}
+
+impl Level {
+    pub const ALL: [Level; 8] = [
+        Level::Debug,
+        Level::Info,
+        Level::Notice,
+        Level::Warning,
+        Level::Error,
+        Level::Critical,
+        Level::Alert,
+        Level::Emergency,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Level::Debug => "debug",
+            Level::Info => "info",
+            Level::Notice => "notice",
+            Level::Warning => "warning",
+            Level::Error => "error",
+            Level::Critical => "critical",
+            Level::Alert => "alert",
+            Level::Emergency => "emergency",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Level::ALL.into_iter().find(|l| l.as_str() == name)
+    }
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Record {
+    /// Número de secuencia global (lo asigna [`push`]).
+    pub seq: u64,
+    pub level: Level,
+    logger: [u8; LOGGER_SIZE],
+    logger_len: u8,
+    message: [u8; MESSAGE_SIZE],
+    message_len: u8,
+}
+
+impl Record {
+    pub const EMPTY: Record = Record {
+        seq: 0,
+        level: Level::Info,
+        logger: [0; LOGGER_SIZE],
+        logger_len: 0,
+        message: [0; MESSAGE_SIZE],
+        message_len: 0,
+    };
+
+    pub fn logger(&self) -> &str {
+        core::str::from_utf8(&self.logger[..self.logger_len as usize]).unwrap_or_default()
+    }
+
+    pub fn message(&self) -> &str {
+        core::str::from_utf8(&self.message[..self.message_len as usize]).unwrap_or_default()
+    }
+}
+
+/// Copia `s` en `out` cortando en un límite de carácter. Devuelve los bytes copiados.
+fn copy_truncated(s: &str, out: &mut [u8]) -> usize {
+    let mut n = s.len().min(out.len());
+    while !s.is_char_boundary(n) {
+        n -= 1;
+    }
+    out[..n].copy_from_slice(&s.as_bytes()[..n]);
+    n
+}
+
+struct Slot {
+    /// `2 * seq + 1` mientras se escribe el registro `seq`; `2 * seq + 2` al terminar.
+    stamp: AtomicU64,
+    record: UnsafeCell<Record>,
+}
+
+// Los accesos a `record` se validan con `stamp`
+unsafe impl Sync for Slot {}
+
+static RING: [Slot; RING_SIZE] =
+    [const { Slot { stamp: AtomicU64::new(0), record: UnsafeCell::new(Record::EMPTY) } }; RING_SIZE];
+static HEAD: AtomicU64 = AtomicU64::new(0);
+
+/// Registra un mensaje (sin el salto de línea final) de `logger` con `level`.
+pub fn push(level: Level, logger: &str, message: &str) {
+    let mut record = Record { level, ..Record::EMPTY };
+    record.logger_len = copy_truncated(logger, &mut record.logger) as u8;
+    record.message_len = copy_truncated(message.trim_end(), &mut record.message) as u8;
+    let seq = HEAD.fetch_add(1, Ordering::AcqRel);
+    record.seq = seq;
+    let slot = &RING[(seq % RING_SIZE as u64) as usize];
+    slot.stamp.store(2 * seq + 1, Ordering::Relaxed);
+    fence(Ordering::Release);
+    unsafe { core::ptr::write_volatile(slot.record.get(), record) };
+    slot.stamp.store(2 * seq + 2, Ordering::Release);
+}
+
+/// Número de secuencia del próximo registro (registros desde el arranque).
+pub fn head() -> u64 {
+    HEAD.load(Ordering::Acquire)
+}
+
+/// Copia en `out` los registros a partir de la secuencia `from` sin consumirlos.
+/// Devuelve cuántos copió y el cursor con el que continuar; los que ya se
+/// sobrescribieron se saltan, igual que en [`crate::events::read_from`].
+pub fn read_from(from: u64, out: &mut [Record]) -> (usize, u64) {
+    let head = HEAD.load(Ordering::Acquire);
+    let oldest = head.saturating_sub(RING_SIZE as u64);
+    let mut cursor = from.clamp(oldest, head);
+    let mut n = 0;
+    while cursor != head && n < out.len() {
+        let slot = &RING[(cursor % RING_SIZE as u64) as usize];
+        let done = 2 * cursor + 2;
+        let stamp = slot.stamp.load(Ordering::Acquire);
+        if stamp < done {
+            // Reservado pero aún sin escribir
+            break;
+        }
+        if stamp == done {
+            let record = unsafe { core::ptr::read_volatile(slot.record.get()) };
+            fence(Ordering::Acquire);
+            if slot.stamp.load(Ordering::Relaxed) == done {
+                out[n] = record;
+                n += 1;
+            }
+        }
+        cursor += 1;
+    }
+    (n, cursor)
+}
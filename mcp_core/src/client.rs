@@ -0,0 +1,603 @@
+//! Cliente MCP: el guest habla con otro servidor MCP (el host u otra VM).
+//!
+//! [`Client::connect`] abre una conexión vsock y hace el saludo (`initialize` y
+//! `notifications/initialized`); después [`Client::list_tools`] y [`Client::call_tool`]
+//! usan las herramientas del otro extremo, p. ej. el `infer` de un modelo mayor en
+//! otra VM. Mientras espera una respuesta, el cliente atiende las peticiones que le
+//! haga el servidor: `sampling/createMessage` y `roots/list` con los manejadores de
+//! [`ClientHandlers`] (solo se anuncian las capacidades que tienen manejador) y `ping`.
+//!
+//! El transporte es un [`Transport`]; [`VsockTransport`] es el de vsock.
+
+use crate::json::{self, Value, Writer};
+use crate::jsonrpc::{self, ErrorCode, Request, RpcError};
+use crate::prompts::Role;
+use crate::session::{SERVER_NAME, SERVER_VERSION, SUPPORTED_PROTOCOL_VERSIONS};
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+use core::sync::atomic::AtomicBool;
+use mcp_vsock_transport::vsock_transport::{self, ConnectionId};
+
+/// Sondeos sin datos antes de dar por perdida una respuesta.
+const RESPONSE_SPIN_LIMIT: usize = 50_000_000;
+/// Trama más grande que acepta [`VsockTransport`].
+const MAX_RESPONSE: usize = 64 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ClientError {
+    /// No se pudo conectar o la conexión se perdió.
+    Disconnected,
+    /// El servidor no contestó a tiempo (la petición se cancela).
+    Timeout,
+    /// El servidor no habla ninguna versión del protocolo que entienda el cliente.
+    UnsupportedVersion,
+    /// La respuesta no es JSON-RPC válido o no tiene la forma esperada.
+    InvalidResponse,
+    /// El servidor contestó con un error.
+    Rpc(RpcError),
+}
+
+impl ClientError {
+    pub fn as_str(&self) -> &str {
+        match self {
+            ClientError::Disconnected => "mcp client: disconnected",
+            ClientError::Timeout => "mcp client: request timed out",
+            ClientError::UnsupportedVersion => "mcp client: unsupported protocol version",
+            ClientError::InvalidResponse => "mcp client: invalid response",
+            ClientError::Rpc(e) => &e.message,
+        }
+    }
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientError::Rpc(e) => write!(f, "mcp client: {}", e),
+            e => f.write_str(e.as_str()),
+        }
+    }
+}
+
+/// Conexión por la que viajan las tramas JSON-RPC del cliente.
+pub trait Transport {
+    fn send(&mut self, frame: &[u8]) -> Result<(), ClientError>;
+    /// Siguiente trama recibida, sin bloquear.
+    fn receive(&mut self) -> Result<Option<Vec<u8>>, ClientError>;
+}
+
+/// Conexión vsock saliente; se cierra al soltarla.
+pub struct VsockTransport {
+    conn: ConnectionId,
+    buf: Vec<u8>,
+}
+
+impl VsockTransport {
+    pub fn connect(cid: u64, port: u32) -> Result<Self, ClientError> {
+        let conn = vsock_transport::connect(cid, port).map_err(|_| ClientError::Disconnected)?;
+        Ok(VsockTransport { conn, buf: alloc::vec![0; MAX_RESPONSE] })
+    }
+}
+
+impl Transport for VsockTransport {
+    fn send(&mut self, frame: &[u8]) -> Result<(), ClientError> {
+        vsock_transport::send_frame(self.conn, frame).map_err(|_| ClientError::Disconnected)
+    }
+
+    fn receive(&mut self) -> Result<Option<Vec<u8>>, ClientError> {
+        match vsock_transport::recv_frame(self.conn, &mut self.buf) {
+            Ok(frame) => Ok(frame.map(<[u8]>::to_vec)),
+            Err(_) => Err(ClientError::Disconnected),
+        }
+    }
+}
+
+impl Drop for VsockTransport {
+    fn drop(&mut self) {
+        vsock_transport::close(self.conn);
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SamplingMessage {
+    pub role: Role,
+    pub text: String,
+}
+
+/// `sampling/createMessage` del servidor (solo contenido de texto).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SamplingRequest {
+    pub messages: Vec<SamplingMessage>,
+    pub system_prompt: Option<String>,
+    pub max_tokens: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SamplingResponse {
+    pub text: String,
+    /// Modelo que generó la respuesta.
+    pub model: String,
+    /// `endTurn`, `maxTokens`, ...
+    pub stop_reason: Option<String>,
+}
+
+/// Directorio que el cliente expone al servidor en `roots/list`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Root {
+    /// URI `file://`.
+    pub uri: String,
+    pub name: Option<String>,
+}
+
+pub type SamplingHandler = fn(&SamplingRequest) -> Result<SamplingResponse, RpcError>;
+pub type RootsHandler = fn() -> Vec<Root>;
+
+/// Manejadores de las peticiones del servidor; sin manejador no se anuncia la capacidad.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ClientHandlers {
+    pub sampling: Option<SamplingHandler>,
+    pub roots: Option<RootsHandler>,
+}
+
+/// Manejadores con el modelo cargado y los montajes del VFS.
+pub const LOCAL_HANDLERS: ClientHandlers = ClientHandlers { sampling: Some(local_sampling), roots: Some(vfs_roots) };
+
+/// Herramienta publicada por el servidor.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RemoteTool {
+    pub name: String,
+    pub description: Option<String>,
+    /// `inputSchema` tal cual, como texto JSON.
+    pub input_schema: Vec<u8>,
+}
+
+/// `CallToolResult` del servidor.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ToolResult {
+    /// Los bloques de texto de `content`, concatenados.
+    pub text: String,
+    /// `structuredContent`, como texto JSON.
+    pub structured: Option<Vec<u8>>,
+    pub is_error: bool,
+}
+
+pub struct Client<T: Transport> {
+    transport: T,
+    handlers: ClientHandlers,
+    next_id: i64,
+    protocol_version: &'static str,
+    server_name: Option<String>,
+}
+
+impl Client<VsockTransport> {
+    /// Conecta con el servidor MCP del puerto `port` de `cid` y hace el saludo.
+    pub fn connect(cid: u64, port: u32, handlers: ClientHandlers) -> Result<Self, ClientError> {
+        let mut client = Client::new(VsockTransport::connect(cid, port)?, handlers);
+        client.initialize()?;
+        logging::log!(
+            logging::Level::Info,
+            "mcp-client",
+            "conectado a {}:{} ({})",
+            cid,
+            port,
+            client.server_name().unwrap_or("?")
+        );
+        Ok(client)
+    }
+}
+
+impl<T: Transport> Client<T> {
+    /// Cliente sin saludo todavía; ver [`Client::initialize`].
+    pub fn new(transport: T, handlers: ClientHandlers) -> Self {
+        Client {
+            transport,
+            handlers,
+            next_id: 1,
+            protocol_version: SUPPORTED_PROTOCOL_VERSIONS[0],
+            server_name: None,
+        }
+    }
+
+    pub fn protocol_version(&self) -> &'static str {
+        self.protocol_version
+    }
+
+    pub fn server_name(&self) -> Option<&str> {
+        self.server_name.as_deref()
+    }
+
+    /// `initialize` con la versión más reciente y, si el servidor acepta una que el
+    /// cliente entiende, `notifications/initialized`.
+    pub fn initialize(&mut self) -> Result<(), ClientError> {
+        let mut w = Writer::new(Vec::new());
+        w.begin_object()
+            .key("protocolVersion")
+            .string(SUPPORTED_PROTOCOL_VERSIONS[0])
+            .key("capabilities")
+            .begin_object();
+        if self.handlers.roots.is_some() {
+            w.key("roots").begin_object().end_object();
+        }
+        if self.handlers.sampling.is_some() {
+            w.key("sampling").begin_object().end_object();
+        }
+        w.end_object()
+            .key("clientInfo")
+            .begin_object()
+            .key("name")
+            .string(SERVER_NAME)
+            .key("version")
+            .string(SERVER_VERSION)
+            .end_object()
+            .end_object();
+        let result = self.request("initialize", Some(&w.into_inner()))?;
+        let result = json::parse(&result).map_err(|_| ClientError::InvalidResponse)?;
+        let version = result.get("protocolVersion").and_then(Value::as_str).ok_or(ClientError::InvalidResponse)?;
+        self.protocol_version = SUPPORTED_PROTOCOL_VERSIONS
+            .iter()
+            .find(|v| **v == version)
+            .ok_or(ClientError::UnsupportedVersion)?;
+        self.server_name = result.get("serverInfo").and_then(|i| i.get("name")).and_then(Value::as_str).map(String::from);
+        self.transport.send(&jsonrpc::notification("notifications/initialized", None))
+    }
+
+    /// Envía la petición `method` y espera su `result` (texto JSON), atendiendo
+    /// mientras tanto las peticiones del servidor. Si no llega a tiempo se cancela.
+    pub fn request(&mut self, method: &str, params: Option<&[u8]>) -> Result<Vec<u8>, ClientError> {
+        let id = self.next_id;
+        self.next_id += 1;
+        let mut w = Writer::new(Vec::new());
+        w.begin_object().key("jsonrpc").string(jsonrpc::VERSION).key("id").i64(id).key("method").string(method);
+        if let Some(params) = params {
+            w.key("params").raw(params);
+        }
+        w.end_object();
+        self.transport.send(&w.into_inner())?;
+        let mut spins = 0;
+        while spins < RESPONSE_SPIN_LIMIT {
+            let Some(frame) = self.transport.receive()? else {
+                spins += 1;
+                core::hint::spin_loop();
+                continue;
+            };
+            spins = 0;
+            if let Some(result) = self.dispatch(&frame, id) {
+                return result;
+            }
+        }
+        let mut w = Writer::new(Vec::new());
+        w.begin_object().key("requestId").i64(id).key("reason").string("timeout").end_object();
+        let _ = self.transport.send(&jsonrpc::notification("notifications/cancelled", Some(&w.into_inner())));
+        Err(ClientError::Timeout)
+    }
+
+    /// Atiende una trama recibida: las peticiones y notificaciones del servidor se
+    /// contestan aquí; devuelve el resultado si es la respuesta a `id`.
+    fn dispatch(&mut self, frame: &[u8], id: i64) -> Option<Result<Vec<u8>, ClientError>> {
+        let Ok(fields) = json::raw_fields(frame) else {
+            // Lotes del servidor (solo peticiones) o basura
+            if let Some(resp) = jsonrpc::handle_frame(frame, |req| self.serve(req)) {
+                let _ = self.transport.send(&resp);
+            }
+            return None;
+        };
+        let field = |key: &str| fields.iter().find(|(k, _)| k.eq_str(key)).map(|(_, v)| *v);
+        if field("method").is_some() {
+            if let Some(resp) = jsonrpc::handle_frame(frame, |req| self.serve(req)) {
+                let _ = self.transport.send(&resp);
+            }
+            return None;
+        }
+        // Respuestas a peticiones anteriores que ya se dieron por perdidas
+        if field("id").and_then(|v| json::parse(v).ok()).and_then(|v| v.as_i64()) != Some(id) {
+            return None;
+        }
+        if let Some(error) = field("error") {
+            let error = json::parse(error).ok();
+            let code = error.as_ref().and_then(|e| e.get("code")).and_then(Value::as_i64);
+            let message = error.as_ref().and_then(|e| e.get("message")).and_then(Value::as_str).unwrap_or_default();
+            let code = code.and_then(ErrorCode::from_code).unwrap_or(ErrorCode::InternalError);
+            return Some(Err(ClientError::Rpc(RpcError::with_message(code, String::from(message)))));
+        }
+        Some(field("result").map(<[u8]>::to_vec).ok_or(ClientError::InvalidResponse))
+    }
+
+    /// Peticiones que el servidor hace al cliente.
+    fn serve(&mut self, req: &Request<'_>) -> Result<Vec<u8>, RpcError> {
+        let params = req.params.unwrap_or(b"{}");
+        match &*req.method {
+            _ if req.is_notification() => Ok(b"{}".to_vec()),
+            "ping" => Ok(b"{}".to_vec()),
+            "sampling/createMessage" => match self.handlers.sampling {
+                Some(handler) => handler(&parse_sampling(params)?).map(|resp| write_sampling(&resp)),
+                None => Err(RpcError::with_message(ErrorCode::MethodNotFound, "sampling not supported")),
+            },
+            "roots/list" => match self.handlers.roots {
+                Some(handler) => Ok(write_roots(&handler())),
+                None => Err(RpcError::with_message(ErrorCode::MethodNotFound, "roots not supported")),
+            },
+            method => Err(RpcError::with_message(ErrorCode::MethodNotFound, format!("method not found: {}", method))),
+        }
+    }
+
+    /// `tools/list`, siguiendo `nextCursor` hasta la última página.
+    pub fn list_tools(&mut self) -> Result<Vec<RemoteTool>, ClientError> {
+        let mut tools = Vec::new();
+        let mut cursor: Option<String> = None;
+        loop {
+            let params = cursor.as_ref().map(|c| {
+                let mut w = Writer::new(Vec::new());
+                w.begin_object().key("cursor").string(c).end_object();
+                w.into_inner()
+            });
+            let result = self.request("tools/list", params.as_deref())?;
+            let fields = json::raw_fields(&result).map_err(|_| ClientError::InvalidResponse)?;
+            let field = |key: &str| fields.iter().find(|(k, _)| k.eq_str(key)).map(|(_, v)| *v);
+            let list = field("tools").ok_or(ClientError::InvalidResponse)?;
+            for tool in json::raw_elements(list).map_err(|_| ClientError::InvalidResponse)? {
+                tools.push(parse_tool(tool).ok_or(ClientError::InvalidResponse)?);
+            }
+            cursor = field("nextCursor").and_then(|c| json::parse(c).ok()).and_then(|c| c.as_str().map(String::from));
+            if cursor.is_none() {
+                return Ok(tools);
+            }
+        }
+    }
+
+    /// `tools/call` de `name` con `arguments` (objeto JSON).
+    pub fn call_tool(&mut self, name: &str, arguments: &[u8]) -> Result<ToolResult, ClientError> {
+        let mut w = Writer::new(Vec::new());
+        w.begin_object().key("name").string(name).key("arguments").raw(arguments).end_object();
+        let result = self.request("tools/call", Some(&w.into_inner()))?;
+        let fields = json::raw_fields(&result).map_err(|_| ClientError::InvalidResponse)?;
+        let field = |key: &str| fields.iter().find(|(k, _)| k.eq_str(key)).map(|(_, v)| *v);
+        let content = field("content").and_then(|c| json::parse(c).ok()).ok_or(ClientError::InvalidResponse)?;
+        let mut text = String::new();
+        for block in content.as_array().ok_or(ClientError::InvalidResponse)? {
+            if block.get("type").and_then(Value::as_str).as_deref() == Some("text") {
+                text.push_str(&block.get("text").and_then(Value::as_str).unwrap_or_default());
+            }
+        }
+        Ok(ToolResult {
+            text,
+            structured: field("structuredContent").map(<[u8]>::to_vec),
+            is_error: field("isError").and_then(|e| json::parse(e).ok()).and_then(|e| e.as_bool()).unwrap_or(false),
+        })
+    }
+}
+
+fn parse_tool(raw: &[u8]) -> Option<RemoteTool> {
+    let fields = json::raw_fields(raw).ok()?;
+    let field = |key: &str| fields.iter().find(|(k, _)| k.eq_str(key)).map(|(_, v)| *v);
+    let string = |key: &str| field(key).and_then(|v| json::parse(v).ok()).and_then(|v| v.as_str().map(String::from));
+    Some(RemoteTool {
+        name: string("name")?,
+        description: string("description"),
+        input_schema: field("inputSchema")?.to_vec(),
+    })
+}
+
+fn parse_sampling(params: &[u8]) -> Result<SamplingRequest, RpcError> {
+    let invalid = |message: &'static str| RpcError::with_message(ErrorCode::InvalidParams, message);
+    let params = json::parse(params).map_err(|_| invalid("expected sampling/createMessage params"))?;
+    let mut messages = Vec::new();
+    for message in params.get("messages").and_then(Value::as_array).ok_or_else(|| invalid("expected messages array"))? {
+        let role = match message.get("role").and_then(Value::as_str).as_deref() {
+            Some("user") => Role::User,
+            Some("assistant") => Role::Assistant,
+            _ => return Err(invalid("role must be \"user\" or \"assistant\"")),
+        };
+        let content = message.get("content");
+        if content.and_then(|c| c.get("type")).and_then(Value::as_str).as_deref() != Some("text") {
+            return Err(invalid("only text content is supported"));
+        }
+        let text = content.and_then(|c| c.get("text")).and_then(Value::as_str).unwrap_or_default();
+        messages.push(SamplingMessage { role, text: String::from(text) });
+    }
+    Ok(SamplingRequest {
+        messages,
+        system_prompt: params.get("systemPrompt").and_then(Value::as_str).map(String::from),
+        max_tokens: params.get("maxTokens").and_then(Value::as_u64).ok_or_else(|| invalid("expected maxTokens"))?,
+    })
+}
+
+fn write_sampling(resp: &SamplingResponse) -> Vec<u8> {
+    let mut w = Writer::new(Vec::new());
+    w.begin_object()
+        .key("role")
+        .string(Role::Assistant.as_str())
+        .key("content")
+        .begin_object()
+        .key("type")
+        .string("text")
+        .key("text")
+        .string(&resp.text)
+        .end_object()
+        .key("model")
+        .string(&resp.model);
+    if let Some(reason) = &resp.stop_reason {
+        w.key("stopReason").string(reason);
+    }
+    w.end_object();
+    w.into_inner()
+}
+
+fn write_roots(roots: &[Root]) -> Vec<u8> {
+    let mut w = Writer::new(Vec::new());
+    w.begin_object().key("roots").begin_array();
+    for root in roots {
+        w.begin_object().key("uri").string(&root.uri);
+        if let Some(name) = &root.name {
+            w.key("name").string(name);
+        }
+        w.end_object();
+    }
+    w.end_array().end_object();
+    w.into_inner()
+}
+
+/// Genera con el modelo cargado a partir del último mensaje del usuario, hasta
+/// `max_tokens` tokens.
+pub fn local_sampling(req: &SamplingRequest) -> Result<SamplingResponse, RpcError> {
+    if unsafe { (*core::ptr::addr_of!(ai_runtime::MODEL)).is_none() } {
+        return Err(RpcError::with_message(ErrorCode::InternalError, "no model loaded"));
+    }
+    let prompt = req.messages.iter().rev().find(|m| m.role == Role::User).map_or("", |m| &m.text);
+    let mut text = String::new();
+    let mut tokens = 0;
+    let mut truncated = false;
+    let _ = ai_runtime::infer_stream(prompt, &AtomicBool::new(false), |token| {
+        if tokens >= req.max_tokens {
+            truncated = true;
+            return false;
+        }
+        text.push_str(token);
+        tokens += 1;
+        true
+    });
+    Ok(SamplingResponse {
+        text,
+        model: String::from(SERVER_NAME),
+        stop_reason: Some(String::from(if truncated { "maxTokens" } else { "endTurn" })),
+    })
+}
+
+/// Un root `file://` por cada montaje del VFS.
+pub fn vfs_roots() -> Vec<Root> {
+    let mut roots = Vec::new();
+    vfs::for_each_mount(|prefix, fs| {
+        roots.push(Root { uri: format!("file://{}", prefix), name: Some(String::from(fs)) });
+    });
+    roots
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::collections::VecDeque;
+    use alloc::rc::Rc;
+    use core::cell::RefCell;
+
+    /// Transporte contra el servidor MCP del propio kernel.
+    struct Loopback {
+        session: crate::session::Session,
+        inbox: VecDeque<Vec<u8>>,
+    }
+
+    impl Transport for Loopback {
+        fn send(&mut self, frame: &[u8]) -> Result<(), ClientError> {
+            self.inbox.extend(crate::mcp_server::handle_frame(&mut self.session, frame));
+            Ok(())
+        }
+
+        fn receive(&mut self) -> Result<Option<Vec<u8>>, ClientError> {
+            Ok(self.inbox.pop_front())
+        }
+    }
+
+    #[test]
+    fn client_uses_the_kernel_server() {
+        let _guard = crate::resources::reset();
+        crate::mcp_server::init();
+        let loopback = Loopback { session: crate::session::Session::new(), inbox: VecDeque::new() };
+        let mut client = Client::new(loopback, ClientHandlers::default());
+        client.initialize().unwrap();
+        assert_eq!((client.protocol_version(), client.server_name()), ("2025-03-26", Some("unikernel-ai")));
+
+        let tools = client.list_tools().unwrap();
+        let names: Vec<&str> = tools.iter().map(|t| t.name.as_str()).collect();
+        assert_eq!(names, ["infer", "health", "metadata", "load_model", "logs", "events", "trace"]);
+        assert!(tools[0].input_schema.starts_with(br#"{"type":"object""#));
+
+        let result = client.call_tool("health", b"{}").unwrap();
+        assert!(!result.is_error && result.text.starts_with(r#"{"status":"#));
+        assert!(result.structured.is_some_and(|s| s.starts_with(br#"{"status":"#)));
+        let result = client.call_tool("load_model", br#"{"path":"/models/nope.bin"}"#).unwrap();
+        assert!(result.is_error);
+        match client.call_tool("nope", b"{}") {
+            Err(ClientError::Rpc(e)) => assert_eq!(e.code, ErrorCode::InvalidParams),
+            other => panic!("{:?}", other),
+        }
+    }
+
+    /// Servidor de prueba: contesta a `initialize` y, ante `tools/call`, pregunta
+    /// antes al cliente (`ping`, `roots/list` y `sampling/createMessage`).
+    struct Peer {
+        inbox: VecDeque<Vec<u8>>,
+        /// Lo que el cliente envió.
+        sent: Rc<RefCell<Vec<String>>>,
+    }
+
+    impl Transport for Peer {
+        fn send(&mut self, frame: &[u8]) -> Result<(), ClientError> {
+            let text = String::from_utf8(frame.to_vec()).unwrap();
+            if text.contains(r#""method":"initialize""#) {
+                self.inbox.push_back(
+                    br#"{"jsonrpc":"2.0","id":1,"result":{"protocolVersion":"2024-11-05","capabilities":{"tools":{}},"serverInfo":{"name":"peer","version":"1"}}}"#.to_vec(),
+                );
+            } else if text.contains(r#""method":"tools/call""#) {
+                self.inbox.push_back(br#"{"jsonrpc":"2.0","id":"p","method":"ping"}"#.to_vec());
+                self.inbox.push_back(br#"{"jsonrpc":"2.0","method":"notifications/message","params":{"level":"info","data":"x"}}"#.to_vec());
+                self.inbox.push_back(br#"{"jsonrpc":"2.0","id":"r","method":"roots/list"}"#.to_vec());
+                self.inbox.push_back(
+                    br#"{"jsonrpc":"2.0","id":"s","method":"sampling/createMessage","params":{"messages":[{"role":"user","content":{"type":"text","text":"hola"}}],"maxTokens":8}}"#.to_vec(),
+                );
+                self.inbox.push_back(br#"{"jsonrpc":"2.0","id":"e","method":"elicitation/create"}"#.to_vec());
+                self.inbox.push_back(br#"{"jsonrpc":"2.0","id":99,"result":{}}"#.to_vec());
+                self.inbox.push_back(
+                    br#"{"jsonrpc":"2.0","id":2,"result":{"content":[{"type":"text","text":"hecho"}],"isError":false}}"#.to_vec(),
+                );
+            }
+            self.sent.borrow_mut().push(text);
+            Ok(())
+        }
+
+        fn receive(&mut self) -> Result<Option<Vec<u8>>, ClientError> {
+            Ok(self.inbox.pop_front())
+        }
+    }
+
+    fn echo(req: &SamplingRequest) -> Result<SamplingResponse, RpcError> {
+        let text = format!("eco: {} ({})", req.messages[0].text, req.max_tokens);
+        Ok(SamplingResponse { text, model: String::from("eco"), stop_reason: None })
+    }
+
+    fn roots() -> Vec<Root> {
+        alloc::vec![Root { uri: String::from("file:///models"), name: None }]
+    }
+
+    #[test]
+    fn server_requests_are_answered_while_waiting() {
+        let sent = Rc::new(RefCell::new(Vec::new()));
+        let peer = Peer { inbox: VecDeque::new(), sent: sent.clone() };
+        let mut client = Client::new(peer, ClientHandlers { sampling: Some(echo), roots: Some(roots) });
+        client.initialize().unwrap();
+        assert_eq!(client.protocol_version(), "2024-11-05");
+        assert!(sent.borrow()[0].contains(r#""capabilities":{"roots":{},"sampling":{}}"#));
+        assert_eq!(sent.borrow()[1], r#"{"jsonrpc":"2.0","method":"notifications/initialized"}"#);
+
+        let result = client.call_tool("infer", br#"{"prompt":"hola"}"#).unwrap();
+        assert_eq!(result.text, "hecho");
+        assert_eq!(
+            sent.borrow()[3..],
+            [
+                r#"{"jsonrpc":"2.0","id":"p","result":{}}"#,
+                r#"{"jsonrpc":"2.0","id":"r","result":{"roots":[{"uri":"file:///models"}]}}"#,
+                r#"{"jsonrpc":"2.0","id":"s","result":{"role":"assistant","content":{"type":"text","text":"eco: hola (8)"},"model":"eco"}}"#,
+                r#"{"jsonrpc":"2.0","id":"e","error":{"code":-32601,"message":"method not found: elicitation/create"}}"#,
+            ]
+        );
+    }
+
+    #[test]
+    fn sampling_without_handler_is_not_offered() {
+        let sent = Rc::new(RefCell::new(Vec::new()));
+        let peer = Peer { inbox: VecDeque::new(), sent: sent.clone() };
+        let mut client = Client::new(peer, ClientHandlers::default());
+        client.initialize().unwrap();
+        assert!(sent.borrow()[0].contains(r#""capabilities":{}"#));
+        client.call_tool("infer", b"{}").unwrap();
+        assert!(sent.borrow()[5].contains(r#""error":{"code":-32601,"message":"sampling not supported"}"#));
+    }
+}
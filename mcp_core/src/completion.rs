@@ -0,0 +1,208 @@
+//! Autocompletado de argumentos: `completion/complete`.
+//!
+//! Las sugerencias salen de fuentes registradas en tiempo de ejecución con
+//! [`register_completion`], una por referencia y argumento: un prompt
+//! (`ref/prompt`), una plantilla de recurso (`ref/resource`) o, como extensión,
+//! una herramienta (`ref/tool`, p. ej. el `path` de `load_model`). La fuente recibe
+//! lo que el usuario lleva escrito y devuelve candidatos; el servidor se queda con
+//! los que empiezan por ese texto, sin repetidos, y envía como mucho
+//! [`MAX_VALUES`]. Un argumento sin fuente se completa con la lista vacía.
+
+use crate::json::{self, Value, Writer};
+use crate::jsonrpc::{ErrorCode, RpcError};
+use crate::prompts::Prompt;
+use alloc::borrow::Cow;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+use core::ptr::addr_of_mut;
+
+/// Valores máximos por respuesta (límite del protocolo).
+pub const MAX_VALUES: usize = 100;
+
+/// Lo que se completa: el argumento de un prompt, de una plantilla de recurso o de
+/// una herramienta.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reference<'a> {
+    /// `{"type": "ref/prompt", "name": ...}`
+    Prompt(&'a str),
+    /// `{"type": "ref/resource", "uri": ...}` con la plantilla (o la URI) del recurso.
+    Resource(&'a str),
+    /// `{"type": "ref/tool", "name": ...}`; no es parte del protocolo.
+    Tool(&'a str),
+}
+
+/// Candidatos para el valor escrito hasta ahora. No hace falta filtrarlos.
+pub type CompletionSource = fn(value: &str) -> Vec<String>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegistryError {
+    /// Ya hay una fuente para esa referencia y ese argumento.
+    AlreadyRegistered,
+}
+
+impl RegistryError {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RegistryError::AlreadyRegistered => "mcp completion: source already registered",
+        }
+    }
+}
+
+impl fmt::Display for RegistryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+static mut REGISTRY: Vec<(Reference<'static>, &'static str, CompletionSource)> = Vec::new();
+
+fn registry() -> &'static mut Vec<(Reference<'static>, &'static str, CompletionSource)> {
+    unsafe { &mut *addr_of_mut!(REGISTRY) }
+}
+
+/// Vacía el registro (las pruebas lo usan bajo el cerrojo de `resources::reset`).
+#[cfg(test)]
+pub(crate) fn clear() {
+    registry().clear();
+}
+
+/// Publica la fuente de sugerencias del argumento `argument` de `reference`.
+pub fn register_completion(
+    reference: Reference<'static>,
+    argument: &'static str,
+    source: CompletionSource,
+) -> Result<(), RegistryError> {
+    if find(reference, argument).is_some() {
+        return Err(RegistryError::AlreadyRegistered);
+    }
+    registry().push((reference, argument, source));
+    Ok(())
+}
+
+/// Devuelve `false` si no había fuente para ese argumento.
+pub fn unregister_completion(reference: Reference<'_>, argument: &str) -> bool {
+    let sources = registry();
+    let before = sources.len();
+    sources.retain(|(r, a, _)| !(*r == reference && *a == argument));
+    sources.len() != before
+}
+
+fn find(reference: Reference<'_>, argument: &str) -> Option<CompletionSource> {
+    registry().iter().find(|(r, a, _)| *r == reference && *a == argument).map(|(_, _, s)| *s)
+}
+
+/// Se queda con los candidatos que empiezan por `value`, sin repetidos y en su
+/// orden. Devuelve también cuántos había antes de recortar a [`MAX_VALUES`].
+pub fn filter(candidates: Vec<String>, value: &str) -> (Vec<String>, usize) {
+    let mut values: Vec<String> = Vec::new();
+    for candidate in candidates {
+        if candidate.starts_with(value) && !values.contains(&candidate) {
+            values.push(candidate);
+        }
+    }
+    let total = values.len();
+    values.truncate(MAX_VALUES);
+    (values, total)
+}
+
+fn string<'a>(value: &Value<'a>, key: &str) -> Option<Cow<'a, str>> {
+    value.get(key).and_then(Value::as_str)
+}
+
+/// `completion/complete` con `{ref, argument: {name, value}}`. Los prompts se
+/// comprueban contra `prompts` (los activos); las referencias desconocidas y los
+/// argumentos que el prompt no declara dan -32602.
+pub fn complete(params: &[u8], prompts: &[Prompt]) -> Result<Vec<u8>, RpcError> {
+    let invalid = |message: &'static str| RpcError::with_message(ErrorCode::InvalidParams, message);
+    let params = json::parse(params).map_err(|_| invalid("expected {\"ref\": {...}, \"argument\": {...}}"))?;
+    let reference = params.get("ref").ok_or_else(|| invalid("expected ref"))?;
+    let (name, value) = params
+        .get("argument")
+        .and_then(|a| Some((string(a, "name")?, string(a, "value")?)))
+        .ok_or_else(|| invalid("expected argument {\"name\": string, \"value\": string}"))?;
+    let unknown = |what: &str, name: &str| RpcError::with_message(ErrorCode::InvalidParams, format!("unknown {}: {}", what, name));
+    let ref_name = string(reference, "name").unwrap_or_default();
+    let ref_uri = string(reference, "uri").unwrap_or_default();
+    let reference = match string(reference, "type").as_deref() {
+        Some("ref/prompt") => {
+            let prompt = prompts.iter().find(|p| p.name == ref_name).ok_or_else(|| unknown("prompt", &ref_name))?;
+            if !prompt.arguments.iter().any(|a| a.name == name) {
+                return Err(unknown("argument", &name));
+            }
+            Reference::Prompt(&ref_name)
+        }
+        Some("ref/resource") => {
+            if !crate::resources::exists(&ref_uri) && !crate::resources::is_template(&ref_uri) {
+                return Err(unknown("resource", &ref_uri));
+            }
+            Reference::Resource(&ref_uri)
+        }
+        Some("ref/tool") => {
+            crate::tools::find(&ref_name).ok_or_else(|| unknown("tool", &ref_name))?;
+            Reference::Tool(&ref_name)
+        }
+        _ => return Err(invalid("ref.type must be \"ref/prompt\", \"ref/resource\" or \"ref/tool\"")),
+    };
+    let candidates = find(reference, &name).map(|source| source(&value)).unwrap_or_default();
+    let (values, total) = filter(candidates, &value);
+    let mut w = Writer::new(Vec::new());
+    w.begin_object().key("completion").begin_object().key("values").begin_array();
+    for value in &values {
+        w.string(value);
+    }
+    w.end_array()
+        .key("total")
+        .u64(total as u64)
+        .key("hasMore")
+        .bool(total > values.len())
+        .end_object()
+        .end_object();
+    Ok(w.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn colors(_value: &str) -> Vec<String> {
+        ["rojo", "rosa", "azul", "rojo"].into_iter().map(String::from).collect()
+    }
+
+    fn many(_value: &str) -> Vec<String> {
+        (0..150).map(|i| format!("n{}", i)).collect()
+    }
+
+    #[test]
+    fn sources_are_filtered_by_prefix() {
+        let _guard = crate::resources::reset();
+        let prompts = crate::prompts::merge(None).unwrap();
+        register_completion(Reference::Prompt("summarize"), "language", colors).unwrap();
+        assert_eq!(
+            register_completion(Reference::Prompt("summarize"), "language", many),
+            Err(RegistryError::AlreadyRegistered)
+        );
+        let params = br#"{"ref":{"type":"ref/prompt","name":"summarize"},"argument":{"name":"language","value":"ro"}}"#;
+        assert_eq!(
+            complete(params, &prompts).unwrap(),
+            br#"{"completion":{"values":["rojo","rosa"],"total":2,"hasMore":false}}"#
+        );
+        // Argumento declarado pero sin fuente
+        let params = br#"{"ref":{"type":"ref/prompt","name":"summarize"},"argument":{"name":"text","value":""}}"#;
+        assert_eq!(complete(params, &prompts).unwrap(), br#"{"completion":{"values":[],"total":0,"hasMore":false}}"#);
+        let params = br#"{"ref":{"type":"ref/prompt","name":"nope"},"argument":{"name":"text","value":""}}"#;
+        assert_eq!(complete(params, &prompts).unwrap_err().message, "unknown prompt: nope");
+        let params = br#"{"ref":{"type":"ref/prompt","name":"chat"},"argument":{"name":"nope","value":""}}"#;
+        assert_eq!(complete(params, &prompts).unwrap_err().message, "unknown argument: nope");
+        let params = br#"{"ref":{"type":"ref/other"},"argument":{"name":"x","value":""}}"#;
+        assert_eq!(complete(params, &prompts).unwrap_err().code, ErrorCode::InvalidParams);
+
+        assert!(unregister_completion(Reference::Prompt("summarize"), "language"));
+        assert!(!unregister_completion(Reference::Prompt("summarize"), "language"));
+        register_completion(Reference::Prompt("summarize"), "language", many).unwrap();
+        let params = br#"{"ref":{"type":"ref/prompt","name":"summarize"},"argument":{"name":"language","value":"n"}}"#;
+        let result = String::from_utf8(complete(params, &prompts).unwrap()).unwrap();
+        assert!(result.ends_with(r#""n99"],"total":150,"hasMore":true}}"#));
+    }
+}
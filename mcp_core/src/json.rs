@@ -0,0 +1,907 @@
+//! JSON sin `std`: parser que toma prestado del buffer de entrada y escritor en
+//! streaming.
+//!
+//! [`parse`] valida la entrada completa (UTF-8, gramática, escapes, números) y
+//! devuelve un árbol de [`Value`] cuyas cadenas y números apuntan al texto original:
+//! solo se reserva memoria para los vectores de arrays y objetos y, bajo demanda, para
+//! las cadenas con escapes ([`Str::to_cow`]). [`Limits`] acota la profundidad, el
+//! tamaño y el número de elementos para que una trama hostil no agote la pila ni el
+//! heap.
+//!
+//! [`Writer`] serializa directamente sobre un [`Output`]: un `Vec<u8>` o un
+//! `&mut [u8]` ([`SliceOutput`]), que marca el desbordamiento en vez de truncar en
+//! silencio.
+
+use alloc::borrow::Cow;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    /// Arrays y objetos anidados como máximo.
+    pub max_depth: usize,
+    /// Bytes de entrada.
+    pub max_size: usize,
+    /// Elementos entre todos los arrays y objetos.
+    pub max_items: usize,
+}
+
+impl Limits {
+    /// Suficiente para cualquier mensaje MCP legítimo (la trama vsock llega a 1 MiB).
+    pub const DEFAULT: Limits = Limits { max_depth: 32, max_size: 1024 * 1024, max_items: 16 * 1024 };
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    InvalidUtf8,
+    UnexpectedEnd,
+    UnexpectedChar,
+    InvalidEscape,
+    /// `\u` mal formado o surrogate sin pareja.
+    InvalidUnicode,
+    InvalidNumber,
+    /// Carácter de control sin escapar dentro de una cadena.
+    ControlCharacter,
+    TrailingCharacters,
+    TooDeep,
+    TooLarge,
+    TooManyItems,
+}
+
+impl ErrorKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorKind::InvalidUtf8 => "invalid UTF-8",
+            ErrorKind::UnexpectedEnd => "unexpected end of input",
+            ErrorKind::UnexpectedChar => "unexpected character",
+            ErrorKind::InvalidEscape => "invalid escape",
+            ErrorKind::InvalidUnicode => "invalid unicode escape",
+            ErrorKind::InvalidNumber => "invalid number",
+            ErrorKind::ControlCharacter => "control character in string",
+            ErrorKind::TrailingCharacters => "trailing characters",
+            ErrorKind::TooDeep => "nesting too deep",
+            ErrorKind::TooLarge => "input too large",
+            ErrorKind::TooManyItems => "too many items",
+        }
+    }
+}
+
+/// Error de parseo con el desplazamiento (en bytes) donde se detectó.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Error {
+    pub kind: ErrorKind,
+    pub offset: usize,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at byte {}", self.kind.as_str(), self.offset)
+    }
+}
+
+/// Cadena JSON tal como aparece en la entrada (sin comillas, escapes incluidos).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Str<'a> {
+    raw: &'a str,
+    escaped: bool,
+}
+
+impl<'a> Str<'a> {
+    /// La cadena sin copiar, si no tiene escapes.
+    pub fn as_borrowed(&self) -> Option<&'a str> {
+        if self.escaped { None } else { Some(self.raw) }
+    }
+
+    /// Texto ya decodificado; solo reserva memoria si hay escapes.
+    pub fn to_cow(&self) -> Cow<'a, str> {
+        if !self.escaped {
+            return Cow::Borrowed(self.raw);
+        }
+        let mut out = String::with_capacity(self.raw.len());
+        let mut chars = self.raw.chars();
+        while let Some(c) = chars.next() {
+            if c != '\\' {
+                out.push(c);
+                continue;
+            }
+            // El parser ya validó los escapes
+            match chars.next() {
+                Some('b') => out.push('\u{8}'),
+                Some('f') => out.push('\u{c}'),
+                Some('n') => out.push('\n'),
+                Some('r') => out.push('\r'),
+                Some('t') => out.push('\t'),
+                Some('u') => {
+                    let high = hex4(&mut chars);
+                    let code = if (0xD800..0xDC00).contains(&high) {
+                        chars.nth(1); // "\u" del surrogate bajo
+                        let low = hex4(&mut chars);
+                        0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00)
+                    } else {
+                        high
+                    };
+                    out.push(char::from_u32(code).unwrap_or('\u{FFFD}'));
+                }
+                Some(other) => out.push(other),
+                None => {}
+            }
+        }
+        Cow::Owned(out)
+    }
+
+    /// Compara con `s` sin reservar memoria si no hay escapes.
+    pub fn eq_str(&self, s: &str) -> bool {
+        match self.as_borrowed() {
+            Some(raw) => raw == s,
+            None => self.to_cow() == s,
+        }
+    }
+}
+
+fn hex4(chars: &mut core::str::Chars<'_>) -> u32 {
+    chars.by_ref().take(4).fold(0, |acc, c| acc << 4 | c.to_digit(16).unwrap_or(0))
+}
+
+/// Número JSON validado, guardado como texto para no perder precisión.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Number<'a>(&'a str);
+
+impl<'a> Number<'a> {
+    pub fn as_str(&self) -> &'a str {
+        self.0
+    }
+
+    /// Valor entero sin signo (sin fracción ni exponente).
+    pub fn as_u64(&self) -> Option<u64> {
+        self.0.parse().ok()
+    }
+
+    pub fn as_i64(&self) -> Option<i64> {
+        self.0.parse().ok()
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        self.0.parse().ok()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value<'a> {
+    Null,
+    Bool(bool),
+    Number(Number<'a>),
+    String(Str<'a>),
+    Array(Vec<Value<'a>>),
+    Object(Vec<(Str<'a>, Value<'a>)>),
+}
+
+impl<'a> Value<'a> {
+    /// Campo `key` de un objeto (el primero si está repetido).
+    pub fn get(&self, key: &str) -> Option<&Value<'a>> {
+        match self {
+            Value::Object(fields) => fields.iter().find(|(k, _)| k.eq_str(key)).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    pub fn is_null(&self) -> bool {
+        matches!(self, Value::Null)
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Value::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    pub fn as_number(&self) -> Option<Number<'a>> {
+        match self {
+            Value::Number(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    pub fn as_u64(&self) -> Option<u64> {
+        self.as_number()?.as_u64()
+    }
+
+    pub fn as_i64(&self) -> Option<i64> {
+        self.as_number()?.as_i64()
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        self.as_number()?.as_f64()
+    }
+
+    pub fn as_string(&self) -> Option<Str<'a>> {
+        match self {
+            Value::String(s) => Some(*s),
+            _ => None,
+        }
+    }
+
+    /// Cadena decodificada (ver [`Str::to_cow`]).
+    pub fn as_str(&self) -> Option<Cow<'a, str>> {
+        Some(self.as_string()?.to_cow())
+    }
+
+    pub fn as_array(&self) -> Option<&[Value<'a>]> {
+        match self {
+            Value::Array(items) => Some(items),
+            _ => None,
+        }
+    }
+
+    pub fn as_object(&self) -> Option<&[(Str<'a>, Value<'a>)]> {
+        match self {
+            Value::Object(fields) => Some(fields),
+            _ => None,
+        }
+    }
+}
+
+/// Parsea un documento JSON completo con [`Limits::DEFAULT`].
+pub fn parse(input: &[u8]) -> Result<Value<'_>, Error> {
+    parse_with(input, Limits::DEFAULT)
+}
+
+pub fn parse_with(input: &[u8], limits: Limits) -> Result<Value<'_>, Error> {
+    let mut parser = Parser::new(input, limits)?;
+    let value = parser.value(0)?;
+    parser.end()?;
+    Ok(value)
+}
+
+/// Texto original de cada campo del objeto raíz, validado pero sin construir el
+/// árbol: sirve para pasar `params` a un manejador sin volver a serializarlo.
+pub fn raw_fields(input: &[u8]) -> Result<Vec<(Str<'_>, &[u8])>, Error> {
+    let mut parser = Parser::new(input, Limits::DEFAULT)?;
+    let mut fields = Vec::new();
+    parser.skip_ws();
+    parser.expect(b'{')?;
+    parser.skip_ws();
+    if parser.peek() == Some(b'}') {
+        parser.pos += 1;
+    } else {
+        loop {
+            parser.skip_ws();
+            let key = parser.string()?;
+            parser.skip_ws();
+            parser.expect(b':')?;
+            parser.skip_ws();
+            let start = parser.pos;
+            parser.value(1)?;
+            fields.push((key, &input[start..parser.pos]));
+            parser.skip_ws();
+            match parser.next()? {
+                b',' => {}
+                b'}' => break,
+                _ => return Err(parser.error_at(parser.pos - 1, ErrorKind::UnexpectedChar)),
+            }
+        }
+    }
+    parser.end()?;
+    Ok(fields)
+}
+
+/// Texto original de cada elemento del array raíz (lotes JSON-RPC), validado igual
+/// que en [`raw_fields`].
+pub fn raw_elements(input: &[u8]) -> Result<Vec<&[u8]>, Error> {
+    let mut parser = Parser::new(input, Limits::DEFAULT)?;
+    let mut elements = Vec::new();
+    parser.skip_ws();
+    parser.expect(b'[')?;
+    parser.skip_ws();
+    if parser.peek() == Some(b']') {
+        parser.pos += 1;
+    } else {
+        loop {
+            parser.skip_ws();
+            let start = parser.pos;
+            parser.value(1)?;
+            elements.push(&input[start..parser.pos]);
+            parser.skip_ws();
+            match parser.next()? {
+                b',' => {}
+                b']' => break,
+                _ => return Err(parser.error_at(parser.pos - 1, ErrorKind::UnexpectedChar)),
+            }
+        }
+    }
+    parser.end()?;
+    Ok(elements)
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    bytes: &'a [u8],
+    pos: usize,
+    limits: Limits,
+    items: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn new(input: &'a [u8], limits: Limits) -> Result<Self, Error> {
+        if input.len() > limits.max_size {
+            return Err(Error { kind: ErrorKind::TooLarge, offset: limits.max_size });
+        }
+        let text = core::str::from_utf8(input)
+            .map_err(|e| Error { kind: ErrorKind::InvalidUtf8, offset: e.valid_up_to() })?;
+        Ok(Parser { text, bytes: input, pos: 0, limits, items: 0 })
+    }
+
+    fn error_at(&self, offset: usize, kind: ErrorKind) -> Error {
+        Error { kind, offset }
+    }
+
+    fn error(&self, kind: ErrorKind) -> Error {
+        self.error_at(self.pos, kind)
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.bytes.get(self.pos).copied()
+    }
+
+    fn next(&mut self) -> Result<u8, Error> {
+        let b = self.peek().ok_or(self.error(ErrorKind::UnexpectedEnd))?;
+        self.pos += 1;
+        Ok(b)
+    }
+
+    fn expect(&mut self, b: u8) -> Result<(), Error> {
+        match self.peek() {
+            Some(c) if c == b => {
+                self.pos += 1;
+                Ok(())
+            }
+            Some(_) => Err(self.error(ErrorKind::UnexpectedChar)),
+            None => Err(self.error(ErrorKind::UnexpectedEnd)),
+        }
+    }
+
+    fn skip_ws(&mut self) {
+        while matches!(self.peek(), Some(b' ' | b'\t' | b'\n' | b'\r')) {
+            self.pos += 1;
+        }
+    }
+
+    fn end(&mut self) -> Result<(), Error> {
+        self.skip_ws();
+        if self.pos != self.bytes.len() {
+            return Err(self.error(ErrorKind::TrailingCharacters));
+        }
+        Ok(())
+    }
+
+    fn count_item(&mut self) -> Result<(), Error> {
+        self.items += 1;
+        if self.items > self.limits.max_items {
+            return Err(self.error(ErrorKind::TooManyItems));
+        }
+        Ok(())
+    }
+
+    fn literal(&mut self, word: &[u8]) -> Result<(), Error> {
+        if self.bytes[self.pos..].starts_with(word) {
+            self.pos += word.len();
+            Ok(())
+        } else if word.starts_with(&self.bytes[self.pos..]) {
+            Err(self.error_at(self.bytes.len(), ErrorKind::UnexpectedEnd))
+        } else {
+            Err(self.error(ErrorKind::UnexpectedChar))
+        }
+    }
+
+    fn value(&mut self, depth: usize) -> Result<Value<'a>, Error> {
+        self.skip_ws();
+        match self.peek().ok_or(self.error(ErrorKind::UnexpectedEnd))? {
+            b'n' => self.literal(b"null").map(|()| Value::Null),
+            b't' => self.literal(b"true").map(|()| Value::Bool(true)),
+            b'f' => self.literal(b"false").map(|()| Value::Bool(false)),
+            b'"' => self.string().map(Value::String),
+            b'-' | b'0'..=b'9' => self.number().map(Value::Number),
+            b'[' => {
+                let depth = self.enter(depth)?;
+                let mut items = Vec::new();
+                self.skip_ws();
+                if self.peek() == Some(b']') {
+                    self.pos += 1;
+                    return Ok(Value::Array(items));
+                }
+                loop {
+                    self.count_item()?;
+                    items.push(self.value(depth)?);
+                    self.skip_ws();
+                    match self.next()? {
+                        b',' => {}
+                        b']' => return Ok(Value::Array(items)),
+                        _ => return Err(self.error_at(self.pos - 1, ErrorKind::UnexpectedChar)),
+                    }
+                }
+            }
+            b'{' => {
+                let depth = self.enter(depth)?;
+                let mut fields = Vec::new();
+                self.skip_ws();
+                if self.peek() == Some(b'}') {
+                    self.pos += 1;
+                    return Ok(Value::Object(fields));
+                }
+                loop {
+                    self.count_item()?;
+                    self.skip_ws();
+                    let key = self.string()?;
+                    self.skip_ws();
+                    self.expect(b':')?;
+                    fields.push((key, self.value(depth)?));
+                    self.skip_ws();
+                    match self.next()? {
+                        b',' => {}
+                        b'}' => return Ok(Value::Object(fields)),
+                        _ => return Err(self.error_at(self.pos - 1, ErrorKind::UnexpectedChar)),
+                    }
+                }
+            }
+            _ => Err(self.error(ErrorKind::UnexpectedChar)),
+        }
+    }
+
+    /// Consume el `[` o `{` y devuelve la profundidad de sus elementos.
+    fn enter(&mut self, depth: usize) -> Result<usize, Error> {
+        if depth >= self.limits.max_depth {
+            return Err(self.error(ErrorKind::TooDeep));
+        }
+        self.pos += 1;
+        Ok(depth + 1)
+    }
+
+    fn string(&mut self) -> Result<Str<'a>, Error> {
+        self.expect(b'"')?;
+        let start = self.pos;
+        let mut escaped = false;
+        loop {
+            match self.next()? {
+                b'"' => break,
+                b'\\' => {
+                    escaped = true;
+                    match self.next()? {
+                        b'"' | b'\\' | b'/' | b'b' | b'f' | b'n' | b'r' | b't' => {}
+                        b'u' => self.unicode_escape()?,
+                        _ => return Err(self.error_at(self.pos - 1, ErrorKind::InvalidEscape)),
+                    }
+                }
+                0x00..=0x1F => return Err(self.error_at(self.pos - 1, ErrorKind::ControlCharacter)),
+                _ => {}
+            }
+        }
+        Ok(Str { raw: &self.text[start..self.pos - 1], escaped })
+    }
+
+    fn hex4(&mut self) -> Result<u32, Error> {
+        let mut code = 0;
+        for _ in 0..4 {
+            let b = self.next()?;
+            let digit = (b as char).to_digit(16).ok_or(self.error_at(self.pos - 1, ErrorKind::InvalidUnicode))?;
+            code = code << 4 | digit;
+        }
+        Ok(code)
+    }
+
+    /// Valida `XXXX` tras `\u`, incluida la pareja de surrogates.
+    fn unicode_escape(&mut self) -> Result<(), Error> {
+        let at = self.pos - 2;
+        match self.hex4()? {
+            0xD800..=0xDBFF => {
+                if self.bytes.get(self.pos..self.pos + 2) != Some(b"\\u") {
+                    return Err(self.error_at(at, ErrorKind::InvalidUnicode));
+                }
+                self.pos += 2;
+                if !(0xDC00..=0xDFFF).contains(&self.hex4()?) {
+                    return Err(self.error_at(at, ErrorKind::InvalidUnicode));
+                }
+                Ok(())
+            }
+            0xDC00..=0xDFFF => Err(self.error_at(at, ErrorKind::InvalidUnicode)),
+            _ => Ok(()),
+        }
+    }
+
+    fn digits(&mut self) -> usize {
+        let start = self.pos;
+        while matches!(self.peek(), Some(b'0'..=b'9')) {
+            self.pos += 1;
+        }
+        self.pos - start
+    }
+
+    fn number(&mut self) -> Result<Number<'a>, Error> {
+        let start = self.pos;
+        if self.peek() == Some(b'-') {
+            self.pos += 1;
+        }
+        match self.peek() {
+            Some(b'0') => self.pos += 1,
+            Some(b'1'..=b'9') => {
+                self.digits();
+            }
+            _ => return Err(self.error_at(start, ErrorKind::InvalidNumber)),
+        }
+        if self.peek() == Some(b'.') {
+            self.pos += 1;
+            if self.digits() == 0 {
+                return Err(self.error_at(start, ErrorKind::InvalidNumber));
+            }
+        }
+        if matches!(self.peek(), Some(b'e' | b'E')) {
+            self.pos += 1;
+            if matches!(self.peek(), Some(b'+' | b'-')) {
+                self.pos += 1;
+            }
+            if self.digits() == 0 {
+                return Err(self.error_at(start, ErrorKind::InvalidNumber));
+            }
+        }
+        Ok(Number(&self.text[start..self.pos]))
+    }
+}
+
+/// Destino de un [`Writer`].
+pub trait Output {
+    fn push(&mut self, bytes: &[u8]);
+}
+
+impl Output for Vec<u8> {
+    fn push(&mut self, bytes: &[u8]) {
+        self.extend_from_slice(bytes);
+    }
+}
+
+/// Buffer fijo. Si no cabe todo, lo escrito no es un documento válido y
+/// [`SliceOutput::finish`] devuelve `None`.
+pub struct SliceOutput<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+    overflow: bool,
+}
+
+impl<'a> SliceOutput<'a> {
+    pub fn new(buf: &'a mut [u8]) -> Self {
+        SliceOutput { buf, len: 0, overflow: false }
+    }
+
+    /// Bytes escritos, o `None` si el buffer se quedó corto.
+    pub fn finish(self) -> Option<usize> {
+        if self.overflow { None } else { Some(self.len) }
+    }
+}
+
+impl Output for SliceOutput<'_> {
+    fn push(&mut self, bytes: &[u8]) {
+        if self.overflow || self.buf.len() - self.len < bytes.len() {
+            self.overflow = true;
+            return;
+        }
+        self.buf[self.len..self.len + bytes.len()].copy_from_slice(bytes);
+        self.len += bytes.len();
+    }
+}
+
+const MAX_WRITER_DEPTH: u32 = 64;
+
+/// Serializador en streaming. Las comas y los `:` los pone el escritor; quien lo usa
+/// solo abre y cierra contenedores y emite claves y valores en orden, p. ej.
+/// `w.begin_object().key("status").string("ok").end_object()`.
+pub struct Writer<O: Output> {
+    out: O,
+    depth: u32,
+    /// Bit `n`: el contenedor de profundidad `n` ya tiene algún elemento.
+    nonempty: u64,
+    after_key: bool,
+}
+
+impl<O: Output> Writer<O> {
+    pub fn new(out: O) -> Self {
+        Writer { out, depth: 0, nonempty: 0, after_key: false }
+    }
+
+    pub fn into_inner(self) -> O {
+        self.out
+    }
+
+    fn separator(&mut self) {
+        if self.after_key {
+            self.after_key = false;
+            return;
+        }
+        if self.depth == 0 {
+            return;
+        }
+        let bit = 1 << (self.depth - 1);
+        if self.nonempty & bit != 0 {
+            self.out.push(b",");
+        }
+        self.nonempty |= bit;
+    }
+
+    fn open(&mut self, c: u8) -> &mut Self {
+        self.separator();
+        debug_assert!(self.depth < MAX_WRITER_DEPTH);
+        self.out.push(&[c]);
+        self.depth += 1;
+        self.nonempty &= !(1 << (self.depth - 1));
+        self
+    }
+
+    fn close(&mut self, c: u8) -> &mut Self {
+        self.out.push(&[c]);
+        self.depth = self.depth.saturating_sub(1);
+        self
+    }
+
+    pub fn begin_object(&mut self) -> &mut Self {
+        self.open(b'{')
+    }
+
+    pub fn end_object(&mut self) -> &mut Self {
+        self.close(b'}')
+    }
+
+    pub fn begin_array(&mut self) -> &mut Self {
+        self.open(b'[')
+    }
+
+    pub fn end_array(&mut self) -> &mut Self {
+        self.close(b']')
+    }
+
+    pub fn key(&mut self, key: &str) -> &mut Self {
+        self.separator();
+        self.escaped(key);
+        self.out.push(b":");
+        self.after_key = true;
+        self
+    }
+
+    pub fn string(&mut self, s: &str) -> &mut Self {
+        self.separator();
+        self.escaped(s);
+        self
+    }
+
+    pub fn u64(&mut self, n: u64) -> &mut Self {
+        self.separator();
+        self.display(n)
+    }
+
+    pub fn i64(&mut self, n: i64) -> &mut Self {
+        self.separator();
+        self.display(n)
+    }
+
+    /// NaN e infinito no existen en JSON: se escriben como `null`.
+    pub fn f64(&mut self, n: f64) -> &mut Self {
+        self.separator();
+        if n.is_finite() {
+            self.display(n)
+        } else {
+            self.out.push(b"null");
+            self
+        }
+    }
+
+    pub fn bool(&mut self, b: bool) -> &mut Self {
+        self.separator();
+        self.out.push(if b { b"true" } else { b"false" });
+        self
+    }
+
+    pub fn null(&mut self) -> &mut Self {
+        self.separator();
+        self.out.push(b"null");
+        self
+    }
+
+    /// Inserta un valor ya serializado (p. ej. un campo de [`raw_fields`]).
+    pub fn raw(&mut self, json: &[u8]) -> &mut Self {
+        self.separator();
+        self.out.push(json);
+        self
+    }
+
+    /// Vuelve a serializar un valor parseado.
+    pub fn value(&mut self, value: &Value<'_>) -> &mut Self {
+        match value {
+            Value::Null => self.null(),
+            Value::Bool(b) => self.bool(*b),
+            Value::Number(n) => self.raw(n.as_str().as_bytes()),
+            Value::String(s) => self.string(&s.to_cow()),
+            Value::Array(items) => {
+                self.begin_array();
+                for item in items {
+                    self.value(item);
+                }
+                self.end_array()
+            }
+            Value::Object(fields) => {
+                self.begin_object();
+                for (k, v) in fields {
+                    self.key(&k.to_cow()).value(v);
+                }
+                self.end_object()
+            }
+        }
+    }
+
+    fn display(&mut self, v: impl fmt::Display) -> &mut Self {
+        struct Adapter<'w, O: Output>(&'w mut O);
+        impl<O: Output> fmt::Write for Adapter<'_, O> {
+            fn write_str(&mut self, s: &str) -> fmt::Result {
+                self.0.push(s.as_bytes());
+                Ok(())
+            }
+        }
+        let _ = fmt::write(&mut Adapter(&mut self.out), format_args!("{}", v));
+        self
+    }
+
+    fn escaped(&mut self, s: &str) {
+        const HEX: &[u8; 16] = b"0123456789abcdef";
+        self.out.push(b"\"");
+        let bytes = s.as_bytes();
+        let mut start = 0;
+        for (i, &b) in bytes.iter().enumerate() {
+            let escape: &[u8] = match b {
+                b'"' => b"\\\"",
+                b'\\' => b"\\\\",
+                b'\n' => b"\\n",
+                b'\r' => b"\\r",
+                b'\t' => b"\\t",
+                0x00..=0x1F => &[b'\\', b'u', b'0', b'0', HEX[(b >> 4) as usize], HEX[(b & 0xF) as usize]],
+                _ => continue,
+            };
+            self.out.push(&bytes[start..i]);
+            self.out.push(escape);
+            start = i + 1;
+        }
+        self.out.push(&bytes[start..]);
+        self.out.push(b"\"");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    #[test]
+    fn parses_nested_document() {
+        let v = parse(br#" {"a": [1, -2.5e3, true, null], "b": {"c": "x"}} "#).unwrap();
+        let a = v.get("a").unwrap().as_array().unwrap();
+        assert_eq!(a[0].as_u64(), Some(1));
+        assert_eq!(a[1].as_f64(), Some(-2500.0));
+        assert_eq!(a[1].as_u64(), None);
+        assert_eq!(a[2].as_bool(), Some(true));
+        assert!(a[3].is_null());
+        assert_eq!(v.get("b").unwrap().get("c").unwrap().as_str().unwrap(), "x");
+        assert!(v.get("z").is_none());
+    }
+
+    #[test]
+    fn strings_borrow_unless_escaped() {
+        let input = br#"["plain", "tab\there \"q\" \u00e9 \ud83d\ude00 \/"]"#;
+        let v = parse(input).unwrap();
+        let items = v.as_array().unwrap();
+        assert!(matches!(items[0].as_str().unwrap(), Cow::Borrowed("plain")));
+        let s = items[1].as_string().unwrap();
+        assert_eq!(s.as_borrowed(), None);
+        assert_eq!(s.to_cow(), "tab\there \"q\" é 😀 /");
+        assert!(s.eq_str("tab\there \"q\" é 😀 /"));
+    }
+
+    #[test]
+    fn rejects_malformed_input() {
+        let cases: &[(&[u8], ErrorKind)] = &[
+            (b"", ErrorKind::UnexpectedEnd),
+            (b"{\"a\":1", ErrorKind::UnexpectedEnd),
+            (b"{\"a\" 1}", ErrorKind::UnexpectedChar),
+            (b"[1,]", ErrorKind::UnexpectedChar),
+            (b"[01]", ErrorKind::UnexpectedChar),
+            (b"[1.]", ErrorKind::InvalidNumber),
+            (b"-", ErrorKind::InvalidNumber),
+            (b"\"\\x\"", ErrorKind::InvalidEscape),
+            (b"\"\\ud800\"", ErrorKind::InvalidUnicode),
+            (b"\"\\udc00\"", ErrorKind::InvalidUnicode),
+            (b"\"a\nb\"", ErrorKind::ControlCharacter),
+            (b"\"\xff\"", ErrorKind::InvalidUtf8),
+            (b"tru", ErrorKind::UnexpectedEnd),
+            (b"nul!", ErrorKind::UnexpectedChar),
+            (b"{} {}", ErrorKind::TrailingCharacters),
+        ];
+        for (input, kind) in cases {
+            assert_eq!(parse(input).map(drop).unwrap_err().kind, *kind, "{:?}", core::str::from_utf8(input));
+        }
+        assert_eq!(parse(b"[1,]").unwrap_err().offset, 3);
+    }
+
+    #[test]
+    fn limits_reject_hostile_input() {
+        let limits = Limits { max_depth: 4, max_size: 64, max_items: 8 };
+        assert!(parse_with(b"[[[[1]]]]", limits).is_ok());
+        assert_eq!(parse_with(b"[[[[[1]]]]]", limits).unwrap_err().kind, ErrorKind::TooDeep);
+        assert_eq!(parse_with(&[b' '; 65], limits).unwrap_err().kind, ErrorKind::TooLarge);
+        assert_eq!(parse_with(b"[1,2,3,4,5,6,7,8,9]", limits).unwrap_err().kind, ErrorKind::TooManyItems);
+        // Sin límite de profundidad la recursión desbordaría la pila
+        let deep = vec![b'['; 100_000];
+        assert_eq!(parse(&deep).unwrap_err().kind, ErrorKind::TooDeep);
+    }
+
+    #[test]
+    fn raw_fields_keep_original_text() {
+        let input = br#"{"method": "infer", "params": {"prompt": "hi", "n": [1, 2]}}"#;
+        let fields = raw_fields(input).unwrap();
+        assert_eq!(fields.len(), 2);
+        assert!(fields[0].0.eq_str("method"));
+        assert_eq!(fields[0].1, b"\"infer\"");
+        assert_eq!(fields[1].1, br#"{"prompt": "hi", "n": [1, 2]}"#);
+        assert!(raw_fields(b"[1]").is_err());
+        assert!(raw_fields(b"{\"a\": tru}").is_err());
+        assert_eq!(raw_fields(b"{}").unwrap().len(), 0);
+        let elements = raw_elements(br#"[{"id": 1}, 2 ,"x"]"#).unwrap();
+        assert_eq!(elements, [&br#"{"id": 1}"#[..], b"2", b"\"x\""]);
+        assert!(raw_elements(b"[]").unwrap().is_empty());
+        assert!(raw_elements(b"[1,]").is_err());
+    }
+
+    #[test]
+    fn writer_escapes_and_separates() {
+        let mut w = Writer::new(Vec::new());
+        w.begin_object()
+            .key("s")
+            .string("a\"b\\c\n\u{1}é")
+            .key("n")
+            .begin_array()
+            .u64(1)
+            .i64(-2)
+            .f64(0.5)
+            .f64(f64::NAN)
+            .end_array()
+            .key("e")
+            .begin_object()
+            .end_object()
+            .key("b")
+            .bool(false)
+            .key("z")
+            .null()
+            .end_object();
+        let out = w.into_inner();
+        assert_eq!(
+            core::str::from_utf8(&out).unwrap(),
+            r#"{"s":"a\"b\\c\n\u0001é","n":[1,-2,0.5,null],"e":{},"b":false,"z":null}"#
+        );
+    }
+
+    #[test]
+    fn writer_round_trips_parsed_values() {
+        let input = r#"{"a":[1,2.5,"x\ty"],"b":{"c":null,"d":true}}"#;
+        let v = parse(input.as_bytes()).unwrap();
+        let mut w = Writer::new(Vec::new());
+        w.value(&v);
+        assert_eq!(w.into_inner(), input.as_bytes());
+    }
+
+    #[test]
+    fn slice_output_reports_overflow() {
+        let mut buf = [0u8; 8];
+        let mut w = Writer::new(SliceOutput::new(&mut buf));
+        w.begin_array().u64(1).u64(2).end_array();
+        assert_eq!(w.into_inner().finish(), Some(5));
+        assert_eq!(&buf[..5], b"[1,2]");
+
+        let mut small = [0u8; 4];
+        let mut w = Writer::new(SliceOutput::new(&mut small));
+        w.begin_object().key("long").string("value").end_object();
+        assert_eq!(w.into_inner().finish(), None);
+    }
+}
@@ -0,0 +1,374 @@
+//! JSON-RPC 2.0 sobre [`crate::json`]: peticiones con `id` (número o cadena),
+//! notificaciones sin respuesta, lotes y objetos de error.
+//!
+//! [`handle_frame`] recibe una trama completa y devuelve la respuesta que hay que
+//! enviar, o `None` si no hay que contestar (notificaciones, lotes solo de
+//! notificaciones, peticiones canceladas). Los `params` llegan al manejador como
+//! texto JSON sin tocar.
+
+use crate::json::{self, Output, Writer};
+use alloc::borrow::Cow;
+use alloc::vec::Vec;
+use core::fmt;
+
+pub const VERSION: &str = "2.0";
+
+/// Códigos de error de JSON-RPC 2.0 y los propios de MCP.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    /// El texto recibido no es JSON válido.
+    ParseError,
+    /// JSON válido pero no es una petición (falta `method`, `jsonrpc` distinto de "2.0", ...).
+    InvalidRequest,
+    MethodNotFound,
+    InvalidParams,
+    InternalError,
+    /// MCP: la petición no terminó a tiempo.
+    RequestTimeout,
+    /// MCP: el recurso pedido no existe.
+    ResourceNotFound,
+    /// El cliente canceló la petición (`notifications/cancelled`). MCP no contesta a
+    /// las peticiones canceladas: [`handle_frame`] no envía este error. El código es
+    /// el de LSP.
+    RequestCancelled,
+}
+
+impl ErrorCode {
+    pub fn code(&self) -> i64 {
+        match self {
+            ErrorCode::ParseError => -32700,
+            ErrorCode::InvalidRequest => -32600,
+            ErrorCode::MethodNotFound => -32601,
+            ErrorCode::InvalidParams => -32602,
+            ErrorCode::InternalError => -32603,
+            ErrorCode::RequestTimeout => -32001,
+            ErrorCode::ResourceNotFound => -32002,
+            ErrorCode::RequestCancelled => -32800,
+        }
+    }
+
+    pub fn from_code(code: i64) -> Option<ErrorCode> {
+        [
+            ErrorCode::ParseError,
+            ErrorCode::InvalidRequest,
+            ErrorCode::MethodNotFound,
+            ErrorCode::InvalidParams,
+            ErrorCode::InternalError,
+            ErrorCode::RequestTimeout,
+            ErrorCode::ResourceNotFound,
+            ErrorCode::RequestCancelled,
+        ]
+        .into_iter()
+        .find(|c| c.code() == code)
+    }
+
+    /// Mensaje por defecto del objeto de error.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorCode::ParseError => "Parse error",
+            ErrorCode::InvalidRequest => "Invalid Request",
+            ErrorCode::MethodNotFound => "Method not found",
+            ErrorCode::InvalidParams => "Invalid params",
+            ErrorCode::InternalError => "Internal error",
+            ErrorCode::RequestTimeout => "Request timed out",
+            ErrorCode::ResourceNotFound => "Resource not found",
+            ErrorCode::RequestCancelled => "Request cancelled",
+        }
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Objeto `error` de una respuesta.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RpcError {
+    pub code: ErrorCode,
+    pub message: Cow<'static, str>,
+}
+
+impl RpcError {
+    pub fn new(code: ErrorCode) -> Self {
+        RpcError { code, message: Cow::Borrowed(code.as_str()) }
+    }
+
+    pub fn with_message(code: ErrorCode, message: impl Into<Cow<'static, str>>) -> Self {
+        RpcError { code, message: message.into() }
+    }
+}
+
+impl From<ErrorCode> for RpcError {
+    fn from(code: ErrorCode) -> Self {
+        RpcError::new(code)
+    }
+}
+
+impl fmt::Display for RpcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.message, self.code.code())
+    }
+}
+
+/// Identificador de una petición; la respuesta lo repite tal cual.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Id<'a> {
+    Number(i64),
+    String(Cow<'a, str>),
+}
+
+impl Id<'_> {
+    pub fn into_owned(self) -> Id<'static> {
+        match self {
+            Id::Number(n) => Id::Number(n),
+            Id::String(s) => Id::String(Cow::Owned(s.into_owned())),
+        }
+    }
+
+    pub fn write<O: Output>(&self, w: &mut Writer<O>) {
+        match self {
+            Id::Number(n) => w.i64(*n),
+            Id::String(s) => w.string(s),
+        };
+    }
+}
+
+/// Petición (con `id`) o notificación (sin `id`) ya validada.
+#[derive(Debug)]
+pub struct Request<'a> {
+    pub id: Option<Id<'a>>,
+    pub method: Cow<'a, str>,
+    /// Texto JSON de `params` (objeto o array), si vienen.
+    pub params: Option<&'a [u8]>,
+    /// Llegó dentro de un lote (MCP no permite `initialize` en lotes).
+    pub batched: bool,
+}
+
+impl Request<'_> {
+    pub fn is_notification(&self) -> bool {
+        self.id.is_none()
+    }
+}
+
+fn first_byte(raw: &[u8]) -> Option<u8> {
+    raw.iter().copied().find(|b| !b" \t\r\n".contains(b))
+}
+
+/// Valida un mensaje JSON-RPC. Si no es válido, devuelve el `id` (si se pudo leer)
+/// junto al error para poder contestar.
+pub fn parse_request(raw: &[u8]) -> Result<Request<'_>, (Option<Id<'_>>, RpcError)> {
+    let fields = match json::raw_fields(raw) {
+        Ok(fields) => fields,
+        // JSON válido que no es un objeto
+        Err(_) if json::parse(raw).is_ok() => return Err((None, ErrorCode::InvalidRequest.into())),
+        Err(_) => return Err((None, ErrorCode::ParseError.into())),
+    };
+    let field = |key: &str| fields.iter().find(|(k, _)| k.eq_str(key)).map(|(_, v)| *v);
+    let invalid = |id, message: &'static str| Err((id, RpcError::with_message(ErrorCode::InvalidRequest, message)));
+
+    let id = match field("id").map(json::parse) {
+        None => None,
+        Some(Ok(json::Value::Number(n))) => match n.as_i64() {
+            Some(n) => Some(Id::Number(n)),
+            None => return invalid(None, "id must be an integer or a string"),
+        },
+        Some(Ok(json::Value::String(s))) => Some(Id::String(s.to_cow())),
+        Some(_) => return invalid(None, "id must be an integer or a string"),
+    };
+    let version = field("jsonrpc").and_then(|v| json::parse(v).ok());
+    if !version.is_some_and(|v| v.as_string().is_some_and(|s| s.eq_str(VERSION))) {
+        return invalid(id, "jsonrpc must be \"2.0\"");
+    }
+    let Some(method) = field("method").and_then(|v| json::parse(v).ok()).and_then(|v| v.as_str()) else {
+        return invalid(id, "method must be a string");
+    };
+    let params = field("params");
+    if params.is_some_and(|p| !matches!(first_byte(p), Some(b'{' | b'['))) {
+        return invalid(id, "params must be an object or an array");
+    }
+    Ok(Request { id, method, params, batched: false })
+}
+
+/// `{"jsonrpc":"2.0","id":...,"result":...}` con `result` ya serializado.
+pub fn response(id: &Id<'_>, result: &[u8]) -> Vec<u8> {
+    let mut w = Writer::new(Vec::new());
+    w.begin_object().key("jsonrpc").string(VERSION).key("id");
+    id.write(&mut w);
+    w.key("result").raw(result).end_object();
+    w.into_inner()
+}
+
+/// Respuesta de error; sin `id` conocido se envía `"id": null`.
+pub fn error_response(id: Option<&Id<'_>>, err: &RpcError) -> Vec<u8> {
+    let mut w = Writer::new(Vec::new());
+    w.begin_object().key("jsonrpc").string(VERSION).key("id");
+    match id {
+        Some(id) => id.write(&mut w),
+        None => {
+            w.null();
+        }
+    }
+    w.key("error")
+        .begin_object()
+        .key("code")
+        .i64(err.code.code())
+        .key("message")
+        .string(&err.message)
+        .end_object()
+        .end_object();
+    w.into_inner()
+}
+
+/// Notificación `{"jsonrpc":"2.0","method":...,"params":...}` con `params` ya serializado.
+pub fn notification(method: &str, params: Option<&[u8]>) -> Vec<u8> {
+    let mut w = Writer::new(Vec::new());
+    w.begin_object().key("jsonrpc").string(VERSION).key("method").string(method);
+    if let Some(params) = params {
+        w.key("params").raw(params);
+    }
+    w.end_object();
+    w.into_inner()
+}
+
+fn handle_one<F>(raw: &[u8], batched: bool, handler: &mut F) -> Option<Vec<u8>>
+where
+    F: FnMut(&Request<'_>) -> Result<Vec<u8>, RpcError>,
+{
+    match parse_request(raw) {
+        Err((id, err)) => Some(error_response(id.as_ref(), &err)),
+        Ok(mut req) => {
+            req.batched = batched;
+            let result = handler(&req);
+            // Las notificaciones no reciben respuesta, ni siquiera de error
+            let id = req.id?;
+            match result {
+                Ok(result) => Some(response(&id, &result)),
+                Err(err) if err.code == ErrorCode::RequestCancelled => None,
+                Err(err) => Some(error_response(Some(&id), &err)),
+            }
+        }
+    }
+}
+
+/// Procesa una trama (mensaje suelto o lote) con `handler`, que devuelve el
+/// `result` serializado. Devuelve la respuesta a enviar, si la hay.
+pub fn handle_frame<F>(frame: &[u8], mut handler: F) -> Option<Vec<u8>>
+where
+    F: FnMut(&Request<'_>) -> Result<Vec<u8>, RpcError>,
+{
+    if first_byte(frame) != Some(b'[') {
+        return handle_one(frame, false, &mut handler);
+    }
+    let elements = match json::raw_elements(frame) {
+        Ok(elements) => elements,
+        Err(_) => return Some(error_response(None, &ErrorCode::ParseError.into())),
+    };
+    if elements.is_empty() {
+        return Some(error_response(None, &ErrorCode::InvalidRequest.into()));
+    }
+    let mut w = Writer::new(Vec::new());
+    w.begin_array();
+    let mut any = false;
+    for raw in elements {
+        if let Some(resp) = handle_one(raw, true, &mut handler) {
+            w.raw(&resp);
+            any = true;
+        }
+    }
+    w.end_array();
+    any.then(|| w.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::String;
+    use alloc::vec;
+
+    fn echo(req: &Request<'_>) -> Result<Vec<u8>, RpcError> {
+        match &*req.method {
+            "echo" => Ok(req.params.unwrap_or(b"null").to_vec()),
+            "fail" => Err(RpcError::with_message(ErrorCode::InvalidParams, "bad \"x\"")),
+            "cancelled" => Err(ErrorCode::RequestCancelled.into()),
+            _ => Err(ErrorCode::MethodNotFound.into()),
+        }
+    }
+
+    fn run(frame: &[u8]) -> Option<String> {
+        handle_frame(frame, echo).map(|r| String::from_utf8(r).unwrap())
+    }
+
+    #[test]
+    fn requests_echo_their_id() {
+        assert_eq!(
+            run(br#"{"jsonrpc":"2.0","id":7,"method":"echo","params":[1]}"#).unwrap(),
+            r#"{"jsonrpc":"2.0","id":7,"result":[1]}"#
+        );
+        assert_eq!(
+            run(br#"{"jsonrpc":"2.0","id":"a\"b","method":"fail","params":{}}"#).unwrap(),
+            r#"{"jsonrpc":"2.0","id":"a\"b","error":{"code":-32602,"message":"bad \"x\""}}"#
+        );
+        assert_eq!(
+            run(br#"{"jsonrpc":"2.0","id":1,"method":"nope"}"#).unwrap(),
+            r#"{"jsonrpc":"2.0","id":1,"error":{"code":-32601,"message":"Method not found"}}"#
+        );
+        // Notificaciones: nunca hay respuesta
+        assert_eq!(run(br#"{"jsonrpc":"2.0","method":"echo"}"#), None);
+        assert_eq!(run(br#"{"jsonrpc":"2.0","method":"nope"}"#), None);
+    }
+
+    #[test]
+    fn invalid_messages_get_error_objects() {
+        let code = |frame: &[u8]| {
+            let resp = handle_frame(frame, echo).unwrap();
+            let value = json::parse(&resp).unwrap();
+            let id_null = value.get("id").unwrap().is_null();
+            (value.get("error").unwrap().get("code").unwrap().as_i64().unwrap(), id_null)
+        };
+        assert_eq!(code(b"{\"jsonrpc\":"), (-32700, true));
+        assert_eq!(code(b"42"), (-32600, true));
+        assert_eq!(code(br#"{"id":1,"method":"echo"}"#), (-32600, false));
+        assert_eq!(code(br#"{"jsonrpc":"1.0","id":1,"method":"echo"}"#), (-32600, false));
+        assert_eq!(code(br#"{"jsonrpc":"2.0","id":1,"method":5}"#), (-32600, false));
+        assert_eq!(code(br#"{"jsonrpc":"2.0","id":1.5,"method":"echo"}"#), (-32600, true));
+        assert_eq!(code(br#"{"jsonrpc":"2.0","id":null,"method":"echo"}"#), (-32600, true));
+        assert_eq!(code(br#"{"jsonrpc":"2.0","id":1,"method":"echo","params":3}"#), (-32600, false));
+        assert_eq!(ErrorCode::from_code(-32002), Some(ErrorCode::ResourceNotFound));
+    }
+
+    #[test]
+    fn batches_collect_responses() {
+        let resp = run(br#"[
+            {"jsonrpc":"2.0","id":1,"method":"echo","params":{"a":1}},
+            {"jsonrpc":"2.0","method":"echo"},
+            1,
+            {"jsonrpc":"2.0","id":"x","method":"nope"}
+        ]"#)
+        .unwrap();
+        assert_eq!(
+            resp,
+            concat!(
+                r#"[{"jsonrpc":"2.0","id":1,"result":{"a":1}},"#,
+                r#"{"jsonrpc":"2.0","id":null,"error":{"code":-32600,"message":"Invalid Request"}},"#,
+                r#"{"jsonrpc":"2.0","id":"x","error":{"code":-32601,"message":"Method not found"}}]"#
+            )
+        );
+        assert_eq!(run(br#"[{"jsonrpc":"2.0","method":"echo"}]"#), None);
+        // Las peticiones canceladas no se contestan
+        assert_eq!(run(br#"{"jsonrpc":"2.0","id":3,"method":"cancelled"}"#), None);
+        assert_eq!(
+            run(br#"[{"jsonrpc":"2.0","id":3,"method":"cancelled"},{"jsonrpc":"2.0","id":4,"method":"echo"}]"#).unwrap(),
+            r#"[{"jsonrpc":"2.0","id":4,"result":null}]"#
+        );
+        assert_eq!(run(b"[]").unwrap(), r#"{"jsonrpc":"2.0","id":null,"error":{"code":-32600,"message":"Invalid Request"}}"#);
+        assert!(run(b"[{}").unwrap().contains("-32700"));
+        let mut seen = vec![];
+        handle_frame(br#"[{"jsonrpc":"2.0","id":2,"method":"a"},{"jsonrpc":"2.0","method":"b"}]"#, |req| {
+            seen.push((req.id.clone().map(Id::into_owned), String::from(&*req.method), req.batched));
+            Ok(b"null".to_vec())
+        });
+        assert_eq!(seen, [(Some(Id::Number(2)), String::from("a"), true), (None, String::from("b"), true)]);
+    }
+}
@@ -1,60 +1,399 @@
-#![no_std]
+#![cfg_attr(not(test), no_std)]
 extern crate alloc;
 
+pub mod client;
+pub mod completion;
+pub mod json;
+pub mod jsonrpc;
+pub mod prompts;
+pub mod resources;
+pub mod session;
+pub mod tools;
+#[cfg(feature = "schema")]
+pub mod schema;
+
 #[cfg(all(feature = "global-allocator", not(test)))]
 use linked_list_allocator::LockedHeap;
 
 pub mod mcp_server {
-    use core::sync::atomic::{AtomicBool, Ordering};
+    use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use alloc::string::String;
     use alloc::vec::Vec;
+    use crate::completion::{self, CompletionSource, Reference};
+    use crate::jsonrpc::{self, ErrorCode, RpcError};
+    use crate::prompts;
+    use crate::resources::{self, ResourceContents};
+    use crate::session::{ServerCapabilities, Session, State};
+    use crate::tools::{self, CancellationToken};
+    use logging::records::{Level, Record};
+    pub use crate::completion::{register_completion, unregister_completion};
+    pub use crate::resources::{
+        notify_updated, register_resource, register_template, unregister_resource, ResourceDef, ResourceReader,
+        ResourceTemplate,
+    };
+    pub use crate::tools::{register_tool, unregister_tool, ToolAnnotations, ToolContext, ToolDef, ToolHandler};
 
     static READY: AtomicBool = AtomicBool::new(false);
+    /// `logging::log_head` en la última comprobación de `log://kernel`.
+    static LOG_SEEN: AtomicUsize = AtomicUsize::new(0);
 
-    pub struct McpTool<'a> {
-        pub name: &'a str,
-        pub handler: fn(&[u8]) -> Option<Vec<u8>>,
-    }
+    pub const MODEL_URI: &str = "model://current";
+    pub const LOG_URI: &str = "log://kernel";
+    pub const CONFIG_URI: &str = "config://kernel";
+    /// Directorio del que `load_model` sugiere modelos.
+    const MODELS_DIR: &str = "/models";
+    /// Tamaño máximo de un fichero en `resources/read` (en base64 ocupa 4/3).
+    const MAX_FILE_RESOURCE: u64 = 512 * 1024;
 
-    static TOOLS: &[McpTool] = &[
-        McpTool { name: "infer", handler: handle_infer },
-        McpTool { name: "health", handler: handle_health },
-        McpTool { name: "metadata", handler: handle_metadata },
-        McpTool { name: "load_model", handler: handle_load_model },
-        McpTool { name: "logs", handler: handle_logs },
+    const NO_ARGUMENTS: &str = r#"{"type":"object","properties":{}}"#;
+
+    /// Herramientas del kernel; se registran en [`init`].
+    static BUILTIN_TOOLS: &[(ToolDef, ToolHandler)] = &[
+        (
+            ToolDef {
+                name: "infer",
+                description: "Genera texto con el modelo cargado a partir de un prompt. Con _meta.progressToken los tokens llegan como notifications/progress.",
+                input_schema: r#"{"type":"object","properties":{"prompt":{"type":"string","description":"Texto de entrada"},"max_tokens":{"type":"integer","minimum":1},"temperature":{"type":"number","minimum":0},"stream":{"type":"boolean","description":"Enviar los tokens como notifications/progress según se generan; requiere _meta.progressToken"}},"required":["prompt"]}"#,
+                output_schema: Some(r#"{"type":"object","properties":{"text":{"type":"string"},"tokens":{"type":"integer"},"latency_ms":{"type":"integer"}},"required":["text","tokens","latency_ms"]}"#),
+                annotations: ToolAnnotations { read_only: true, destructive: false, idempotent: false, open_world: false },
+            },
+            handle_infer,
+        ),
+        (
+            ToolDef {
+                name: "health",
+                description: "Estado del servicio: ok, degraded (virtqueue bloqueada) o not_loaded (sin modelo).",
+                input_schema: NO_ARGUMENTS,
+                output_schema: Some(r#"{"type":"object","properties":{"status":{"type":"string","enum":["ok","degraded","not_loaded"]},"details":{"type":"string"}},"required":["status","details"]}"#),
+                annotations: ToolAnnotations::READ_ONLY,
+            },
+            handle_health,
+        ),
+        (
+            ToolDef {
+                name: "metadata",
+                description: "Modelo cargado, cuantización, arquitectura y build del kernel.",
+                input_schema: NO_ARGUMENTS,
+                output_schema: Some(r#"{"type":"object","properties":{"model_name":{"type":"string"},"quantization":{"type":"string"},"arch":{"type":"string"},"features":{"type":"array","items":{"type":"string"}},"build":{"type":"string"}},"required":["model_name","quantization","arch","features","build"]}"#),
+                annotations: ToolAnnotations::READ_ONLY,
+            },
+            handle_metadata,
+        ),
+        (
+            ToolDef {
+                name: "load_model",
+                description: "Carga un modelo desde el VFS (virtio-fs o imagen en virtio-blk) y sustituye al actual.",
+                input_schema: r#"{"type":"object","properties":{"path":{"type":"string","description":"Ruta absoluta del modelo, p. ej. /models/tiny.bin"}},"required":["path"]}"#,
+                output_schema: Some(r#"{"type":"object","properties":{"status":{"type":"string"},"path":{"type":"string"}},"required":["status","path"]}"#),
+                annotations: ToolAnnotations { read_only: false, destructive: true, idempotent: true, open_world: false },
+            },
+            handle_load_model,
+        ),
+        (
+            ToolDef {
+                name: "logs",
+                description: "Texto del buffer de logs del kernel desde la última llamada.",
+                input_schema: NO_ARGUMENTS,
+                output_schema: None,
+                annotations: ToolAnnotations { read_only: true, destructive: false, idempotent: false, open_world: false },
+            },
+            handle_logs,
+        ),
+        (
+            ToolDef {
+                name: "events",
+                description: "Eventos de la traza de drivers desde la última llamada.",
+                input_schema: NO_ARGUMENTS,
+                output_schema: None,
+                annotations: ToolAnnotations { read_only: true, destructive: false, idempotent: false, open_world: false },
+            },
+            handle_events,
+        ),
+        (
+            ToolDef {
+                name: "trace",
+                description: "Consulta o cambia las categorías activas de la traza de drivers.",
+                input_schema: r#"{"type":"object","properties":{"categories":{"type":"string","description":"Lista separada por comas, p. ej. blk,fs; sin ella solo se consulta"}}}"#,
+                output_schema: Some(r#"{"type":"object","properties":{"categories":{"type":"string"}},"required":["categories"]}"#),
+                annotations: ToolAnnotations { read_only: false, destructive: false, idempotent: true, open_world: false },
+            },
+            handle_trace,
+        ),
     ];
 
+    /// Recursos del kernel; se registran en [`init`].
+    static BUILTIN_RESOURCES: &[(ResourceDef, ResourceReader)] = &[
+        (
+            ResourceDef {
+                uri: MODEL_URI,
+                name: "model",
+                description: "Modelo cargado: tamaño, si se usa desde la ventana DAX y RAM del guest que ocupa.",
+                mime_type: Some("application/json"),
+            },
+            read_model,
+        ),
+        (
+            ResourceDef {
+                uri: LOG_URI,
+                name: "kernel-log",
+                description: "Contenido del buffer de logs del kernel; no consume lo pendiente de la herramienta logs.",
+                mime_type: Some("text/plain"),
+            },
+            read_log,
+        ),
+        (
+            ResourceDef {
+                uri: CONFIG_URI,
+                name: "kernel-config",
+                description: "Configuración del kernel: servidor MCP, transporte, montajes del VFS y categorías de traza.",
+                mime_type: Some("application/json"),
+            },
+            read_config,
+        ),
+    ];
+
+    static BUILTIN_TEMPLATES: &[(ResourceTemplate, ResourceReader)] = &[(
+        ResourceTemplate {
+            uri_template: "file:///{+path}",
+            name: "file",
+            description: "Fichero o directorio del VFS (virtio-fs o imagen en virtio-blk), p. ej. file:///models/tiny.bin.",
+            mime_type: None,
+        },
+        read_file,
+    )];
+
+    /// Fuentes de autocompletado del kernel; se registran en [`init`].
+    static BUILTIN_COMPLETIONS: &[(Reference<'static>, &str, CompletionSource)] = &[
+        (Reference::Tool("load_model"), "path", complete_model_path),
+        (Reference::Tool("infer"), "prompt", complete_model_prompt),
+        (Reference::Prompt("chat"), "message", complete_model_prompt),
+        (Reference::Resource("file:///{+path}"), "path", complete_file_path),
+    ];
+
+    /// Capacidades que se anuncian en `initialize`: solo lo que el servidor implementa.
+    pub const CAPABILITIES: ServerCapabilities =
+        ServerCapabilities { completions: true, tools: true, resources: true, prompts: true, logging: true };
+
     pub fn init() {
+        for (def, handler) in BUILTIN_TOOLS {
+            if let Err(e) = register_tool(*def, *handler) {
+                logging::log!(Level::Error, "mcp", "herramienta no registrada: {}", e.as_str());
+            }
+        }
+        let resources = BUILTIN_RESOURCES.iter().map(|(def, reader)| register_resource(*def, *reader));
+        let templates = BUILTIN_TEMPLATES.iter().map(|(def, reader)| register_template(*def, *reader));
+        for e in resources.chain(templates).filter_map(Result::err) {
+            logging::log!(Level::Error, "mcp", "recurso no registrado: {}", e.as_str());
+        }
+        for (reference, argument, source) in BUILTIN_COMPLETIONS {
+            if let Err(e) = register_completion(*reference, argument, *source) {
+                logging::log!(Level::Error, "mcp", "autocompletado no registrado: {}", e.as_str());
+            }
+        }
         READY.store(true, Ordering::SeqCst);
-        logging::log_write("[mcp] Servidor MCP inicializado (stub)");
+        logging::log(Level::Info, "mcp", "Servidor MCP inicializado (stub)");
+    }
+
+    /// Escribe en la conexión MCP un mensaje iniciado por el servidor (notificaciones).
+    #[cfg(not(test))]
+    fn send(message: &[u8]) -> bool {
+        mcp_vsock_transport::vsock_transport::write_frame(message)
+    }
+
+    /// Mensajes que el servidor habría enviado por su cuenta.
+    #[cfg(test)]
+    pub(crate) static SENT: std::sync::Mutex<Vec<Vec<u8>>> = std::sync::Mutex::new(Vec::new());
+
+    #[cfg(test)]
+    fn send(message: &[u8]) -> bool {
+        SENT.lock().unwrap().push(message.to_vec());
+        true
+    }
+
+    /// Lee sin bloquear la siguiente trama de la conexión.
+    #[cfg(not(test))]
+    fn receive() -> Option<Vec<u8>> {
+        mcp_vsock_transport::vsock_transport::read_frame()
+    }
+
+    /// Tramas que llegarían por la conexión mientras trabaja un manejador.
+    #[cfg(test)]
+    pub(crate) static INCOMING: std::sync::Mutex<Vec<Vec<u8>>> = std::sync::Mutex::new(Vec::new());
+
+    #[cfg(test)]
+    fn receive() -> Option<Vec<u8>> {
+        let mut incoming = INCOMING.lock().unwrap();
+        (!incoming.is_empty()).then(|| incoming.remove(0))
+    }
+
+    // Tramas leídas por `poll_incoming` mientras corría un manejador; el bucle del
+    // servidor las atiende en orden antes de leer más.
+    static mut DEFERRED: Vec<Vec<u8>> = Vec::new();
+
+    pub(crate) fn deferred() -> &'static mut Vec<Vec<u8>> {
+        unsafe { &mut *core::ptr::addr_of_mut!(DEFERRED) }
+    }
+
+    /// `notifications/cancelled` con `{requestId, reason}`. Las peticiones que ya
+    /// terminaron (o que no existen) se ignoran.
+    fn cancel_request(session: &Session, params: &[u8]) {
+        let Ok(params) = crate::json::parse(params) else { return };
+        let id = match params.get("requestId") {
+            Some(crate::json::Value::Number(n)) => n.as_i64().map(jsonrpc::Id::Number),
+            Some(crate::json::Value::String(s)) => Some(jsonrpc::Id::String(s.to_cow())),
+            _ => None,
+        };
+        if id.is_some_and(|id| session.cancel(&id)) {
+            let reason = params.get("reason").and_then(|r| r.as_str()).unwrap_or_default();
+            logging::log!(Level::Info, "mcp", "petición cancelada: {}", reason);
+        }
+    }
+
+    /// Atiende lo que haya llegado por la conexión mientras trabaja un manejador: las
+    /// cancelaciones se aplican en el acto y el resto se aparta para el bucle.
+    fn poll_incoming(session: &Session) {
+        while let Some(frame) = receive() {
+            match jsonrpc::parse_request(&frame) {
+                Ok(req) if req.id.is_none() && req.method == "notifications/cancelled" => {
+                    cancel_request(session, req.params.unwrap_or(b"{}"));
+                }
+                _ => deferred().push(frame),
+            }
+        }
+    }
+
+    /// Envía los avisos pendientes: `notifications/{tools,resources,prompts}/list_changed`
+    /// si cambiaron los registros o las plantillas de prompts, y
+    /// `notifications/resources/updated` por cada recurso suscrito que cambió (el
+    /// buffer de logs se vigila aquí), y `notifications/message` por cada registro del
+    /// kernel que pase el nivel de `logging/setLevel`. Solo con la sesión ya operativa.
+    pub fn flush_notifications(session: &mut Session) {
+        if session.state() != State::Ready {
+            return;
+        }
+        if tools::take_list_changed() && !send(&jsonrpc::notification("notifications/tools/list_changed", None)) {
+            logging::log(Level::Error, "mcp", "no se pudo enviar tools/list_changed");
+        }
+        if resources::take_list_changed() && !send(&jsonrpc::notification("notifications/resources/list_changed", None)) {
+            logging::log(Level::Error, "mcp", "no se pudo enviar resources/list_changed");
+        }
+        if prompts::take_list_changed() && !send(&jsonrpc::notification("notifications/prompts/list_changed", None)) {
+            logging::log(Level::Error, "mcp", "no se pudo enviar prompts/list_changed");
+        }
+        let head = logging::log_head();
+        if LOG_SEEN.swap(head, Ordering::Relaxed) != head {
+            notify_updated(LOG_URI);
+        }
+        for uri in resources::take_updated().iter().filter(|uri| session.is_subscribed(uri)) {
+            let mut w = crate::json::Writer::new(Vec::new());
+            w.begin_object().key("uri").string(uri).end_object();
+            if !send(&jsonrpc::notification("notifications/resources/updated", Some(&w.into_inner()))) {
+                logging::log(Level::Error, "mcp", "no se pudo enviar resources/updated");
+            }
+        }
+        let mut batch = [Record::EMPTY; 16];
+        loop {
+            let n = session.take_log_records(&mut batch);
+            if n == 0 {
+                break;
+            }
+            for record in &batch[..n] {
+                // Sin registrar el fallo: el aviso sería a su vez otro registro que enviar
+                if !send(&log_message(record)) {
+                    return;
+                }
+            }
+        }
+    }
+
+    /// `notifications/message` con `{level, logger, data}` para un registro del kernel.
+    fn log_message(record: &Record) -> Vec<u8> {
+        let mut w = crate::json::Writer::new(Vec::new());
+        w.begin_object()
+            .key("level")
+            .string(record.level.as_str())
+            .key("logger")
+            .string(record.logger())
+            .key("data")
+            .string(record.message())
+            .end_object();
+        jsonrpc::notification("notifications/message", Some(&w.into_inner()))
+    }
+
+    /// `logging/setLevel` con `{level}`, uno de los niveles de syslog (RFC 5424).
+    fn set_level(session: &mut Session, params: &[u8]) -> Result<Vec<u8>, RpcError> {
+        let invalid = || RpcError::with_message(ErrorCode::InvalidParams, "expected {\"level\": \"debug\" | \"info\" | ...}");
+        let params = crate::json::parse(params).map_err(|_| invalid())?;
+        let name = params.get("level").and_then(|l| l.as_str()).ok_or_else(invalid)?;
+        let level = Level::from_name(&name)
+            .ok_or_else(|| RpcError::with_message(ErrorCode::InvalidParams, alloc::format!("unknown level: {}", name)))?;
+        session.set_log_level(level);
+        Ok(b"{}".to_vec())
     }
 
     pub fn is_ready() -> bool {
         READY.load(Ordering::SeqCst)
     }
 
-    fn handle_infer(input: &[u8]) -> Option<Vec<u8>> {
-        let req = crate::ai_stub::parse_infer_req(input)?;
-        let ai_result = ai_runtime::infer(req.prompt);
-        let resp = crate::ai_stub::InferResponse {
-            text: ai_result,
-            tokens: ai_result.split_whitespace().count() as u32,
-            latency_ms: 1,
-        };
-        let mut buf = [0u8; 256];
+    /// Copia los `n` bytes escritos en `buf`; 0 significa que la respuesta no cabía.
+    fn written(buf: &[u8], n: usize) -> Result<Vec<u8>, RpcError> {
+        if n == 0 {
+            return Err(RpcError::with_message(ErrorCode::InternalError, "response too large"));
+        }
+        Ok(buf[..n].to_vec())
+    }
+
+    /// Genera con el modelo cargado. Si el cliente pasó `_meta.progressToken`, cada
+    /// token sale en cuanto se genera como `notifications/progress` (`progress`: tokens
+    /// hasta ahora, `total`: `max_tokens` si viene, `message`: el texto nuevo); el
+    /// resultado final lleva el texto completo. `stream: true` exige el token. La
+    /// generación para si el cliente cancela la petición.
+    fn handle_infer(ctx: &mut ToolContext<'_>, input: &[u8]) -> Result<Vec<u8>, RpcError> {
+        let req = crate::ai_stub::parse_infer_req(input)
+            .ok_or(RpcError::with_message(ErrorCode::InvalidParams, "expected {\"prompt\": string}"))?;
+        if req.stream && !ctx.progress.enabled() {
+            return Err(RpcError::with_message(
+                ErrorCode::InvalidParams,
+                "stream requires _meta.progressToken in tools/call params",
+            ));
+        }
+        let max_tokens = req.params.as_ref().and_then(|p| p.max_tokens).map(|n| n as usize);
+        let total = max_tokens.map(|n| n as f64);
+        let mut text = String::new();
+        let mut tokens = 0;
+        let cancel = ctx.cancel.clone();
+        ai_runtime::infer_stream(&req.prompt, cancel.flag(), |token| {
+            text.push_str(token);
+            tokens += 1;
+            ctx.progress.report(tokens as f64, total, Some(token));
+            // Entre token y token se atienden las cancelaciones
+            !ctx.check_cancelled() && max_tokens.is_none_or(|max| tokens < max)
+        })
+        .map_err(|_| RpcError::new(ErrorCode::RequestCancelled))?;
+        let resp = crate::ai_stub::InferResponse { text: &text, tokens: tokens as u32, latency_ms: 1 };
+        // Peor caso: cada byte escapado como `\u00XX`
+        let mut buf = alloc::vec![0u8; 64 + 6 * text.len()];
         let n = crate::ai_stub::serialize_infer_response(&resp, &mut buf);
-        Some(buf[..n].to_vec())
+        written(&buf, n)
     }
 
-    fn handle_health(_input: &[u8]) -> Option<Vec<u8>> {
-        let status = if unsafe { core::ptr::addr_of!(ai_runtime::MODEL).as_ref().is_some() } { "ok" } else { "not_loaded" };
-        let details = if unsafe { core::ptr::addr_of!(ai_runtime::MODEL).as_ref().is_some() } { "modelo cargado" } else { "sin modelo" };
+    fn handle_health(_ctx: &mut ToolContext<'_>, _input: &[u8]) -> Result<Vec<u8>, RpcError> {
+        let loaded = unsafe { (*core::ptr::addr_of!(ai_runtime::MODEL)).is_some() };
+        // Bloqueos de virtqueues sin recuperar (ver `logging::metrics`) degradan el servicio
+        let (status, details) = if logging::metrics::degraded() {
+            ("degraded", "virtqueue bloqueada sin recuperar")
+        } else if loaded {
+            ("ok", "modelo cargado")
+        } else {
+            ("not_loaded", "sin modelo")
+        };
         let resp = crate::ai_stub::HealthResponse { status, details };
         let mut buf = [0u8; 128];
         let n = crate::ai_stub::serialize_health_response(&resp, &mut buf);
-        Some(buf[..n].to_vec())
+        written(&buf, n)
     }
 
-    fn handle_metadata(_input: &[u8]) -> Option<Vec<u8>> {
+    fn handle_metadata(_ctx: &mut ToolContext<'_>, _input: &[u8]) -> Result<Vec<u8>, RpcError> {
         let (model_name, quantization) = if let Some(_model) = unsafe { core::ptr::addr_of!(ai_runtime::MODEL).as_ref() } {
             ("modelo-bin", "none")
         } else {
@@ -69,71 +408,352 @@ pub mod mcp_server {
         };
         let mut buf = [0u8; 128];
         let n = crate::ai_stub::serialize_metadata_response(&resp, &mut buf);
-        Some(buf[..n].to_vec())
+        written(&buf, n)
     }
 
-    fn handle_load_model(input: &[u8]) -> Option<Vec<u8>> {
-        let path = crate::ai_stub::parse_path_field(input)?;
-        match ai_runtime::load_model(path) {
-            Ok(()) => {
-                let n = crate::ai_stub::serialize_status_ok(path, input);
-                Some(input[..n].to_vec())
-            },
-            Err(e) => {
-                let n = crate::ai_stub::serialize_status_error(e, input);
-                Some(input[..n].to_vec())
-            }
-        }
+    fn handle_load_model(_ctx: &mut ToolContext<'_>, input: &[u8]) -> Result<Vec<u8>, RpcError> {
+        let path = crate::ai_stub::parse_path_field(input)
+            .ok_or(RpcError::with_message(ErrorCode::InvalidParams, "expected {\"path\": string}"))?;
+        let mut buf = [0u8; 512];
+        // El fallo de la carga es un fallo de la herramienta (`isError`), no del protocolo
+        ai_runtime::load_model(&path).map_err(|e| RpcError::with_message(ErrorCode::InternalError, e))?;
+        notify_updated(MODEL_URI);
+        // El modelo nuevo puede traer sus propias plantillas
+        prompts::notify_list_changed();
+        let n = crate::ai_stub::serialize_status_ok(&path, &mut buf);
+        written(&buf, n)
     }
 
-    fn handle_logs(_input: &[u8]) -> Option<Vec<u8>> {
+    /// Texto del buffer de logs que la sesión aún no había leído, como cadena JSON.
+    fn handle_logs(ctx: &mut ToolContext<'_>, _input: &[u8]) -> Result<Vec<u8>, RpcError> {
         let mut buf = [0u8; 1024];
-        let n = logging::log_read(&mut buf);
-        Some(buf[..n].to_vec())
+        let n = ctx.session.read_logs(&mut buf);
+        let mut w = crate::json::Writer::new(Vec::new());
+        w.string(&alloc::string::String::from_utf8_lossy(&buf[..n]));
+        Ok(w.into_inner())
+    }
+
+    /// Eventos de la traza de drivers desde la última llamada, como array JSON.
+    fn handle_events(_ctx: &mut ToolContext<'_>, _input: &[u8]) -> Result<Vec<u8>, RpcError> {
+        use logging::events::{self, Event};
+        let mut batch = [Event::EMPTY; 32];
+        let n = events::read(&mut batch);
+        let mut w = crate::json::Writer::new(Vec::new());
+        w.begin_array();
+        for e in &batch[..n] {
+            w.begin_object()
+                .key("seq").u64(e.seq)
+                .key("tsc").u64(e.tsc)
+                .key("category").string(e.category.as_str())
+                .key("kind").string(e.kind.as_str())
+                .key("device_type").u64(e.device_type as u64)
+                .key("device").u64(e.device as u64)
+                .key("queue").u64(e.queue as u64)
+                .key("desc").u64(e.desc as u64)
+                .key("op").u64(e.op as u64)
+                .key("bytes").u64(e.bytes as u64)
+                .key("latency").u64(e.latency)
+                .key("error").i64(e.error as i64)
+                .end_object();
+        }
+        w.end_array();
+        Ok(w.into_inner())
+    }
+
+    /// Categorías activas de la traza, como `{"categories": "blk,fs"}`. Si `params`
+    /// trae `categories`, esa lista pasa a ser el conjunto activo; si no, solo consulta.
+    fn handle_trace(_ctx: &mut ToolContext<'_>, input: &[u8]) -> Result<Vec<u8>, RpcError> {
+        use logging::events::{self, Category};
+        let invalid = || RpcError::with_message(ErrorCode::InvalidParams, "expected {\"categories\": \"blk,fs,...\"}");
+        let params = crate::json::parse(input).map_err(|_| invalid())?;
+        if let Some(list) = params.get("categories") {
+            let list = list.as_str().ok_or_else(invalid)?;
+            let mut mask = 0;
+            for name in list.split(',').map(str::trim).filter(|n| !n.is_empty()) {
+                mask |= Category::from_name(name)
+                    .ok_or_else(|| RpcError::with_message(ErrorCode::InvalidParams, alloc::format!("unknown category: {}", name)))?
+                    .bit();
+            }
+            events::set_mask(mask);
+        }
+        let mut w = crate::json::Writer::new(Vec::new());
+        w.begin_object().key("categories").string(&trace_categories()).end_object();
+        Ok(w.into_inner())
     }
 
-    pub fn dispatch(tool: &str, input: &[u8]) -> Option<Vec<u8>> {
-        for t in TOOLS {
-            if t.name == tool {
-                return (t.handler)(input);
+    /// Categorías activas de la traza separadas por comas.
+    fn trace_categories() -> String {
+        use logging::events::{self, Category};
+        let mut out = String::new();
+        for category in Category::ALL.into_iter().filter(|c| events::enabled(*c)) {
+            if !out.is_empty() {
+                out.push(',');
             }
+            out.push_str(category.as_str());
         }
-        None
+        out
     }
 
-    pub fn mcp_server_loop() {
-        use mcp_vsock_transport::vsock_transport::{read_frame, write_frame};
-        let mut buf = [0u8; 4096];
-        logging::log_write("[mcp] MCP server loop iniciado");
+    fn read_model(uri: &str, _vars: &[(&str, &str)]) -> Result<Vec<ResourceContents>, RpcError> {
+        let model = unsafe { (*core::ptr::addr_of!(ai_runtime::MODEL)).as_ref() };
+        let mut w = crate::json::Writer::new(Vec::new());
+        w.begin_object().key("loaded").bool(model.is_some());
+        if let Some(model) = model {
+            w.key("size").u64(model.size as u64).key("zero_copy").bool(model.zero_copy);
+        }
+        w.key("resident_bytes").u64(ai_runtime::resident_bytes() as u64).end_object();
+        let text = String::from_utf8(w.into_inner()).unwrap_or_default();
+        Ok(alloc::vec![ResourceContents::text(uri, Some("application/json"), text)])
+    }
+
+    /// Todo lo que sigue en el buffer de logs, sin mover el cursor de `logs`.
+    fn read_log(uri: &str, _vars: &[(&str, &str)]) -> Result<Vec<ResourceContents>, RpcError> {
+        let mut text = Vec::new();
+        let mut chunk = [0u8; 512];
+        let mut cursor = 0;
         loop {
-            let mut log_json_invalid = false;
-            if let Some(frame) = read_frame(&mut buf) {
-                if let Some((method, params)) = crate::ai_stub::parse_json_rpc(frame) {
-                    if !["infer", "health", "metadata", "load_model"].contains(&method) {
-                        logging::log_write("[mcp] Método desconocido");
-                        continue;
-                    }
-                    if let Some(resp) = crate::mcp_server::dispatch(method, params) {
-                        let _ = write_frame(&resp);
-                    }
-                } else {
-                    log_json_invalid = true;
+            let (n, next) = logging::log_read_from(cursor, &mut chunk);
+            if n == 0 {
+                break;
+            }
+            text.extend_from_slice(&chunk[..n]);
+            cursor = next;
+        }
+        let text = String::from_utf8_lossy(&text).into_owned();
+        Ok(alloc::vec![ResourceContents::text(uri, Some("text/plain"), text)])
+    }
+
+    fn read_config(uri: &str, _vars: &[(&str, &str)]) -> Result<Vec<ResourceContents>, RpcError> {
+        use crate::session::{SERVER_NAME, SERVER_VERSION, SUPPORTED_PROTOCOL_VERSIONS};
+        let mut w = crate::json::Writer::new(Vec::new());
+        w.begin_object()
+            .key("server")
+            .begin_object()
+            .key("name")
+            .string(SERVER_NAME)
+            .key("version")
+            .string(SERVER_VERSION)
+            .end_object()
+            .key("protocol_versions")
+            .begin_array();
+        for version in SUPPORTED_PROTOCOL_VERSIONS {
+            w.string(version);
+        }
+        w.end_array()
+            .key("transport")
+            .begin_object()
+            .key("type")
+            .string("vsock")
+            .key("port")
+            .u64(mcp_vsock_transport::vsock_transport::MCP_VSOCK_PORT as u64)
+            .end_object()
+            .key("arch")
+            .string("x86_64")
+            .key("mounts")
+            .begin_array();
+        vfs::for_each_mount(|prefix, fs| {
+            w.begin_object().key("prefix").string(prefix).key("fs").string(fs).end_object();
+        });
+        w.end_array().key("trace").string(&trace_categories()).end_object();
+        let text = String::from_utf8(w.into_inner()).unwrap_or_default();
+        Ok(alloc::vec![ResourceContents::text(uri, Some("application/json"), text)])
+    }
+
+    fn vfs_error(uri: &str, e: vfs::VfsError) -> RpcError {
+        use vfs::VfsError;
+        match e {
+            VfsError::NotFound | VfsError::NotMounted | VfsError::InvalidPath => {
+                RpcError::with_message(ErrorCode::ResourceNotFound, alloc::format!("resource not found: {}", uri))
+            }
+            e => RpcError::with_message(ErrorCode::InternalError, e.as_str()),
+        }
+    }
+
+    /// `file:///{+path}`: un directorio se lee como lista de entradas (una por línea,
+    /// `/` al final de los subdirectorios); un fichero, como texto si es UTF-8 o como
+    /// binario si no.
+    fn read_file(uri: &str, vars: &[(&str, &str)]) -> Result<Vec<ResourceContents>, RpcError> {
+        let mut path = String::from("/");
+        path.push_str(resources::variable(vars, "path").unwrap_or_default());
+        let meta = vfs::stat(&path).map_err(|e| vfs_error(uri, e))?;
+        if meta.kind == vfs::FileKind::Dir {
+            let mut listing = String::new();
+            vfs::list(&path, |entry| {
+                listing.push_str(entry.name);
+                if entry.kind == vfs::FileKind::Dir {
+                    listing.push('/');
+                }
+                listing.push('\n');
+                true
+            })
+            .map_err(|e| vfs_error(uri, e))?;
+            return Ok(alloc::vec![ResourceContents::text(uri, Some("text/plain"), listing)]);
+        }
+        if meta.size > MAX_FILE_RESOURCE {
+            let message = alloc::format!("file too large for resources/read: {} bytes", meta.size);
+            return Err(RpcError::with_message(ErrorCode::InternalError, message));
+        }
+        let file = vfs::open(&path).map_err(|e| vfs_error(uri, e))?;
+        let mut data = alloc::vec![0u8; file.size() as usize];
+        let mut read = 0;
+        while read < data.len() {
+            match file.read_at(read as u64, &mut data[read..]) {
+                Ok(0) => break,
+                Ok(n) => read += n,
+                Err(e) => {
+                    file.close();
+                    return Err(vfs_error(uri, e));
+                }
+            }
+        }
+        file.close();
+        data.truncate(read);
+        Ok(alloc::vec![match String::from_utf8(data) {
+            Ok(text) if path.ends_with(".json") => ResourceContents::text(uri, Some("application/json"), text),
+            Ok(text) => ResourceContents::text(uri, Some("text/plain"), text),
+            Err(e) => ResourceContents::blob(uri, Some("application/octet-stream"), e.into_bytes()),
+        }])
+    }
+
+    /// Modelos (`*.bin`) del directorio de modelos, como rutas absolutas.
+    fn complete_model_path(_value: &str) -> Vec<String> {
+        let mut paths = Vec::new();
+        let _ = vfs::list(MODELS_DIR, |entry| {
+            if entry.kind == vfs::FileKind::File && entry.name.ends_with(".bin") {
+                paths.push(alloc::format!("{}/{}", MODELS_DIR, entry.name));
+            }
+            true
+        });
+        paths
+    }
+
+    /// Prompts que sabe responder el modelo cargado.
+    fn complete_model_prompt(_value: &str) -> Vec<String> {
+        let mut prompts = Vec::new();
+        ai_runtime::known_prompts(|prompt| prompts.push(String::from(prompt)));
+        prompts
+    }
+
+    /// Entradas del directorio que se está escribiendo en `file:///{+path}` (la ruta
+    /// va sin la `/` inicial); los subdirectorios llevan `/` al final.
+    fn complete_file_path(value: &str) -> Vec<String> {
+        let dir = value.rfind('/').map_or("", |i| &value[..=i]);
+        let mut entries = Vec::new();
+        let _ = vfs::list(&alloc::format!("/{}", dir.trim_end_matches('/')), |entry| {
+            let suffix = if entry.kind == vfs::FileKind::Dir { "/" } else { "" };
+            entries.push(alloc::format!("{}{}{}", dir, entry.name, suffix));
+            true
+        });
+        entries
+    }
+
+    fn handle_request(session: &mut Session, req: &jsonrpc::Request<'_>, cancel: CancellationToken) -> Result<Vec<u8>, RpcError> {
+        let params = req.params.unwrap_or(b"{}");
+        match &*req.method {
+            "initialize" if req.batched => {
+                Err(RpcError::with_message(ErrorCode::InvalidRequest, "initialize must not be batched"))
+            }
+            "initialize" => {
+                let result = session.initialize(params, &CAPABILITIES)?;
+                // El cliente pide las listas después de iniciar: los cambios previos no cuentan
+                tools::take_list_changed();
+                resources::take_list_changed();
+                prompts::take_list_changed();
+                logging::log!(Level::Info, "mcp", "sesión iniciada por {}", session.client_name().unwrap_or("?"));
+                Ok(result)
+            }
+            "notifications/initialized" => {
+                session.initialized();
+                Ok(b"{}".to_vec())
+            }
+            "notifications/cancelled" => {
+                cancel_request(session, params);
+                Ok(b"{}".to_vec())
+            }
+            "ping" => Ok(b"{}".to_vec()),
+            method => {
+                session.check_ready()?;
+                match method {
+                    // `ToolAnnotations` no existen en 2024-11-05
+                    "tools/list" => tools::list(params, tools::PAGE_SIZE, session.protocol_version() != "2024-11-05"),
+                    "tools/call" => tools::call(session, req.id.as_ref(), params, send, cancel, poll_incoming),
+                    "resources/list" => resources::list(params, resources::PAGE_SIZE),
+                    "resources/templates/list" => resources::list_templates(params, resources::PAGE_SIZE),
+                    "resources/read" => resources::read(params),
+                    "resources/subscribe" => resources::subscribe(session, params),
+                    "resources/unsubscribe" => resources::unsubscribe(session, params),
+                    "prompts/list" => prompts::list(&prompts::active(), params, prompts::PAGE_SIZE),
+                    "prompts/get" => prompts::get(&prompts::active(), params),
+                    "logging/setLevel" => set_level(session, params),
+                    "completion/complete" => completion::complete(params, &prompts::active()),
+                    _ => Err(RpcError::with_message(ErrorCode::MethodNotFound, alloc::format!("method not found: {}", method))),
                 }
             }
-            if log_json_invalid {
-                logging::log_write("[mcp] JSON-RPC inválido");
+        }
+    }
+
+    /// Procesa una trama JSON-RPC (mensaje o lote) de la sesión y devuelve la
+    /// respuesta, si la hay. Las peticiones canceladas mientras corrían no se contestan.
+    pub fn handle_frame(session: &mut Session, frame: &[u8]) -> Option<Vec<u8>> {
+        jsonrpc::handle_frame(frame, |req| {
+            let id = req.id.clone().map(jsonrpc::Id::into_owned);
+            let cancel = match &id {
+                Some(id) => session.begin_request(id.clone()),
+                None => CancellationToken::new(),
+            };
+            let result = handle_request(session, req, cancel);
+            if id.is_some_and(|id| session.finish_request(&id)) {
+                logging::metrics::MCP_CANCELLED_REQUESTS.inc();
+                return Err(RpcError::new(ErrorCode::RequestCancelled));
+            }
+            if let Err(e) = &result {
+                logging::log!(Level::Warning, "mcp", "error JSON-RPC: {}", e.message);
+            }
+            result
+        })
+    }
+
+    /// Sesión del servidor y conexión (`generation`) a la que pertenece.
+    static mut SERVER: Option<(Session, u32)> = None;
+
+    /// Un paso del servidor MCP: atiende una trama, si hay alguna, y envía los avisos
+    /// pendientes. No bloquea: el scheduler del kernel lo llama en cada ronda.
+    pub fn poll() {
+        use mcp_vsock_transport::vsock_transport::generation;
+        let server = unsafe { &mut *core::ptr::addr_of_mut!(SERVER) };
+        let (session, connection) = server.get_or_insert_with(|| {
+            logging::log(Level::Info, "mcp", "MCP server loop iniciado");
+            (Session::new(), generation())
+        });
+        // Primero lo que llegó mientras corría un manejador
+        let frame = if deferred().is_empty() { receive() } else { Some(deferred().remove(0)) };
+        if let Some(frame) = frame {
+            // Conexión nueva: la sesión anterior no sirve
+            if generation() != *connection {
+                *connection = generation();
+                *session = Session::new();
+            }
+            if let Some(resp) = handle_frame(session, &frame) {
+                if !send(&resp) {
+                    logging::log(Level::Error, "mcp", "no se pudo enviar la respuesta");
+                }
             }
         }
+        flush_notifications(session);
     }
 }
 
 pub mod ai_stub {
-    use logging::log_write;
+    use crate::json::{self, SliceOutput, Writer};
+    use alloc::borrow::Cow;
+    use logging::Level;
 
     #[derive(Debug)]
     pub struct InferRequest<'a> {
-        pub prompt: &'a str,
+        pub prompt: Cow<'a, str>,
         pub params: Option<InferParams>,
+        /// El cliente quiere los tokens según se generan.
+        pub stream: bool,
     }
 
     #[derive(Debug)]
@@ -164,41 +784,574 @@ pub mod ai_stub {
         pub build: &'a str,
     }
 
-    pub fn parse_infer_req(_json_bytes: &[u8]) -> Option<InferRequest<'_>> {
-        None // TODO: implementar
+    /// Serializa con `f` en `buf`. Devuelve los bytes escritos, 0 si no caben.
+    fn write_into(buf: &mut [u8], f: impl FnOnce(&mut Writer<SliceOutput<'_>>)) -> usize {
+        let mut w = Writer::new(SliceOutput::new(buf));
+        f(&mut w);
+        w.into_inner().finish().unwrap_or(0)
     }
 
-    pub fn serialize_infer_response(_resp: &InferResponse, _buf: &mut [u8]) -> usize {
-        0 // TODO: implementar
+    /// `{"prompt": "...", "max_tokens": n, "temperature": t, "stream": b}`; todos menos
+    /// `prompt` son opcionales.
+    pub fn parse_infer_req(json_bytes: &[u8]) -> Option<InferRequest<'_>> {
+        let value = json::parse(json_bytes).ok()?;
+        let prompt = value.get("prompt")?.as_str()?;
+        let max_tokens = value.get("max_tokens").and_then(|v| v.as_u64()).map(|n| n.min(u32::MAX as u64) as u32);
+        let temperature = value.get("temperature").and_then(|v| v.as_f64()).map(|t| t as f32);
+        let params = (max_tokens.is_some() || temperature.is_some()).then_some(InferParams { max_tokens, temperature });
+        let stream = value.get("stream").and_then(|v| v.as_bool()).unwrap_or(false);
+        Some(InferRequest { prompt, params, stream })
     }
 
-    pub fn serialize_health_response(_resp: &HealthResponse, _buf: &mut [u8]) -> usize {
-        0 // TODO: implementar
+    pub fn serialize_infer_response(resp: &InferResponse, buf: &mut [u8]) -> usize {
+        write_into(buf, |w| {
+            w.begin_object()
+                .key("text")
+                .string(resp.text)
+                .key("tokens")
+                .u64(resp.tokens as u64)
+                .key("latency_ms")
+                .u64(resp.latency_ms as u64)
+                .end_object();
+        })
     }
 
-    pub fn serialize_metadata_response(_resp: &MetadataResponse, _buf: &mut [u8]) -> usize {
-        0 // TODO: implementar
+    pub fn serialize_health_response(resp: &HealthResponse, buf: &mut [u8]) -> usize {
+        write_into(buf, |w| {
+            w.begin_object().key("status").string(resp.status).key("details").string(resp.details).end_object();
+        })
     }
 
-    pub fn parse_path_field(_json_bytes: &[u8]) -> Option<&str> {
-        None // TODO: implementar
+    pub fn serialize_metadata_response(resp: &MetadataResponse, buf: &mut [u8]) -> usize {
+        write_into(buf, |w| {
+            w.begin_object()
+                .key("model_name")
+                .string(resp.model_name)
+                .key("quantization")
+                .string(resp.quantization)
+                .key("arch")
+                .string(resp.arch)
+                .key("features")
+                .begin_array();
+            for feature in resp.features {
+                w.string(feature);
+            }
+            w.end_array().key("build").string(resp.build).end_object();
+        })
     }
 
-    pub fn serialize_status_ok(_path: &str, _buf: &[u8]) -> usize {
-        0 // TODO: implementar
+    /// Campo `path` de `{"path": "..."}`.
+    pub fn parse_path_field(json_bytes: &[u8]) -> Option<Cow<'_, str>> {
+        json::parse(json_bytes).ok()?.get("path")?.as_str()
     }
 
-    pub fn serialize_status_error(_err: &str, _buf: &[u8]) -> usize {
-        0 // TODO: implementar
+    pub fn serialize_status_ok(path: &str, buf: &mut [u8]) -> usize {
+        write_into(buf, |w| {
+            w.begin_object().key("status").string("ok").key("path").string(path).end_object();
+        })
     }
 
-    pub fn parse_json_rpc(_frame: &[u8]) -> Option<(&str, &[u8])> {
-        None // TODO: implementar
+    pub fn serialize_status_error(err: &str, buf: &mut [u8]) -> usize {
+        write_into(buf, |w| {
+            w.begin_object().key("status").string("error").key("error").string(err).end_object();
+        })
+    }
+
+    /// Método y `params` (texto JSON sin tocar, `{}` si no vienen) de una petición.
+    pub fn parse_json_rpc(frame: &[u8]) -> Option<(&str, &[u8])> {
+        let fields = json::raw_fields(frame).ok()?;
+        let raw = |key: &str| fields.iter().find(|(k, _)| k.eq_str(key)).map(|(_, v)| *v);
+        let method = json::parse(raw("method")?).ok()?.as_string()?.as_borrowed()?;
+        let params = raw("params").unwrap_or(b"{}");
+        Some((method, params))
     }
 
     pub fn infer(prompt: &str) -> &'static str {
-        log_write("[ai] Recibido prompt: ");
-        log_write(prompt);
+        logging::log!(Level::Debug, "ai", "Recibido prompt: {}", prompt);
         "[ai] Respuesta de ejemplo"
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::ai_stub::*;
+
+    #[test]
+    fn parse_json_rpc_splits_method_and_params() {
+        let frame = br#"{"method":"infer","params":{"prompt":"Hola\nAI","max_tokens":16}}"#;
+        let (method, params) = parse_json_rpc(frame).unwrap();
+        assert_eq!(method, "infer");
+        let req = parse_infer_req(params).unwrap();
+        assert_eq!(req.prompt, "Hola\nAI");
+        assert_eq!(req.params.unwrap().max_tokens, Some(16));
+        assert_eq!(parse_json_rpc(br#"{"method":"health"}"#), Some(("health", &b"{}"[..])));
+        assert_eq!(parse_json_rpc(br#"{"params":{}}"#), None);
+        assert_eq!(parse_json_rpc(b"{\"method\":"), None);
+    }
+
+    #[test]
+    fn serializers_write_json() {
+        let mut buf = [0u8; 128];
+        let n = serialize_metadata_response(
+            &MetadataResponse { model_name: "m", quantization: "q4", arch: "x86_64", features: &["SSE2", "AVX"], build: "dev" },
+            &mut buf,
+        );
+        assert_eq!(
+            &buf[..n],
+            br#"{"model_name":"m","quantization":"q4","arch":"x86_64","features":["SSE2","AVX"],"build":"dev"}"#
+        );
+        let n = serialize_status_error("no \"such\" file", &mut buf);
+        assert_eq!(&buf[..n], br#"{"status":"error","error":"no \"such\" file"}"#);
+        assert_eq!(parse_path_field(br#"{"path":"/models/a.bin"}"#).as_deref(), Some("/models/a.bin"));
+        // Sin espacio no se escribe nada
+        let n = serialize_health_response(&HealthResponse { status: "ok", details: "modelo cargado" }, &mut buf[..8]);
+        assert_eq!(n, 0);
+    }
+
+    /// HAL de las pruebas: solo `ai_runtime` lo usa, para los frames de los modelos
+    /// copiados, que salen del heap del host y no se devuelven. No hay dispositivos.
+    struct TestHal;
+
+    impl drivers_virtio::hal::Hal for TestHal {
+        fn dma_alloc(&self, _size: usize, _align: usize) -> *mut u8 {
+            core::ptr::null_mut()
+        }
+
+        fn alloc_frames(&self, size: usize) -> *mut u8 {
+            match std::alloc::Layout::from_size_align(size, 2 * 1024 * 1024) {
+                Ok(layout) => unsafe { std::alloc::alloc(layout) },
+                Err(_) => core::ptr::null_mut(),
+            }
+        }
+
+        fn alloc_frame(&self) -> Option<usize> {
+            None
+        }
+
+        fn free_frame(&self, _addr: usize) {}
+
+        fn take_unreported_free_frame(&self) -> Option<usize> {
+            None
+        }
+
+        fn release_reported_frame(&self, _addr: usize) {}
+
+        fn virt_to_phys(&self, virt: usize) -> u64 {
+            virt as u64
+        }
+
+        fn map_mmio(&self, _phys: u64, _size: usize) -> *mut u8 {
+            core::ptr::null_mut()
+        }
+
+        fn map_shared_readonly(&self, _phys: u64, _size: usize) -> *const u8 {
+            core::ptr::null()
+        }
+
+        fn unmap_shared(&self, _virt: usize, _size: usize) {}
+    }
+
+    /// Registro con las herramientas del kernel y una sesión ya operativa. El cerrojo
+    /// serializa las pruebas que usan el registro global.
+    fn ready_session() -> (std::sync::MutexGuard<'static, ()>, crate::session::Session) {
+        use crate::mcp_server::handle_frame;
+        let guard = crate::resources::reset();
+        drivers_virtio::hal::set_hal(&TestHal);
+        crate::mcp_server::init();
+        let mut session = crate::session::Session::new();
+        let init = br#"{"jsonrpc":"2.0","id":0,"method":"initialize","params":{"protocolVersion":"2025-03-26","capabilities":{},"clientInfo":{"name":"t","version":"1"}}}"#;
+        assert!(handle_frame(&mut session, init).is_some());
+        assert_eq!(handle_frame(&mut session, br#"{"jsonrpc":"2.0","method":"notifications/initialized"}"#), None);
+        (guard, session)
+    }
+
+    #[test]
+    fn server_answers_every_request() {
+        use crate::mcp_server::handle_frame;
+        let (_guard, mut session) = ready_session();
+        let session = &mut session;
+        let resp = handle_frame(session, br#"{"jsonrpc":"2.0","id":"h","method":"tools/call","params":{"name":"health"}}"#).unwrap();
+        let resp = String::from_utf8(resp).unwrap();
+        assert!(resp.starts_with(r#"{"jsonrpc":"2.0","id":"h","result":{"content":[{"type":"text","text":"{\"status\":"#));
+        assert!(resp.contains(r#""structuredContent":{"status":"#) && resp.ends_with(r#""isError":false}}"#));
+        let resp = handle_frame(session, br#"{"jsonrpc":"2.0","id":2,"method":"health"}"#).unwrap();
+        assert_eq!(
+            resp,
+            br#"{"jsonrpc":"2.0","id":2,"error":{"code":-32601,"message":"method not found: health"}}"#
+        );
+        let call = br#"{"jsonrpc":"2.0","id":3,"method":"tools/call","params":{"name":"trace","arguments":{"categories":"blk,nope"}}}"#;
+        let resp = handle_frame(session, call).unwrap();
+        assert!(String::from_utf8(resp).unwrap().contains(r#""code":-32602,"message":"unknown category: nope""#));
+        let call = br#"{"jsonrpc":"2.0","id":4,"method":"tools/call","params":{"name":"infer","arguments":{}}}"#;
+        let resp = handle_frame(session, call).unwrap();
+        assert!(String::from_utf8(resp).unwrap().contains(r#""code":-32602,"message":"missing argument: prompt""#));
+        let call = br#"{"jsonrpc":"2.0","id":5,"method":"tools/call","params":{"name":"load_model","arguments":{"path":"/nope.bin"}}}"#;
+        let resp = String::from_utf8(handle_frame(session, call).unwrap()).unwrap();
+        assert!(resp.ends_with(r#""isError":true}}"#));
+        assert_eq!(handle_frame(session, br#"{"jsonrpc":"2.0","method":"tools/list"}"#), None);
+    }
+
+    #[test]
+    fn tools_list_describes_every_tool() {
+        use crate::mcp_server::handle_frame;
+        let (_guard, mut session) = ready_session();
+        let resp = handle_frame(&mut session, br#"{"jsonrpc":"2.0","id":1,"method":"tools/list"}"#).unwrap();
+        let resp = crate::json::parse(&resp).unwrap();
+        let tools = resp.get("result").unwrap().get("tools").unwrap().as_array().unwrap();
+        let names: Vec<_> = tools.iter().map(|t| t.get("name").unwrap().as_str().unwrap().into_owned()).collect();
+        assert_eq!(names, ["infer", "health", "metadata", "load_model", "logs", "events", "trace"]);
+        for tool in tools {
+            assert_eq!(tool.get("inputSchema").unwrap().get("type").unwrap().as_str().as_deref(), Some("object"));
+            assert!(tool.get("annotations").is_some());
+        }
+    }
+
+    #[test]
+    fn server_enforces_lifecycle() {
+        use crate::mcp_server::handle_frame;
+        let mut session = crate::session::Session::new();
+        let resp = handle_frame(&mut session, br#"{"jsonrpc":"2.0","id":1,"method":"health"}"#).unwrap();
+        assert_eq!(
+            resp,
+            br#"{"jsonrpc":"2.0","id":1,"error":{"code":-32600,"message":"server not initialized"}}"#
+        );
+        assert_eq!(
+            handle_frame(&mut session, br#"{"jsonrpc":"2.0","id":2,"method":"ping"}"#).unwrap(),
+            br#"{"jsonrpc":"2.0","id":2,"result":{}}"#
+        );
+        let batch = br#"[{"jsonrpc":"2.0","id":3,"method":"initialize","params":{"protocolVersion":"2025-03-26","capabilities":{},"clientInfo":{"name":"t","version":"1"}}}]"#;
+        assert!(String::from_utf8(handle_frame(&mut session, batch).unwrap()).unwrap().contains("must not be batched"));
+        let (_guard, mut session) = ready_session();
+        let resp = handle_frame(&mut session, br#"{"jsonrpc":"2.0","id":4,"method":"initialize","params":{}}"#).unwrap();
+        assert!(String::from_utf8(resp).unwrap().contains("already initialized"));
+    }
+
+    fn app_tool(_ctx: &mut crate::tools::ToolContext<'_>, _args: &[u8]) -> Result<Vec<u8>, crate::jsonrpc::RpcError> {
+        Ok(br#""desde la app""#.to_vec())
+    }
+
+    #[test]
+    fn registered_tools_announce_list_changes() {
+        use crate::mcp_server::{flush_notifications, handle_frame, register_tool, unregister_tool, ToolAnnotations, ToolDef, SENT};
+        let (_guard, mut session) = ready_session();
+        SENT.lock().unwrap().clear();
+        // Las herramientas del kernel registradas antes de `initialize` no generan aviso
+        flush_notifications(&mut session);
+        assert!(SENT.lock().unwrap().is_empty());
+        let def = ToolDef {
+            name: "app.status",
+            description: "Estado de una aplicación del guest",
+            input_schema: r#"{"type":"object"}"#,
+            output_schema: None,
+            annotations: ToolAnnotations::READ_ONLY,
+        };
+        register_tool(def, app_tool).unwrap();
+        flush_notifications(&mut session);
+        assert_eq!(
+            SENT.lock().unwrap().as_slice(),
+            [br#"{"jsonrpc":"2.0","method":"notifications/tools/list_changed"}"#.to_vec()]
+        );
+        let call = br#"{"jsonrpc":"2.0","id":1,"method":"tools/call","params":{"name":"app.status"}}"#;
+        let resp = String::from_utf8(handle_frame(&mut session, call).unwrap()).unwrap();
+        assert!(resp.contains(r#""text":"desde la app""#));
+        assert!(unregister_tool("app.status"));
+        let resp = String::from_utf8(handle_frame(&mut session, call).unwrap()).unwrap();
+        assert!(resp.contains("unknown tool: app.status"));
+    }
+
+    /// Sistema de ficheros en memoria para los recursos `file:///`.
+    struct MemFs;
+
+    const MEM_FILES: &[(&str, &[u8])] = &[
+        // Diccionario [len][prompt][len][respuesta]
+        ("/models/dict.bin", b"\x04hola\x14Hola desde el modelo"),
+        ("/models/empty.bin", b""),
+        ("/models/empty.prompts.json", br#"{"prompts":[{"name":"chat","description":"Chat del modelo de prueba","arguments":[{"name":"message","required":true}],"messages":[{"role":"user","text":"[INST] {{message}} [/INST]"}]}]}"#),
+        ("/models/notes.txt", b"hola"),
+        ("/models/tiny.bin", &[0, 159, 146, 150]),
+    ];
+
+    impl vfs::FileSystem for MemFs {
+        fn name(&self) -> &'static str {
+            "memfs"
+        }
+
+        fn open(&mut self, path: &str) -> Result<vfs::FileHandle, vfs::VfsError> {
+            let id = MEM_FILES.iter().position(|(p, _)| *p == path).ok_or(vfs::VfsError::NotFound)?;
+            Ok(vfs::FileHandle { id: id as u64, aux: 0, size: MEM_FILES[id].1.len() as u64 })
+        }
+
+        fn read(&mut self, handle: &vfs::FileHandle, offset: u64, buf: &mut [u8]) -> Result<usize, vfs::VfsError> {
+            let data = &MEM_FILES[handle.id as usize].1[offset as usize..];
+            let n = data.len().min(buf.len());
+            buf[..n].copy_from_slice(&data[..n]);
+            Ok(n)
+        }
+
+        fn close(&mut self, _handle: vfs::FileHandle) {}
+
+        fn stat(&mut self, path: &str) -> Result<vfs::Metadata, vfs::VfsError> {
+            let (size, kind) = match MEM_FILES.iter().find(|(p, _)| *p == path) {
+                Some((_, data)) => (data.len() as u64, vfs::FileKind::File),
+                None if path == "/models" => (0, vfs::FileKind::Dir),
+                None => return Err(vfs::VfsError::NotFound),
+            };
+            Ok(vfs::Metadata { size, kind, mode: 0o444, mtime: 0 })
+        }
+
+        fn list(&mut self, path: &str, f: &mut dyn FnMut(&vfs::DirEntry) -> bool) -> Result<usize, vfs::VfsError> {
+            let mut n = 0;
+            for (name, _) in MEM_FILES.iter().filter_map(|(p, d)| Some((p.strip_prefix(path)?.strip_prefix('/')?, d))) {
+                n += 1;
+                if !f(&vfs::DirEntry { name, kind: vfs::FileKind::File }) {
+                    break;
+                }
+            }
+            Ok(n)
+        }
+    }
+
+    /// `resources/read` de `uri`: `text` o `blob` del primer contenido, o el código de error.
+    fn read_resource(session: &mut crate::session::Session, uri: &str) -> Result<String, i64> {
+        let frame = format!(r#"{{"jsonrpc":"2.0","id":1,"method":"resources/read","params":{{"uri":"{}"}}}}"#, uri);
+        let resp = crate::mcp_server::handle_frame(session, frame.as_bytes()).unwrap();
+        let resp = crate::json::parse(&resp).unwrap();
+        if let Some(error) = resp.get("error") {
+            return Err(error.get("code").unwrap().as_i64().unwrap());
+        }
+        let item = &resp.get("result").unwrap().get("contents").unwrap().as_array().unwrap()[0];
+        Ok(item.get("text").or_else(|| item.get("blob")).unwrap().as_str().unwrap().into_owned())
+    }
+
+    #[test]
+    fn resources_expose_kernel_state() {
+        use crate::mcp_server::handle_frame;
+        let (_guard, mut session) = ready_session();
+        let resp = handle_frame(&mut session, br#"{"jsonrpc":"2.0","id":1,"method":"resources/list"}"#).unwrap();
+        let resp = crate::json::parse(&resp).unwrap();
+        let resources = resp.get("result").unwrap().get("resources").unwrap().as_array().unwrap();
+        let uris: Vec<_> = resources.iter().map(|r| r.get("uri").unwrap().as_str().unwrap().into_owned()).collect();
+        assert_eq!(uris, ["model://current", "log://kernel", "config://kernel"]);
+        let resp = handle_frame(&mut session, br#"{"jsonrpc":"2.0","id":2,"method":"resources/templates/list"}"#).unwrap();
+        assert!(String::from_utf8(resp).unwrap().contains(r#""uriTemplate":"file:///{+path}""#));
+
+        assert_eq!(read_resource(&mut session, "model://current").unwrap(), r#"{"loaded":false,"resident_bytes":0}"#);
+        logging::log_write("[test] linea de log");
+        assert!(read_resource(&mut session, "log://kernel").unwrap().contains("[test] linea de log"));
+        let config = read_resource(&mut session, "config://kernel").unwrap();
+        assert!(config.starts_with(r#"{"server":{"name":"unikernel-ai","#) && config.contains(r#""port":5000"#));
+
+        vfs::mount("/", Box::leak(Box::new(MemFs))).unwrap();
+        assert_eq!(read_resource(&mut session, "file:///models").unwrap(), "dict.bin\nempty.bin\nempty.prompts.json\nnotes.txt\ntiny.bin\n");
+        assert_eq!(read_resource(&mut session, "file:///models/notes.txt").unwrap(), "hola");
+        // No es UTF-8: se envía en base64
+        assert_eq!(read_resource(&mut session, "file:///models/tiny.bin").unwrap(), "AJ+Slg==");
+        assert_eq!(read_resource(&mut session, "file:///models/nope.bin"), Err(-32002));
+        vfs::unmount("/");
+    }
+
+    #[test]
+    fn subscribed_resources_announce_updates() {
+        use crate::mcp_server::{flush_notifications, handle_frame, notify_updated, SENT};
+        let (_guard, mut session) = ready_session();
+        SENT.lock().unwrap().clear();
+        let subscribe = br#"{"jsonrpc":"2.0","id":1,"method":"resources/subscribe","params":{"uri":"log://kernel"}}"#;
+        assert_eq!(handle_frame(&mut session, subscribe).unwrap(), br#"{"jsonrpc":"2.0","id":1,"result":{}}"#);
+        let unknown = br#"{"jsonrpc":"2.0","id":2,"method":"resources/subscribe","params":{"uri":"log://nope"}}"#;
+        assert!(String::from_utf8(handle_frame(&mut session, unknown).unwrap()).unwrap().contains(r#""code":-32002"#));
+        flush_notifications(&mut session);
+        SENT.lock().unwrap().clear();
+
+        logging::log_write("[test] crece el log");
+        notify_updated("model://current");
+        flush_notifications(&mut session);
+        assert_eq!(
+            SENT.lock().unwrap().as_slice(),
+            [br#"{"jsonrpc":"2.0","method":"notifications/resources/updated","params":{"uri":"log://kernel"}}"#.to_vec()]
+        );
+        let unsubscribe = br#"{"jsonrpc":"2.0","id":3,"method":"resources/unsubscribe","params":{"uri":"log://kernel"}}"#;
+        handle_frame(&mut session, unsubscribe).unwrap();
+        logging::log_write("[test] otra linea");
+        flush_notifications(&mut session);
+        assert_eq!(SENT.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn log_records_reach_the_client_as_messages() {
+        use crate::mcp_server::{flush_notifications, handle_frame, SENT};
+        use logging::Level;
+        let (_guard, mut session) = ready_session();
+        let set_level = |session: &mut crate::session::Session, level: &str| {
+            let frame = format!(r#"{{"jsonrpc":"2.0","id":1,"method":"logging/setLevel","params":{{"level":"{}"}}}}"#, level);
+            String::from_utf8(handle_frame(session, frame.as_bytes()).unwrap()).unwrap()
+        };
+        assert!(set_level(&mut session, "verbose").contains(r#""code":-32602,"message":"unknown level: verbose""#));
+        assert_eq!(set_level(&mut session, "warning"), r#"{"jsonrpc":"2.0","id":1,"result":{}}"#);
+        flush_notifications(&mut session);
+        SENT.lock().unwrap().clear();
+
+        logging::log(Level::Info, "test", "descartado");
+        logging::log(Level::Error, "test", "disco lleno");
+        flush_notifications(&mut session);
+        // Otros tests también escriben en el log del kernel
+        let ours: Vec<_> =
+            SENT.lock().unwrap().iter().filter(|m| String::from_utf8_lossy(m).contains(r#""logger":"test""#)).cloned().collect();
+        assert_eq!(
+            ours,
+            [br#"{"jsonrpc":"2.0","method":"notifications/message","params":{"level":"error","logger":"test","data":"disco lleno"}}"#.to_vec()]
+        );
+    }
+
+    #[test]
+    fn poll_serves_one_frame_per_round() {
+        use crate::mcp_server::{poll, INCOMING, SENT};
+        let _guard = crate::resources::reset();
+        crate::mcp_server::init();
+        SENT.lock().unwrap().clear();
+        let init = br#"{"jsonrpc":"2.0","id":0,"method":"initialize","params":{"protocolVersion":"2025-03-26","capabilities":{},"clientInfo":{"name":"t","version":"1"}}}"#;
+        let initialized = br#"{"jsonrpc":"2.0","method":"notifications/initialized"}"#;
+        let ping = br#"{"jsonrpc":"2.0","id":1,"method":"ping"}"#;
+        INCOMING.lock().unwrap().extend([init.to_vec(), initialized.to_vec(), ping.to_vec()]);
+        poll();
+        // El resto espera a las rondas siguientes: las demás tareas siguen corriendo
+        assert_eq!(INCOMING.lock().unwrap().len(), 2);
+        assert!(String::from_utf8(SENT.lock().unwrap()[0].clone()).unwrap().starts_with(r#"{"jsonrpc":"2.0","id":0,"result":{"#));
+        poll();
+        poll();
+        assert!(INCOMING.lock().unwrap().is_empty());
+        assert!(SENT.lock().unwrap().contains(&br#"{"jsonrpc":"2.0","id":1,"result":{}}"#.to_vec()));
+        // Sin tramas no bloquea
+        poll();
+    }
+
+    #[test]
+    fn prompts_follow_the_loaded_model() {
+        use crate::mcp_server::{flush_notifications, handle_frame, SENT};
+        let (_guard, mut session) = ready_session();
+        let list = br#"{"jsonrpc":"2.0","id":1,"method":"prompts/list"}"#;
+        let resp = String::from_utf8(handle_frame(&mut session, list).unwrap()).unwrap();
+        assert!(resp.contains(r#""name":"summarize""#) && resp.contains(r#""description":"Conversación con el modelo cargado.""#));
+        let get = br#"{"jsonrpc":"2.0","id":2,"method":"prompts/get","params":{"name":"chat","arguments":{"message":"hola"}}}"#;
+        let resp = String::from_utf8(handle_frame(&mut session, get).unwrap()).unwrap();
+        assert!(resp.contains(r#""text":"hola""#));
+
+        vfs::mount("/", Box::leak(Box::new(MemFs))).unwrap();
+        SENT.lock().unwrap().clear();
+        let load = br#"{"jsonrpc":"2.0","id":3,"method":"tools/call","params":{"name":"load_model","arguments":{"path":"/models/empty.bin"}}}"#;
+        assert!(String::from_utf8(handle_frame(&mut session, load).unwrap()).unwrap().ends_with(r#""isError":false}}"#));
+        flush_notifications(&mut session);
+        assert!(SENT.lock().unwrap().contains(&br#"{"jsonrpc":"2.0","method":"notifications/prompts/list_changed"}"#.to_vec()));
+        let resp = String::from_utf8(handle_frame(&mut session, get).unwrap()).unwrap();
+        assert!(resp.contains(r#""description":"Chat del modelo de prueba""#) && resp.contains(r#""text":"[INST] hola [/INST]""#));
+        let missing = br#"{"jsonrpc":"2.0","id":4,"method":"prompts/get","params":{"name":"chat"}}"#;
+        assert!(String::from_utf8(handle_frame(&mut session, missing).unwrap()).unwrap().contains("missing argument: message"));
+        ai_runtime::unload_model();
+        vfs::unmount("/");
+    }
+
+    #[test]
+    fn completion_suggests_models_and_prompts() {
+        use crate::mcp_server::handle_frame;
+        let (_guard, mut session) = ready_session();
+        vfs::mount("/", Box::leak(Box::new(MemFs))).unwrap();
+        let mut complete = |reference: &str, argument: &str, value: &str| {
+            let frame = format!(
+                r#"{{"jsonrpc":"2.0","id":1,"method":"completion/complete","params":{{"ref":{},"argument":{{"name":"{}","value":"{}"}}}}}}"#,
+                reference, argument, value
+            );
+            String::from_utf8(handle_frame(&mut session, frame.as_bytes()).unwrap()).unwrap()
+        };
+        let load_model = r#"{"type":"ref/tool","name":"load_model"}"#;
+        assert!(complete(load_model, "path", "").contains(
+            r#""values":["/models/dict.bin","/models/empty.bin","/models/tiny.bin"],"total":3,"hasMore":false"#
+        ));
+        assert!(complete(load_model, "path", "/models/d").contains(r#""values":["/models/dict.bin"]"#));
+        let file = r#"{"type":"ref/resource","uri":"file:///{+path}"}"#;
+        assert!(complete(file, "path", "models/n").contains(r#""values":["models/notes.txt"]"#));
+        assert!(complete(r#"{"type":"ref/tool","name":"nope"}"#, "x", "").contains(r#""code":-32602,"message":"unknown tool: nope""#));
+
+        // Sin modelo no hay prompts que sugerir
+        let chat = r#"{"type":"ref/prompt","name":"chat"}"#;
+        assert!(complete(chat, "message", "").contains(r#""values":[]"#));
+        let load = br#"{"jsonrpc":"2.0","id":2,"method":"tools/call","params":{"name":"load_model","arguments":{"path":"/models/dict.bin"}}}"#;
+        assert!(String::from_utf8(handle_frame(&mut session, load).unwrap()).unwrap().ends_with(r#""isError":false}}"#));
+        let mut complete = |reference: &str, argument: &str, value: &str| {
+            let frame = format!(
+                r#"{{"jsonrpc":"2.0","id":3,"method":"completion/complete","params":{{"ref":{},"argument":{{"name":"{}","value":"{}"}}}}}}"#,
+                reference, argument, value
+            );
+            String::from_utf8(handle_frame(&mut session, frame.as_bytes()).unwrap()).unwrap()
+        };
+        assert!(complete(chat, "message", "h").contains(r#""values":["hola"]"#));
+        assert!(complete(r#"{"type":"ref/tool","name":"infer"}"#, "prompt", "").contains(r#""values":["hola"]"#));
+        ai_runtime::unload_model();
+        vfs::unmount("/");
+    }
+
+    #[test]
+    fn infer_streams_tokens_as_progress() {
+        use crate::mcp_server::{handle_frame, SENT};
+        let (_guard, mut session) = ready_session();
+        vfs::mount("/", Box::leak(Box::new(MemFs))).unwrap();
+        ai_runtime::load_model("/models/dict.bin").unwrap();
+        SENT.lock().unwrap().clear();
+        let call = br#"{"jsonrpc":"2.0","id":1,"method":"tools/call","params":{"name":"infer","arguments":{"prompt":"hola","stream":true},"_meta":{"progressToken":"t"}}}"#;
+        let resp = String::from_utf8(handle_frame(&mut session, call).unwrap()).unwrap();
+        assert!(resp.contains(r#""structuredContent":{"text":"Hola desde el modelo","tokens":4,"latency_ms":1}"#));
+        let sent: Vec<_> = SENT.lock().unwrap().drain(..).map(|m| String::from_utf8(m).unwrap()).collect();
+        assert_eq!(sent.len(), 4);
+        assert_eq!(
+            sent[0],
+            r#"{"jsonrpc":"2.0","method":"notifications/progress","params":{"progressToken":"t","progress":1,"message":"Hola "}}"#
+        );
+        assert!(sent[3].contains(r#""progress":4,"message":"modelo""#));
+
+        // max_tokens corta la generación y es el total del progreso
+        let call = br#"{"jsonrpc":"2.0","id":2,"method":"tools/call","params":{"name":"infer","arguments":{"prompt":"hola","max_tokens":2},"_meta":{"progressToken":7}}}"#;
+        let resp = String::from_utf8(handle_frame(&mut session, call).unwrap()).unwrap();
+        assert!(resp.contains(r#""structuredContent":{"text":"Hola desde ","tokens":2"#));
+        assert!(String::from_utf8(SENT.lock().unwrap().pop().unwrap()).unwrap().contains(r#""progressToken":7,"progress":2,"total":2"#));
+
+        // Sin progressToken: sin notificaciones, y `stream` es un error
+        SENT.lock().unwrap().clear();
+        let call = br#"{"jsonrpc":"2.0","id":3,"method":"tools/call","params":{"name":"infer","arguments":{"prompt":"hola"}}}"#;
+        assert!(String::from_utf8(handle_frame(&mut session, call).unwrap()).unwrap().contains("Hola desde el modelo"));
+        let call = br#"{"jsonrpc":"2.0","id":4,"method":"tools/call","params":{"name":"infer","arguments":{"prompt":"hola","stream":true}}}"#;
+        let resp = String::from_utf8(handle_frame(&mut session, call).unwrap()).unwrap();
+        assert!(resp.contains(r#""code":-32602,"message":"stream requires _meta.progressToken in tools/call params""#));
+        assert!(SENT.lock().unwrap().is_empty());
+        ai_runtime::unload_model();
+        vfs::unmount("/");
+    }
+
+    #[test]
+    fn cancelled_requests_stop_and_get_no_response() {
+        use crate::mcp_server::{deferred, handle_frame, INCOMING, SENT};
+        let (_guard, mut session) = ready_session();
+        vfs::mount("/", Box::leak(Box::new(MemFs))).unwrap();
+        ai_runtime::load_model("/models/dict.bin").unwrap();
+        SENT.lock().unwrap().clear();
+        deferred().clear();
+        let cancelled = logging::metrics::MCP_CANCELLED_REQUESTS.get();
+        // Llegan mientras se genera el primer token
+        INCOMING.lock().unwrap().extend([
+            br#"{"jsonrpc":"2.0","method":"notifications/cancelled","params":{"requestId":"lento","reason":"timeout"}}"#.to_vec(),
+            br#"{"jsonrpc":"2.0","id":2,"method":"ping"}"#.to_vec(),
+        ]);
+        let call = br#"{"jsonrpc":"2.0","id":"lento","method":"tools/call","params":{"name":"infer","arguments":{"prompt":"hola"},"_meta":{"progressToken":1}}}"#;
+        assert_eq!(handle_frame(&mut session, call), None);
+        assert_eq!(SENT.lock().unwrap().len(), 1, "solo el progreso del primer token");
+        assert_eq!(logging::metrics::MCP_CANCELLED_REQUESTS.get(), cancelled + 1);
+        // El resto de tramas queda para el bucle del servidor
+        assert_eq!(deferred().drain(..).collect::<Vec<_>>(), [br#"{"jsonrpc":"2.0","id":2,"method":"ping"}"#.to_vec()]);
+
+        // Cancelar una petición que ya terminó no tiene efecto
+        let cancel = br#"{"jsonrpc":"2.0","method":"notifications/cancelled","params":{"requestId":"lento"}}"#;
+        assert_eq!(handle_frame(&mut session, cancel), None);
+        let call = br#"{"jsonrpc":"2.0","id":3,"method":"tools/call","params":{"name":"infer","arguments":{"prompt":"hola"}}}"#;
+        assert!(String::from_utf8(handle_frame(&mut session, call).unwrap()).unwrap().contains("Hola desde el modelo"));
+        assert_eq!(logging::metrics::MCP_CANCELLED_REQUESTS.get(), cancelled + 1);
+        ai_runtime::unload_model();
+        vfs::unmount("/");
+    }
+}
@@ -0,0 +1,329 @@
+//! Prompts MCP: `prompts/list` (paginado) y `prompts/get` con sustitución de
+//! argumentos.
+//!
+//! Las plantillas se escriben en JSON:
+//!
+//! ```json
+//! {"prompts": [{"name": "chat", "description": "...",
+//!   "arguments": [{"name": "message", "required": true}],
+//!   "messages": [{"role": "user", "text": "{{message}}"}]}]}
+//! ```
+//!
+//! `{{argumento}}` se sustituye por su valor (vacío si es opcional y no viene). El
+//! servidor trae un conjunto por defecto ([`DEFAULT_TEMPLATES`]); un modelo puede traer
+//! el suyo junto al fichero del modelo (`ai_runtime::prompts_path`), cuyas plantillas
+//! sustituyen a las del mismo nombre y se añaden al resto.
+
+use crate::json::{self, Value, Writer};
+use crate::jsonrpc::{ErrorCode, RpcError};
+use crate::tools::page;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// Plantillas compiladas en el servidor.
+pub const DEFAULT_TEMPLATES: &str = r#"{"prompts":[
+{"name":"chat","description":"Conversación con el modelo cargado.","arguments":[{"name":"message","description":"Mensaje del usuario","required":true}],"messages":[{"role":"user","text":"{{message}}"}]},
+{"name":"summarize","description":"Resume un texto en pocas frases.","arguments":[{"name":"text","description":"Texto a resumir","required":true},{"name":"language","description":"Idioma del resumen"}],"messages":[{"role":"user","text":"Resume en pocas frases el siguiente texto. {{language}}\n\n{{text}}"}]},
+{"name":"explain-log","description":"Explica un extracto del log del kernel y señala los errores.","arguments":[{"name":"log","description":"Líneas del log (p. ej. el recurso log://kernel)","required":true}],"messages":[{"role":"user","text":"Explica qué ha pasado en este log del kernel y señala los errores:\n\n{{log}}"}]}
+]}"#;
+
+/// Prompts por página de `prompts/list`.
+pub const PAGE_SIZE: usize = 16;
+
+static LIST_CHANGED: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    User,
+    Assistant,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::User => "user",
+            Role::Assistant => "assistant",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PromptArgument {
+    pub name: String,
+    pub description: Option<String>,
+    pub required: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PromptMessage {
+    pub role: Role,
+    /// Texto con los `{{argumento}}` sin sustituir.
+    pub text: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Prompt {
+    pub name: String,
+    pub description: Option<String>,
+    pub arguments: Vec<PromptArgument>,
+    pub messages: Vec<PromptMessage>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TemplateError {
+    /// No es JSON o falta el array `prompts`.
+    Malformed,
+    MissingName,
+    /// Mensaje sin `text` o con un `role` distinto de `user` y `assistant`.
+    InvalidMessage,
+    DuplicateName,
+}
+
+impl TemplateError {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TemplateError::Malformed => "mcp prompts: malformed templates",
+            TemplateError::MissingName => "mcp prompts: prompt or argument without name",
+            TemplateError::InvalidMessage => "mcp prompts: invalid message",
+            TemplateError::DuplicateName => "mcp prompts: duplicate prompt name",
+        }
+    }
+}
+
+impl fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+fn string(value: &Value<'_>, key: &str) -> Option<String> {
+    value.get(key).and_then(|v| v.as_str()).map(|s| s.into_owned())
+}
+
+fn parse_prompt(value: &Value<'_>) -> Result<Prompt, TemplateError> {
+    let name = string(value, "name").ok_or(TemplateError::MissingName)?;
+    let mut arguments = Vec::new();
+    for arg in value.get("arguments").and_then(|a| a.as_array()).unwrap_or(&[]) {
+        arguments.push(PromptArgument {
+            name: string(arg, "name").ok_or(TemplateError::MissingName)?,
+            description: string(arg, "description"),
+            required: arg.get("required").and_then(|r| r.as_bool()).unwrap_or(false),
+        });
+    }
+    let mut messages = Vec::new();
+    for message in value.get("messages").and_then(|m| m.as_array()).ok_or(TemplateError::InvalidMessage)? {
+        let role = match string(message, "role").as_deref() {
+            Some("user") => Role::User,
+            Some("assistant") => Role::Assistant,
+            _ => return Err(TemplateError::InvalidMessage),
+        };
+        messages.push(PromptMessage { role, text: string(message, "text").ok_or(TemplateError::InvalidMessage)? });
+    }
+    Ok(Prompt { name, description: string(value, "description"), arguments, messages })
+}
+
+/// Lee un fichero de plantillas.
+pub fn parse_templates(input: &[u8]) -> Result<Vec<Prompt>, TemplateError> {
+    let root = json::parse(input).map_err(|_| TemplateError::Malformed)?;
+    let mut prompts: Vec<Prompt> = Vec::new();
+    for value in root.get("prompts").and_then(|p| p.as_array()).ok_or(TemplateError::Malformed)? {
+        let prompt = parse_prompt(value)?;
+        if prompts.iter().any(|p| p.name == prompt.name) {
+            return Err(TemplateError::DuplicateName);
+        }
+        prompts.push(prompt);
+    }
+    Ok(prompts)
+}
+
+/// Las plantillas por defecto con las de `model` encima.
+pub fn merge(model: Option<&[u8]>) -> Result<Vec<Prompt>, TemplateError> {
+    let mut prompts = parse_templates(DEFAULT_TEMPLATES.as_bytes())?;
+    for prompt in parse_templates(model.unwrap_or(br#"{"prompts":[]}"#))? {
+        match prompts.iter_mut().find(|p| p.name == prompt.name) {
+            Some(existing) => *existing = prompt,
+            None => prompts.push(prompt),
+        }
+    }
+    Ok(prompts)
+}
+
+/// Prompts del modelo cargado. Si sus plantillas no son válidas se registra el
+/// error y se usan solo las del servidor.
+pub fn active() -> Vec<Prompt> {
+    merge(ai_runtime::prompt_templates()).unwrap_or_else(|e| {
+        logging::log!(logging::Level::Warning, "mcp", "plantillas del modelo ignoradas: {}", e.as_str());
+        merge(None).unwrap_or_default()
+    })
+}
+
+/// Avisa de que las plantillas cambiaron (p. ej. se cargó otro modelo).
+pub fn notify_list_changed() {
+    LIST_CHANGED.store(true, Ordering::Relaxed);
+}
+
+/// Indica (y olvida) si las plantillas cambiaron desde la última consulta.
+pub fn take_list_changed() -> bool {
+    LIST_CHANGED.swap(false, Ordering::Relaxed)
+}
+
+/// `ListPromptsResult`, en el orden de las plantillas.
+pub fn list(prompts: &[Prompt], params: &[u8], page_size: usize) -> Result<Vec<u8>, RpcError> {
+    page(params, page_size, "prompts", prompts, |w, prompt| {
+        w.begin_object().key("name").string(&prompt.name);
+        if let Some(description) = &prompt.description {
+            w.key("description").string(description);
+        }
+        w.key("arguments").begin_array();
+        for arg in &prompt.arguments {
+            w.begin_object().key("name").string(&arg.name);
+            if let Some(description) = &arg.description {
+                w.key("description").string(description);
+            }
+            w.key("required").bool(arg.required).end_object();
+        }
+        w.end_array().end_object();
+    })
+}
+
+/// Sustituye cada `{{nombre}}` de `text` por su valor en `values`. Los nombres que no
+/// son argumentos del prompt se dejan como están.
+fn render(text: &str, values: &[(&str, String)]) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(open) = rest.find("{{") {
+        out.push_str(&rest[..open]);
+        let after = &rest[open + 2..];
+        let Some(close) = after.find("}}") else {
+            rest = &rest[open..];
+            break;
+        };
+        match values.iter().find(|(name, _)| *name == after[..close].trim()) {
+            Some((_, value)) => out.push_str(value),
+            None => out.push_str(&rest[open..open + 2 + close + 2]),
+        }
+        rest = &after[close + 2..];
+    }
+    out.push_str(rest);
+    out
+}
+
+fn invalid_params(message: impl Into<alloc::borrow::Cow<'static, str>>) -> RpcError {
+    RpcError::with_message(ErrorCode::InvalidParams, message)
+}
+
+/// `prompts/get` con `{name, arguments}`: los mensajes del prompt con los argumentos
+/// sustituidos. Faltar un argumento obligatorio es un error de parámetros.
+pub fn get(prompts: &[Prompt], params: &[u8]) -> Result<Vec<u8>, RpcError> {
+    let params = json::parse(params).map_err(|_| invalid_params("params must be an object"))?;
+    let name = params.get("name").and_then(|n| n.as_str()).ok_or_else(|| invalid_params("missing prompt name"))?;
+    let prompt = prompts
+        .iter()
+        .find(|p| p.name == name)
+        .ok_or_else(|| invalid_params(format!("unknown prompt: {}", name)))?;
+    let arguments = params.get("arguments");
+    let mut values = Vec::with_capacity(prompt.arguments.len());
+    for arg in &prompt.arguments {
+        let value = match arguments.and_then(|a| a.get(&arg.name)) {
+            Some(value) => value
+                .as_str()
+                .ok_or_else(|| invalid_params(format!("argument {} must be a string", arg.name)))?
+                .into_owned(),
+            None if arg.required => return Err(invalid_params(format!("missing argument: {}", arg.name))),
+            None => String::new(),
+        };
+        values.push((arg.name.as_str(), value));
+    }
+
+    let mut w = Writer::new(Vec::new());
+    w.begin_object();
+    if let Some(description) = &prompt.description {
+        w.key("description").string(description);
+    }
+    w.key("messages").begin_array();
+    for message in &prompt.messages {
+        w.begin_object()
+            .key("role")
+            .string(message.role.as_str())
+            .key("content")
+            .begin_object()
+            .key("type")
+            .string("text")
+            .key("text")
+            .string(&render(&message.text, &values))
+            .end_object()
+            .end_object();
+    }
+    w.end_array().end_object();
+    Ok(w.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MODEL_TEMPLATES: &[u8] = br#"{"prompts":[
+        {"name":"chat","description":"Formato de chat del modelo","arguments":[{"name":"message","required":true},{"name":"persona"}],
+         "messages":[{"role":"user","text":"<|system|>{{persona}}<|user|>{{ message }}"},{"role":"assistant","text":"<|assistant|>"}]},
+        {"name":"translate","arguments":[{"name":"text","required":true}],"messages":[{"role":"user","text":"Traduce: {{text}} {{otro}}"}]}
+    ]}"#;
+
+    fn text(result: Vec<u8>) -> String {
+        String::from_utf8(result).unwrap()
+    }
+
+    #[test]
+    fn defaults_and_model_templates_merge() {
+        let defaults = merge(None).unwrap();
+        let names: Vec<_> = defaults.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(names, ["chat", "summarize", "explain-log"]);
+        let prompts = merge(Some(MODEL_TEMPLATES)).unwrap();
+        let names: Vec<_> = prompts.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(names, ["chat", "summarize", "explain-log", "translate"]);
+        assert_eq!(prompts[0].description.as_deref(), Some("Formato de chat del modelo"));
+
+        assert_eq!(parse_templates(b"[]"), Err(TemplateError::Malformed));
+        assert_eq!(parse_templates(br#"{"prompts":[{"messages":[]}]}"#), Err(TemplateError::MissingName));
+        assert_eq!(
+            parse_templates(br#"{"prompts":[{"name":"a","messages":[{"role":"system","text":"x"}]}]}"#),
+            Err(TemplateError::InvalidMessage)
+        );
+        assert_eq!(
+            parse_templates(br#"{"prompts":[{"name":"a","messages":[]},{"name":"a","messages":[]}]}"#),
+            Err(TemplateError::DuplicateName)
+        );
+    }
+
+    #[test]
+    fn list_and_get_substitute_arguments() {
+        let prompts = merge(Some(MODEL_TEMPLATES)).unwrap();
+        let page = text(list(&prompts, b"{}", 1).unwrap());
+        assert_eq!(
+            page,
+            concat!(
+                r#"{"prompts":[{"name":"chat","description":"Formato de chat del modelo","arguments":["#,
+                r#"{"name":"message","required":true},{"name":"persona","required":false}]}],"nextCursor":"1"}"#
+            )
+        );
+        assert_eq!(
+            text(get(&prompts, br#"{"name":"chat","arguments":{"message":"hola"}}"#).unwrap()),
+            concat!(
+                r#"{"description":"Formato de chat del modelo","messages":["#,
+                r#"{"role":"user","content":{"type":"text","text":"<|system|><|user|>hola"}},"#,
+                r#"{"role":"assistant","content":{"type":"text","text":"<|assistant|>"}}]}"#
+            )
+        );
+        let translated = text(get(&prompts, br#"{"name":"translate","arguments":{"text":"{{text}}"}}"#).unwrap());
+        assert!(translated.contains(r#""text":"Traduce: {{text}} {{otro}}""#));
+
+        let err = get(&prompts, br#"{"name":"chat"}"#).unwrap_err();
+        assert_eq!((err.code, &*err.message), (ErrorCode::InvalidParams, "missing argument: message"));
+        let err = get(&prompts, br#"{"name":"chat","arguments":{"message":1}}"#).unwrap_err();
+        assert_eq!(&*err.message, "argument message must be a string");
+        assert_eq!(&*get(&prompts, br#"{"name":"nope"}"#).unwrap_err().message, "unknown prompt: nope");
+        assert_eq!(render("a {{x", &[("x", String::from("1"))]), "a {{x");
+    }
+}
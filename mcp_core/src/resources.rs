@@ -0,0 +1,466 @@
+//! Recursos MCP: registro en tiempo de ejecución, `resources/list`,
+//! `resources/templates/list` (paginados), `resources/read` y avisos de cambios.
+//!
+//! Un recurso tiene una URI fija (`log://kernel`); una plantilla (RFC 6570, solo
+//! `{var}` y `{+var}`) cubre una familia de URIs (`file:///{+path}`) que se resuelven
+//! al leerlas. El lector devuelve el contenido como texto o como binario, que se
+//! envía en base64 (`blob`).
+//!
+//! Las suscripciones son de cada [`Session`]; quien cambia un recurso llama a
+//! [`notify_updated`] y el servidor envía `notifications/resources/updated` a la
+//! sesión si está suscrita a esa URI.
+
+use crate::json::{self, Writer};
+use crate::jsonrpc::{ErrorCode, RpcError};
+use crate::session::Session;
+use crate::tools::page;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+use core::ptr::addr_of_mut;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// Recurso con URI fija tal como lo ve el cliente.
+#[derive(Debug, Clone, Copy)]
+pub struct ResourceDef {
+    pub uri: &'static str,
+    pub name: &'static str,
+    pub description: &'static str,
+    pub mime_type: Option<&'static str>,
+}
+
+/// Familia de recursos descrita por una plantilla de URI.
+#[derive(Debug, Clone, Copy)]
+pub struct ResourceTemplate {
+    pub uri_template: &'static str,
+    pub name: &'static str,
+    pub description: &'static str,
+    /// Solo si todos los recursos de la familia tienen el mismo tipo.
+    pub mime_type: Option<&'static str>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Body {
+    Text(String),
+    /// Se envía en base64.
+    Blob(Vec<u8>),
+}
+
+/// Un elemento de `contents` en `resources/read`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResourceContents {
+    pub uri: String,
+    pub mime_type: Option<&'static str>,
+    pub body: Body,
+}
+
+impl ResourceContents {
+    pub fn text(uri: &str, mime_type: Option<&'static str>, text: String) -> Self {
+        ResourceContents { uri: String::from(uri), mime_type, body: Body::Text(text) }
+    }
+
+    pub fn blob(uri: &str, mime_type: Option<&'static str>, data: Vec<u8>) -> Self {
+        ResourceContents { uri: String::from(uri), mime_type, body: Body::Blob(data) }
+    }
+}
+
+/// Recibe la URI pedida y, si se resolvió con una plantilla, sus variables
+/// (`(nombre, valor)`, en orden); devuelve el contenido del recurso.
+pub type ResourceReader = fn(&str, &[(&str, &str)]) -> Result<Vec<ResourceContents>, RpcError>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegistryError {
+    AlreadyRegistered,
+    /// La URI no empieza por un esquema (`log:`, `file:`, ...).
+    InvalidUri,
+    /// Llaves sin cerrar o variables sin nombre.
+    InvalidTemplate,
+}
+
+impl RegistryError {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RegistryError::AlreadyRegistered => "mcp resources: uri already registered",
+            RegistryError::InvalidUri => "mcp resources: invalid uri",
+            RegistryError::InvalidTemplate => "mcp resources: invalid uri template",
+        }
+    }
+}
+
+impl fmt::Display for RegistryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Elementos por página de `resources/list` y `resources/templates/list`.
+pub const PAGE_SIZE: usize = 16;
+
+// Mismo acuerdo que el registro de herramientas: scheduler cooperativo y copia de la
+// entrada antes de llamar al lector.
+static mut RESOURCES: Vec<(ResourceDef, ResourceReader)> = Vec::new();
+static mut TEMPLATES: Vec<(ResourceTemplate, ResourceReader)> = Vec::new();
+/// URIs cambiadas desde el último [`take_updated`], sin repetir.
+static mut UPDATED: Vec<String> = Vec::new();
+static LIST_CHANGED: AtomicBool = AtomicBool::new(false);
+
+fn resources() -> &'static mut Vec<(ResourceDef, ResourceReader)> {
+    unsafe { &mut *addr_of_mut!(RESOURCES) }
+}
+
+fn templates() -> &'static mut Vec<(ResourceTemplate, ResourceReader)> {
+    unsafe { &mut *addr_of_mut!(TEMPLATES) }
+}
+
+fn updated() -> &'static mut Vec<String> {
+    unsafe { &mut *addr_of_mut!(UPDATED) }
+}
+
+/// Vacía los registros de recursos, herramientas y autocompletado (comparten el
+/// cerrojo de las pruebas).
+#[cfg(test)]
+pub(crate) fn reset() -> std::sync::MutexGuard<'static, ()> {
+    let guard = crate::tools::reset();
+    crate::completion::clear();
+    resources().clear();
+    templates().clear();
+    updated().clear();
+    LIST_CHANGED.store(false, Ordering::Relaxed);
+    guard
+}
+
+fn has_scheme(uri: &str) -> bool {
+    let Some((scheme, _)) = uri.split_once(':') else { return false };
+    let mut bytes = scheme.bytes();
+    bytes.next().is_some_and(|b| b.is_ascii_alphabetic())
+        && bytes.all(|b| b.is_ascii_alphanumeric() || b"+.-".contains(&b))
+}
+
+/// Publica un recurso con URI fija.
+pub fn register_resource(def: ResourceDef, reader: ResourceReader) -> Result<(), RegistryError> {
+    if !has_scheme(def.uri) || def.uri.contains('{') {
+        return Err(RegistryError::InvalidUri);
+    }
+    if resources().iter().any(|(d, _)| d.uri == def.uri) {
+        return Err(RegistryError::AlreadyRegistered);
+    }
+    resources().push((def, reader));
+    LIST_CHANGED.store(true, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Publica una plantilla. Las URIs con recurso propio no se resuelven con plantillas.
+pub fn register_template(def: ResourceTemplate, reader: ResourceReader) -> Result<(), RegistryError> {
+    if !has_scheme(def.uri_template) {
+        return Err(RegistryError::InvalidUri);
+    }
+    if !valid_template(def.uri_template) {
+        return Err(RegistryError::InvalidTemplate);
+    }
+    if templates().iter().any(|(d, _)| d.uri_template == def.uri_template) {
+        return Err(RegistryError::AlreadyRegistered);
+    }
+    templates().push((def, reader));
+    LIST_CHANGED.store(true, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Retira un recurso o una plantilla (por su URI o su plantilla). Devuelve `false`
+/// si no estaba registrado.
+pub fn unregister_resource(uri: &str) -> bool {
+    let removed = if let Some(index) = resources().iter().position(|(d, _)| d.uri == uri) {
+        resources().remove(index);
+        true
+    } else if let Some(index) = templates().iter().position(|(d, _)| d.uri_template == uri) {
+        templates().remove(index);
+        true
+    } else {
+        false
+    };
+    if removed {
+        LIST_CHANGED.store(true, Ordering::Relaxed);
+    }
+    removed
+}
+
+/// Indica (y olvida) si los recursos o plantillas cambiaron desde la última consulta.
+pub fn take_list_changed() -> bool {
+    LIST_CHANGED.swap(false, Ordering::Relaxed)
+}
+
+/// Avisa de que el contenido de `uri` cambió; las sesiones suscritas recibirán
+/// `notifications/resources/updated`.
+pub fn notify_updated(uri: &str) {
+    let pending = updated();
+    if !pending.iter().any(|u| u == uri) {
+        pending.push(String::from(uri));
+    }
+}
+
+/// URIs cambiadas desde la última consulta.
+pub fn take_updated() -> Vec<String> {
+    core::mem::take(updated())
+}
+
+fn valid_template(template: &str) -> bool {
+    let mut rest = template;
+    while let Some(open) = rest.find(['{', '}']) {
+        if rest.as_bytes()[open] == b'}' {
+            return false;
+        }
+        let Some(close) = rest[open..].find('}') else { return false };
+        let name = &rest[open + 1..open + close];
+        let name = name.strip_prefix('+').unwrap_or(name);
+        if name.is_empty() || !name.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'_') {
+            return false;
+        }
+        rest = &rest[open + close + 1..];
+    }
+    true
+}
+
+fn match_from<'t, 'u>(template: &'t str, uri: &'u str, vars: &mut Vec<(&'t str, &'u str)>) -> bool {
+    let Some(open) = template.find('{') else { return template == uri };
+    let Some(uri) = uri.strip_prefix(&template[..open]) else { return false };
+    let Some(close) = template[open..].find('}').map(|c| open + c) else { return false };
+    let (name, template) = (&template[open + 1..close], &template[close + 1..]);
+    let (name, limit) = match name.strip_prefix('+') {
+        Some(name) => (name, uri.len()),
+        // `{var}` no cruza segmentos
+        None => (name, uri.find('/').unwrap_or(uri.len())),
+    };
+    // De la asignación más larga a la más corta; una variable no queda vacía
+    for end in (1..=limit).rev().filter(|&end| uri.is_char_boundary(end)) {
+        vars.push((name, &uri[..end]));
+        if match_from(template, &uri[end..], vars) {
+            return true;
+        }
+        vars.pop();
+    }
+    false
+}
+
+/// Variables de `uri` si encaja con `template`: `{var}` toma un segmento (sin `/`) y
+/// `{+var}` cualquier texto. Los valores no se decodifican.
+pub fn match_template<'t, 'u>(template: &'t str, uri: &'u str) -> Option<Vec<(&'t str, &'u str)>> {
+    let mut vars = Vec::new();
+    match_from(template, uri, &mut vars).then_some(vars)
+}
+
+/// Valor de la variable `name` de una URI resuelta con plantilla.
+pub fn variable<'u>(vars: &[(&str, &'u str)], name: &str) -> Option<&'u str> {
+    vars.iter().find(|(n, _)| *n == name).map(|(_, v)| *v)
+}
+
+/// Indica si `uri` tiene recurso propio o la cubre alguna plantilla.
+pub fn exists(uri: &str) -> bool {
+    resources().iter().any(|(d, _)| d.uri == uri)
+        || templates().iter().any(|(d, _)| match_template(d.uri_template, uri).is_some())
+}
+
+/// Indica si hay una plantilla registrada con exactamente `uri_template`.
+pub fn is_template(uri_template: &str) -> bool {
+    templates().iter().any(|(d, _)| d.uri_template == uri_template)
+}
+
+fn invalid_params(message: &'static str) -> RpcError {
+    RpcError::with_message(ErrorCode::InvalidParams, message)
+}
+
+/// `ListResourcesResult`, en orden de registro.
+pub fn list(params: &[u8], page_size: usize) -> Result<Vec<u8>, RpcError> {
+    page(params, page_size, "resources", resources(), |w, (def, _)| {
+        w.begin_object().key("uri").string(def.uri).key("name").string(def.name).key("description").string(def.description);
+        if let Some(mime) = def.mime_type {
+            w.key("mimeType").string(mime);
+        }
+        w.end_object();
+    })
+}
+
+/// `ListResourceTemplatesResult`, en orden de registro.
+pub fn list_templates(params: &[u8], page_size: usize) -> Result<Vec<u8>, RpcError> {
+    page(params, page_size, "resourceTemplates", templates(), |w, (def, _)| {
+        w.begin_object()
+            .key("uriTemplate")
+            .string(def.uri_template)
+            .key("name")
+            .string(def.name)
+            .key("description")
+            .string(def.description);
+        if let Some(mime) = def.mime_type {
+            w.key("mimeType").string(mime);
+        }
+        w.end_object();
+    })
+}
+
+/// `uri` de `resources/read`, `resources/subscribe` y `resources/unsubscribe`.
+fn parse_uri(params: &[u8]) -> Result<String, RpcError> {
+    let params = json::parse(params).map_err(|_| invalid_params("params must be an object"))?;
+    let uri = params.get("uri").and_then(|u| u.as_str()).ok_or_else(|| invalid_params("missing uri"))?;
+    Ok(uri.into_owned())
+}
+
+fn not_found(uri: &str) -> RpcError {
+    RpcError::with_message(ErrorCode::ResourceNotFound, format!("resource not found: {}", uri))
+}
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Base64 estándar con relleno (`blob` de `BlobResourceContents`).
+pub fn base64(data: &[u8]) -> String {
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let n = (chunk[0] as u32) << 16 | (*chunk.get(1).unwrap_or(&0) as u32) << 8 | *chunk.get(2).unwrap_or(&0) as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(BASE64[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+/// `resources/read` con `{uri}`: primero los recursos con URI fija y después las
+/// plantillas, en orden de registro.
+pub fn read(params: &[u8]) -> Result<Vec<u8>, RpcError> {
+    let uri = parse_uri(params)?;
+    let contents = if let Some((_, reader)) = resources().iter().find(|(d, _)| d.uri == uri).copied() {
+        reader(&uri, &[])?
+    } else {
+        let (template, reader) = *templates()
+            .iter()
+            .find(|(d, _)| match_template(d.uri_template, &uri).is_some())
+            .ok_or_else(|| not_found(&uri))?;
+        let vars = match_template(template.uri_template, &uri).unwrap_or_default();
+        reader(&uri, &vars)?
+    };
+    let mut w = Writer::new(Vec::new());
+    w.begin_object().key("contents").begin_array();
+    for item in &contents {
+        w.begin_object().key("uri").string(&item.uri);
+        if let Some(mime) = item.mime_type {
+            w.key("mimeType").string(mime);
+        }
+        match &item.body {
+            Body::Text(text) => w.key("text").string(text),
+            Body::Blob(data) => w.key("blob").string(&base64(data)),
+        };
+        w.end_object();
+    }
+    w.end_array().end_object();
+    Ok(w.into_inner())
+}
+
+/// `resources/subscribe`: la sesión recibirá los cambios de `uri`, que debe existir.
+pub fn subscribe(session: &mut Session, params: &[u8]) -> Result<Vec<u8>, RpcError> {
+    let uri = parse_uri(params)?;
+    if !exists(&uri) {
+        return Err(not_found(&uri));
+    }
+    session.subscribe(uri);
+    Ok(b"{}".to_vec())
+}
+
+/// `resources/unsubscribe`; no es un error si la sesión no estaba suscrita.
+pub fn unsubscribe(session: &mut Session, params: &[u8]) -> Result<Vec<u8>, RpcError> {
+    let uri = parse_uri(params)?;
+    session.unsubscribe(&uri);
+    Ok(b"{}".to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_note(uri: &str, _vars: &[(&str, &str)]) -> Result<Vec<ResourceContents>, RpcError> {
+        Ok(alloc::vec![ResourceContents::text(uri, Some("text/plain"), String::from("hola"))])
+    }
+
+    fn read_blob(uri: &str, vars: &[(&str, &str)]) -> Result<Vec<ResourceContents>, RpcError> {
+        let name = variable(vars, "name").unwrap();
+        if name == "missing" {
+            return Err(not_found(uri));
+        }
+        Ok(alloc::vec![ResourceContents::blob(uri, None, name.as_bytes().to_vec())])
+    }
+
+    const NOTE: ResourceDef =
+        ResourceDef { uri: "note://a", name: "a", description: "Nota", mime_type: Some("text/plain") };
+    const BLOBS: ResourceTemplate =
+        ResourceTemplate { uri_template: "blob://{dir}/{name}", name: "blob", description: "Binario", mime_type: None };
+
+    fn text(result: Vec<u8>) -> String {
+        String::from_utf8(result).unwrap()
+    }
+
+    #[test]
+    fn templates_match_uris() {
+        assert_eq!(match_template("file:///{+path}", "file:///models/a.bin"), Some(alloc::vec![("path", "models/a.bin")]));
+        assert_eq!(match_template("blob://{dir}/{name}", "blob://x/y"), Some(alloc::vec![("dir", "x"), ("name", "y")]));
+        assert_eq!(match_template("blob://{dir}/{name}", "blob://x/y/z"), None);
+        assert_eq!(match_template("blob://{dir}/{name}", "blob://x/"), None);
+        assert_eq!(match_template("log://kernel", "log://kernel"), Some(Vec::new()));
+        assert!(valid_template("a://{+p}/{q}") && !valid_template("a://{}") && !valid_template("a://{p") && !valid_template("a://p}"));
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foobar"), "Zm9vYmFy");
+    }
+
+    #[test]
+    fn registry_lists_and_reads() {
+        let _guard = reset();
+        register_resource(NOTE, read_note).unwrap();
+        register_template(BLOBS, read_blob).unwrap();
+        assert!(take_list_changed() && !take_list_changed());
+        assert_eq!(register_resource(NOTE, read_note), Err(RegistryError::AlreadyRegistered));
+        assert_eq!(register_resource(ResourceDef { uri: "sin-esquema", ..NOTE }, read_note), Err(RegistryError::InvalidUri));
+        assert_eq!(
+            register_template(ResourceTemplate { uri_template: "blob://{", ..BLOBS }, read_blob),
+            Err(RegistryError::InvalidTemplate)
+        );
+
+        assert_eq!(
+            text(list(b"{}", 16).unwrap()),
+            r#"{"resources":[{"uri":"note://a","name":"a","description":"Nota","mimeType":"text/plain"}]}"#
+        );
+        assert_eq!(
+            text(list_templates(b"{}", 16).unwrap()),
+            r#"{"resourceTemplates":[{"uriTemplate":"blob://{dir}/{name}","name":"blob","description":"Binario"}]}"#
+        );
+        assert_eq!(
+            text(read(br#"{"uri":"note://a"}"#).unwrap()),
+            r#"{"contents":[{"uri":"note://a","mimeType":"text/plain","text":"hola"}]}"#
+        );
+        assert_eq!(text(read(br#"{"uri":"blob://d/foobar"}"#).unwrap()), r#"{"contents":[{"uri":"blob://d/foobar","blob":"Zm9vYmFy"}]}"#);
+        let err = read(br#"{"uri":"note://b"}"#).unwrap_err();
+        assert_eq!((err.code, &*err.message), (ErrorCode::ResourceNotFound, "resource not found: note://b"));
+        assert_eq!(read(br#"{"uri":"blob://d/missing"}"#).unwrap_err().code, ErrorCode::ResourceNotFound);
+        assert_eq!(read(b"{}").unwrap_err().code, ErrorCode::InvalidParams);
+
+        assert!(unregister_resource("blob://{dir}/{name}") && !unregister_resource("blob://{dir}/{name}"));
+        assert!(take_list_changed());
+        assert!(!exists("blob://d/x") && exists("note://a"));
+    }
+
+    #[test]
+    fn subscriptions_follow_updates() {
+        let _guard = reset();
+        register_resource(NOTE, read_note).unwrap();
+        let mut session = Session::new();
+        assert_eq!(subscribe(&mut session, br#"{"uri":"note://b"}"#).unwrap_err().code, ErrorCode::ResourceNotFound);
+        subscribe(&mut session, br#"{"uri":"note://a"}"#).unwrap();
+        assert!(session.is_subscribed("note://a"));
+        notify_updated("note://a");
+        notify_updated("note://a");
+        assert_eq!(take_updated(), ["note://a"]);
+        assert!(take_updated().is_empty());
+        unsubscribe(&mut session, br#"{"uri":"note://a"}"#).unwrap();
+        assert!(!session.is_subscribed("note://a"));
+    }
+}
@@ -0,0 +1,962 @@
+//! Tipos del protocolo MCP, traducidos del esquema TypeScript de la revisión
+//! 2025-03-26 (`schema.ts` / `schema.json` del repositorio de la especificación).
+//!
+//! Los nombres de los tipos y de los campos siguen al esquema (en `snake_case`, con
+//! `#[serde(rename_all = "camelCase")]`); los campos opcionales del esquema son
+//! `Option` y no se serializan si valen `None`. Los valores JSON arbitrarios
+//! (`arguments`, `experimental`, `data`, ...) son `serde_json::Value`. Solo necesita
+//! `alloc`: se activa con la feature `schema`.
+//!
+//! Las pruebas de este módulo comprueban cada tipo contra una copia del `schema.json`
+//! oficial en `schema/2025-03-26/`, recortada a las definiciones que se usan aquí.
+
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec::Vec;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+pub const LATEST_PROTOCOL_VERSION: &str = "2025-03-26";
+pub const JSONRPC_VERSION: &str = "2.0";
+
+/// Nombres de los métodos y notificaciones.
+pub mod methods {
+    pub const INITIALIZE: &str = "initialize";
+    pub const INITIALIZED: &str = "notifications/initialized";
+    pub const PING: &str = "ping";
+    pub const TOOLS_LIST: &str = "tools/list";
+    pub const TOOLS_CALL: &str = "tools/call";
+    pub const TOOLS_LIST_CHANGED: &str = "notifications/tools/list_changed";
+    pub const RESOURCES_LIST: &str = "resources/list";
+    pub const RESOURCES_TEMPLATES_LIST: &str = "resources/templates/list";
+    pub const RESOURCES_READ: &str = "resources/read";
+    pub const RESOURCES_SUBSCRIBE: &str = "resources/subscribe";
+    pub const RESOURCES_UNSUBSCRIBE: &str = "resources/unsubscribe";
+    pub const RESOURCES_UPDATED: &str = "notifications/resources/updated";
+    pub const RESOURCES_LIST_CHANGED: &str = "notifications/resources/list_changed";
+    pub const PROMPTS_LIST: &str = "prompts/list";
+    pub const PROMPTS_GET: &str = "prompts/get";
+    pub const PROMPTS_LIST_CHANGED: &str = "notifications/prompts/list_changed";
+    pub const LOGGING_SET_LEVEL: &str = "logging/setLevel";
+    pub const LOGGING_MESSAGE: &str = "notifications/message";
+    pub const PROGRESS: &str = "notifications/progress";
+    pub const CANCELLED: &str = "notifications/cancelled";
+    pub const SAMPLING_CREATE_MESSAGE: &str = "sampling/createMessage";
+    pub const ROOTS_LIST: &str = "roots/list";
+    pub const ROOTS_LIST_CHANGED: &str = "notifications/roots/list_changed";
+    pub const COMPLETION_COMPLETE: &str = "completion/complete";
+}
+
+// --- JSON-RPC ---
+
+/// Identificador de petición: cadena o entero.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum RequestId {
+    Number(i64),
+    String(String),
+}
+
+/// Token con el que el receptor asocia las notificaciones de progreso a una petición.
+pub type ProgressToken = RequestId;
+
+/// Posición opaca de paginación.
+pub type Cursor = String;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JsonRpcRequest {
+    pub jsonrpc: String,
+    pub id: RequestId,
+    pub method: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub params: Option<Value>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JsonRpcNotification {
+    pub jsonrpc: String,
+    pub method: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub params: Option<Value>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JsonRpcResponse {
+    pub jsonrpc: String,
+    pub id: RequestId,
+    pub result: Value,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ErrorObject {
+    pub code: i64,
+    pub message: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<Value>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JsonRpcError {
+    pub jsonrpc: String,
+    pub id: RequestId,
+    pub error: ErrorObject,
+}
+
+/// `_meta` de los parámetros de una petición.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RequestMeta {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub progress_token: Option<ProgressToken>,
+}
+
+/// Resultado sin contenido (`ping`, `logging/setLevel`, `resources/subscribe`, ...).
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct EmptyResult {
+    #[serde(rename = "_meta", default, skip_serializing_if = "Option::is_none")]
+    pub meta: Option<Map<String, Value>>,
+}
+
+/// Parámetros de las peticiones paginadas (`*/list`).
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct PaginatedRequestParams {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cursor: Option<Cursor>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CancelledNotificationParams {
+    pub request_id: RequestId,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProgressNotificationParams {
+    pub progress_token: ProgressToken,
+    pub progress: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub total: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+}
+
+// --- Inicialización ---
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Implementation {
+    pub name: String,
+    pub version: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ListChangedCapability {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub list_changed: Option<bool>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ResourcesCapability {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subscribe: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub list_changed: Option<bool>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ClientCapabilities {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub experimental: Option<Map<String, Value>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub roots: Option<ListChangedCapability>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sampling: Option<Map<String, Value>>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ServerCapabilities {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub experimental: Option<Map<String, Value>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub logging: Option<Map<String, Value>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub completions: Option<Map<String, Value>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prompts: Option<ListChangedCapability>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resources: Option<ResourcesCapability>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tools: Option<ListChangedCapability>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InitializeRequestParams {
+    pub protocol_version: String,
+    pub capabilities: ClientCapabilities,
+    pub client_info: Implementation,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InitializeResult {
+    pub protocol_version: String,
+    pub capabilities: ServerCapabilities,
+    pub server_info: Implementation,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub instructions: Option<String>,
+}
+
+// --- Contenido ---
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    User,
+    Assistant,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Annotations {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub audience: Option<Vec<Role>>,
+    /// 0 (opcional) a 1 (imprescindible).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub priority: Option<f64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TextContent {
+    pub text: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub annotations: Option<Annotations>,
+}
+
+/// Imagen en base64.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImageContent {
+    pub data: String,
+    pub mime_type: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub annotations: Option<Annotations>,
+}
+
+/// Audio en base64.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AudioContent {
+    pub data: String,
+    pub mime_type: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub annotations: Option<Annotations>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EmbeddedResource {
+    pub resource: ResourceContents,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub annotations: Option<Annotations>,
+}
+
+/// Contenido de un resultado de herramienta o de un mensaje de prompt, distinguido
+/// por el campo `type`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Content {
+    Text(TextContent),
+    Image(ImageContent),
+    Audio(AudioContent),
+    Resource(EmbeddedResource),
+}
+
+impl Content {
+    pub fn text(text: impl Into<String>) -> Self {
+        Content::Text(TextContent { text: text.into(), annotations: None })
+    }
+}
+
+// --- Herramientas ---
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ToolInputSchema {
+    /// Siempre `"object"`.
+    #[serde(rename = "type")]
+    pub kind: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub properties: Option<Map<String, Value>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub required: Option<Vec<String>>,
+}
+
+impl Default for ToolInputSchema {
+    fn default() -> Self {
+        ToolInputSchema { kind: String::from("object"), properties: None, required: None }
+    }
+}
+
+/// Pistas sobre el comportamiento de una herramienta (no son garantías).
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ToolAnnotations {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub read_only_hint: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub destructive_hint: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub idempotent_hint: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub open_world_hint: Option<bool>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Tool {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    pub input_schema: ToolInputSchema,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub annotations: Option<ToolAnnotations>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ListToolsResult {
+    pub tools: Vec<Tool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<Cursor>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CallToolRequestParams {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub arguments: Option<Map<String, Value>>,
+    #[serde(rename = "_meta", default, skip_serializing_if = "Option::is_none")]
+    pub meta: Option<RequestMeta>,
+}
+
+/// Los errores de la propia herramienta se devuelven aquí con `is_error`, no como
+/// error JSON-RPC, para que el modelo los vea.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CallToolResult {
+    pub content: Vec<Content>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub is_error: Option<bool>,
+}
+
+// --- Recursos ---
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Resource {
+    pub uri: String,
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mime_type: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub annotations: Option<Annotations>,
+    /// Tamaño en bytes antes de codificar, si se conoce.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ResourceTemplate {
+    /// Plantilla RFC 6570.
+    pub uri_template: String,
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mime_type: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub annotations: Option<Annotations>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TextResourceContents {
+    pub uri: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mime_type: Option<String>,
+    pub text: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BlobResourceContents {
+    pub uri: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mime_type: Option<String>,
+    /// Datos en base64.
+    pub blob: String,
+}
+
+/// Contenido de un recurso: texto o binario, según el campo presente.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ResourceContents {
+    Text(TextResourceContents),
+    Blob(BlobResourceContents),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ListResourcesResult {
+    pub resources: Vec<Resource>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<Cursor>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ListResourceTemplatesResult {
+    pub resource_templates: Vec<ResourceTemplate>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<Cursor>,
+}
+
+/// Parámetros de `resources/read`, `resources/subscribe`, `resources/unsubscribe` y
+/// `notifications/resources/updated`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ResourceUriParams {
+    pub uri: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ReadResourceResult {
+    pub contents: Vec<ResourceContents>,
+}
+
+// --- Prompts ---
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PromptArgument {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub required: Option<bool>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Prompt {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub arguments: Option<Vec<PromptArgument>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ListPromptsResult {
+    pub prompts: Vec<Prompt>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<Cursor>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GetPromptRequestParams {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub arguments: Option<BTreeMap<String, String>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PromptMessage {
+    pub role: Role,
+    pub content: Content,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GetPromptResult {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    pub messages: Vec<PromptMessage>,
+}
+
+// --- Logging ---
+
+/// Niveles de syslog (RFC 5424), de menor a mayor gravedad.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LoggingLevel {
+    Debug,
+    Info,
+    Notice,
+    Warning,
+    Error,
+    Critical,
+    Alert,
+    Emergency,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SetLevelRequestParams {
+    pub level: LoggingLevel,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LoggingMessageNotificationParams {
+    pub level: LoggingLevel,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub logger: Option<String>,
+    pub data: Value,
+}
+
+// --- Sampling (servidor -> cliente) ---
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SamplingMessage {
+    pub role: Role,
+    /// Solo `Text`, `Image` o `Audio`.
+    pub content: Content,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ModelHint {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ModelPreferences {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hints: Option<Vec<ModelHint>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cost_priority: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub speed_priority: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub intelligence_priority: Option<f64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum IncludeContext {
+    None,
+    ThisServer,
+    AllServers,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CreateMessageRequestParams {
+    pub messages: Vec<SamplingMessage>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model_preferences: Option<ModelPreferences>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub system_prompt: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub include_context: Option<IncludeContext>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f64>,
+    pub max_tokens: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stop_sequences: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<Map<String, Value>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CreateMessageResult {
+    pub role: Role,
+    pub content: Content,
+    pub model: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stop_reason: Option<String>,
+}
+
+// --- Roots (servidor -> cliente) ---
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Root {
+    /// Siempre `file://` en esta revisión.
+    pub uri: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ListRootsResult {
+    pub roots: Vec<Root>,
+}
+
+// --- Autocompletado ---
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum Reference {
+    #[serde(rename = "ref/prompt")]
+    Prompt { name: String },
+    #[serde(rename = "ref/resource")]
+    Resource { uri: String },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CompleteArgument {
+    pub name: String,
+    pub value: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CompleteRequestParams {
+    #[serde(rename = "ref")]
+    pub reference: Reference,
+    pub argument: CompleteArgument,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Completion {
+    /// Como mucho 100 valores.
+    pub values: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub total: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub has_more: Option<bool>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CompleteResult {
+    pub completion: Completion,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::ToString;
+    use alloc::vec;
+    use serde_json::json;
+
+    fn text_resource() -> ResourceContents {
+        ResourceContents::Text(TextResourceContents {
+            uri: "file:///models/tiny.bin".to_string(),
+            mime_type: Some("text/plain".to_string()),
+            text: "hola".to_string(),
+        })
+    }
+
+    /// Un ejemplo de cada definición del esquema, con los campos opcionales rellenos.
+    fn samples() -> Vec<(&'static str, Value)> {
+        let annotations = Annotations { audience: Some(vec![Role::User, Role::Assistant]), priority: Some(0.5) };
+        let to = |v: &dyn erased::Ser| v.to_value();
+        vec![
+            ("Implementation", to(&Implementation { name: "unikernel-ai".into(), version: "0.1.0".into() })),
+            (
+                "ClientCapabilities",
+                to(&ClientCapabilities {
+                    experimental: Some(Map::new()),
+                    roots: Some(ListChangedCapability { list_changed: Some(true) }),
+                    sampling: Some(Map::new()),
+                }),
+            ),
+            (
+                "ServerCapabilities",
+                to(&ServerCapabilities {
+                    experimental: Some(Map::new()),
+                    logging: Some(Map::new()),
+                    completions: Some(Map::new()),
+                    prompts: Some(ListChangedCapability { list_changed: Some(false) }),
+                    resources: Some(ResourcesCapability { subscribe: Some(true), list_changed: Some(true) }),
+                    tools: Some(ListChangedCapability { list_changed: Some(true) }),
+                }),
+            ),
+            (
+                "InitializeResult",
+                to(&InitializeResult {
+                    protocol_version: LATEST_PROTOCOL_VERSION.into(),
+                    capabilities: ServerCapabilities::default(),
+                    server_info: Implementation { name: "s".into(), version: "1".into() },
+                    instructions: Some("usa infer".into()),
+                }),
+            ),
+            ("Annotations", to(&annotations)),
+            ("TextContent", to(&Content::Text(TextContent { text: "t".into(), annotations: Some(annotations.clone()) }))),
+            (
+                "ImageContent",
+                to(&Content::Image(ImageContent { data: "AA==".into(), mime_type: "image/png".into(), annotations: None })),
+            ),
+            (
+                "AudioContent",
+                to(&Content::Audio(AudioContent { data: "AA==".into(), mime_type: "audio/wav".into(), annotations: None })),
+            ),
+            (
+                "EmbeddedResource",
+                to(&Content::Resource(EmbeddedResource { resource: text_resource(), annotations: Some(annotations.clone()) })),
+            ),
+            ("TextResourceContents", to(&text_resource())),
+            (
+                "BlobResourceContents",
+                to(&ResourceContents::Blob(BlobResourceContents {
+                    uri: "file:///a".into(),
+                    mime_type: Some("application/octet-stream".into()),
+                    blob: "AA==".into(),
+                })),
+            ),
+            (
+                "Tool",
+                to(&Tool {
+                    name: "infer".into(),
+                    description: Some("inferencia".into()),
+                    input_schema: ToolInputSchema {
+                        kind: "object".into(),
+                        properties: Some(json!({"prompt": {"type": "string"}}).as_object().unwrap().clone()),
+                        required: Some(vec!["prompt".into()]),
+                    },
+                    annotations: Some(ToolAnnotations {
+                        title: Some("Inferencia".into()),
+                        read_only_hint: Some(true),
+                        destructive_hint: Some(false),
+                        idempotent_hint: Some(false),
+                        open_world_hint: Some(false),
+                    }),
+                }),
+            ),
+            ("ListToolsResult", to(&ListToolsResult { tools: vec![], next_cursor: Some("2".into()) })),
+            (
+                "CallToolResult",
+                to(&CallToolResult { content: vec![Content::text("hola")], is_error: Some(false) }),
+            ),
+            (
+                "Resource",
+                to(&Resource {
+                    uri: "file:///a".into(),
+                    name: "a".into(),
+                    description: Some("d".into()),
+                    mime_type: Some("text/plain".into()),
+                    annotations: Some(annotations.clone()),
+                    size: Some(4),
+                }),
+            ),
+            (
+                "ResourceTemplate",
+                to(&ResourceTemplate {
+                    uri_template: "file:///{path}".into(),
+                    name: "f".into(),
+                    description: Some("d".into()),
+                    mime_type: Some("text/plain".into()),
+                    annotations: None,
+                }),
+            ),
+            ("ListResourcesResult", to(&ListResourcesResult { resources: vec![], next_cursor: Some("2".into()) })),
+            (
+                "ListResourceTemplatesResult",
+                to(&ListResourceTemplatesResult { resource_templates: vec![], next_cursor: None }),
+            ),
+            ("ReadResourceResult", to(&ReadResourceResult { contents: vec![text_resource()] })),
+            (
+                "Prompt",
+                to(&Prompt {
+                    name: "resumen".into(),
+                    description: Some("d".into()),
+                    arguments: Some(vec![PromptArgument {
+                        name: "texto".into(),
+                        description: Some("d".into()),
+                        required: Some(true),
+                    }]),
+                }),
+            ),
+            ("ListPromptsResult", to(&ListPromptsResult { prompts: vec![], next_cursor: None })),
+            (
+                "GetPromptResult",
+                to(&GetPromptResult {
+                    description: Some("d".into()),
+                    messages: vec![PromptMessage { role: Role::User, content: Content::text("hola") }],
+                }),
+            ),
+            ("SetLevelRequest/params", to(&SetLevelRequestParams { level: LoggingLevel::Warning })),
+            (
+                "LoggingMessageNotification/params",
+                to(&LoggingMessageNotificationParams {
+                    level: LoggingLevel::Error,
+                    logger: Some("kernel".into()),
+                    data: json!({"msg": "x"}),
+                }),
+            ),
+            (
+                "ProgressNotification/params",
+                to(&ProgressNotificationParams {
+                    progress_token: RequestId::String("t".into()),
+                    progress: 1.0,
+                    total: Some(2.0),
+                    message: Some("mitad".into()),
+                }),
+            ),
+            (
+                "CancelledNotification/params",
+                to(&CancelledNotificationParams { request_id: RequestId::Number(3), reason: Some("timeout".into()) }),
+            ),
+            (
+                "InitializeRequest/params",
+                to(&InitializeRequestParams {
+                    protocol_version: LATEST_PROTOCOL_VERSION.into(),
+                    capabilities: ClientCapabilities::default(),
+                    client_info: Implementation { name: "c".into(), version: "1".into() },
+                }),
+            ),
+            (
+                "CallToolRequest/params",
+                to(&CallToolRequestParams {
+                    name: "infer".into(),
+                    arguments: Some(Map::new()),
+                    meta: Some(RequestMeta { progress_token: Some(RequestId::Number(1)) }),
+                }),
+            ),
+            (
+                "CreateMessageRequest/params",
+                to(&CreateMessageRequestParams {
+                    messages: vec![SamplingMessage { role: Role::User, content: Content::text("hola") }],
+                    model_preferences: Some(ModelPreferences {
+                        hints: Some(vec![ModelHint { name: Some("claude".into()) }]),
+                        cost_priority: Some(0.1),
+                        speed_priority: Some(0.2),
+                        intelligence_priority: Some(0.9),
+                    }),
+                    system_prompt: Some("s".into()),
+                    include_context: Some(IncludeContext::ThisServer),
+                    temperature: Some(0.7),
+                    max_tokens: 64,
+                    stop_sequences: Some(vec!["\n".into()]),
+                    metadata: Some(Map::new()),
+                }),
+            ),
+            (
+                "CreateMessageResult",
+                to(&CreateMessageResult {
+                    role: Role::Assistant,
+                    content: Content::text("hola"),
+                    model: "m".into(),
+                    stop_reason: Some("endTurn".into()),
+                }),
+            ),
+            ("Root", to(&Root { uri: "file:///home".into(), name: Some("home".into()) })),
+            ("ListRootsResult", to(&ListRootsResult { roots: vec![] })),
+            ("PromptReference", to(&Reference::Prompt { name: "resumen".into() })),
+            ("ResourceReference", to(&Reference::Resource { uri: "file:///{path}".into() })),
+            (
+                "CompleteRequest/params",
+                to(&CompleteRequestParams {
+                    reference: Reference::Prompt { name: "resumen".into() },
+                    argument: CompleteArgument { name: "texto".into(), value: "ho".into() },
+                }),
+            ),
+            (
+                "CompleteResult",
+                to(&CompleteResult { completion: Completion { values: vec!["hola".into()], total: Some(1), has_more: Some(false) } }),
+            ),
+            (
+                "JSONRPCError",
+                to(&JsonRpcError {
+                    jsonrpc: JSONRPC_VERSION.into(),
+                    id: RequestId::Number(1),
+                    error: ErrorObject { code: -32601, message: "Method not found".into(), data: Some(json!("x")) },
+                }),
+            ),
+        ]
+    }
+
+    /// `serde_json::to_value` sobre `&dyn` (la tabla mezcla tipos).
+    mod erased {
+        pub trait Ser {
+            fn to_value(&self) -> serde_json::Value;
+        }
+
+        impl<T: serde::Serialize> Ser for T {
+            fn to_value(&self) -> serde_json::Value {
+                serde_json::to_value(self).unwrap()
+            }
+        }
+    }
+
+    #[test]
+    fn wire_format_matches_spec_examples() {
+        let init: InitializeRequestParams = serde_json::from_value(json!({
+            "protocolVersion": "2025-03-26",
+            "capabilities": {"roots": {"listChanged": true}, "sampling": {}},
+            "clientInfo": {"name": "ExampleClient", "version": "1.0.0"}
+        }))
+        .unwrap();
+        assert_eq!(init.capabilities.roots.unwrap().list_changed, Some(true));
+        assert_eq!(init.client_info.name, "ExampleClient");
+
+        let call: CallToolRequestParams = serde_json::from_value(json!({
+            "name": "get_weather", "arguments": {"location": "New York"}, "_meta": {"progressToken": "abc"}
+        }))
+        .unwrap();
+        assert_eq!(call.meta.unwrap().progress_token, Some(RequestId::String("abc".into())));
+
+        let result = CallToolResult { content: vec![Content::text("72°F")], is_error: None };
+        assert_eq!(serde_json::to_value(&result).unwrap(), json!({"content": [{"type": "text", "text": "72°F"}]}));
+
+        let contents: ResourceContents =
+            serde_json::from_value(json!({"uri": "file:///logo.png", "mimeType": "image/png", "blob": "AA=="})).unwrap();
+        assert!(matches!(contents, ResourceContents::Blob(_)));
+
+        let reference: CompleteRequestParams =
+            serde_json::from_value(json!({"ref": {"type": "ref/prompt", "name": "code_review"}, "argument": {"name": "language", "value": "py"}}))
+                .unwrap();
+        assert_eq!(reference.reference, Reference::Prompt { name: "code_review".into() });
+        assert!(LoggingLevel::Error > LoggingLevel::Warning);
+        assert_eq!(serde_json::to_value(IncludeContext::ThisServer).unwrap(), json!("thisServer"));
+    }
+
+    #[test]
+    fn samples_round_trip() {
+        for (name, value) in samples() {
+            let text = serde_json::to_string(&value).unwrap();
+            let back: Value = serde_json::from_str(&text).unwrap();
+            assert_eq!(back, value, "{}", name);
+        }
+    }
+
+    /// Definición `name` del esquema oficial; `Tipo/params` baja a sus parámetros.
+    fn definition<'s>(schema: &'s Value, name: &str) -> &'s Value {
+        let mut parts = name.split('/');
+        let mut def = &schema["definitions"][parts.next().unwrap()];
+        for part in parts {
+            def = &def["properties"][part];
+        }
+        // Referencias simples (`{"$ref": "#/definitions/X"}`)
+        if let Some(target) = def["$ref"].as_str() {
+            return definition(schema, target.trim_start_matches("#/definitions/"));
+        }
+        def
+    }
+
+    /// Cada ejemplo debe tener todos los campos obligatorios de su definición en el
+    /// esquema oficial y ningún campo que la definición no conozca.
+    #[test]
+    fn samples_match_official_schema() {
+        let schema: Value = serde_json::from_str(include_str!("../schema/2025-03-26/schema.json")).unwrap();
+        for (name, value) in samples() {
+            let def = definition(&schema, name);
+            assert!(def.is_object(), "{}: no está en el esquema", name);
+            let object = value.as_object().unwrap();
+            for required in def["required"].as_array().into_iter().flatten() {
+                let key = required.as_str().unwrap();
+                assert!(object.contains_key(key), "{}: falta el campo obligatorio {}", name, key);
+            }
+            let properties = def["properties"].as_object().unwrap();
+            // `_meta` viene de `Request`/`Result` y no siempre se repite en los derivados
+            for key in object.keys().filter(|k| *k != "_meta") {
+                assert!(properties.contains_key(key), "{}: campo {} fuera del esquema", name, key);
+            }
+        }
+    }
+}
@@ -0,0 +1,395 @@
+//! Ciclo de vida de una conexión MCP.
+//!
+//! El cliente envía `initialize` con su versión del protocolo, sus capacidades y
+//! `clientInfo`; el servidor contesta con la versión acordada, sus capacidades y
+//! `serverInfo`, y el cliente confirma con `notifications/initialized`. Hasta
+//! entonces solo se atienden `initialize` y `ping`. Cada conexión del transporte
+//! empieza con una [`Session`] nueva, que guarda también sus suscripciones a recursos,
+//! las peticiones en curso, para poder cancelarlas, y sus cursores de los logs del kernel.
+
+use crate::json::{self, Output, Writer};
+use crate::jsonrpc::{ErrorCode, Id, RpcError};
+use crate::tools::CancellationToken;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::cell::Cell;
+use logging::records::{self, Level, Record};
+
+/// Versiones del protocolo que entiende el servidor, de la más reciente a la más antigua.
+pub const SUPPORTED_PROTOCOL_VERSIONS: &[&str] = &["2025-03-26", "2024-11-05"];
+pub const LATEST_PROTOCOL_VERSION: &str = SUPPORTED_PROTOCOL_VERSIONS[0];
+
+pub const SERVER_NAME: &str = "unikernel-ai";
+pub const SERVER_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    /// Esperando `initialize`.
+    Uninitialized,
+    /// `initialize` contestado; falta `notifications/initialized`.
+    Initializing,
+    Ready,
+}
+
+/// Capacidades que anuncia el servidor en `initialize`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ServerCapabilities {
+    pub completions: bool,
+    pub tools: bool,
+    pub resources: bool,
+    pub prompts: bool,
+    pub logging: bool,
+}
+
+impl ServerCapabilities {
+    pub fn write<O: Output>(&self, w: &mut Writer<O>) {
+        w.begin_object();
+        if self.completions {
+            w.key("completions").begin_object().end_object();
+        }
+        if self.logging {
+            w.key("logging").begin_object().end_object();
+        }
+        if self.prompts {
+            // Las plantillas cambian con el modelo cargado
+            w.key("prompts").begin_object().key("listChanged").bool(true).end_object();
+        }
+        if self.resources {
+            w.key("resources").begin_object().key("subscribe").bool(true).key("listChanged").bool(true).end_object();
+        }
+        if self.tools {
+            // Las herramientas se registran en tiempo de ejecución (`tools::register_tool`)
+            w.key("tools").begin_object().key("listChanged").bool(true).end_object();
+        }
+        w.end_object();
+    }
+}
+
+/// Capacidades del cliente que le interesan al servidor.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ClientCapabilities {
+    pub roots: bool,
+    pub roots_list_changed: bool,
+    pub sampling: bool,
+}
+
+pub struct Session {
+    state: State,
+    protocol_version: &'static str,
+    client_name: Option<String>,
+    client_capabilities: ClientCapabilities,
+    /// URIs de `resources/subscribe`.
+    subscriptions: Vec<String>,
+    /// Peticiones sin contestar y su marca de cancelación.
+    in_flight: Vec<(Id<'static>, CancellationToken)>,
+    /// Nivel mínimo de `logging/setLevel`; sin él no se envían `notifications/message`.
+    log_level: Option<Level>,
+    /// Siguiente registro de `logging::records` por enviar.
+    record_cursor: u64,
+    /// Posición de la herramienta `logs` en el buffer de logs.
+    logs_cursor: Cell<usize>,
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Session {
+    pub const fn new() -> Self {
+        Session {
+            state: State::Uninitialized,
+            protocol_version: LATEST_PROTOCOL_VERSION,
+            client_name: None,
+            client_capabilities: ClientCapabilities { roots: false, roots_list_changed: false, sampling: false },
+            subscriptions: Vec::new(),
+            in_flight: Vec::new(),
+            log_level: None,
+            record_cursor: 0,
+            logs_cursor: Cell::new(0),
+        }
+    }
+
+    pub fn state(&self) -> State {
+        self.state
+    }
+
+    /// Versión acordada en `initialize` (la más reciente mientras no se haya acordado).
+    pub fn protocol_version(&self) -> &'static str {
+        self.protocol_version
+    }
+
+    pub fn client_name(&self) -> Option<&str> {
+        self.client_name.as_deref()
+    }
+
+    pub fn client_capabilities(&self) -> ClientCapabilities {
+        self.client_capabilities
+    }
+
+    pub fn subscribe(&mut self, uri: String) {
+        if !self.is_subscribed(&uri) {
+            self.subscriptions.push(uri);
+        }
+    }
+
+    /// Devuelve `false` si la sesión no estaba suscrita a `uri`.
+    pub fn unsubscribe(&mut self, uri: &str) -> bool {
+        let before = self.subscriptions.len();
+        self.subscriptions.retain(|u| u != uri);
+        self.subscriptions.len() != before
+    }
+
+    pub fn is_subscribed(&self, uri: &str) -> bool {
+        self.subscriptions.iter().any(|u| u == uri)
+    }
+
+    /// Anota una petición que empieza; el token devuelto se activa si el cliente la cancela.
+    pub fn begin_request(&mut self, id: Id<'static>) -> CancellationToken {
+        let token = CancellationToken::new();
+        self.in_flight.push((id, token.clone()));
+        token
+    }
+
+    /// Retira una petición terminada. Devuelve `true` si se canceló mientras corría y,
+    /// por tanto, no hay que contestarla.
+    pub fn finish_request(&mut self, id: &Id<'_>) -> bool {
+        let Some(index) = self.in_flight.iter().position(|(i, _)| i == id) else { return false };
+        self.in_flight.remove(index).1.is_cancelled()
+    }
+
+    /// `notifications/cancelled`: activa el token de la petición. Devuelve `false` si
+    /// no está en curso (ya terminó o nunca existió), lo que se ignora.
+    pub fn cancel(&self, id: &Id<'_>) -> bool {
+        match self.in_flight.iter().find(|(i, _)| i == id) {
+            Some((_, token)) => {
+                token.cancel();
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn log_level(&self) -> Option<Level> {
+        self.log_level
+    }
+
+    /// `logging/setLevel`. La primera vez los avisos empiezan en los registros que
+    /// lleguen a partir de ahora; los anteriores siguen en `log://kernel`.
+    pub fn set_log_level(&mut self, level: Level) {
+        if self.log_level.is_none() {
+            self.record_cursor = records::head();
+        }
+        self.log_level = Some(level);
+    }
+
+    /// Copia en `out` los registros nuevos con nivel `>=` al de la sesión y avanza su
+    /// cursor. Devuelve cuántos copió; 0 también si no hay nivel.
+    pub fn take_log_records(&mut self, out: &mut [Record]) -> usize {
+        let Some(level) = self.log_level else { return 0 };
+        let mut n = 0;
+        while n < out.len() {
+            let (read, next) = records::read_from(self.record_cursor, &mut out[n..]);
+            self.record_cursor = next;
+            if read == 0 {
+                break;
+            }
+            // Se compactan los que pasan el filtro al principio de lo leído
+            let start = n;
+            for i in start..start + read {
+                if out[i].level >= level {
+                    out[n] = out[i];
+                    n += 1;
+                }
+            }
+        }
+        n
+    }
+
+    /// Lee del buffer de logs lo que esta sesión aún no ha visto con la herramienta
+    /// `logs`, sin quitárselo a otros lectores.
+    pub fn read_logs(&self, out: &mut [u8]) -> usize {
+        let (n, next) = logging::log_read_from(self.logs_cursor.get(), out);
+        self.logs_cursor.set(next);
+        n
+    }
+
+    /// Atiende `initialize` y devuelve su `result`. Si el cliente pide una versión
+    /// que no se soporta, se contesta con la más reciente y el cliente decide si sigue.
+    pub fn initialize(&mut self, params: &[u8], capabilities: &ServerCapabilities) -> Result<Vec<u8>, RpcError> {
+        if self.state != State::Uninitialized {
+            return Err(RpcError::with_message(ErrorCode::InvalidRequest, "already initialized"));
+        }
+        let invalid = |message: &'static str| RpcError::with_message(ErrorCode::InvalidParams, message);
+        let params = json::parse(params).map_err(|_| invalid("params must be an object"))?;
+        let requested = params
+            .get("protocolVersion")
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| invalid("missing protocolVersion"))?;
+        let client = params.get("capabilities").filter(|c| c.as_object().is_some()).ok_or_else(|| invalid("missing capabilities"))?;
+        let client_name = params
+            .get("clientInfo")
+            .and_then(|info| info.get("name"))
+            .and_then(|name| name.as_str())
+            .ok_or_else(|| invalid("missing clientInfo.name"))?;
+
+        self.protocol_version = SUPPORTED_PROTOCOL_VERSIONS
+            .iter()
+            .copied()
+            .find(|v| *v == requested)
+            .unwrap_or(LATEST_PROTOCOL_VERSION);
+        let roots = client.get("roots");
+        self.client_capabilities = ClientCapabilities {
+            roots: roots.is_some(),
+            roots_list_changed: roots.and_then(|r| r.get("listChanged")).and_then(|v| v.as_bool()).unwrap_or(false),
+            sampling: client.get("sampling").is_some(),
+        };
+        self.client_name = Some(String::from(client_name));
+        self.state = State::Initializing;
+
+        let mut w = Writer::new(Vec::new());
+        w.begin_object().key("protocolVersion").string(self.protocol_version).key("capabilities");
+        capabilities.write(&mut w);
+        w.key("serverInfo")
+            .begin_object()
+            .key("name")
+            .string(SERVER_NAME)
+            .key("version")
+            .string(SERVER_VERSION)
+            .end_object()
+            .end_object();
+        Ok(w.into_inner())
+    }
+
+    /// `notifications/initialized`: a partir de aquí se atiende todo.
+    pub fn initialized(&mut self) {
+        if self.state == State::Initializing {
+            self.state = State::Ready;
+        }
+    }
+
+    /// Error si la sesión todavía no admite peticiones normales.
+    pub fn check_ready(&self) -> Result<(), RpcError> {
+        match self.state {
+            State::Ready => Ok(()),
+            State::Uninitialized => Err(RpcError::with_message(ErrorCode::InvalidRequest, "server not initialized")),
+            State::Initializing => {
+                Err(RpcError::with_message(ErrorCode::InvalidRequest, "waiting for notifications/initialized"))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CAPS: ServerCapabilities =
+        ServerCapabilities { completions: false, tools: true, resources: false, prompts: false, logging: true };
+
+    fn init_params(version: &str) -> Vec<u8> {
+        let mut w = Writer::new(Vec::new());
+        w.begin_object()
+            .key("protocolVersion")
+            .string(version)
+            .key("capabilities")
+            .raw(br#"{"roots":{"listChanged":true}}"#)
+            .key("clientInfo")
+            .raw(br#"{"name":"test","version":"1"}"#)
+            .end_object();
+        w.into_inner()
+    }
+
+    #[test]
+    fn handshake_negotiates_version() {
+        let mut session = Session::new();
+        assert_eq!(session.check_ready().unwrap_err().code, ErrorCode::InvalidRequest);
+        let result = session.initialize(&init_params("2024-11-05"), &CAPS).unwrap();
+        assert_eq!(
+            result,
+            br#"{"protocolVersion":"2024-11-05","capabilities":{"logging":{},"tools":{"listChanged":true}},"serverInfo":{"name":"unikernel-ai","version":"0.1.0"}}"#
+        );
+        assert_eq!(session.state(), State::Initializing);
+        assert!(session.check_ready().is_err());
+        assert_eq!(
+            session.client_capabilities(),
+            ClientCapabilities { roots: true, roots_list_changed: true, sampling: false }
+        );
+        assert_eq!(session.client_name(), Some("test"));
+        session.initialized();
+        assert!(session.check_ready().is_ok());
+        assert!(session.initialize(&init_params("2024-11-05"), &CAPS).is_err());
+
+        // Versión desconocida: se ofrece la más reciente
+        let mut session = Session::new();
+        let result = session.initialize(&init_params("1999-01-01"), &CAPS).unwrap();
+        let result = json::parse(&result).unwrap();
+        assert_eq!(result.get("protocolVersion").unwrap().as_str().as_deref(), Some(LATEST_PROTOCOL_VERSION));
+    }
+
+    #[test]
+    fn in_flight_requests_can_be_cancelled() {
+        let mut session = Session::new();
+        let token = session.begin_request(Id::Number(1));
+        session.begin_request(Id::String("a".into()));
+        assert!(!session.cancel(&Id::Number(2)));
+        assert!(session.cancel(&Id::Number(1)) && token.is_cancelled());
+        assert!(session.finish_request(&Id::Number(1)));
+        assert!(!session.finish_request(&Id::String("a".into())));
+        // Ya terminada: cancelarla no tiene efecto
+        assert!(!session.cancel(&Id::Number(1)) && !session.finish_request(&Id::Number(1)));
+    }
+
+    #[test]
+    fn log_records_follow_the_session_level() {
+        let mut session = Session::new();
+        let mut out = [Record::EMPTY; 8];
+        logging::log(Level::Error, "session-test", "antes de setLevel");
+        assert_eq!(session.take_log_records(&mut out), 0);
+        session.set_log_level(Level::Warning);
+        logging::log(Level::Info, "session-test", "informativo");
+        logging::log(Level::Error, "session-test", "fallo");
+        let mut seen = Vec::new();
+        loop {
+            let n = session.take_log_records(&mut out);
+            if n == 0 {
+                break;
+            }
+            // Otros tests escriben en el mismo ring
+            let ours = out[..n].iter().filter(|r| r.logger() == "session-test");
+            seen.extend(ours.map(|r| (r.level, String::from(r.message()))));
+        }
+        assert_eq!(seen, [(Level::Error, String::from("fallo"))]);
+    }
+
+    #[test]
+    fn sessions_read_logs_independently() {
+        logging::log(Level::Info, "session-test", "compartido");
+        let read_all = |session: &Session| {
+            let mut text = Vec::new();
+            let mut chunk = [0u8; 512];
+            loop {
+                let n = session.read_logs(&mut chunk);
+                if n == 0 {
+                    break String::from_utf8_lossy(&text).into_owned();
+                }
+                text.extend_from_slice(&chunk[..n]);
+            }
+        };
+        let (a, b) = (Session::new(), Session::new());
+        assert!(read_all(&a).contains("[session-test] compartido"));
+        assert!(read_all(&b).contains("[session-test] compartido"));
+        assert!(!read_all(&a).contains("compartido"));
+    }
+
+    #[test]
+    fn initialize_validates_params() {
+        let mut session = Session::new();
+        let err = session.initialize(br#"{"protocolVersion":"2025-03-26"}"#, &CAPS).unwrap_err();
+        assert_eq!(err.code, ErrorCode::InvalidParams);
+        // `initialized` antes de `initialize` no adelanta el estado
+        session.initialized();
+        assert_eq!(session.state(), State::Uninitialized);
+    }
+}
@@ -0,0 +1,566 @@
+//! Herramientas MCP: registro en tiempo de ejecución, `tools/list` (paginado) y
+//! `tools/call`.
+//!
+//! El kernel y las aplicaciones del guest registran sus herramientas con
+//! [`register_tool`] y las retiran con [`unregister_tool`]; cada cambio del conjunto
+//! se avisa al cliente con `notifications/tools/list_changed` (ver
+//! [`take_list_changed`]). Cada [`ToolDef`] describe sus argumentos con un JSON
+//! Schema (`inputSchema`) que se publica tal cual en `tools/list` y con el que se
+//! comprueban los argumentos antes de llamar al manejador: campos obligatorios y
+//! tipo de cada propiedad.
+//!
+//! Los errores de protocolo (herramienta desconocida, argumentos no válidos) son
+//! errores JSON-RPC; los fallos de la propia herramienta se devuelven como
+//! `CallToolResult` con `isError: true` para que los vea el modelo.
+
+use crate::json::{self, Value, Writer};
+use crate::jsonrpc::{self, ErrorCode, Id, RpcError};
+use crate::session::Session;
+use alloc::format;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::fmt;
+use core::ptr::addr_of_mut;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// Pistas de comportamiento (`ToolAnnotations`, desde 2025-03-26).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ToolAnnotations {
+    /// No modifica nada.
+    pub read_only: bool,
+    /// Puede destruir datos (solo si no es `read_only`).
+    pub destructive: bool,
+    /// Repetir la llamada con los mismos argumentos no tiene más efecto.
+    pub idempotent: bool,
+    /// Habla con sistemas externos.
+    pub open_world: bool,
+}
+
+impl ToolAnnotations {
+    pub const READ_ONLY: ToolAnnotations =
+        ToolAnnotations { read_only: true, destructive: false, idempotent: true, open_world: false };
+}
+
+/// Descripción de una herramienta tal como la ve el cliente.
+#[derive(Debug, Clone, Copy)]
+pub struct ToolDef {
+    pub name: &'static str,
+    pub description: &'static str,
+    /// JSON Schema (`"type": "object"`) de `arguments`.
+    pub input_schema: &'static str,
+    /// JSON Schema del resultado si es un objeto de forma fija; se envía además
+    /// como `structuredContent`.
+    pub output_schema: Option<&'static str>,
+    pub annotations: ToolAnnotations,
+}
+
+/// Recibe el contexto de la llamada y `arguments` (texto JSON ya validado contra el
+/// esquema) y devuelve el resultado serializado.
+pub type ToolHandler = fn(&mut ToolContext<'_>, &[u8]) -> Result<Vec<u8>, RpcError>;
+
+/// Marca de cancelación de una petición; el manejador la consulta en los puntos en
+/// los que puede parar (ver [`ToolContext::check_cancelled`]).
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// La marca tal cual, para código que no conoce este tipo (`ai_runtime`).
+    pub fn flag(&self) -> &AtomicBool {
+        &self.0
+    }
+}
+
+/// Envío de `notifications/progress` para la petición en curso. Solo envía si el
+/// cliente pidió progreso con `_meta.progressToken`.
+pub struct ProgressSink {
+    token: Option<Id<'static>>,
+    send: fn(&[u8]) -> bool,
+}
+
+impl ProgressSink {
+    pub fn new(token: Option<Id<'static>>, send: fn(&[u8]) -> bool) -> Self {
+        ProgressSink { token, send }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.token.is_some()
+    }
+
+    /// Avisa de que se lleva `progress` de `total` (si se conoce). Devuelve `false`
+    /// si no se envió nada.
+    pub fn report(&self, progress: f64, total: Option<f64>, message: Option<&str>) -> bool {
+        let Some(token) = &self.token else { return false };
+        let mut w = Writer::new(Vec::new());
+        w.begin_object().key("progressToken");
+        token.write(&mut w);
+        w.key("progress").f64(progress);
+        if let Some(total) = total {
+            w.key("total").f64(total);
+        }
+        if let Some(message) = message {
+            w.key("message").string(message);
+        }
+        w.end_object();
+        (self.send)(&jsonrpc::notification("notifications/progress", Some(&w.into_inner())))
+    }
+}
+
+/// Lo que un manejador sabe de la petición que atiende.
+pub struct ToolContext<'a> {
+    pub session: &'a Session,
+    /// `None` si la llamada llegó como notificación (no habrá respuesta).
+    pub request_id: Option<&'a Id<'a>>,
+    pub cancel: CancellationToken,
+    pub progress: ProgressSink,
+    /// Lee sin bloquear lo que haya llegado por la conexión y aplica las cancelaciones.
+    pub poll: fn(&Session),
+}
+
+impl ToolContext<'_> {
+    /// Atiende las cancelaciones que hayan llegado e indica si esta petición se
+    /// canceló. Los manejadores largos la llaman entre pasos.
+    pub fn check_cancelled(&self) -> bool {
+        (self.poll)(self.session);
+        self.cancel.is_cancelled()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegistryError {
+    AlreadyRegistered,
+    InvalidName,
+    /// `input_schema` no es un objeto JSON con `"type": "object"`.
+    InvalidSchema,
+}
+
+impl RegistryError {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RegistryError::AlreadyRegistered => "mcp tools: name already registered",
+            RegistryError::InvalidName => "mcp tools: invalid name",
+            RegistryError::InvalidSchema => "mcp tools: inputSchema must be an object schema",
+        }
+    }
+}
+
+impl fmt::Display for RegistryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Herramientas por página de `tools/list`.
+pub const PAGE_SIZE: usize = 16;
+
+// El scheduler es cooperativo: nadie toca el registro mientras otra tarea lo usa.
+// Las llamadas copian la entrada antes de ejecutar el manejador, que puede registrar
+// o retirar herramientas.
+static mut REGISTRY: Vec<(ToolDef, ToolHandler)> = Vec::new();
+static LIST_CHANGED: AtomicBool = AtomicBool::new(false);
+
+fn registry() -> &'static mut Vec<(ToolDef, ToolHandler)> {
+    unsafe { &mut *addr_of_mut!(REGISTRY) }
+}
+
+/// Vacía el registro. Las pruebas que lo usan se serializan con el cerrojo devuelto.
+#[cfg(test)]
+pub(crate) fn reset() -> std::sync::MutexGuard<'static, ()> {
+    static LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+    let guard = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    registry().clear();
+    LIST_CHANGED.store(false, Ordering::Relaxed);
+    guard
+}
+
+/// Publica una herramienta. El nombre es único y solo admite `[A-Za-z0-9_.-]`.
+pub fn register_tool(def: ToolDef, handler: ToolHandler) -> Result<(), RegistryError> {
+    let valid_name = !def.name.is_empty()
+        && def.name.len() <= 64
+        && def.name.bytes().all(|b| b.is_ascii_alphanumeric() || b"_.-".contains(&b));
+    if !valid_name {
+        return Err(RegistryError::InvalidName);
+    }
+    let schema = json::parse(def.input_schema.as_bytes()).map_err(|_| RegistryError::InvalidSchema)?;
+    if !schema.get("type").and_then(|t| t.as_string()).is_some_and(|t| t.eq_str("object")) {
+        return Err(RegistryError::InvalidSchema);
+    }
+    let tools = registry();
+    if tools.iter().any(|(d, _)| d.name == def.name) {
+        return Err(RegistryError::AlreadyRegistered);
+    }
+    tools.push((def, handler));
+    LIST_CHANGED.store(true, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Retira una herramienta. Devuelve `false` si no estaba registrada.
+pub fn unregister_tool(name: &str) -> bool {
+    let tools = registry();
+    let Some(index) = tools.iter().position(|(d, _)| d.name == name) else { return false };
+    tools.remove(index);
+    LIST_CHANGED.store(true, Ordering::Relaxed);
+    true
+}
+
+/// Definición de una herramienta registrada.
+pub fn find(name: &str) -> Option<ToolDef> {
+    registry().iter().find(|(d, _)| d.name == name).map(|(d, _)| *d)
+}
+
+/// Indica (y olvida) si el conjunto de herramientas cambió desde la última consulta.
+pub fn take_list_changed() -> bool {
+    LIST_CHANGED.swap(false, Ordering::Relaxed)
+}
+
+fn invalid_params(message: impl Into<alloc::borrow::Cow<'static, str>>) -> RpcError {
+    RpcError::with_message(ErrorCode::InvalidParams, message)
+}
+
+/// `cursor` de una petición paginada: posición (en decimal) del primer elemento.
+pub fn parse_cursor(params: &[u8]) -> Result<usize, RpcError> {
+    let params = json::parse(params).map_err(|_| invalid_params("params must be an object"))?;
+    match params.get("cursor") {
+        None => Ok(0),
+        Some(cursor) => cursor
+            .as_str()
+            .and_then(|c| c.parse().ok())
+            .ok_or_else(|| invalid_params("invalid cursor")),
+    }
+}
+
+fn write_tool(w: &mut Writer<Vec<u8>>, tool: &ToolDef, annotations: bool) {
+    w.begin_object()
+        .key("name")
+        .string(tool.name)
+        .key("description")
+        .string(tool.description)
+        .key("inputSchema")
+        .raw(tool.input_schema.as_bytes());
+    if let Some(schema) = tool.output_schema {
+        w.key("outputSchema").raw(schema.as_bytes());
+    }
+    if annotations {
+        let a = &tool.annotations;
+        w.key("annotations")
+            .begin_object()
+            .key("readOnlyHint")
+            .bool(a.read_only)
+            .key("destructiveHint")
+            .bool(a.destructive)
+            .key("idempotentHint")
+            .bool(a.idempotent)
+            .key("openWorldHint")
+            .bool(a.open_world)
+            .end_object();
+    }
+    w.end_object();
+}
+
+/// Escribe la página de `items` que empieza en el `cursor` de `params` como array
+/// `key`, con `nextCursor` si quedan más. La usan todos los `*/list`.
+pub(crate) fn page<T>(
+    params: &[u8],
+    page_size: usize,
+    key: &str,
+    items: &[T],
+    mut write: impl FnMut(&mut Writer<Vec<u8>>, &T),
+) -> Result<Vec<u8>, RpcError> {
+    let start = parse_cursor(params)?;
+    if start > items.len() {
+        return Err(invalid_params("invalid cursor"));
+    }
+    let end = (start + page_size).min(items.len());
+    let mut w = Writer::new(Vec::new());
+    w.begin_object().key(key).begin_array();
+    for item in &items[start..end] {
+        write(&mut w, item);
+    }
+    w.end_array();
+    if end < items.len() {
+        w.key("nextCursor").string(&format!("{}", end));
+    }
+    w.end_object();
+    Ok(w.into_inner())
+}
+
+/// `ListToolsResult` con la página que empieza en `cursor`, en orden de registro.
+/// Las anotaciones solo existen desde la versión 2025-03-26 del protocolo.
+pub fn list(params: &[u8], page_size: usize, annotations: bool) -> Result<Vec<u8>, RpcError> {
+    page(params, page_size, "tools", registry(), |w, (tool, _)| write_tool(w, tool, annotations))
+}
+
+fn type_matches(expected: &str, value: &Value<'_>) -> bool {
+    match expected {
+        "string" => matches!(value, Value::String(_)),
+        "integer" => value.as_i64().is_some() || value.as_u64().is_some(),
+        "number" => matches!(value, Value::Number(_)),
+        "boolean" => matches!(value, Value::Bool(_)),
+        "object" => matches!(value, Value::Object(_)),
+        "array" => matches!(value, Value::Array(_)),
+        "null" => value.is_null(),
+        _ => true,
+    }
+}
+
+/// Comprueba `arguments` contra el `inputSchema`: objeto, propiedades obligatorias
+/// presentes y con el `type` declarado.
+pub fn validate(schema: &str, arguments: &Value<'_>) -> Result<(), RpcError> {
+    let schema = json::parse(schema.as_bytes()).map_err(|_| RpcError::new(ErrorCode::InternalError))?;
+    if arguments.as_object().is_none() {
+        return Err(invalid_params("arguments must be an object"));
+    }
+    for required in schema.get("required").and_then(|r| r.as_array()).unwrap_or(&[]) {
+        let Some(name) = required.as_str() else { continue };
+        if arguments.get(&name).is_none() {
+            return Err(invalid_params(format!("missing argument: {}", name)));
+        }
+    }
+    for (name, property) in schema.get("properties").and_then(|p| p.as_object()).unwrap_or(&[]) {
+        let name = name.to_cow();
+        let (Some(value), Some(expected)) = (arguments.get(&name), property.get("type").and_then(|t| t.as_str())) else {
+            continue;
+        };
+        if !type_matches(&expected, value) {
+            return Err(invalid_params(format!("argument {} must be of type {}", name, expected)));
+        }
+    }
+    Ok(())
+}
+
+/// `CallToolResult` de texto.
+pub fn text_result(text: &str, is_error: bool) -> Vec<u8> {
+    let mut w = Writer::new(Vec::new());
+    w.begin_object()
+        .key("content")
+        .begin_array()
+        .begin_object()
+        .key("type")
+        .string("text")
+        .key("text")
+        .string(text)
+        .end_object()
+        .end_array()
+        .key("isError")
+        .bool(is_error)
+        .end_object();
+    w.into_inner()
+}
+
+/// `_meta.progressToken` de los parámetros de una petición.
+pub fn progress_token(params: &[u8]) -> Option<Id<'static>> {
+    let params = json::parse(params).ok()?;
+    match params.get("_meta")?.get("progressToken")? {
+        Value::Number(n) => n.as_i64().map(Id::Number),
+        Value::String(s) => Some(Id::String(s.to_cow()).into_owned()),
+        _ => None,
+    }
+}
+
+/// `tools/call` con `{name, arguments}`. `send` escribe en la conexión las
+/// notificaciones que emita el manejador; `cancel` y `poll` le llegan en el
+/// [`ToolContext`].
+pub fn call(
+    session: &Session,
+    request_id: Option<&Id<'_>>,
+    params: &[u8],
+    send: fn(&[u8]) -> bool,
+    cancel: CancellationToken,
+    poll: fn(&Session),
+) -> Result<Vec<u8>, RpcError> {
+    let fields = json::raw_fields(params).map_err(|_| invalid_params("params must be an object"))?;
+    let field = |key: &str| fields.iter().find(|(k, _)| k.eq_str(key)).map(|(_, v)| *v);
+    let name = field("name")
+        .and_then(|n| json::parse(n).ok())
+        .and_then(|n| n.as_str())
+        .ok_or_else(|| invalid_params("missing tool name"))?;
+    let (tool, handler) = *registry()
+        .iter()
+        .find(|(d, _)| d.name == name)
+        .ok_or_else(|| invalid_params(format!("unknown tool: {}", name)))?;
+    let arguments = field("arguments").unwrap_or(b"{}");
+    let parsed = json::parse(arguments).map_err(|_| invalid_params("arguments must be an object"))?;
+    validate(tool.input_schema, &parsed)?;
+
+    let request_id = request_id.cloned();
+    let mut ctx = ToolContext {
+        session,
+        request_id: request_id.as_ref(),
+        cancel,
+        progress: ProgressSink::new(progress_token(params), send),
+        poll,
+    };
+    let output = match handler(&mut ctx, arguments) {
+        Ok(output) => output,
+        // Argumentos que el esquema no puede expresar: sigue siendo un error de protocolo.
+        // Una petición cancelada no se contesta.
+        Err(e) if matches!(e.code, ErrorCode::InvalidParams | ErrorCode::RequestCancelled) => return Err(e),
+        Err(e) => return Ok(text_result(&e.message, true)),
+    };
+    let value = json::parse(&output).map_err(|_| RpcError::with_message(ErrorCode::InternalError, "tool returned invalid JSON"))?;
+    // Una cadena se envía como texto; cualquier otro valor, como su JSON
+    let mut w = Writer::new(Vec::new());
+    w.begin_object().key("content").begin_array().begin_object().key("type").string("text").key("text");
+    match value.as_str() {
+        Some(text) => w.string(&text),
+        None => w.string(core::str::from_utf8(&output).unwrap_or_default()),
+    };
+    w.end_object().end_array();
+    if tool.output_schema.is_some() {
+        w.key("structuredContent").raw(&output);
+    }
+    w.key("isError").bool(false).end_object();
+    Ok(w.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::String;
+    use std::sync::Mutex;
+
+    static SENT: Mutex<Vec<Vec<u8>>> = Mutex::new(Vec::new());
+
+    fn capture(frame: &[u8]) -> bool {
+        SENT.lock().unwrap().push(frame.to_vec());
+        true
+    }
+
+    fn add(ctx: &mut ToolContext<'_>, args: &[u8]) -> Result<Vec<u8>, RpcError> {
+        let args = json::parse(args).unwrap();
+        let (a, b) = (args.get("a").unwrap().as_i64().unwrap(), args.get("b").unwrap().as_i64().unwrap());
+        if b == 0 {
+            return Err(RpcError::with_message(ErrorCode::InternalError, "b must not be zero"));
+        }
+        ctx.progress.report(1.0, Some(1.0), Some("sumado"));
+        Ok(format!("{{\"sum\":{}}}", a + b).into_bytes())
+    }
+
+    fn echo(_ctx: &mut ToolContext<'_>, args: &[u8]) -> Result<Vec<u8>, RpcError> {
+        let args = json::parse(args).unwrap();
+        let mut w = Writer::new(Vec::new());
+        w.string(&args.get("text").and_then(|t| t.as_str()).unwrap_or_default());
+        Ok(w.into_inner())
+    }
+
+    /// Se retira a sí misma y registra `echo` en su lugar.
+    fn swap(_ctx: &mut ToolContext<'_>, _args: &[u8]) -> Result<Vec<u8>, RpcError> {
+        unregister_tool("swap");
+        register_tool(ECHO, echo).unwrap();
+        Ok(b"null".to_vec())
+    }
+
+    const ADD: ToolDef = ToolDef {
+        name: "add",
+        description: "Suma",
+        input_schema: r#"{"type":"object","properties":{"a":{"type":"integer"},"b":{"type":"integer"}},"required":["a","b"]}"#,
+        output_schema: Some(r#"{"type":"object","properties":{"sum":{"type":"integer"}}}"#),
+        annotations: ToolAnnotations::READ_ONLY,
+    };
+
+    const ECHO: ToolDef = ToolDef {
+        name: "echo",
+        description: "Eco",
+        input_schema: r#"{"type":"object","properties":{"text":{"type":"string"}}}"#,
+        output_schema: None,
+        annotations: ToolAnnotations::READ_ONLY,
+    };
+
+    fn text(result: Vec<u8>) -> String {
+        String::from_utf8(result).unwrap()
+    }
+
+    fn no_poll(_session: &Session) {}
+
+    fn call_text(params: &[u8]) -> Result<String, RpcError> {
+        call(&Session::new(), Some(&Id::Number(1)), params, capture, CancellationToken::new(), no_poll).map(text)
+    }
+
+    #[test]
+    fn registry_tracks_changes() {
+        let _guard = reset();
+        register_tool(ADD, add).unwrap();
+        assert!(take_list_changed());
+        assert!(!take_list_changed());
+        assert_eq!(register_tool(ADD, add), Err(RegistryError::AlreadyRegistered));
+        assert_eq!(register_tool(ToolDef { name: "a b", ..ECHO }, echo), Err(RegistryError::InvalidName));
+        assert_eq!(register_tool(ToolDef { input_schema: "[]", ..ECHO }, echo), Err(RegistryError::InvalidSchema));
+        assert!(!take_list_changed());
+
+        register_tool(ToolDef { name: "swap", ..ECHO }, swap).unwrap();
+        call_text(br#"{"name":"swap"}"#).unwrap();
+        assert!(find("swap").is_none() && find("echo").is_some());
+        assert!(take_list_changed());
+        assert!(unregister_tool("echo") && !unregister_tool("echo"));
+        assert_eq!(call_text(br#"{"name":"echo"}"#).unwrap_err().code, ErrorCode::InvalidParams);
+    }
+
+    #[test]
+    fn list_paginates() {
+        let _guard = reset();
+        register_tool(ADD, add).unwrap();
+        register_tool(ECHO, echo).unwrap();
+        let page = text(list(b"{}", 1, true).unwrap());
+        assert_eq!(
+            page,
+            concat!(
+                r#"{"tools":[{"name":"add","description":"Suma","#,
+                r#""inputSchema":{"type":"object","properties":{"a":{"type":"integer"},"b":{"type":"integer"}},"required":["a","b"]},"#,
+                r#""outputSchema":{"type":"object","properties":{"sum":{"type":"integer"}}},"#,
+                r#""annotations":{"readOnlyHint":true,"destructiveHint":false,"idempotentHint":true,"openWorldHint":false}}],"#,
+                r#""nextCursor":"1"}"#
+            )
+        );
+        let page = text(list(br#"{"cursor":"1"}"#, 1, false).unwrap());
+        assert!(page.starts_with(r#"{"tools":[{"name":"echo""#) && !page.contains("nextCursor") && !page.contains("annotations"));
+        assert_eq!(list(br#"{"cursor":"x"}"#, 1, true).unwrap_err().code, ErrorCode::InvalidParams);
+        assert_eq!(list(br#"{"cursor":"9"}"#, 1, true).unwrap_err().code, ErrorCode::InvalidParams);
+    }
+
+    #[test]
+    fn call_validates_and_wraps_results() {
+        let _guard = reset();
+        register_tool(ADD, add).unwrap();
+        register_tool(ECHO, echo).unwrap();
+        SENT.lock().unwrap().clear();
+        assert_eq!(
+            call_text(br#"{"name":"add","arguments":{"a":2,"b":3}}"#).unwrap(),
+            r#"{"content":[{"type":"text","text":"{\"sum\":5}"}],"structuredContent":{"sum":5},"isError":false}"#
+        );
+        // Sin progressToken no se notifica nada
+        assert!(SENT.lock().unwrap().is_empty());
+        call_text(br#"{"name":"add","arguments":{"a":2,"b":3},"_meta":{"progressToken":"p1"}}"#).unwrap();
+        assert_eq!(
+            text(SENT.lock().unwrap().pop().unwrap()),
+            r#"{"jsonrpc":"2.0","method":"notifications/progress","params":{"progressToken":"p1","progress":1,"total":1,"message":"sumado"}}"#
+        );
+        assert_eq!(
+            call_text(br#"{"name":"echo","arguments":{"text":"hola"}}"#).unwrap(),
+            r#"{"content":[{"type":"text","text":"hola"}],"isError":false}"#
+        );
+        // Fallo de la herramienta: resultado con isError
+        assert_eq!(
+            call_text(br#"{"name":"add","arguments":{"a":2,"b":0}}"#).unwrap(),
+            r#"{"content":[{"type":"text","text":"b must not be zero"}],"isError":true}"#
+        );
+        // Errores de protocolo
+        let err = call_text(br#"{"name":"add","arguments":{"a":2}}"#).unwrap_err();
+        assert_eq!((err.code, &*err.message), (ErrorCode::InvalidParams, "missing argument: b"));
+        let err = call_text(br#"{"name":"add","arguments":{"a":2,"b":"3"}}"#).unwrap_err();
+        assert_eq!(&*err.message, "argument b must be of type integer");
+        let err = call_text(br#"{"name":"nope"}"#).unwrap_err();
+        assert_eq!(&*err.message, "unknown tool: nope");
+        assert!(call_text(br#"{"name":"echo","arguments":[1]}"#).is_err());
+    }
+}
@@ -1,43 +1,211 @@
 #![no_std]
 
+extern crate alloc;
+
 pub mod vsock_transport {
-    pub fn init() {
-        // Si se requiere log, usar el sistema de logging global o dejar vacío
-        // log::info!("[mcp-vsock] Transporte MCP/vsock inicializado");
+    use alloc::vec::Vec;
+    use core::fmt;
+    use core::sync::atomic::{AtomicU32, Ordering};
+    use drivers_virtio::vsock::{self, VsockError};
+
+    pub use drivers_virtio::vsock::{ConnectionId, VMADDR_CID_HOST};
+
+    /// Puerto vsock en el que escucha el servidor MCP.
+    pub const MCP_VSOCK_PORT: u32 = 5000;
+    const MAX_FRAME: usize = 1024 * 1024;
+    /// Esperas sin datos antes de dar por perdida una trama a medio recibir.
+    const READ_SPIN_LIMIT: usize = 1_000_000;
+
+    /// Fallo de una conexión al leer o escribir tramas; la conexión ya no es usable.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum FrameError {
+        Vsock(VsockError),
+        /// La trama no cabe en el buffer o supera el máximo.
+        TooLarge,
+        /// El otro extremo dejó de enviar (o de consumir) a mitad de una trama.
+        Stalled,
     }
 
-    use drivers_virtio::vsock;
+    impl FrameError {
+        pub fn as_str(&self) -> &'static str {
+            match self {
+                FrameError::Vsock(e) => e.as_str(),
+                FrameError::TooLarge => "mcp frame too large",
+                FrameError::Stalled => "mcp frame stalled",
+            }
+        }
+    }
 
-    /// Framing MCP: lectura y escritura de mensajes length-prefixed (u32 big-endian)
-    pub fn read_frame(buf: &mut [u8]) -> Option<&[u8]> {
+    impl fmt::Display for FrameError {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str(self.as_str())
+        }
+    }
+
+    static mut CONNECTION: Option<ConnectionId> = None;
+    /// Conexiones aceptadas hasta ahora (ver [`generation`]).
+    static GENERATION: AtomicU32 = AtomicU32::new(0);
+
+    pub fn init() -> Result<(), VsockError> {
+        vsock::listen(MCP_VSOCK_PORT)
+    }
+
+    /// Conexión MCP actual; si no hay ninguna, recoge la siguiente del host.
+    fn connection() -> Option<ConnectionId> {
+        let current = unsafe { &mut *core::ptr::addr_of_mut!(CONNECTION) };
+        if current.is_none() {
+            *current = vsock::accept(MCP_VSOCK_PORT);
+            if current.is_some() {
+                GENERATION.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        *current
+    }
+
+    /// Cambia cada vez que se acepta una conexión nueva; el servidor MCP empieza
+    /// entonces una sesión nueva.
+    pub fn generation() -> u32 {
+        GENERATION.load(Ordering::Relaxed)
+    }
+
+    /// Cierra la conexión actual (el host cerró o el flujo quedó desincronizado).
+    fn drop_connection(conn: ConnectionId) {
+        vsock::close(conn);
+        unsafe { CONNECTION = None; }
+    }
+
+    fn read_exact(conn: ConnectionId, buf: &mut [u8]) -> Result<(), FrameError> {
+        let mut filled = 0;
+        let mut spins = 0;
+        while filled < buf.len() {
+            match vsock::recv(conn, &mut buf[filled..]).map_err(FrameError::Vsock)? {
+                0 => {
+                    spins += 1;
+                    if spins >= READ_SPIN_LIMIT {
+                        return Err(FrameError::Stalled);
+                    }
+                    core::hint::spin_loop();
+                }
+                n => {
+                    filled += n;
+                    spins = 0;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn write_all(conn: ConnectionId, data: &[u8]) -> Result<(), FrameError> {
+        let mut sent = 0;
+        let mut spins = 0;
+        while sent < data.len() {
+            match vsock::send(conn, &data[sent..]).map_err(FrameError::Vsock)? {
+                0 => {
+                    // Sin crédito en el otro extremo: se espera a que consuma
+                    spins += 1;
+                    if spins >= READ_SPIN_LIMIT {
+                        return Err(FrameError::Stalled);
+                    }
+                    core::hint::spin_loop();
+                }
+                n => {
+                    sent += n;
+                    spins = 0;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Longitud de la siguiente trama de `conn`; `Ok(None)` sin bloquear si no hay
+    /// ninguna empezada.
+    fn recv_len(conn: ConnectionId) -> Result<Option<usize>, FrameError> {
         let mut header = [0u8; 4];
-        let n = vsock::recv(&mut header);
-        if n != Some(4) { return None; }
+        let n = vsock::recv(conn, &mut header).map_err(FrameError::Vsock)?;
+        if n == 0 {
+            return Ok(None);
+        }
+        read_exact(conn, &mut header[n..])?;
         let len = u32::from_be_bytes(header) as usize;
-        if len > buf.len() || len > 1024 * 1024 { return None; }
-        let n = vsock::recv(&mut buf[..len]);
-        if n != Some(len) { return None; }
-        Some(&buf[..len])
+        if len > MAX_FRAME {
+            // El resto de la trama no se puede descartar de forma fiable
+            return Err(FrameError::TooLarge);
+        }
+        Ok(Some(len))
+    }
+
+    /// Framing MCP: lectura y escritura de mensajes length-prefixed (u32 big-endian).
+    ///
+    /// Lee una trama de `conn` en `buf`; `Ok(None)` sin bloquear si no hay ninguna
+    /// empezada. Tras un error el flujo ya no es fiable y hay que cerrar la conexión.
+    pub fn recv_frame(conn: ConnectionId, buf: &mut [u8]) -> Result<Option<&[u8]>, FrameError> {
+        let Some(len) = recv_len(conn)? else { return Ok(None) };
+        if len > buf.len() {
+            return Err(FrameError::TooLarge);
+        }
+        read_exact(conn, &mut buf[..len])?;
+        Ok(Some(&buf[..len]))
+    }
+
+    /// Como [`recv_frame`], pero en un buffer del tamaño de la trama (hasta `MAX_FRAME`).
+    pub fn recv_frame_vec(conn: ConnectionId) -> Result<Option<Vec<u8>>, FrameError> {
+        let Some(len) = recv_len(conn)? else { return Ok(None) };
+        let mut buf = Vec::new();
+        // Sin heap para la trama: se trata como una trama demasiado grande
+        buf.try_reserve_exact(len).map_err(|_| FrameError::TooLarge)?;
+        buf.resize(len, 0);
+        read_exact(conn, &mut buf)?;
+        Ok(Some(buf))
+    }
+
+    /// Escribe `json` como una trama en `conn`.
+    pub fn send_frame(conn: ConnectionId, json: &[u8]) -> Result<(), FrameError> {
+        if json.len() > MAX_FRAME {
+            return Err(FrameError::TooLarge);
+        }
+        write_all(conn, &(json.len() as u32).to_be_bytes())?;
+        write_all(conn, json)
+    }
+
+    /// Abre una conexión MCP saliente al puerto `port` de `cid` (el host u otra VM);
+    /// el guest hace de cliente (ver `mcp_core::client`).
+    pub fn connect(cid: u64, port: u32) -> Result<ConnectionId, FrameError> {
+        vsock::connect(cid, port).map_err(FrameError::Vsock)
+    }
+
+    pub fn close(conn: ConnectionId) {
+        vsock::close(conn);
+    }
+
+    /// Siguiente trama de la conexión del servidor. Devuelve `None` sin bloquear si
+    /// no hay ninguna trama empezada.
+    pub fn read_frame() -> Option<Vec<u8>> {
+        let conn = connection()?;
+        match recv_frame_vec(conn) {
+            Ok(frame) => frame,
+            Err(_) => {
+                drop_connection(conn);
+                None
+            }
+        }
     }
 
     pub fn write_frame(json: &[u8]) -> bool {
-        if json.len() > 1024 * 1024 { return false; }
-        let mut frame = [0u8; 4096]; // 4 KiB, suficiente para la mayoría de mensajes
-        let len = (json.len() as u32).to_be_bytes();
-        frame[..4].copy_from_slice(&len);
-        frame[4..4+json.len()].copy_from_slice(json);
-        vsock::send(&frame[..4+json.len()])
+        if json.len() > MAX_FRAME { return false; }
+        let Some(conn) = connection() else { return false };
+        if send_frame(conn, json).is_err() {
+            drop_connection(conn);
+            return false;
+        }
+        true
     }
 
     pub fn frame_message<'a>(json: &'a [u8], out: &'a mut [u8]) -> Option<&'a [u8]> {
-        if json.len() > 1024 * 1024 { return None; }
+        if json.len() > MAX_FRAME { return None; }
         if out.len() < json.len() + 4 { return None; }
         let len = json.len() as u32;
         out[0..4].copy_from_slice(&len.to_be_bytes());
         out[4..4+json.len()].copy_from_slice(json);
         Some(&out[..json.len()+4])
     }
-
-    // pub use read_frame;
-    // pub use write_frame;
 }
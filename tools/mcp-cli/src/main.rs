@@ -1,40 +1,233 @@
+//! Cliente MCP de pruebas: abre una sesión con el servidor del guest (`initialize` y
+//! `notifications/initialized`), envía una petición JSON-RPC 2.0 y muestra el
+//! resultado o el error.
+//!
+//! Uso: `mcp-cli [herramienta|método] [json]`. Un nombre sin `/` (`health`, `infer`,
+//! ...) se llama con `tools/call` y el JSON como `arguments`, pidiendo progreso: los
+//! tokens de `infer` se muestran según llegan. Un método (`tools/list`,
+//! `ping`, `resources/read`, `prompts/get`, ...) se envía tal cual con el JSON como `params`. Sin
+//! argumentos llama a `infer` con un prompt de prueba.
+
 #[cfg(unix)]
 use std::os::unix::net::UnixStream;
 #[cfg(windows)]
 use std::net::TcpStream;
-use std::io::{Write, Read};
-
-fn main() {
-    #[cfg(unix)]
-    {
-        let mut stream = UnixStream::connect("/tmp/vm.sock").expect("No se pudo conectar al vsock socket");
-        let infer_req = r#"{\"method\":\"infer\",\"params\":{\"prompt\":\"Hola AI desde host\"}}"#;
-        let mut frame = Vec::with_capacity(4 + infer_req.len());
-        let len = (infer_req.len() as u32).to_be_bytes();
-        frame.extend_from_slice(&len);
-        frame.extend_from_slice(infer_req.as_bytes());
-        stream.write_all(&frame).expect("Error enviando frame");
-        let mut resp_len = [0u8; 4];
-        stream.read_exact(&mut resp_len).expect("Error leyendo longitud de respuesta");
-        let resp_len = u32::from_be_bytes(resp_len) as usize;
-        let mut resp = vec![0u8; resp_len];
-        stream.read_exact(&mut resp).expect("Error leyendo respuesta");
-        println!("Respuesta infer: {}", String::from_utf8_lossy(&resp));
+use std::io::{self, Write, Read};
+use std::process::ExitCode;
+
+use serde_json::{json, Value};
+
+/// Versión del protocolo MCP que pide el cliente.
+const PROTOCOL_VERSION: &str = "2025-03-26";
+
+#[cfg(unix)]
+fn connect() -> io::Result<UnixStream> {
+    UnixStream::connect("/tmp/vm.sock")
+}
+
+#[cfg(windows)]
+fn connect() -> io::Result<TcpStream> {
+    TcpStream::connect("127.0.0.1:5000")
+}
+
+/// Trama length-prefixed (u32 big-endian), igual que `mcp_vsock_transport`.
+fn send_frame(stream: &mut impl Write, json: &[u8]) -> io::Result<()> {
+    let mut frame = Vec::with_capacity(4 + json.len());
+    frame.extend_from_slice(&(json.len() as u32).to_be_bytes());
+    frame.extend_from_slice(json);
+    stream.write_all(&frame)
+}
+
+fn read_frame(stream: &mut impl Read) -> io::Result<Vec<u8>> {
+    let mut len = [0u8; 4];
+    stream.read_exact(&mut len)?;
+    let mut resp = vec![0u8; u32::from_be_bytes(len) as usize];
+    stream.read_exact(&mut resp)?;
+    Ok(resp)
+}
+
+/// Nombre del código de error JSON-RPC/MCP (ver `mcp_core::jsonrpc::ErrorCode`).
+fn error_name(code: i64) -> &'static str {
+    match code {
+        -32700 => "parse error",
+        -32600 => "invalid request",
+        -32601 => "method not found",
+        -32602 => "invalid params",
+        -32603 => "internal error",
+        -32001 => "request timeout",
+        -32002 => "resource not found",
+        _ => "error",
+    }
+}
+
+fn send(stream: &mut (impl Read + Write), message: &Value) -> Result<(), String> {
+    send_frame(stream, message.to_string().as_bytes()).map_err(|e| format!("error enviando la petición: {}", e))
+}
+
+fn receive(stream: &mut (impl Read + Write)) -> Result<Value, String> {
+    let resp = read_frame(stream).map_err(|e| format!("error leyendo la respuesta: {}", e))?;
+    serde_json::from_slice(&resp)
+        .map_err(|e| format!("respuesta no es JSON válido ({}): {}", e, String::from_utf8_lossy(&resp)))
+}
+
+/// Espera la respuesta (o el lote de respuestas) de la petición enviada. Mientras,
+/// muestra el `message` de cada `notifications/progress` sin salto de línea (los
+/// tokens de `infer`) e ignora las demás notificaciones. Devuelve la respuesta y si
+/// se mostró progreso.
+fn receive_response(stream: &mut (impl Read + Write)) -> Result<(Value, bool), String> {
+    let mut streamed = false;
+    loop {
+        let message = receive(stream)?;
+        if message.get("id").is_some() || message.is_array() {
+            return Ok((message, streamed));
+        }
+        match message.get("method").and_then(Value::as_str) {
+            Some("notifications/progress") => {
+                if let Some(text) = message.pointer("/params/message").and_then(Value::as_str) {
+                    print!("{}", text);
+                    let _ = io::stdout().flush();
+                    streamed = true;
+                }
+            }
+            // Logs del kernel tras `logging/setLevel`
+            Some("notifications/message") => {
+                let field = |name: &str| message.pointer(&format!("/params/{}", name)).and_then(Value::as_str).unwrap_or("?");
+                eprintln!("[{}] {}: {}", field("level"), field("logger"), field("data"));
+            }
+            _ => {}
+        }
     }
-    #[cfg(windows)]
-    {
-        let mut stream = TcpStream::connect("127.0.0.1:5000").expect("No se pudo conectar al servidor TCP (usa el puerto adecuado)");
-        let infer_req = r#"{\"method\":\"infer\",\"params\":{\"prompt\":\"Hola AI desde host\"}}"#;
-        let mut frame = Vec::with_capacity(4 + infer_req.len());
-        let len = (infer_req.len() as u32).to_be_bytes();
-        frame.extend_from_slice(&len);
-        frame.extend_from_slice(infer_req.as_bytes());
-        stream.write_all(&frame).expect("Error enviando frame");
-        let mut resp_len = [0u8; 4];
-        stream.read_exact(&mut resp_len).expect("Error leyendo longitud de respuesta");
-        let resp_len = u32::from_be_bytes(resp_len) as usize;
-        let mut resp = vec![0u8; resp_len];
-        stream.read_exact(&mut resp).expect("Error leyendo respuesta");
-        println!("Respuesta infer: {}", String::from_utf8_lossy(&resp));
+}
+
+/// `initialize` + `notifications/initialized`.
+fn handshake(stream: &mut (impl Read + Write)) -> Result<(), String> {
+    let init = json!({
+        "jsonrpc": "2.0",
+        "id": 0,
+        "method": "initialize",
+        "params": {
+            "protocolVersion": PROTOCOL_VERSION,
+            "capabilities": {},
+            "clientInfo": {"name": "mcp-cli", "version": env!("CARGO_PKG_VERSION")}
+        }
+    });
+    send(stream, &init)?;
+    let (resp, _) = receive_response(stream)?;
+    let Some(result) = resp.get("result") else {
+        print_response(&resp, false);
+        return Err("initialize rechazado".to_string());
+    };
+    let version = result.get("protocolVersion").and_then(Value::as_str).unwrap_or("?");
+    if version != PROTOCOL_VERSION {
+        return Err(format!("el servidor solo ofrece la versión {} del protocolo", version));
+    }
+    send(stream, &json!({"jsonrpc": "2.0", "method": "notifications/initialized"}))
+}
+
+/// Muestra una respuesta; devuelve `false` si es un error. Con `streamed`, el texto de
+/// un `CallToolResult` correcto ya se mostró como progreso y solo se cierra la línea.
+fn print_response(resp: &Value, streamed: bool) -> bool {
+    if let Some(result) = resp.get("result") {
+        // `CallToolResult`: se muestra el texto; `isError` es un fallo de la herramienta
+        if let Some(content) = result.get("content").and_then(Value::as_array) {
+            let is_error = result.get("isError").and_then(Value::as_bool).unwrap_or(false);
+            if streamed {
+                println!();
+                if !is_error {
+                    return true;
+                }
+            }
+            for item in content {
+                match item.get("text").and_then(Value::as_str) {
+                    Some(text) if is_error => eprintln!("error de la herramienta: {}", text),
+                    Some(text) => println!("{}", text),
+                    None => println!("{}", item),
+                }
+            }
+            return !is_error;
+        }
+        // `ReadResourceResult`: el texto tal cual; los binarios, solo su tamaño en base64
+        if let Some(contents) = result.get("contents").and_then(Value::as_array) {
+            for item in contents {
+                let uri = item.get("uri").and_then(Value::as_str).unwrap_or("?");
+                match (item.get("text").and_then(Value::as_str), item.get("blob").and_then(Value::as_str)) {
+                    (Some(text), _) => println!("{}", text),
+                    (None, Some(blob)) => println!("{}: binario, {} caracteres en base64", uri, blob.len()),
+                    (None, None) => println!("{}", item),
+                }
+            }
+            return true;
+        }
+        // `GetPromptResult`: cada mensaje con su rol
+        if let Some(messages) = result.get("messages").and_then(Value::as_array) {
+            for message in messages {
+                let role = message.get("role").and_then(Value::as_str).unwrap_or("?");
+                match message.get("content").and_then(|c| c.get("text")).and_then(Value::as_str) {
+                    Some(text) => println!("[{}] {}", role, text),
+                    None => println!("[{}] {}", role, message.get("content").unwrap_or(&Value::Null)),
+                }
+            }
+            return true;
+        }
+        // `CompleteResult`: un valor por línea y, si hay más, cuántos faltan
+        if let Some(completion) = result.get("completion") {
+            let values = completion.get("values").and_then(Value::as_array).map(Vec::as_slice).unwrap_or_default();
+            for value in values {
+                println!("{}", value.as_str().unwrap_or_default());
+            }
+            if completion.get("hasMore").and_then(Value::as_bool) == Some(true) {
+                let total = completion.get("total").and_then(Value::as_u64).unwrap_or(0);
+                println!("... ({} en total)", total);
+            }
+            return true;
+        }
+        println!("{}", serde_json::to_string_pretty(result).unwrap_or_default());
+        return true;
+    }
+    match resp.get("error") {
+        Some(error) => {
+            let code = error.get("code").and_then(Value::as_i64).unwrap_or(0);
+            let message = error.get("message").and_then(Value::as_str).unwrap_or("(sin mensaje)");
+            eprintln!("error {} ({}): {}", code, error_name(code), message);
+        }
+        None => eprintln!("respuesta JSON-RPC no válida: {}", resp),
+    }
+    false
+}
+
+fn run() -> Result<bool, String> {
+    let mut args = std::env::args().skip(1);
+    let name = args.next().unwrap_or_else(|| "infer".to_string());
+    let params: Value = match args.next() {
+        Some(text) => serde_json::from_str(&text).map_err(|e| format!("el argumento no es JSON válido: {}", e))?,
+        None if name == "infer" => json!({"prompt": "Hola AI desde host"}),
+        None => json!({}),
+    };
+    let request = if name.contains('/') || name == "ping" {
+        json!({"jsonrpc": "2.0", "id": 1, "method": name, "params": params})
+    } else {
+        let meta = json!({"progressToken": 1});
+        json!({"jsonrpc": "2.0", "id": 1, "method": "tools/call", "params": {"name": name, "arguments": params, "_meta": meta}})
+    };
+
+    let mut stream = connect().map_err(|e| format!("no se pudo conectar al servidor MCP: {}", e))?;
+    handshake(&mut stream)?;
+    send(&mut stream, &request)?;
+    let (resp, streamed) = receive_response(&mut stream)?;
+    match resp.as_array() {
+        // Se muestran todas las respuestas del lote, aunque alguna sea un error
+        Some(batch) => Ok(batch.iter().filter(|r| !print_response(r, false)).count() == 0),
+        None => Ok(print_response(&resp, streamed)),
+    }
+}
+
+fn main() -> ExitCode {
+    match run() {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => ExitCode::FAILURE,
+        Err(e) => {
+            eprintln!("mcp-cli: {}", e);
+            ExitCode::FAILURE
+        }
     }
 }
@@ -0,0 +1,317 @@
+#![cfg_attr(not(test), no_std)]
+//! VFS-lite del kernel: un trait `FileSystem` común y una tabla de montaje por prefijo.
+//!
+//! `ai_runtime::load_model` y las lecturas de recursos MCP resuelven rutas a través de
+//! este crate, sin saber si el fichero está en virtio-fs o en una imagen de solo
+//! lectura sobre virtio-blk.
+
+extern crate alloc;
+
+pub mod tar;
+pub mod virtiofs;
+
+use core::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VfsError {
+    NotFound,
+    NotADirectory,
+    IsADirectory,
+    /// Ningún sistema de ficheros montado cubre la ruta.
+    NotMounted,
+    InvalidPath,
+    /// El fichero no cabe en el buffer del llamador.
+    BufferTooSmall,
+    /// El backend no implementa la operación (p. ej. proyección sin DAX).
+    Unsupported,
+    /// Imagen o respuesta del backend mal formada.
+    Corrupt,
+    /// Error de E/S del backend.
+    Io(&'static str),
+}
+
+impl VfsError {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            VfsError::NotFound => "vfs: not found",
+            VfsError::NotADirectory => "vfs: not a directory",
+            VfsError::IsADirectory => "vfs: is a directory",
+            VfsError::NotMounted => "vfs: no filesystem mounted",
+            VfsError::InvalidPath => "vfs: invalid path",
+            VfsError::BufferTooSmall => "vfs: buffer too small",
+            VfsError::Unsupported => "vfs: unsupported",
+            VfsError::Corrupt => "vfs: corrupt filesystem",
+            VfsError::Io(msg) => msg,
+        }
+    }
+}
+
+impl fmt::Display for VfsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileKind {
+    File,
+    Dir,
+    Other,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Metadata {
+    pub size: u64,
+    pub kind: FileKind,
+    pub mode: u32,
+    pub mtime: u64,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct DirEntry<'a> {
+    pub name: &'a str,
+    pub kind: FileKind,
+}
+
+/// Handle opaco: cada backend decide qué guarda en `id` y `aux`.
+#[derive(Debug, Clone, Copy)]
+pub struct FileHandle {
+    pub id: u64,
+    pub aux: u64,
+    pub size: u64,
+}
+
+/// Operaciones mínimas que ofrece un sistema de ficheros montado. Las rutas que
+/// recibe son relativas al punto de montaje y empiezan por `/`.
+pub trait FileSystem {
+    fn name(&self) -> &'static str;
+    fn open(&mut self, path: &str) -> Result<FileHandle, VfsError>;
+    fn read(&mut self, handle: &FileHandle, offset: u64, buf: &mut [u8]) -> Result<usize, VfsError>;
+    fn close(&mut self, handle: FileHandle);
+    fn stat(&mut self, path: &str) -> Result<Metadata, VfsError>;
+    /// Llama a `f` por cada entrada de `path` (sin `.` ni `..`) hasta que devuelva `false`.
+    fn list(&mut self, path: &str, f: &mut dyn FnMut(&DirEntry) -> bool) -> Result<usize, VfsError>;
+
+    /// Proyecta el fichero en memoria sin copiarlo (p. ej. ventana DAX de virtio-fs).
+    fn map(&mut self, _path: &str) -> Result<&'static [u8], VfsError> {
+        Err(VfsError::Unsupported)
+    }
+
+    fn unmap(&mut self, _data: &'static [u8]) -> Result<(), VfsError> {
+        Err(VfsError::Unsupported)
+    }
+}
+
+const MAX_MOUNTS: usize = 4;
+
+struct Mount {
+    prefix: &'static str,
+    fs: &'static mut dyn FileSystem,
+}
+
+static mut MOUNTS: [Option<Mount>; MAX_MOUNTS] = [None, None, None, None];
+
+fn mounts() -> &'static mut [Option<Mount>; MAX_MOUNTS] {
+    unsafe { &mut *core::ptr::addr_of_mut!(MOUNTS) }
+}
+
+/// Monta `fs` en `prefix` (p. ej. `/` o `/blk`). Un prefijo ya montado se reemplaza.
+pub fn mount(prefix: &'static str, fs: &'static mut dyn FileSystem) -> Result<(), VfsError> {
+    if !prefix.starts_with('/') {
+        return Err(VfsError::InvalidPath);
+    }
+    let table = mounts();
+    let slot = match table.iter().position(|m| matches!(m, Some(m) if m.prefix == prefix)) {
+        Some(idx) => idx,
+        None => table.iter().position(|m| m.is_none()).ok_or(VfsError::Unsupported)?,
+    };
+    table[slot] = Some(Mount { prefix, fs });
+    Ok(())
+}
+
+pub fn unmount(prefix: &str) {
+    for slot in mounts().iter_mut() {
+        if matches!(slot, Some(m) if m.prefix == prefix) {
+            *slot = None;
+        }
+    }
+}
+
+pub fn is_mounted(prefix: &str) -> bool {
+    mounts().iter().flatten().any(|m| m.prefix == prefix)
+}
+
+/// Recorre los montajes activos (prefijo y nombre del backend).
+pub fn for_each_mount(mut f: impl FnMut(&'static str, &'static str)) {
+    for m in mounts().iter().flatten() {
+        f(m.prefix, m.fs.name());
+    }
+}
+
+/// Busca el montaje con el prefijo más largo que cubre `path`. Devuelve el índice y
+/// la ruta relativa al montaje.
+fn resolve(path: &str) -> Result<(usize, &str), VfsError> {
+    if !path.starts_with('/') {
+        return Err(VfsError::InvalidPath);
+    }
+    let mut best: Option<(usize, usize)> = None;
+    for (idx, m) in mounts().iter().enumerate() {
+        let Some(m) = m else { continue };
+        let prefix = m.prefix.trim_end_matches('/');
+        let covers = path.starts_with(prefix)
+            && (path.len() == prefix.len() || path.as_bytes()[prefix.len()] == b'/');
+        if covers && best.is_none_or(|(_, len)| prefix.len() > len) {
+            best = Some((idx, prefix.len()));
+        }
+    }
+    let (idx, len) = best.ok_or(VfsError::NotMounted)?;
+    let rest = &path[len..];
+    Ok((idx, if rest.is_empty() { "/" } else { rest }))
+}
+
+fn fs_at(idx: usize) -> Result<&'static mut dyn FileSystem, VfsError> {
+    match mounts()[idx].as_mut() {
+        Some(m) => Ok(&mut *m.fs),
+        None => Err(VfsError::NotMounted),
+    }
+}
+
+/// Fichero abierto a través de la tabla de montaje.
+#[derive(Debug)]
+pub struct File {
+    mount: usize,
+    handle: FileHandle,
+}
+
+impl File {
+    pub fn size(&self) -> u64 {
+        self.handle.size
+    }
+
+    pub fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<usize, VfsError> {
+        fs_at(self.mount)?.read(&self.handle, offset, buf)
+    }
+
+    pub fn close(self) {
+        if let Ok(fs) = fs_at(self.mount) {
+            fs.close(self.handle);
+        }
+    }
+}
+
+pub fn open(path: &str) -> Result<File, VfsError> {
+    let (mount, rel) = resolve(path)?;
+    let handle = fs_at(mount)?.open(rel)?;
+    Ok(File { mount, handle })
+}
+
+pub fn stat(path: &str) -> Result<Metadata, VfsError> {
+    let (mount, rel) = resolve(path)?;
+    fs_at(mount)?.stat(rel)
+}
+
+pub fn list(path: &str, mut f: impl FnMut(&DirEntry) -> bool) -> Result<usize, VfsError> {
+    let (mount, rel) = resolve(path)?;
+    fs_at(mount)?.list(rel, &mut f)
+}
+
+/// Lee el fichero completo en `buf`. Falla con `BufferTooSmall` si no cabe.
+pub fn read_file(path: &str, buf: &mut [u8]) -> Result<usize, VfsError> {
+    let file = open(path)?;
+    if file.size() > buf.len() as u64 {
+        file.close();
+        return Err(VfsError::BufferTooSmall);
+    }
+    let result = file.read_at(0, &mut buf[..file.size() as usize]);
+    file.close();
+    result
+}
+
+/// Proyecta el fichero en memoria sin copiarlo, si el backend lo permite.
+pub fn map_file(path: &str) -> Result<&'static [u8], VfsError> {
+    let (mount, rel) = resolve(path)?;
+    fs_at(mount)?.map(rel)
+}
+
+/// Deshace una proyección de [`map_file`]; se prueba en cada montaje hasta que uno la reconozca.
+pub fn unmap_file(data: &'static [u8]) -> Result<(), VfsError> {
+    for m in mounts().iter_mut().flatten() {
+        if m.fs.unmap(data).is_ok() {
+            return Ok(());
+        }
+    }
+    Err(VfsError::NotFound)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::boxed::Box;
+
+    /// Backend que solo sabe su nombre y devuelve en `size` la longitud de la ruta relativa.
+    struct Named(&'static str);
+
+    impl FileSystem for Named {
+        fn name(&self) -> &'static str {
+            self.0
+        }
+
+        fn open(&mut self, path: &str) -> Result<FileHandle, VfsError> {
+            Ok(FileHandle { id: 0, aux: 0, size: path.len() as u64 })
+        }
+
+        fn read(&mut self, _handle: &FileHandle, _offset: u64, _buf: &mut [u8]) -> Result<usize, VfsError> {
+            Ok(0)
+        }
+
+        fn close(&mut self, _handle: FileHandle) {}
+
+        fn stat(&mut self, _path: &str) -> Result<Metadata, VfsError> {
+            Err(VfsError::NotFound)
+        }
+
+        fn list(&mut self, _path: &str, _f: &mut dyn FnMut(&DirEntry) -> bool) -> Result<usize, VfsError> {
+            Ok(0)
+        }
+    }
+
+    fn named(name: &'static str) -> &'static mut dyn FileSystem {
+        Box::leak(Box::new(Named(name)))
+    }
+
+    fn backend_of(path: &str) -> Result<(&'static str, &str), VfsError> {
+        let (idx, rel) = resolve(path)?;
+        Ok((fs_at(idx)?.name(), rel))
+    }
+
+    #[test]
+    fn mount_table_resolves_longest_prefix() {
+        assert_eq!(mount("blk", named("bad")), Err(VfsError::InvalidPath));
+        assert_eq!(backend_of("/a"), Err(VfsError::NotMounted));
+        mount("/", named("root")).unwrap();
+        mount("/blk", named("blk")).unwrap();
+        mount("/blk/models/", named("models")).unwrap();
+
+        assert_eq!(backend_of("/hello.txt"), Ok(("root", "/hello.txt")));
+        assert_eq!(backend_of("/blk"), Ok(("blk", "/")));
+        assert_eq!(backend_of("/blk/a.bin"), Ok(("blk", "/a.bin")));
+        // El prefijo solo cubre componentes completos
+        assert_eq!(backend_of("/blkx/a.bin"), Ok(("root", "/blkx/a.bin")));
+        assert_eq!(backend_of("/blk/models/tiny.bin"), Ok(("models", "/tiny.bin")));
+        assert_eq!(backend_of("relative"), Err(VfsError::InvalidPath));
+        assert_eq!(open("/blk/models/x").map(|f| f.size()), Ok(2));
+
+        // Montar otra vez un prefijo lo reemplaza; la tabla tiene MAX_MOUNTS entradas
+        mount("/blk", named("blk2")).unwrap();
+        assert_eq!(backend_of("/blk/a.bin"), Ok(("blk2", "/a.bin")));
+        mount("/extra", named("extra")).unwrap();
+        assert_eq!(mount("/full", named("full")), Err(VfsError::Unsupported));
+        let mut names = Vec::new();
+        for_each_mount(|prefix, name| names.push((prefix, name)));
+        assert_eq!(names, [("/", "root"), ("/blk", "blk2"), ("/blk/models/", "models"), ("/extra", "extra")]);
+
+        unmount("/blk");
+        assert!(!is_mounted("/blk"));
+        assert_eq!(backend_of("/blk/a.bin"), Ok(("root", "/blk/a.bin")));
+    }
+}
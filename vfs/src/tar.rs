@@ -0,0 +1,499 @@
+//! Sistema de ficheros de solo lectura sobre una imagen ustar en un `BlockDevice`.
+//!
+//! Al montar se recorren las cabeceras una vez y se construye un índice en memoria
+//! (ruta, tamaño y desplazamiento de los datos). Los directorios pueden estar
+//! explícitos en la imagen o deducirse de las rutas de los ficheros.
+
+use crate::{DirEntry, FileHandle, FileKind, FileSystem, Metadata, VfsError};
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use drivers_virtio::blk::BlockDevice;
+
+const TAR_BLOCK: u64 = 512;
+/// Tamaño máximo de un nombre largo GNU (PATH_MAX).
+const MAX_LONG_NAME: u64 = 4096;
+
+// Typeflags
+const REGTYPE: u8 = b'0';
+const AREGTYPE: u8 = 0;
+const DIRTYPE: u8 = b'5';
+const GNU_LONGNAME: u8 = b'L';
+const PAX_HEADER: u8 = b'x';
+const PAX_GLOBAL: u8 = b'g';
+
+struct TarEntry {
+    path: String,
+    kind: FileKind,
+    size: u64,
+    mode: u32,
+    mtime: u64,
+    data_offset: u64,
+}
+
+pub struct TarFs {
+    dev: &'static mut dyn BlockDevice,
+    entries: Vec<TarEntry>,
+    scratch: Vec<u8>,
+}
+
+/// Campo numérico de la cabecera: octal terminado en NUL/espacio, o base-256 si el
+/// primer byte tiene el bit alto (extensión GNU para tamaños grandes).
+fn parse_number(field: &[u8]) -> Option<u64> {
+    if field.first().is_some_and(|b| b & 0x80 != 0) {
+        let mut value = (field[0] & 0x7F) as u64;
+        for &b in &field[1..] {
+            value = value.checked_mul(256)? | b as u64;
+        }
+        return Some(value);
+    }
+    let mut value = 0u64;
+    for &b in field.iter().skip_while(|&&b| b == b' ') {
+        match b {
+            b'0'..=b'7' => value = value.checked_mul(8)? + (b - b'0') as u64,
+            0 | b' ' => break,
+            _ => return None,
+        }
+    }
+    Some(value)
+}
+
+fn field_str(field: &[u8]) -> &[u8] {
+    let len = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+    &field[..len]
+}
+
+fn checksum_ok(header: &[u8]) -> bool {
+    let Some(expected) = parse_number(&header[148..156]) else { return false };
+    let sum: u64 = header
+        .iter()
+        .enumerate()
+        .map(|(i, &b)| if (148..156).contains(&i) { b' ' as u64 } else { b as u64 })
+        .sum();
+    sum == expected
+}
+
+/// Normaliza una ruta relativa al montaje: sin `/` inicial ni final, sin `.`.
+fn normalize(path: &str) -> String {
+    let mut out = String::new();
+    for component in path.split('/').filter(|c| !c.is_empty() && *c != ".") {
+        if !out.is_empty() {
+            out.push('/');
+        }
+        out.push_str(component);
+    }
+    out
+}
+
+impl TarFs {
+    /// Indexa la imagen ustar de `dev`. Falla con `Corrupt` si la primera cabecera no
+    /// es válida (el dispositivo no contiene un tar).
+    pub fn new(dev: &'static mut dyn BlockDevice) -> Result<Self, VfsError> {
+        let block_size = dev.block_size();
+        let mut fs = TarFs { dev, entries: Vec::new(), scratch: vec![0; block_size] };
+        fs.scan()?;
+        Ok(fs)
+    }
+
+    fn capacity(&self) -> u64 {
+        self.dev.num_blocks() * self.dev.block_size() as u64
+    }
+
+    /// Lee bytes arbitrarios de la imagen a través de bloques completos del dispositivo.
+    fn read_bytes(&mut self, pos: u64, buf: &mut [u8]) -> Result<(), VfsError> {
+        let bs = self.dev.block_size() as u64;
+        let mut done = 0;
+        while done < buf.len() {
+            let at = pos + done as u64;
+            let lba = at / bs;
+            let within = (at % bs) as usize;
+            let remaining = buf.len() - done;
+            if within == 0 && remaining >= bs as usize {
+                // Tramo alineado: se lee directamente sobre el buffer del llamador
+                let n = remaining - remaining % bs as usize;
+                self.dev.read_blocks(lba, &mut buf[done..done + n]).map_err(|e| VfsError::Io(e.as_str()))?;
+                done += n;
+            } else {
+                self.dev.read_blocks(lba, &mut self.scratch).map_err(|e| VfsError::Io(e.as_str()))?;
+                let n = remaining.min(bs as usize - within);
+                buf[done..done + n].copy_from_slice(&self.scratch[within..within + n]);
+                done += n;
+            }
+        }
+        Ok(())
+    }
+
+    fn scan(&mut self) -> Result<(), VfsError> {
+        let capacity = self.capacity();
+        let mut pos = 0u64;
+        let mut long_name: Option<String> = None;
+        let mut header = [0u8; TAR_BLOCK as usize];
+        while pos + TAR_BLOCK <= capacity {
+            self.read_bytes(pos, &mut header)?;
+            if header.iter().all(|&b| b == 0) {
+                break; // fin de archivo
+            }
+            if !checksum_ok(&header) {
+                if self.entries.is_empty() && pos == 0 {
+                    return Err(VfsError::Corrupt);
+                }
+                break;
+            }
+            let size = parse_number(&header[124..136]).ok_or(VfsError::Corrupt)?;
+            let data_offset = pos + TAR_BLOCK;
+            let typeflag = header[156];
+            pos = size
+                .div_ceil(TAR_BLOCK)
+                .checked_mul(TAR_BLOCK)
+                .and_then(|len| data_offset.checked_add(len))
+                .ok_or(VfsError::Corrupt)?;
+
+            match typeflag {
+                GNU_LONGNAME => {
+                    if size > MAX_LONG_NAME {
+                        return Err(VfsError::Corrupt);
+                    }
+                    let mut name = vec![0u8; size as usize];
+                    self.read_bytes(data_offset, &mut name)?;
+                    let name = field_str(&name);
+                    long_name = Some(String::from(core::str::from_utf8(name).map_err(|_| VfsError::Corrupt)?));
+                    continue;
+                }
+                PAX_HEADER | PAX_GLOBAL => continue,
+                _ => {}
+            }
+
+            let path = match long_name.take() {
+                Some(name) => name,
+                None => {
+                    let name = field_str(&header[0..100]);
+                    let prefix = field_str(&header[345..500]);
+                    let mut full = String::new();
+                    if !prefix.is_empty() {
+                        full.push_str(core::str::from_utf8(prefix).map_err(|_| VfsError::Corrupt)?);
+                        full.push('/');
+                    }
+                    full.push_str(core::str::from_utf8(name).map_err(|_| VfsError::Corrupt)?);
+                    full
+                }
+            };
+            let kind = match typeflag {
+                REGTYPE | AREGTYPE => FileKind::File,
+                DIRTYPE => FileKind::Dir,
+                _ => FileKind::Other,
+            };
+            let path = normalize(&path);
+            if path.is_empty() {
+                continue;
+            }
+            self.entries.push(TarEntry {
+                path,
+                kind,
+                size: if kind == FileKind::File { size } else { 0 },
+                mode: parse_number(&header[100..108]).unwrap_or(0) as u32,
+                mtime: parse_number(&header[136..148]).unwrap_or(0),
+                data_offset,
+            });
+        }
+        Ok(())
+    }
+
+    fn find(&self, path: &str) -> Option<usize> {
+        self.entries.iter().position(|e| e.path == path)
+    }
+
+    /// `true` si `dir` es un directorio explícito o implícito (prefijo de alguna ruta).
+    fn is_dir(&self, dir: &str) -> bool {
+        dir.is_empty()
+            || self.entries.iter().any(|e| {
+                (e.path == dir && e.kind == FileKind::Dir)
+                    || (e.path.len() > dir.len() && e.path.starts_with(dir) && e.path.as_bytes()[dir.len()] == b'/')
+            })
+    }
+
+    /// Número de ficheros regulares indexados.
+    pub fn file_count(&self) -> usize {
+        self.entries.iter().filter(|e| e.kind == FileKind::File).count()
+    }
+}
+
+impl FileSystem for TarFs {
+    fn name(&self) -> &'static str {
+        "tar"
+    }
+
+    fn open(&mut self, path: &str) -> Result<FileHandle, VfsError> {
+        let path = normalize(path);
+        match self.find(&path) {
+            Some(idx) if self.entries[idx].kind == FileKind::File => {
+                Ok(FileHandle { id: idx as u64, aux: 0, size: self.entries[idx].size })
+            }
+            Some(_) => Err(VfsError::IsADirectory),
+            None if self.is_dir(&path) => Err(VfsError::IsADirectory),
+            None => Err(VfsError::NotFound),
+        }
+    }
+
+    fn read(&mut self, handle: &FileHandle, offset: u64, buf: &mut [u8]) -> Result<usize, VfsError> {
+        let entry = self.entries.get(handle.id as usize).ok_or(VfsError::NotFound)?;
+        if offset >= entry.size {
+            return Ok(0);
+        }
+        let n = buf.len().min((entry.size - offset) as usize);
+        let pos = entry.data_offset + offset;
+        self.read_bytes(pos, &mut buf[..n])?;
+        Ok(n)
+    }
+
+    fn close(&mut self, _handle: FileHandle) {}
+
+    fn stat(&mut self, path: &str) -> Result<Metadata, VfsError> {
+        let path = normalize(path);
+        if let Some(entry) = self.find(&path).map(|idx| &self.entries[idx]) {
+            return Ok(Metadata { size: entry.size, kind: entry.kind, mode: entry.mode, mtime: entry.mtime });
+        }
+        if self.is_dir(&path) {
+            return Ok(Metadata { size: 0, kind: FileKind::Dir, mode: 0o555, mtime: 0 });
+        }
+        Err(VfsError::NotFound)
+    }
+
+    fn list(&mut self, path: &str, f: &mut dyn FnMut(&DirEntry) -> bool) -> Result<usize, VfsError> {
+        let dir = normalize(path);
+        if !self.is_dir(&dir) {
+            return Err(if self.find(&dir).is_some() { VfsError::NotADirectory } else { VfsError::NotFound });
+        }
+        let mut seen: Vec<&str> = Vec::new();
+        for entry in &self.entries {
+            let rest = if dir.is_empty() {
+                entry.path.as_str()
+            } else if entry.path.len() > dir.len() + 1
+                && entry.path.starts_with(dir.as_str())
+                && entry.path.as_bytes()[dir.len()] == b'/'
+            {
+                &entry.path[dir.len() + 1..]
+            } else {
+                continue;
+            };
+            let (name, kind) = match rest.find('/') {
+                Some(idx) => (&rest[..idx], FileKind::Dir),
+                None => (rest, entry.kind),
+            };
+            if seen.contains(&name) {
+                continue;
+            }
+            seen.push(name);
+            if !f(&DirEntry { name, kind }) {
+                break;
+            }
+        }
+        Ok(seen.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use drivers_virtio::blk::BlkError;
+    use std::boxed::Box;
+
+    /// Disco en memoria con bloques de `block_size` bytes.
+    struct MemDisk {
+        data: Vec<u8>,
+        block_size: usize,
+    }
+
+    impl BlockDevice for MemDisk {
+        fn block_size(&self) -> usize {
+            self.block_size
+        }
+
+        fn num_blocks(&self) -> u64 {
+            (self.data.len() / self.block_size) as u64
+        }
+
+        fn read_only(&self) -> bool {
+            true
+        }
+
+        fn read_blocks(&mut self, lba: u64, buf: &mut [u8]) -> Result<(), BlkError> {
+            let start = lba as usize * self.block_size;
+            let src = self.data.get(start..start + buf.len()).ok_or(BlkError::OutOfRange)?;
+            buf.copy_from_slice(src);
+            Ok(())
+        }
+
+        fn write_blocks(&mut self, _lba: u64, _buf: &[u8]) -> Result<(), BlkError> {
+            Err(BlkError::ReadOnly)
+        }
+
+        fn flush(&mut self) -> Result<(), BlkError> {
+            Ok(())
+        }
+    }
+
+    fn header(name: &str, prefix: &str, size: u64, typeflag: u8) -> [u8; 512] {
+        let mut h = [0u8; 512];
+        h[..name.len()].copy_from_slice(name.as_bytes());
+        h[100..108].copy_from_slice(b"0000644\0");
+        h[124..136].copy_from_slice(std::format!("{:011o}\0", size).as_bytes());
+        h[136..148].copy_from_slice(b"00000001750\0");
+        h[148..156].copy_from_slice(b"        ");
+        h[156] = typeflag;
+        h[257..263].copy_from_slice(b"ustar\0");
+        h[345..345 + prefix.len()].copy_from_slice(prefix.as_bytes());
+        let sum: u32 = h.iter().map(|&b| b as u32).sum();
+        h[148..156].copy_from_slice(std::format!("{:06o}\0 ", sum).as_bytes());
+        h
+    }
+
+    /// Cabecera con el tamaño en base-256 (extensión GNU) y el checksum recalculado.
+    fn header_base256(name: &str, size: u64, typeflag: u8) -> [u8; 512] {
+        let mut h = header(name, "", 0, typeflag);
+        h[124..128].copy_from_slice(&[0x80, 0, 0, 0]);
+        h[128..136].copy_from_slice(&size.to_be_bytes());
+        h[148..156].copy_from_slice(b"        ");
+        let sum: u32 = h.iter().map(|&b| b as u32).sum();
+        h[148..156].copy_from_slice(std::format!("{:06o}\0 ", sum).as_bytes());
+        h
+    }
+
+    fn append(image: &mut Vec<u8>, h: [u8; 512], data: &[u8]) {
+        image.extend_from_slice(&h);
+        image.extend_from_slice(data);
+        image.resize(image.len().next_multiple_of(512), 0);
+    }
+
+    fn pattern(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i % 251) as u8).collect()
+    }
+
+    /// Imagen con directorios explícitos e implícitos, nombre largo GNU, `prefix` ustar
+    /// y una cabecera PAX que debe ignorarse.
+    fn image() -> Vec<u8> {
+        let long = std::format!("models/{}.bin", "x".repeat(120));
+        let mut image = Vec::new();
+        append(&mut image, header("models/", "", 0, DIRTYPE), &[]);
+        append(&mut image, header("models/tiny.bin", "", 5000, REGTYPE), &pattern(5000));
+        append(&mut image, header("./hello.txt", "", 5, AREGTYPE), b"hola\n");
+        append(&mut image, header("././@LongLink", "", long.len() as u64 + 1, GNU_LONGNAME), std::format!("{}\0", long).as_bytes());
+        append(&mut image, header("replaced", "", 3, REGTYPE), b"abc");
+        append(&mut image, header("pax", "", 20, PAX_HEADER), b"20 path=ignored.txt\n");
+        append(&mut image, header("deep.bin", "data/nested", 2, REGTYPE), b"ok");
+        image.extend_from_slice(&[0u8; 1024]);
+        image
+    }
+
+    fn mount(image: Vec<u8>, block_size: usize) -> Result<TarFs, VfsError> {
+        let mut data = image;
+        data.resize(data.len().next_multiple_of(block_size), 0);
+        TarFs::new(Box::leak(Box::new(MemDisk { data, block_size })))
+    }
+
+    fn read_all(fs: &mut TarFs, path: &str) -> Vec<u8> {
+        let handle = fs.open(path).unwrap();
+        let mut buf = vec![0u8; handle.size as usize];
+        assert_eq!(fs.read(&handle, 0, &mut buf), Ok(buf.len()));
+        buf
+    }
+
+    #[test]
+    fn parses_octal_and_base256_numbers() {
+        assert_eq!(parse_number(b"0000644\0"), Some(0o644));
+        assert_eq!(parse_number(b"  1750 \0"), Some(0o1750));
+        assert_eq!(parse_number(b"\0\0\0\0"), Some(0));
+        assert_eq!(parse_number(b"12389\0"), None);
+        let mut big = [0u8; 12];
+        big[0] = 0x80;
+        big[7] = 0x01; // 2^32
+        assert_eq!(parse_number(&big), Some(1 << 32));
+    }
+
+    #[test]
+    fn indexes_entries_and_reads_files() {
+        let mut fs = mount(image(), 512).unwrap();
+        assert_eq!(fs.file_count(), 4);
+        assert_eq!(read_all(&mut fs, "/hello.txt"), b"hola\n");
+        assert_eq!(read_all(&mut fs, "/models/tiny.bin"), pattern(5000));
+        assert_eq!(read_all(&mut fs, "/data/nested/deep.bin"), b"ok");
+        let long = std::format!("/models/{}.bin", "x".repeat(120));
+        assert_eq!(read_all(&mut fs, &long), b"abc");
+        assert_eq!(fs.open("/replaced").map(|_| ()), Err(VfsError::NotFound));
+        assert_eq!(fs.open("/ignored.txt").map(|_| ()), Err(VfsError::NotFound));
+    }
+
+    #[test]
+    fn reads_at_offsets_across_device_blocks() {
+        // Bloques de 4KiB: las cabeceras y los datos de tar no están alineados a ellos
+        let mut fs = mount(image(), 4096).unwrap();
+        let handle = fs.open("models/tiny.bin").unwrap();
+        let mut buf = vec![0u8; 4500];
+        assert_eq!(fs.read(&handle, 300, &mut buf), Ok(4500));
+        assert_eq!(buf, pattern(5000)[300..4800]);
+        assert_eq!(fs.read(&handle, 4990, &mut buf), Ok(10));
+        assert_eq!(fs.read(&handle, 5000, &mut buf), Ok(0));
+    }
+
+    #[test]
+    fn stat_and_list_directories() {
+        let mut fs = mount(image(), 512).unwrap();
+        let meta = fs.stat("/models/tiny.bin").unwrap();
+        assert_eq!((meta.kind, meta.size, meta.mode, meta.mtime), (FileKind::File, 5000, 0o644, 0o1750));
+        assert_eq!(fs.stat("/models").unwrap().kind, FileKind::Dir);
+        // `data` y `data/nested` solo aparecen en las rutas
+        assert_eq!(fs.stat("/data/nested").unwrap().kind, FileKind::Dir);
+        assert_eq!(fs.stat("/").unwrap().kind, FileKind::Dir);
+        assert_eq!(fs.stat("/nope").map(|_| ()), Err(VfsError::NotFound));
+        assert_eq!(fs.open("/data").map(|_| ()), Err(VfsError::IsADirectory));
+
+        let mut root = Vec::new();
+        let count = fs
+            .list("/", &mut |e| {
+                root.push((String::from(e.name), e.kind));
+                true
+            })
+            .unwrap();
+        assert_eq!(count, 3);
+        assert_eq!(
+            root,
+            [
+                (String::from("models"), FileKind::Dir),
+                (String::from("hello.txt"), FileKind::File),
+                (String::from("data"), FileKind::Dir),
+            ]
+        );
+        assert_eq!(fs.list("/models", &mut |_| true), Ok(2));
+        assert_eq!(fs.list("/models", &mut |_| false), Ok(1));
+        assert_eq!(fs.list("/hello.txt", &mut |_| true), Err(VfsError::NotADirectory));
+        assert_eq!(fs.list("/nope", &mut |_| true), Err(VfsError::NotFound));
+    }
+
+    #[test]
+    fn rejects_images_that_are_not_tar() {
+        assert!(matches!(mount(vec![0xAB; 2048], 512), Err(VfsError::Corrupt)));
+        // Una imagen vacía (solo los bloques finales) es válida y no tiene ficheros
+        assert_eq!(mount(vec![0; 1024], 512).unwrap().file_count(), 0);
+        // Una cabecera corrupta tras otras válidas termina el índice
+        let mut image = image();
+        let end = image.len() - 1024;
+        image.truncate(end);
+        image.extend_from_slice(&[0xAB; 512]);
+        assert_eq!(mount(image, 512).unwrap().file_count(), 4);
+    }
+
+    #[test]
+    fn rejects_oversized_long_names_and_sizes() {
+        let mut image = Vec::new();
+        let name = vec![b'x'; MAX_LONG_NAME as usize + 1];
+        append(&mut image, header("././@LongLink", "", name.len() as u64, GNU_LONGNAME), &name);
+        append(&mut image, header("short", "", 0, REGTYPE), &[]);
+        image.extend_from_slice(&[0u8; 1024]);
+        assert!(matches!(mount(image, 512), Err(VfsError::Corrupt)));
+
+        // El final de los datos no cabe en un u64
+        let mut image = Vec::new();
+        append(&mut image, header_base256("huge.bin", u64::MAX - 100, REGTYPE), &[]);
+        image.extend_from_slice(&[0u8; 1024]);
+        assert!(matches!(mount(image, 512), Err(VfsError::Corrupt)));
+    }
+}
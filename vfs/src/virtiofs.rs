@@ -0,0 +1,81 @@
+//! Adaptador del cliente virtio-fs (`drivers_virtio::fs`) al trait `FileSystem`.
+
+use crate::{DirEntry, FileHandle, FileKind, FileSystem, Metadata, VfsError};
+use drivers_virtio::fs::{self, FileAttr, FsError};
+
+// Tipos de fuse_dirent (d_type)
+const DT_DIR: u32 = 4;
+const DT_REG: u32 = 8;
+
+/// Sistema de ficheros exportado por virtiofsd. No tiene estado propio: el driver
+/// mantiene la conexión FUSE.
+pub struct VirtioFs;
+
+impl From<FsError> for VfsError {
+    fn from(e: FsError) -> Self {
+        match e {
+            FsError::Fuse(2) => VfsError::NotFound,
+            FsError::Fuse(20) => VfsError::NotADirectory,
+            FsError::Fuse(21) => VfsError::IsADirectory,
+            FsError::InvalidPath => VfsError::InvalidPath,
+            FsError::BufferTooSmall => VfsError::BufferTooSmall,
+            FsError::DaxUnavailable | FsError::DaxWindowFull => VfsError::Unsupported,
+            FsError::Protocol => VfsError::Corrupt,
+            e => VfsError::Io(e.as_str()),
+        }
+    }
+}
+
+fn metadata(attr: &FileAttr) -> Metadata {
+    let kind = if attr.is_dir() {
+        FileKind::Dir
+    } else if attr.is_file() {
+        FileKind::File
+    } else {
+        FileKind::Other
+    };
+    Metadata { size: attr.size, kind, mode: attr.mode & 0o7777, mtime: attr.mtime }
+}
+
+impl FileSystem for VirtioFs {
+    fn name(&self) -> &'static str {
+        "virtio-fs"
+    }
+
+    fn open(&mut self, path: &str) -> Result<FileHandle, VfsError> {
+        let file = fs::open(path)?;
+        Ok(FileHandle { id: file.nodeid, aux: file.fh, size: file.attr.size })
+    }
+
+    fn read(&mut self, handle: &FileHandle, offset: u64, buf: &mut [u8]) -> Result<usize, VfsError> {
+        Ok(fs::read(handle.id, handle.aux, offset, buf)?)
+    }
+
+    fn close(&mut self, handle: FileHandle) {
+        fs::File { nodeid: handle.id, fh: handle.aux, attr: FileAttr::default() }.close();
+    }
+
+    fn stat(&mut self, path: &str) -> Result<Metadata, VfsError> {
+        Ok(metadata(&fs::stat(path)?))
+    }
+
+    fn list(&mut self, path: &str, f: &mut dyn FnMut(&DirEntry) -> bool) -> Result<usize, VfsError> {
+        let count = fs::read_dir(path, |entry| {
+            let kind = match entry.kind {
+                DT_DIR => FileKind::Dir,
+                DT_REG => FileKind::File,
+                _ => FileKind::Other,
+            };
+            f(&DirEntry { name: entry.name, kind })
+        })?;
+        Ok(count)
+    }
+
+    fn map(&mut self, path: &str) -> Result<&'static [u8], VfsError> {
+        Ok(fs::map_file(path)?)
+    }
+
+    fn unmap(&mut self, data: &'static [u8]) -> Result<(), VfsError> {
+        Ok(fs::unmap_file(data)?)
+    }
+}
@@ -1,8 +1,25 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
 use std::process::Command;
 use std::path::Path;
 
 fn main() {
     let args: Vec<String> = std::env::args().collect();
+    if args.len() > 1 && args[1] == "pack-models" {
+        // Empaqueta un directorio de modelos en una imagen ustar para virtio-blk
+        if args.len() < 4 {
+            eprintln!("Uso: cargo run -p xtask -- pack-models <directorio> <imagen.tar>");
+            std::process::exit(1);
+        }
+        match pack_models(Path::new(&args[2]), Path::new(&args[3])) {
+            Ok(count) => println!("Imagen {} generada con {} fichero(s)", args[3], count),
+            Err(e) => {
+                eprintln!("ERROR empaquetando {}: {}", args[2], e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
     if args.len() > 1 && args[1] == "qemu" {
         // Verificar que el kernel ELF existe
         let kernel_path = "target/kernel.elf";
@@ -16,7 +33,20 @@ fn main() {
                 "-m", "512M",
                 "-kernel", kernel_path,
                 "-serial", "stdio",
-                "-display", "none"
+                "-display", "none",
+                // q35 con dos root ports libres para device_add/device_del desde el monitor
+                "-machine", "q35",
+                "-device", "pcie-root-port,id=hp0,chassis=1,slot=1",
+                "-device", "pcie-root-port,id=hp1,chassis=2,slot=2",
+                "-monitor", "unix:target/qemu-monitor.sock,server=on,wait=off",
+                // virtio-console: consola de depuración (hvc0) y puerto de logs
+                "-device", "virtio-serial-pci",
+                "-chardev", "socket,id=dbg,path=target/debug-console.sock,server=on,wait=off",
+                "-device", "virtconsole,chardev=dbg",
+                "-chardev", "file,id=klog,path=target/kernel.log",
+                "-device", "virtserialport,chardev=klog,name=org.microkernelia.log",
+                "-device", "virtio-rng-pci",
+                "-device", "virtio-balloon-pci,free-page-reporting=on"
             ])
             .status()
             .expect("falló al lanzar QEMU (¿está instalado qemu-system-x86_64?)");
@@ -36,3 +66,210 @@ fn main() {
     }
     println!("Build kernel OK");
 }
+
+/// Escribe `dir` como imagen ustar en `out`. Las rutas quedan relativas a `dir`, igual
+/// que las ve el guest cuando virtiofsd comparte ese directorio.
+fn pack_models(dir: &Path, out: &Path) -> io::Result<usize> {
+    if !dir.is_dir() {
+        return Err(io::Error::new(io::ErrorKind::NotFound, "no es un directorio"));
+    }
+    let mut writer = BufWriter::new(File::create(out)?);
+    let mut count = 0;
+    append_dir(&mut writer, dir, "", &mut count)?;
+    // Fin de archivo: dos bloques a cero
+    writer.write_all(&[0u8; 1024])?;
+    writer.flush()?;
+    Ok(count)
+}
+
+fn append_dir(writer: &mut impl Write, dir: &Path, rel: &str, count: &mut usize) -> io::Result<()> {
+    let mut entries: Vec<_> = std::fs::read_dir(dir)?.collect::<Result<_, _>>()?;
+    entries.sort_by_key(|e| e.file_name());
+    for entry in entries {
+        // El guest busca los modelos por su nombre exacto: no se renombra nada en silencio
+        let name = entry.file_name().into_string().map_err(|name| {
+            io::Error::new(io::ErrorKind::InvalidData, format!("nombre no UTF-8: {}", name.to_string_lossy()))
+        })?;
+        let path = if rel.is_empty() { name } else { format!("{}/{}", rel, name) };
+        let meta = entry.metadata()?;
+        let mtime = meta
+            .modified()
+            .ok()
+            .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        if meta.is_dir() {
+            writer.write_all(&ustar_header(&path, 0, 0o755, mtime, b'5')?)?;
+            append_dir(writer, &entry.path(), &path, count)?;
+        } else if meta.is_file() {
+            writer.write_all(&ustar_header(&path, meta.len(), 0o644, mtime, b'0')?)?;
+            let mut file = File::open(entry.path())?;
+            let copied = io::copy(&mut file, writer)?;
+            if copied != meta.len() {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, path));
+            }
+            let padding = (512 - (copied % 512) as usize) % 512;
+            writer.write_all(&vec![0u8; padding])?;
+            *count += 1;
+        }
+    }
+    Ok(())
+}
+
+/// Cabecera ustar de 512 bytes. Las rutas de más de 100 bytes se reparten entre
+/// `prefix` (155) y `name` (100) cortando por una `/`.
+fn ustar_header(path: &str, size: u64, mode: u32, mtime: u64, typeflag: u8) -> io::Result<[u8; 512]> {
+    let (prefix, name) = if path.len() <= 100 {
+        ("", path)
+    } else {
+        path.char_indices()
+            .filter(|&(i, c)| c == '/' && i <= 155 && path.len() - i - 1 <= 100)
+            .map(|(i, _)| (&path[..i], &path[i + 1..]))
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("ruta demasiado larga: {}", path)))?
+    };
+    let mut header = [0u8; 512];
+    let mut put = |offset: usize, bytes: &[u8]| header[offset..offset + bytes.len()].copy_from_slice(bytes);
+    put(0, name.as_bytes());
+    put(100, format!("{:07o}\0", mode).as_bytes());
+    put(108, b"0000000\0"); // uid
+    put(116, b"0000000\0"); // gid
+    put(124, &number_field(size));
+    put(136, &number_field(mtime));
+    put(148, b"        "); // checksum provisional (espacios)
+    put(156, &[typeflag]);
+    put(257, b"ustar\0");
+    put(263, b"00");
+    put(345, prefix.as_bytes());
+    let checksum: u32 = header.iter().map(|&b| b as u32).sum();
+    header[148..156].copy_from_slice(format!("{:06o}\0 ", checksum).as_bytes());
+    Ok(header)
+}
+
+/// Campo numérico de 12 bytes: octal si cabe en 11 dígitos (hasta 8GiB - 1) y, si
+/// no, base-256 con el bit alto del primer byte (extensión GNU, la lee `vfs::tar`).
+fn number_field(value: u64) -> [u8; 12] {
+    let mut field = [0u8; 12];
+    if value < 1 << 33 {
+        field.copy_from_slice(format!("{:011o}\0", value).as_bytes());
+    } else {
+        field[0] = 0x80;
+        field[4..].copy_from_slice(&value.to_be_bytes());
+    }
+    field
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn field(header: &[u8], range: std::ops::Range<usize>) -> &str {
+        let raw = &header[range];
+        let len = raw.iter().position(|&b| b == 0).unwrap_or(raw.len());
+        std::str::from_utf8(&raw[..len]).unwrap()
+    }
+
+    fn octal(header: &[u8], range: std::ops::Range<usize>) -> u64 {
+        u64::from_str_radix(field(header, range).trim(), 8).unwrap()
+    }
+
+    fn checksum_ok(header: &[u8]) -> bool {
+        let sum: u64 = header
+            .iter()
+            .enumerate()
+            .map(|(i, &b)| if (148..156).contains(&i) { b' ' as u64 } else { b as u64 })
+            .sum();
+        octal(header, 148..154) == sum
+    }
+
+    #[test]
+    fn header_fields_and_checksum() {
+        let header = ustar_header("models/tiny.bin", 5000, 0o644, 1_700_000_000, b'0').unwrap();
+        assert_eq!(field(&header, 0..100), "models/tiny.bin");
+        assert_eq!(octal(&header, 100..108), 0o644);
+        assert_eq!(octal(&header, 124..136), 5000);
+        assert_eq!(octal(&header, 136..148), 1_700_000_000);
+        assert_eq!(header[156], b'0');
+        assert_eq!(&header[257..263], b"ustar\0");
+        assert_eq!(field(&header, 345..500), "");
+        assert!(checksum_ok(&header));
+    }
+
+    #[test]
+    fn long_paths_are_split_into_prefix_and_name() {
+        let dir = "d".repeat(60);
+        let path = format!("{}/{}/{}", dir, dir, "f".repeat(90));
+        let header = ustar_header(&path, 0, 0o644, 0, b'0').unwrap();
+        assert_eq!(format!("{}/{}", field(&header, 345..500), field(&header, 0..100)), path);
+        assert!(field(&header, 0..100).len() <= 100);
+        assert!(checksum_ok(&header));
+        // Sin una `/` que deje cada parte dentro de su campo no se puede representar
+        assert!(ustar_header(&"x".repeat(101), 0, 0o644, 0, b'0').is_err());
+    }
+
+    #[test]
+    fn packs_directory_tree() {
+        let root = std::env::temp_dir().join(format!("xtask-pack-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("models/sub")).unwrap();
+        std::fs::write(root.join("models/tiny.bin"), vec![7u8; 700]).unwrap();
+        std::fs::write(root.join("models/sub/empty.txt"), b"").unwrap();
+        std::fs::write(root.join("hello.txt"), b"hola\n").unwrap();
+        let out = root.with_extension("tar");
+
+        assert_eq!(pack_models(&root, &out).unwrap(), 3);
+        let image = std::fs::read(&out).unwrap();
+        std::fs::remove_dir_all(&root).unwrap();
+        std::fs::remove_file(&out).unwrap();
+
+        // Recorre la imagen: cabecera, datos alineados a 512 y dos bloques finales a cero
+        assert_eq!(image.len() % 512, 0);
+        let mut entries = Vec::new();
+        let mut pos = 0;
+        while image[pos..pos + 512].iter().any(|&b| b != 0) {
+            let header = &image[pos..pos + 512];
+            assert!(checksum_ok(header));
+            let size = octal(header, 124..136) as usize;
+            let data = &image[pos + 512..pos + 512 + size];
+            entries.push((field(header, 0..100).to_string(), header[156], data.to_vec()));
+            pos += 512 + size.div_ceil(512) * 512;
+        }
+        assert_eq!(image.len() - pos, 1024);
+        assert!(image[pos..].iter().all(|&b| b == 0));
+        assert_eq!(
+            entries,
+            [
+                ("hello.txt".to_string(), b'0', b"hola\n".to_vec()),
+                ("models".to_string(), b'5', Vec::new()),
+                ("models/sub".to_string(), b'5', Vec::new()),
+                ("models/sub/empty.txt".to_string(), b'0', Vec::new()),
+                ("models/tiny.bin".to_string(), b'0', vec![7u8; 700]),
+            ]
+        );
+        assert!(pack_models(&root, &out).is_err());
+    }
+
+    #[test]
+    fn sizes_from_8gib_use_base256() {
+        assert_eq!(&number_field((1 << 33) - 1), b"77777777777\0");
+        let header = ustar_header("models/big.bin", 1 << 33, 0o644, 0, b'0').unwrap();
+        assert_eq!(&header[124..128], &[0x80, 0, 0, 0]);
+        assert_eq!(u64::from_be_bytes(header[128..136].try_into().unwrap()), 1 << 33);
+        assert!(checksum_ok(&header));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn rejects_non_utf8_names() {
+        use std::os::unix::ffi::OsStrExt;
+        let root = std::env::temp_dir().join(format!("xtask-utf8-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(root.join(std::ffi::OsStr::from_bytes(b"model\xff.bin")), b"x").unwrap();
+        let out = root.with_extension("tar");
+        let err = pack_models(&root, &out).unwrap_err();
+        std::fs::remove_dir_all(&root).unwrap();
+        let _ = std::fs::remove_file(&out);
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}